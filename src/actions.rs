@@ -0,0 +1,686 @@
+use crate::diagnostics::{AmbiguousRef, UnknownColumn, UnknownModel, UnknownSourceTable, UNKNOWN_COLUMN, UNKNOWN_MODEL, UNKNOWN_SOURCE, UNKNOWN_SOURCE_TABLE, UNUSED_CTE, AMBIGUOUS_REF};
+use crate::position::{byte_range_to_range, position_to_char, PositionEncoding};
+use crate::project::{ModelEntry, ProjectManifest};
+use crate::state::DocumentState;
+use crate::yaml::YamlKey;
+use ropey::Rope;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tower_lsp::lsp_types::{
+    CodeAction, CodeActionKind, CreateFile, CreateFileOptions, Diagnostic, DocumentChangeOperation, DocumentChanges,
+    NumberOrString, OneOf, OptionalVersionedTextDocumentIdentifier, Position, Range, ResourceOp, TextDocumentEdit,
+    TextEdit, Url, WorkspaceEdit,
+};
+
+/// Body of a model created by the "Create model" quick fix.
+const NEW_MODEL_TEXT: &str = "select 1 as placeholder\n";
+
+/// Where a new model `name` referenced from `current` goes: the first model path, in the
+/// same subfolder `current` has under its own model path.
+pub fn new_model_path(manifest: &ProjectManifest, current: &Path, name: &str) -> Option<PathBuf> {
+    let first = manifest.root_dir.join(manifest.config.model_paths.first()?);
+    let subfolder = manifest.config.model_paths.iter()
+        .find_map(|dir| current.strip_prefix(manifest.root_dir.join(dir)).ok())
+        .and_then(|relative| relative.parent())
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    Some(first.join(subfolder).join(format!("{}.sql", name)))
+}
+
+/// "Create model name.sql" for an unknown-model diagnostic.
+pub fn create_model_action(manifest: &ProjectManifest, current: &Path, diagnostic: &Diagnostic) -> Option<CodeAction> {
+    if diagnostic.code != Some(NumberOrString::String(UNKNOWN_MODEL.to_string())) {
+        return None;
+    }
+    let data: UnknownModel = serde_json::from_value(diagnostic.data.clone()?).ok()?;
+    let path = new_model_path(manifest, current, &data.name)?;
+    let uri = Url::from_file_path(&path).ok()?;
+    Some(CodeAction {
+        title: format!("Create model {}.sql", data.name),
+        kind: Some(CodeActionKind::QUICKFIX),
+        diagnostics: Some(vec![diagnostic.clone()]),
+        edit: Some(create_file_edit(uri, NEW_MODEL_TEXT.to_string())),
+        ..CodeAction::default()
+    })
+}
+
+/// The byte range of the `nth` quoted string inside the diagnostic's range, without quotes.
+fn quoted_name(rope: &Rope, range: Range, nth: usize, encoding: PositionEncoding) -> Option<std::ops::Range<usize>> {
+    let start = rope.char_to_byte(position_to_char(rope, range.start, encoding)?);
+    let end = rope.char_to_byte(position_to_char(rope, range.end, encoding)?);
+    let text = rope.byte_slice(start..end).to_string();
+    let quotes: Vec<usize> = text.match_indices(['\'', '"']).map(|(i, _)| i).collect();
+    let (open, close) = (quotes.get(nth * 2)?, quotes.get(nth * 2 + 1)?);
+    Some(start + open + 1..start + close)
+}
+
+/// "Did you mean" fixes for an unknown model, source table or column: one action per
+/// candidate, replacing the quoted name or the column.
+pub fn did_you_mean_actions(uri: &Url, rope: &Rope, diagnostic: &Diagnostic, encoding: PositionEncoding) -> Vec<CodeAction> {
+    let code = match &diagnostic.code {
+        Some(NumberOrString::String(code)) => code.as_str(),
+        _ => return Vec::new(),
+    };
+    let Some(data) = diagnostic.data.clone() else { return Vec::new() };
+    // The model is the first quoted string in ref('name'), the table the second in source('src', 'name')
+    let (candidates, nth) = match code {
+        UNKNOWN_MODEL => match serde_json::from_value::<UnknownModel>(data) {
+            Ok(data) => (data.candidates, Some(0)),
+            Err(_) => return Vec::new(),
+        },
+        UNKNOWN_SOURCE_TABLE => match serde_json::from_value::<UnknownSourceTable>(data) {
+            Ok(data) => (data.candidates, Some(1)),
+            Err(_) => return Vec::new(),
+        },
+        // The diagnostic covers just the column name
+        UNKNOWN_COLUMN => match serde_json::from_value::<UnknownColumn>(data) {
+            Ok(data) => (data.candidates, None),
+            Err(_) => return Vec::new(),
+        },
+        _ => return Vec::new(),
+    };
+    let range = match nth {
+        Some(nth) => match quoted_name(rope, diagnostic.range, nth, encoding) {
+            Some(name) => byte_range_to_range(rope, &name, encoding),
+            None => return Vec::new(),
+        },
+        None => diagnostic.range,
+    };
+
+    candidates.into_iter().enumerate().map(|(i, candidate)| CodeAction {
+        title: format!("Change to '{}'", candidate),
+        kind: Some(CodeActionKind::QUICKFIX),
+        diagnostics: Some(vec![diagnostic.clone()]),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(uri.clone(), vec![TextEdit { range, new_text: candidate }])])),
+            ..WorkspaceEdit::default()
+        }),
+        is_preferred: Some(i == 0),
+        ..CodeAction::default()
+    }).collect()
+}
+
+/// "Qualify as ref('pkg', 'name')" for an ambiguous ref, one per defining package. The
+/// quoted name is rewritten in place, keeping its quote style.
+pub fn qualify_ref_actions(uri: &Url, rope: &Rope, diagnostic: &Diagnostic, encoding: PositionEncoding) -> Vec<CodeAction> {
+    if diagnostic.code != Some(NumberOrString::String(AMBIGUOUS_REF.to_string())) {
+        return Vec::new();
+    }
+    let Some(data) = diagnostic.data.clone().and_then(|d| serde_json::from_value::<AmbiguousRef>(d).ok()) else { return Vec::new() };
+    let Some(name) = quoted_name(rope, diagnostic.range, 0, encoding) else { return Vec::new() };
+    let quoted = name.start - 1..name.end + 1;
+    let quote = rope.byte_slice(quoted.start..name.start).to_string();
+    let range = byte_range_to_range(rope, &quoted, encoding);
+
+    data.packages.iter().map(|package| CodeAction {
+        title: format!("Qualify as ref('{}', '{}')", package, data.name),
+        kind: Some(CodeActionKind::QUICKFIX),
+        diagnostics: Some(vec![diagnostic.clone()]),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(uri.clone(), vec![TextEdit {
+                range,
+                new_text: format!("{q}{}{q}, {q}{}{q}", package, data.name, q = quote),
+            }])])),
+            ..WorkspaceEdit::default()
+        }),
+        ..CodeAction::default()
+    }).collect()
+}
+
+/// The byte range of the table name in the FROM or JOIN item at `byte_idx`.
+fn table_name_at(doc: &DocumentState, byte_idx: usize) -> Option<std::ops::Range<usize>> {
+    let tree = doc.tree.as_ref()?;
+    let mut node = tree.root_node().descendant_for_byte_range(byte_idx, byte_idx)?;
+    loop {
+        let parent = node.parent()?;
+        if parent.kind() == "from_item" && parent.child_by_field_name("table_name") == Some(node) {
+            return Some(node.byte_range());
+        }
+        node = parent;
+    }
+}
+
+/// "Replace with {{ ref(...) }}" / "Replace with {{ source(...) }}" for a hardcoded table
+/// name at `byte_idx` whose last part is a model, seed or snapshot, or whose
+/// `schema.table` is a declared source. Only the name is replaced, so an alias stays.
+pub fn hardcoded_table_actions(uri: &Url, doc: &DocumentState, manifest: &ProjectManifest, byte_idx: usize, encoding: PositionEncoding) -> Vec<CodeAction> {
+    let Some(span) = table_name_at(doc, byte_idx) else { return Vec::new() };
+    let name = doc.text.byte_slice(span.clone()).to_string();
+    let parts: Vec<&str> = name.split('.').map(|p| p.trim_matches(['`', '"'])).collect();
+    let Some(table) = parts.last().copied() else { return Vec::new() };
+
+    let mut replacements = Vec::new();
+    if manifest.has_ref_target(table) {
+        replacements.push(format!("{{{{ ref('{}') }}}}", table));
+    }
+    if let [.., schema, _] = parts.as_slice() {
+        let mut sources: Vec<(String, String)> = manifest.sources.iter()
+            .filter(|s| s.schema.eq_ignore_ascii_case(schema) && s.identifier.eq_ignore_ascii_case(table))
+            .map(|s| (s.source_name.clone(), s.table_name.clone()))
+            .collect();
+        sources.sort();
+        replacements.extend(sources.into_iter().map(|(src, tbl)| format!("{{{{ source('{}', '{}') }}}}", src, tbl)));
+    }
+
+    let range = byte_range_to_range(&doc.text, &span, encoding);
+    replacements.into_iter().map(|new_text| CodeAction {
+        title: format!("Replace with {}", new_text),
+        kind: Some(CodeActionKind::REFACTOR_REWRITE),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(uri.clone(), vec![TextEdit { range, new_text }])])),
+            ..WorkspaceEdit::default()
+        }),
+        ..CodeAction::default()
+    }).collect()
+}
+
+/// The `select_subexpression` spanning exactly `span` (with or without its parentheses)
+/// when it is the subquery of a FROM or JOIN item.
+fn subquery_at<'t>(tree: &'t tree_sitter::Tree, span: &std::ops::Range<usize>) -> Option<tree_sitter::Node<'t>> {
+    let mut node = tree.root_node().descendant_for_byte_range(span.start, span.end)?;
+    loop {
+        if node.kind() == "select_subexpression" && node.parent().is_some_and(|p| p.kind() == "from_item") {
+            let inner = node.named_child(0).map(|q| q.byte_range());
+            return (node.byte_range() == *span || inner.as_ref() == Some(span)).then_some(node);
+        }
+        if node.start_byte() < span.start || node.end_byte() > span.end {
+            return None;
+        }
+        node = node.parent()?;
+    }
+}
+
+/// `body` re-indented by `indent`, given that its first line started at column `column`.
+fn reindent(body: &str, column: usize, indent: &str) -> String {
+    body.lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let line = if i == 0 { line } else {
+                let leading = line.len() - line.trim_start().len();
+                &line[leading.min(column)..]
+            };
+            if line.is_empty() { String::new() } else { format!("{}{}", indent, line) }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// "Extract to CTE" for a selected FROM/JOIN subquery: the subquery becomes a new CTE,
+/// placed after the last CTE it uses (or first), and is replaced by the CTE's name.
+pub fn extract_cte_action(uri: &Url, doc: &DocumentState, range: Range, encoding: PositionEncoding) -> Option<CodeAction> {
+    let tree = doc.tree.as_ref()?;
+    let start = doc.text.char_to_byte(position_to_char(&doc.text, range.start, encoding)?);
+    let end = doc.text.char_to_byte(position_to_char(&doc.text, range.end, encoding)?);
+    let selected = doc.text.byte_slice(start..end).to_string();
+    let span = start + (selected.len() - selected.trim_start().len())..start + selected.trim_end().len();
+    if span.is_empty() {
+        return None;
+    }
+    let subquery = subquery_at(tree, &span)?;
+    let body_span = subquery.named_child(0)?.byte_range();
+
+    // The statement's outermost query holds the WITH clause
+    let mut query = subquery;
+    while let Some(parent) = query.parent().filter(|p| p.kind() != "query_statement") {
+        query = parent;
+    }
+    let mut cursor = query.walk();
+    let ctes: Vec<(String, std::ops::Range<usize>)> = query.named_children(&mut cursor)
+        .find(|n| n.kind() == "cte_clause")
+        .map(|clause| {
+            let mut cursor = clause.walk();
+            clause.named_children(&mut cursor)
+                .filter(|n| n.kind() == "cte")
+                .filter_map(|cte| {
+                    let name = cte.child_by_field_name("alias_name")?;
+                    Some((doc.text.byte_slice(name.byte_range()).to_string(), cte.byte_range()))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let taken = |name: &str| ctes.iter().any(|(n, _)| n.eq_ignore_ascii_case(name)) || doc.ctes.keys().any(|n| n.eq_ignore_ascii_case(name));
+    let name = std::iter::once("subquery".to_string())
+        .chain((2..).map(|i| format!("subquery_{}", i)))
+        .find(|n| !taken(n))?;
+
+    let body = doc.text.byte_slice(body_span.clone()).to_string();
+    let line_start = doc.text.line_to_byte(doc.text.byte_to_line(body_span.start));
+    let definition = format!("{} as (\n{}\n)", name, reindent(&body, body_span.start - line_start, "    "));
+    let words: Vec<String> = body.split(|c: char| !(c.is_alphanumeric() || c == '_')).map(str::to_lowercase).collect();
+    let insert = match ctes.iter().rposition(|(n, _)| words.contains(&n.to_lowercase())) {
+        Some(i) => TextEdit {
+            range: byte_range_to_range(&doc.text, &(ctes[i].1.end..ctes[i].1.end), encoding),
+            new_text: format!(",\n{}", definition),
+        },
+        None => match ctes.first() {
+            Some((_, first)) => TextEdit {
+                range: byte_range_to_range(&doc.text, &(first.start..first.start), encoding),
+                new_text: format!("{},\n", definition),
+            },
+            None => TextEdit {
+                range: byte_range_to_range(&doc.text, &(query.start_byte()..query.start_byte()), encoding),
+                new_text: format!("with {}\n", definition),
+            },
+        },
+    };
+    let replace = TextEdit { range: byte_range_to_range(&doc.text, &subquery.byte_range(), encoding), new_text: name };
+
+    Some(CodeAction {
+        title: "Extract to CTE".to_string(),
+        kind: Some(CodeActionKind::REFACTOR_EXTRACT),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(uri.clone(), vec![insert, replace])])),
+            ..WorkspaceEdit::default()
+        }),
+        ..CodeAction::default()
+    })
+}
+
+/// A new file at `uri` holding `text`. Left alone if it appeared in the meantime.
+fn create_file_edit(uri: Url, text: String) -> WorkspaceEdit {
+    WorkspaceEdit {
+        document_changes: Some(DocumentChanges::Operations(vec![
+            DocumentChangeOperation::Op(ResourceOp::Create(CreateFile {
+                uri: uri.clone(),
+                options: Some(CreateFileOptions { overwrite: Some(false), ignore_if_exists: Some(true) }),
+                annotation_id: None,
+            })),
+            DocumentChangeOperation::Edit(TextDocumentEdit {
+                text_document: OptionalVersionedTextDocumentIdentifier { uri, version: None },
+                edits: vec![OneOf::Left(TextEdit {
+                    range: Range::new(Position::new(0, 0), Position::new(0, 0)),
+                    new_text: text,
+                })],
+            }),
+        ])),
+        ..WorkspaceEdit::default()
+    }
+}
+
+/// Inserts `new_text` at byte `offset` of the file at `uri`, whose current text is `text`.
+fn insert_edit(uri: Url, text: &str, offset: usize, new_text: String, encoding: PositionEncoding) -> WorkspaceEdit {
+    let range = byte_range_to_range(&Rope::from_str(text), &(offset..offset), encoding);
+    WorkspaceEdit {
+        changes: Some(HashMap::from([(uri, vec![TextEdit { range, new_text }])])),
+        ..WorkspaceEdit::default()
+    }
+}
+
+/// yml files a directory's model docs conventionally live in, in order of preference.
+const SCHEMA_FILES: [&str; 2] = ["schema.yml", "_models.yml"];
+
+/// The schema yml next to `model`: an existing one, else where a new one goes.
+pub fn schema_yml_path(model: &Path) -> Option<PathBuf> {
+    let dir = model.parent()?;
+    let existing = SCHEMA_FILES.iter().map(|f| dir.join(f)).find(|p| p.is_file());
+    Some(existing.unwrap_or_else(|| dir.join(SCHEMA_FILES[0])))
+}
+
+/// A `- name:` list item for `name` with its columns, its dash at `dash` spaces. Nested
+/// lists are indented the same way relative to their key. `*` items become a comment.
+fn model_entry_yaml(name: &str, columns: &[String], dash: usize) -> String {
+    let pad = " ".repeat(dash);
+    let mut entry = format!("{pad}- name: {name}\n");
+    if !columns.is_empty() {
+        entry.push_str(&format!("{pad}  columns:\n"));
+        for column in columns {
+            if column.ends_with('*') || column.contains("* ") {
+                entry.push_str(&format!("{pad}  {pad}# plus the columns of {column}\n"));
+            } else {
+                entry.push_str(&format!("{pad}  {pad}- name: {column}\n"));
+            }
+        }
+    }
+    entry
+}
+
+/// A `- name:` source item with a single table, indented like [`model_entry_yaml`].
+fn source_entry_yaml(source: &str, table: &str, dash: usize) -> String {
+    let pad = " ".repeat(dash);
+    format!("{pad}- name: {source}\n{pad}  tables:\n{pad}  {pad}- name: {table}\n")
+}
+
+/// Indentation of the line `line` of `text`.
+fn line_indent(text: &str, line: usize) -> Option<usize> {
+    let line = text.lines().nth(line)?;
+    Some(line.len() - line.trim_start().len())
+}
+
+/// Byte offset of the start of line `line`, or the end of `text` past its last line.
+fn line_start(text: &str, line: usize) -> usize {
+    text.split_inclusive('\n').take(line).map(str::len).sum()
+}
+
+/// `insert` at `offset`, on a line of its own when it goes after an unterminated last line.
+fn insertion_at(text: &str, offset: usize, insert: String) -> (usize, String) {
+    if offset == text.len() && !text.is_empty() && !text.ends_with('\n') {
+        (offset, format!("\n{}", insert))
+    } else {
+        (offset, insert)
+    }
+}
+
+/// The offset just past the block that starts on `line` and holds the keys under `prefix`
+/// (nested keys, list items, block scalars), before any trailing blank or comment lines.
+fn block_end(text: &str, keys: &[YamlKey], line: usize, prefix: &[String]) -> usize {
+    let lines: Vec<&str> = text.lines().collect();
+    let stop = keys.iter()
+        .find(|k| k.line > line && !k.path.starts_with(prefix))
+        .map_or(lines.len(), |k| k.line);
+    let last = (line..stop)
+        .rev()
+        .find(|&i| !lines[i].trim().is_empty() && !lines[i].trim_start().starts_with('#'))
+        .unwrap_or(line);
+    line_start(text, last + 1)
+}
+
+/// Where a new item of the top-level list `key` goes in an existing yml file and the text
+/// to insert: after the list's last item, or under a new `key:` at the end of the file.
+/// `entry` renders the item given the dash indentation the file uses. None when the list
+/// is written inline (`models: []`).
+fn list_insertion(text: &str, key: &str, entry: impl Fn(usize) -> String) -> Option<(usize, String)> {
+    let keys = crate::yaml::scan_keys(text);
+    let Some(list) = keys.iter().find(|k| k.path.is_empty() && k.key == key) else {
+        let dash = keys.iter()
+            .find(|k| k.path.len() == 1 && k.key == "name")
+            .and_then(|k| line_indent(text, k.line))
+            .unwrap_or(2);
+        return Some(insertion_at(text, text.len(), format!("{}:\n{}", key, entry(dash))));
+    };
+    if list.value.is_some() {
+        return None;
+    }
+    let dash = keys.iter()
+        .find(|k| k.path == [key] && k.key == "name")
+        .and_then(|k| line_indent(text, k.line))
+        .unwrap_or(2);
+    Some(insertion_at(text, block_end(text, &keys, list.line, &[key.to_string()]), entry(dash)))
+}
+
+/// "Generate schema.yml entry" for an undocumented model: a `- name:` entry listing the
+/// columns of its final select, appended to `yml` or written to it as a new file.
+/// `existing` is the yml's current text when it exists.
+pub fn schema_stub_action(name: &str, columns: &[String], yml: &Path, existing: Option<&str>, encoding: PositionEncoding) -> Option<CodeAction> {
+    let uri = Url::from_file_path(yml).ok()?;
+    let edit = match existing {
+        Some(text) => {
+            let (offset, new_text) = list_insertion(text, "models", |dash| model_entry_yaml(name, columns, dash))?;
+            insert_edit(uri, text, offset, new_text, encoding)
+        }
+        None => create_file_edit(uri, format!("version: 2\n\nmodels:\n{}", model_entry_yaml(name, columns, 2))),
+    };
+    Some(CodeAction {
+        title: "Generate schema.yml entry".to_string(),
+        kind: Some(CodeActionKind::REFACTOR),
+        edit: Some(edit),
+        ..CodeAction::default()
+    })
+}
+
+/// "Add missing columns to schema.yml" for a documented model: `- name:` entries, in
+/// select-list order, for the output columns its yml entry doesn't list yet. `*` items
+/// can't be expanded and are skipped; documented columns the select lacks are left alone.
+pub fn missing_columns_action(name: &str, columns: &[String], entry: &ModelEntry, read: &dyn Fn(&Path) -> Option<String>, encoding: PositionEncoding) -> Option<CodeAction> {
+    let mut missing: Vec<String> = Vec::new();
+    for column in columns.iter().filter(|c| !c.contains('*')) {
+        let known = entry.columns.iter().any(|c| c.name.eq_ignore_ascii_case(column))
+            || missing.iter().any(|m| m.eq_ignore_ascii_case(column));
+        if !known {
+            missing.push(column.clone());
+        }
+    }
+    if missing.is_empty() {
+        return None;
+    }
+    let text = read(&entry.path)?;
+    let (offset, new_text) = nested_list_insertion(&text, "models", name, "columns", &missing)?;
+    Some(CodeAction {
+        title: "Add missing columns to schema.yml".to_string(),
+        kind: Some(CodeActionKind::REFACTOR),
+        edit: Some(insert_edit(Url::from_file_path(&entry.path).ok()?, &text, offset, new_text, encoding)),
+        ..CodeAction::default()
+    })
+}
+
+/// Where `- name:` items for `names` go in the `list` (`tables:`, `columns:`) of the
+/// top-level `section` item called `item`, and the text to insert. Adds the key when the
+/// item has none.
+fn nested_list_insertion(text: &str, section: &str, item: &str, list: &str, names: &[String]) -> Option<(usize, String)> {
+    let keys = crate::yaml::scan_keys(text);
+    let name = crate::yaml::find_named_item(&keys, &[section], item)?;
+    // Items sit under a top-level key, so the item's own indentation is the dash offset
+    let dash_offset = line_indent(text, name.line)?;
+    let item_path = vec![section.to_string(), item.to_string()];
+    let items = |dash: usize| names.iter().map(|n| format!("{}- name: {}\n", " ".repeat(dash), n)).collect::<String>();
+    match keys.iter().find(|k| k.path == item_path && k.key == list) {
+        Some(key) if key.value.is_some() => None,
+        Some(key) => {
+            let list_path = [item_path, vec![list.to_string()]].concat();
+            let dash = keys.iter()
+                .find(|k| k.path == list_path && k.key == "name")
+                .and_then(|k| line_indent(text, k.line))
+                .unwrap_or(key.key_column + dash_offset);
+            let offset = block_end(text, &keys, key.line, &list_path);
+            Some(insertion_at(text, offset, items(dash)))
+        }
+        None => {
+            let offset = block_end(text, &keys, name.line, &item_path);
+            Some(insertion_at(text, offset, format!("{}{}:\n{}", " ".repeat(name.key_column), list, items(name.key_column + dash_offset))))
+        }
+    }
+}
+
+/// "Add table 'x' to source 'y'" for an unknown table of a known source.
+pub fn add_source_table_action(manifest: &ProjectManifest, diagnostic: &Diagnostic, read: &dyn Fn(&Path) -> Option<String>, encoding: PositionEncoding) -> Option<CodeAction> {
+    if diagnostic.code != Some(NumberOrString::String(UNKNOWN_SOURCE_TABLE.to_string())) {
+        return None;
+    }
+    let data: UnknownSourceTable = serde_json::from_value(diagnostic.data.clone()?).ok()?;
+    let yml = manifest.sources.iter().find(|s| s.source_name == data.source).map(|s| s.path.clone())?;
+    let text = read(&yml)?;
+    let (offset, new_text) = nested_list_insertion(&text, "sources", &data.source, "tables", std::slice::from_ref(&data.table))?;
+    Some(CodeAction {
+        title: format!("Add table '{}' to source '{}'", data.table, data.source),
+        kind: Some(CodeActionKind::QUICKFIX),
+        diagnostics: Some(vec![diagnostic.clone()]),
+        edit: Some(insert_edit(Url::from_file_path(&yml).ok()?, &text, offset, new_text, encoding)),
+        ..CodeAction::default()
+    })
+}
+
+/// The yml file closest to `current`, looking in its folder and then each parent up to
+/// the model path. Within a folder, one that already lists sources wins.
+fn nearest_yml(manifest: &ProjectManifest, current: &Path, read: &dyn Fn(&Path) -> Option<String>) -> Option<PathBuf> {
+    let mut dir = current.parent();
+    while let Some(d) = dir.filter(|d| manifest.is_under(d, &manifest.config.model_paths)) {
+        let mut ymls: Vec<PathBuf> = std::fs::read_dir(d).into_iter().flatten()
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == "yml" || e == "yaml"))
+            .collect();
+        ymls.sort();
+        let has_sources = |p: &PathBuf| read(p).is_some_and(|t| crate::yaml::scan_keys(&t).iter().any(|k| k.path.is_empty() && k.key == "sources"));
+        if let Some(yml) = ymls.iter().find(|p| has_sources(p)).or(ymls.first()) {
+            return Some(yml.clone());
+        }
+        dir = d.parent();
+    }
+    None
+}
+
+/// "Create source 'x' with table 'y'" for a source the project doesn't declare: added to
+/// the nearest yml file, or to a new `sources.yml` in the first model path.
+pub fn create_source_action(manifest: &ProjectManifest, current: &Path, diagnostic: &Diagnostic, read: &dyn Fn(&Path) -> Option<String>, encoding: PositionEncoding) -> Option<CodeAction> {
+    if diagnostic.code != Some(NumberOrString::String(UNKNOWN_SOURCE.to_string())) {
+        return None;
+    }
+    let data: UnknownSourceTable = serde_json::from_value(diagnostic.data.clone()?).ok()?;
+    let entry = |dash| source_entry_yaml(&data.source, &data.table, dash);
+    let edit = match nearest_yml(manifest, current, read) {
+        Some(yml) => {
+            let text = read(&yml)?;
+            let (offset, new_text) = list_insertion(&text, "sources", entry)?;
+            insert_edit(Url::from_file_path(&yml).ok()?, &text, offset, new_text, encoding)
+        }
+        None => {
+            let yml = manifest.root_dir.join(manifest.config.model_paths.first()?).join("sources.yml");
+            create_file_edit(Url::from_file_path(&yml).ok()?, format!("version: 2\n\nsources:\n{}", entry(2)))
+        }
+    };
+    Some(CodeAction {
+        title: format!("Create source '{}' with table '{}'", data.source, data.table),
+        kind: Some(CodeActionKind::QUICKFIX),
+        diagnostics: Some(vec![diagnostic.clone()]),
+        edit: Some(edit),
+        ..CodeAction::default()
+    })
+}
+
+/// "Wrap in {% if is_incremental() %}" for a non-empty selection: the selected lines go
+/// between the tags, which sit on their own lines at the first line's indentation.
+/// Preferred in incremental models. None when either end of the selection, or of the
+/// lines it covers, falls inside a jinja tag.
+pub fn wrap_incremental_action(uri: &Url, doc: &DocumentState, range: Range, encoding: PositionEncoding) -> Option<CodeAction> {
+    if range.start == range.end {
+        return None;
+    }
+    let rope = &doc.text;
+    let start = rope.char_to_byte(position_to_char(rope, range.start, encoding)?);
+    let end = rope.char_to_byte(position_to_char(rope, range.end, encoding)?);
+    // A selection ending at the start of a line doesn't take that line
+    let last_line = match rope.byte_to_line(end) {
+        line if line > rope.byte_to_line(start) && end == rope.line_to_byte(line) => line - 1,
+        line => line,
+    };
+    let first_line = rope.byte_to_line(start);
+    let block = rope.line_to_byte(first_line)..rope.line_to_byte((last_line + 1).min(rope.len_lines()));
+
+    let text = rope.to_string();
+    if [start, end, block.start, block.end].iter().any(|&idx| crate::jinja::splits_jinja(&text, idx)) {
+        return None;
+    }
+    let lines = &text[block.clone()];
+    if lines.trim().is_empty() {
+        return None;
+    }
+    let indent: String = lines.lines()
+        .find(|l| !l.trim().is_empty())
+        .map(|l| l.chars().take_while(|c| c.is_whitespace()).collect())
+        .unwrap_or_default();
+    let new_text = match lines.strip_suffix('\n') {
+        Some(body) => format!("{indent}{{% if is_incremental() %}}\n{body}\n{indent}{{% endif %}}\n"),
+        None => format!("{indent}{{% if is_incremental() %}}\n{lines}\n{indent}{{% endif %}}"),
+    };
+    let incremental = doc.config.as_ref().and_then(|c| c.get("materialized")) == Some("incremental");
+    Some(CodeAction {
+        title: "Wrap in {% if is_incremental() %}".to_string(),
+        kind: Some(CodeActionKind::REFACTOR_REWRITE),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(uri.clone(), vec![TextEdit { range: byte_range_to_range(rope, &block, encoding), new_text }])])),
+            ..WorkspaceEdit::default()
+        }),
+        is_preferred: incremental.then_some(true),
+        ..CodeAction::default()
+    })
+}
+
+/// "Remove CTE 'x'" for an unused-CTE hint: deletes `x as (...)` with the comma joining
+/// it to its neighbours (the one before it, or after it when it comes first), and the
+/// `with` too when it was the only CTE.
+pub fn remove_cte_action(uri: &Url, doc: &DocumentState, diagnostic: &Diagnostic, encoding: PositionEncoding) -> Option<CodeAction> {
+    if diagnostic.code != Some(NumberOrString::String(UNUSED_CTE.to_string())) {
+        return None;
+    }
+    let start = doc.text.char_to_byte(position_to_char(&doc.text, diagnostic.range.start, encoding)?);
+    let (name, cte) = doc.ctes.iter().find(|(_, cte)| cte.name_range.start == start)?;
+    let text = doc.text.to_string();
+    let block = cte.name_range.start..cte.body_range.end + 1;
+
+    let before = text[..block.start].trim_end();
+    let gap = |from: usize| text[from..].len() - text[from..].trim_start().len();
+    let after = block.end + gap(block.end);
+    let delete = if before.ends_with(',') {
+        before.len() - 1..block.end
+    } else if text[after..].starts_with(',') {
+        block.start..after + 1 + gap(after + 1)
+    } else {
+        let with = before.len().checked_sub(4).filter(|&i| before.get(i..).is_some_and(|w| w.eq_ignore_ascii_case("with")))?;
+        with..after
+    };
+
+    Some(CodeAction {
+        title: format!("Remove CTE '{}'", name),
+        kind: Some(CodeActionKind::QUICKFIX),
+        diagnostics: Some(vec![diagnostic.clone()]),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(uri.clone(), vec![TextEdit {
+                range: byte_range_to_range(&doc.text, &delete, encoding),
+                new_text: String::new(),
+            }])])),
+            ..WorkspaceEdit::default()
+        }),
+        is_preferred: Some(true),
+        ..CodeAction::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inserted(text: &str, columns: &[&str]) -> Option<String> {
+        let columns: Vec<String> = columns.iter().map(|c| c.to_string()).collect();
+        let (offset, insert) = list_insertion(text, "models", |dash| model_entry_yaml("orders", &columns, dash))?;
+        Some(format!("{}{}{}", &text[..offset], insert, &text[offset..]))
+    }
+
+    #[test]
+    fn test_schema_insertion() {
+        // After the last model, before the next top-level key, in the file's own indentation
+        let text = "version: 2\n\nmodels:\n    - name: customers\n      description: |\n        All of them.\n\nsources:\n    - name: raw\n";
+        assert_eq!(
+            inserted(text, &["id", "o.*"]).unwrap(),
+            "version: 2\n\nmodels:\n    - name: customers\n      description: |\n        All of them.\n    - name: orders\n      columns:\n          - name: id\n          # plus the columns of o.*\n\nsources:\n    - name: raw\n"
+        );
+        let parsed: serde_yaml::Value = serde_yaml::from_str(&inserted(text, &["id", "o.*"]).unwrap()).unwrap();
+        assert_eq!(parsed["models"][1]["columns"][0]["name"].as_str(), Some("id"));
+
+        // Indentless lists, no trailing newline
+        let text = "models:\n- name: customers";
+        assert_eq!(inserted(text, &["id"]).unwrap(), "models:\n- name: customers\n- name: orders\n  columns:\n  - name: id\n");
+
+        // No models key yet
+        let text = "sources:\n  - name: raw\n";
+        assert_eq!(inserted(text, &[]).unwrap(), "sources:\n  - name: raw\nmodels:\n  - name: orders\n");
+
+        assert!(inserted("models: []\n", &["id"]).is_none());
+    }
+
+    #[test]
+    fn test_nested_list_insertion() {
+        let insert = |text: &str| nested_list_insertion(text, "sources", "raw", "tables", &["orders".to_string()])
+            .map(|(offset, insert)| format!("{}{}{}", &text[..offset], insert, &text[offset..]));
+
+        // Into the right source when two list the same table
+        let text = "sources:\n- name: raw\n  tables:\n  - name: users\n  # more to come\n- name: other\n  tables:\n  - name: users\n";
+        assert_eq!(
+            insert(text).unwrap(),
+            "sources:\n- name: raw\n  tables:\n  - name: users\n  - name: orders\n  # more to come\n- name: other\n  tables:\n  - name: users\n"
+        );
+
+        // A source without tables gets the key
+        let text = "sources:\n  - name: raw\n    schema: landing\n";
+        assert_eq!(insert(text).unwrap(), "sources:\n  - name: raw\n    schema: landing\n    tables:\n      - name: orders\n");
+
+        assert!(insert("sources:\n  - name: other\n").is_none());
+
+        // Model columns, a list the entry doesn't have yet
+        let text = "models:\n  - name: orders\n    description: |\n      All orders.\n";
+        let columns = ["id".to_string(), "amount".to_string()];
+        let (offset, insert) = nested_list_insertion(text, "models", "orders", "columns", &columns).unwrap();
+        assert_eq!(
+            format!("{}{}", &text[..offset], insert),
+            "models:\n  - name: orders\n    description: |\n      All orders.\n    columns:\n      - name: id\n      - name: amount\n"
+        );
+    }
+}
@@ -0,0 +1,434 @@
+use crate::project::ColumnDoc;
+use indexmap::IndexMap;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct RawColumn {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    data_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct RawDependsOn {
+    #[serde(default)]
+    nodes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct RawNode {
+    #[serde(default)]
+    unique_id: String,
+    #[serde(default)]
+    resource_type: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    package_name: String,
+    #[serde(default)]
+    original_file_path: String,
+    /// `package://path/to/schema.yml` of the documenting yml entry.
+    #[serde(default)]
+    patch_path: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    columns: IndexMap<String, RawColumn>,
+    #[serde(default)]
+    depends_on: RawDependsOn,
+    #[serde(default)]
+    config: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct RawSource {
+    #[serde(default)]
+    package_name: String,
+    #[serde(default)]
+    source_name: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    original_file_path: String,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    source_description: Option<String>,
+    #[serde(default)]
+    loader: Option<String>,
+    #[serde(default)]
+    database: Option<String>,
+    #[serde(default)]
+    schema: Option<String>,
+    #[serde(default)]
+    identifier: Option<String>,
+    #[serde(default)]
+    loaded_at_field: Option<String>,
+    #[serde(default)]
+    freshness: serde_json::Value,
+    #[serde(default)]
+    columns: IndexMap<String, RawColumn>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct RawManifest {
+    #[serde(default)]
+    nodes: HashMap<String, RawNode>,
+    #[serde(default)]
+    sources: HashMap<String, RawSource>,
+}
+
+/// A model, seed, snapshot, analysis or test of the project in manifest.json.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArtifactNode {
+    pub unique_id: String,
+    /// `model`, `seed`, `snapshot`, `analysis` or `test`.
+    pub resource_type: String,
+    pub name: String,
+    pub path: PathBuf,
+    /// The yml file documenting the node, if any.
+    pub patch_path: Option<PathBuf>,
+    pub description: Option<String>,
+    pub columns: Vec<ColumnDoc>,
+    /// Unique ids of the nodes and sources it depends on.
+    pub depends_on: Vec<String>,
+    pub materialized: Option<String>,
+    pub alias: Option<String>,
+    /// A seed's `column_types`, by column.
+    pub column_types: Vec<(String, String)>,
+}
+
+/// A source table of the project in manifest.json.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArtifactSource {
+    pub source_name: String,
+    pub table_name: String,
+    pub path: PathBuf,
+    /// The table's description, or the source's when the table has none.
+    pub description: Option<String>,
+    pub loader: Option<String>,
+    pub database: Option<String>,
+    pub schema: Option<String>,
+    pub identifier: Option<String>,
+    pub loaded_at_field: Option<String>,
+    pub freshness: Option<String>,
+    pub columns: Vec<ColumnDoc>,
+}
+
+/// What target/manifest.json says about the project's own nodes. Package nodes are left
+/// out; they are scanned from dbt_packages/.
+#[derive(Debug, Clone)]
+pub struct ManifestArtifact {
+    /// When dbt wrote the file. Project files changed later are scanned instead.
+    pub written: SystemTime,
+    pub nodes: Vec<ArtifactNode>,
+    pub sources: Vec<ArtifactSource>,
+    /// Every file a node or source came from.
+    files: HashSet<PathBuf>,
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+/// Columns in the order the yml lists them, which manifest.json keeps.
+fn columns(raw: IndexMap<String, RawColumn>) -> Vec<ColumnDoc> {
+    raw.into_iter()
+        .map(|(key, col)| ColumnDoc {
+            name: if col.name.is_empty() { key } else { col.name },
+            description: non_empty(col.description),
+            data_type: non_empty(col.data_type),
+            ..ColumnDoc::default()
+        })
+        .collect()
+}
+
+fn config_str(config: &serde_json::Value, key: &str) -> Option<String> {
+    config.get(key).and_then(|v| v.as_str()).map(str::to_string)
+}
+
+impl ManifestArtifact {
+    /// Reads `<target-path>/manifest.json` of the project `project` at `root_dir`. None
+    /// when it is missing or doesn't parse.
+    pub fn read(root_dir: &Path, target_path: &str, project: &str) -> Option<Self> {
+        let path = root_dir.join(target_path).join("manifest.json");
+        let modified = |p: &Path| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+        let written = modified(&path)?;
+        // Folder configs and paths may have changed since, which affects every node
+        if modified(&root_dir.join("dbt_project.yml")).is_some_and(|m| m > written) {
+            eprintln!("Ignoring {}: dbt_project.yml changed since it was written", path.display());
+            return None;
+        }
+        let text = std::fs::read_to_string(&path).ok()?;
+        let raw: RawManifest = match serde_json::from_str(&text) {
+            Ok(raw) => raw,
+            Err(e) => {
+                eprintln!("Ignoring {}: {}", path.display(), e);
+                return None;
+            }
+        };
+
+        // patch_path is `package://relative/path`
+        let project_file = |path: &str| root_dir.join(path.strip_prefix(&format!("{}://", project)).unwrap_or(path));
+        let nodes: Vec<ArtifactNode> = raw.nodes.into_values()
+            .filter(|n| n.package_name == project)
+            .map(|n| {
+                let column_types = n.config.get("column_types").and_then(|t| t.as_object())
+                    .map(|types| types.iter().filter_map(|(c, t)| Some((c.clone(), t.as_str()?.to_string()))).collect())
+                    .unwrap_or_default();
+                ArtifactNode {
+                    path: project_file(&n.original_file_path),
+                    patch_path: n.patch_path.as_deref().map(project_file),
+                    description: non_empty(n.description),
+                    columns: columns(n.columns),
+                    depends_on: n.depends_on.nodes,
+                    materialized: config_str(&n.config, "materialized"),
+                    alias: config_str(&n.config, "alias"),
+                    column_types,
+                    unique_id: n.unique_id,
+                    resource_type: n.resource_type,
+                    name: n.name,
+                }
+            })
+            .collect();
+        let sources: Vec<ArtifactSource> = raw.sources.into_values()
+            .filter(|s| s.package_name == project)
+            .map(|s| ArtifactSource {
+                path: project_file(&s.original_file_path),
+                description: non_empty(s.description).or_else(|| non_empty(s.source_description)),
+                loader: non_empty(s.loader),
+                database: non_empty(s.database),
+                schema: non_empty(s.schema),
+                identifier: non_empty(s.identifier),
+                loaded_at_field: non_empty(s.loaded_at_field),
+                freshness: serde_yaml::to_value(&s.freshness).ok().and_then(|f| crate::project::freshness_summary(&f)),
+                columns: columns(s.columns),
+                source_name: s.source_name,
+                table_name: s.name,
+            })
+            .collect();
+
+        let files = nodes.iter()
+            .flat_map(|n| std::iter::once(n.path.clone()).chain(n.patch_path.clone()))
+            .chain(sources.iter().map(|s| s.path.clone()))
+            .collect();
+        Some(Self { written, nodes, sources, files })
+    }
+
+    /// Whether the artifact describes `path` as it is now: the file contributed to it
+    /// and hasn't changed since dbt wrote it.
+    pub fn covers(&self, path: &Path) -> bool {
+        self.files.contains(path)
+            && std::fs::metadata(path).and_then(|m| m.modified()).is_ok_and(|modified| modified <= self.written)
+    }
+
+    /// The project node defined by the file at `path`.
+    pub fn node_for_file(&self, path: &Path) -> Option<&ArtifactNode> {
+        self.nodes.iter().find(|n| n.path == path && n.resource_type != "test")
+            .or_else(|| self.nodes.iter().find(|n| n.path == path))
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct RawCatalogColumn {
+    #[serde(default)]
+    name: String,
+    #[serde(default, rename = "type")]
+    data_type: Option<String>,
+    #[serde(default)]
+    index: usize,
+    #[serde(default)]
+    comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct RawCatalogTable {
+    #[serde(default)]
+    columns: HashMap<String, RawCatalogColumn>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct RawCatalog {
+    #[serde(default)]
+    nodes: HashMap<String, RawCatalogTable>,
+    #[serde(default)]
+    sources: HashMap<String, RawCatalogTable>,
+}
+
+/// The warehouse columns target/catalog.json lists for the project's relations.
+#[derive(Debug, Clone)]
+pub struct Catalog {
+    /// When `dbt docs generate` wrote the file.
+    pub written: SystemTime,
+    /// Columns in warehouse order, with their type and comment, keyed like ref and
+    /// source targets: `orders` or `raw.orders`.
+    pub columns: HashMap<String, Vec<ColumnDoc>>,
+}
+
+impl Catalog {
+    /// Parses the catalog at `path`, keeping the models, seeds, snapshots and sources of
+    /// the project `project`.
+    fn read(path: &Path, project: &str) -> Option<Self> {
+        let written = std::fs::metadata(path).and_then(|m| m.modified()).ok()?;
+        let text = std::fs::read_to_string(path).ok()?;
+        let raw: RawCatalog = match serde_json::from_str(&text) {
+            Ok(raw) => raw,
+            Err(e) => {
+                eprintln!("Ignoring {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        let columns = raw.nodes.into_iter().chain(raw.sources)
+            .filter_map(|(unique_id, table)| {
+                let mut parts = unique_id.splitn(3, '.');
+                let (kind, package, key) = (parts.next()?, parts.next()?, parts.next()?);
+                if package != project || !matches!(kind, "model" | "seed" | "snapshot" | "source") {
+                    return None;
+                }
+                let mut raw_columns: Vec<(String, RawCatalogColumn)> = table.columns.into_iter().collect();
+                raw_columns.sort_by_key(|(_, col)| col.index);
+                let columns = raw_columns.into_iter()
+                    .map(|(key, col)| ColumnDoc {
+                        name: if col.name.is_empty() { key } else { col.name },
+                        description: non_empty(col.comment),
+                        data_type: non_empty(col.data_type),
+                        ..ColumnDoc::default()
+                    })
+                    .collect();
+                Some((key.to_string(), columns))
+            })
+            .collect();
+        Some(Self { written, columns })
+    }
+}
+
+/// target/catalog.json, read again whenever dbt rewrites it.
+#[derive(Debug)]
+pub struct CatalogFile {
+    path: PathBuf,
+    project: String,
+    loaded: RwLock<Option<Arc<Catalog>>>,
+}
+
+impl CatalogFile {
+    pub fn new(root_dir: &Path, target_path: &str, project: &str) -> Self {
+        Self {
+            path: root_dir.join(target_path).join("catalog.json"),
+            project: project.to_string(),
+            loaded: RwLock::new(None),
+        }
+    }
+
+    /// The catalog as it is on disk now. None while there is none, or it doesn't parse.
+    pub fn current(&self) -> Option<Arc<Catalog>> {
+        let modified = std::fs::metadata(&self.path).and_then(|m| m.modified()).ok()?;
+        if let Some(catalog) = self.loaded.read().ok()?.as_ref().filter(|c| c.written == modified) {
+            return Some(catalog.clone());
+        }
+        let catalog = Catalog::read(&self.path, &self.project).map(Arc::new);
+        *self.loaded.write().ok()? = catalog.clone();
+        catalog
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_manifest_artifact() {
+        let root = std::env::temp_dir().join(format!("dbt-lsp-artifact-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("target")).unwrap();
+        std::fs::create_dir_all(root.join("models")).unwrap();
+        std::fs::write(root.join("models").join("orders.sql"), "select 1").unwrap();
+        std::fs::write(root.join("target").join("manifest.json"), r#"{
+  "nodes": {
+    "model.shop.orders": {
+      "unique_id": "model.shop.orders", "resource_type": "model", "name": "orders", "package_name": "shop",
+      "original_file_path": "models/orders.sql", "patch_path": "shop://models/schema.yml",
+      "description": "One row per order", "config": {"materialized": "table"},
+      "columns": {"id": {"name": "id", "description": "", "data_type": "int"}, "amount": {"name": "amount"}},
+      "depends_on": {"nodes": ["source.shop.raw.orders"]}
+    },
+    "model.dbt_utils.helper": {
+      "unique_id": "model.dbt_utils.helper", "resource_type": "model", "name": "helper", "package_name": "dbt_utils",
+      "original_file_path": "models/helper.sql"
+    }
+  },
+  "sources": {
+    "source.shop.raw.orders": {
+      "package_name": "shop", "source_name": "raw", "name": "orders", "original_file_path": "models/schema.yml",
+      "source_description": "The shop database", "schema": "raw_shop",
+      "freshness": {"warn_after": {"count": 12, "period": "hour"}, "error_after": {"count": null, "period": null}}
+    }
+  }
+}"#).unwrap();
+
+        let artifact = ManifestArtifact::read(&root, "target", "shop").unwrap();
+        assert_eq!(artifact.nodes.len(), 1);
+        let orders = &artifact.nodes[0];
+        assert_eq!(orders.patch_path, Some(root.join("models").join("schema.yml")));
+        assert_eq!((orders.materialized.as_deref(), orders.depends_on.as_slice()), (Some("table"), &["source.shop.raw.orders".to_string()][..]));
+        // In yml order, not sorted
+        assert_eq!(orders.columns, vec![
+            ColumnDoc { name: "id".to_string(), data_type: Some("int".to_string()), ..ColumnDoc::default() },
+            ColumnDoc { name: "amount".to_string(), ..ColumnDoc::default() },
+        ]);
+        let source = &artifact.sources[0];
+        assert_eq!((source.description.as_deref(), source.freshness.as_deref()), (Some("The shop database"), Some("warn after 12 hour")));
+
+        assert!(artifact.covers(&root.join("models").join("orders.sql")));
+        // Files dbt didn't see, and files changed after it ran, aren't covered
+        assert!(!artifact.covers(&root.join("models").join("new.sql")));
+        let later = artifact.written + std::time::Duration::from_secs(5);
+        std::fs::File::options().write(true).open(root.join("models").join("orders.sql")).unwrap().set_modified(later).unwrap();
+        assert!(!artifact.covers(&root.join("models").join("orders.sql")));
+
+        std::fs::write(root.join("target").join("manifest.json"), "{ not json").unwrap();
+        assert!(ManifestArtifact::read(&root, "target", "shop").is_none());
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_catalog_file() {
+        let root = std::env::temp_dir().join(format!("dbt-lsp-catalog-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("target")).unwrap();
+        let catalog = CatalogFile::new(&root, "target", "shop");
+        assert!(catalog.current().is_none());
+
+        std::fs::write(root.join("target").join("catalog.json"), r#"{
+  "nodes": {
+    "model.shop.orders": {"columns": {
+      "AMOUNT": {"type": "NUMBER", "index": 2, "name": "AMOUNT", "comment": null},
+      "ID": {"type": "NUMBER", "index": 1, "name": "ID", "comment": "Primary key"}
+    }},
+    "model.dbt_utils.helper": {"columns": {"X": {"type": "TEXT", "index": 1, "name": "X"}}}
+  },
+  "sources": {
+    "source.shop.raw.orders": {"columns": {"id": {"type": "INT64", "index": 1, "name": "id"}}}
+  }
+}"#).unwrap();
+        let loaded = catalog.current().unwrap();
+        let names: Vec<&str> = loaded.columns["orders"].iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["ID", "AMOUNT"]);
+        assert_eq!(loaded.columns["orders"][0].description.as_deref(), Some("Primary key"));
+        assert_eq!(loaded.columns["raw.orders"][0].data_type.as_deref(), Some("INT64"));
+        assert!(!loaded.columns.contains_key("helper"));
+
+        // Rewritten by dbt: picked up on the next lookup
+        std::fs::write(root.join("target").join("catalog.json"), r#"{"nodes": {}}"#).unwrap();
+        let later = loaded.written + std::time::Duration::from_secs(5);
+        std::fs::File::options().write(true).open(root.join("target").join("catalog.json")).unwrap().set_modified(later).unwrap();
+        assert!(catalog.current().unwrap().columns.is_empty());
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,464 @@
+use std::ops::Range;
+use tree_sitter::{Node, Tree};
+
+/// A table in a FROM clause: the referenced name (None for subqueries) and its alias.
+struct FromSource {
+    table: Option<String>,
+    alias: Option<String>,
+    /// Byte range of the table name.
+    table_range: Option<Range<usize>>,
+}
+
+fn node_text<'a>(node: Node, text: &'a str) -> &'a str {
+    text.get(node.byte_range()).unwrap_or("")
+}
+
+fn alias_of<'a>(node: Node, text: &'a str) -> Option<&'a str> {
+    let mut cursor = node.walk();
+    let alias = node.named_children(&mut cursor)
+        .find(|c| c.kind() == "as_alias")
+        .and_then(|a| a.child_by_field_name("alias_name"))
+        .map(|n| node_text(n, text));
+    alias
+}
+
+fn find_descendant<'a>(node: Node<'a>, pred: &dyn Fn(Node) -> bool) -> Option<Node<'a>> {
+    if pred(node) {
+        return Some(node);
+    }
+    let mut cursor = node.walk();
+    let children: Vec<Node<'a>> = node.named_children(&mut cursor).collect();
+    children.into_iter().find_map(|child| find_descendant(child, pred))
+}
+
+/// Whether the identifier names a table or alias rather than referencing a column.
+fn is_name_field(ident: Node) -> bool {
+    ident.parent().is_some_and(|parent| {
+        ["table_name", "alias_name"].iter().any(|field| parent.child_by_field_name(field).is_some_and(|n| n.id() == ident.id()))
+    })
+}
+
+fn collect_from_sources(node: Node, text: &str, out: &mut Vec<FromSource>) {
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        match child.kind() {
+            "from_item" => {
+                let alias = alias_of(child, text).map(str::to_string);
+                if let Some(table) = child.child_by_field_name("table_name") {
+                    out.push(FromSource { table: Some(node_text(table, text).to_string()), alias, table_range: Some(table.byte_range()) });
+                } else if child.named_children(&mut child.walk()).any(|c| c.kind() == "join_operation") {
+                    collect_from_sources(child, text, out);
+                } else {
+                    out.push(FromSource { table: None, alias, table_range: None });
+                }
+            }
+            "join_operation" => collect_from_sources(child, text, out),
+            _ => {}
+        }
+    }
+}
+
+/// The first (leftmost) select of the CTE named `name`.
+fn find_cte_select<'a>(root: Node<'a>, text: &str, name: &str) -> Option<Node<'a>> {
+    let cte = find_descendant(root, &|n| {
+        n.kind() == "cte" && n.child_by_field_name("alias_name").is_some_and(|a| node_text(a, text).eq_ignore_ascii_case(name))
+    })?;
+    let mut cursor = cte.walk();
+    let query = cte.named_children(&mut cursor).find(|c| c.kind() == "query_expr")?;
+    find_descendant(query, &|n| n.kind() == "select")
+}
+
+/// The byte range of the select item producing `column`, if exactly one does.
+fn select_item_for(select: Node, text: &str, column: &str) -> Option<Range<usize>> {
+    let mut cursor = select.walk();
+    let list = select.named_children(&mut cursor).find(|c| c.kind() == "select_list")?;
+    let mut list_cursor = list.walk();
+    let matches: Vec<Range<usize>> = list.named_children(&mut list_cursor)
+        .filter(|item| item.kind() == "select_expression")
+        .filter(|item| {
+            let output_name = alias_of(*item, text).or_else(|| {
+                let expr = item.named_child(0).filter(|e| e.kind() == "identifier")?;
+                node_text(expr, text).rsplit('.').next()
+            });
+            output_name.is_some_and(|n| n.eq_ignore_ascii_case(column))
+        })
+        .map(|item| item.byte_range())
+        .collect();
+    match matches.as_slice() {
+        [only] => Some(only.clone()),
+        _ => None,
+    }
+}
+
+/// The output columns of the CTE named `name`, in order. Star items are kept as their
+/// text (`*`, `o.*`, `* except (x)`); unnamed expressions are skipped. Returns None when
+/// the CTE or its select list isn't in the tree.
+pub fn cte_output_columns(tree: &Tree, text: &str, name: &str) -> Option<Vec<String>> {
+    select_output_columns(find_cte_select(tree.root_node(), text, name)?, text)
+}
+
+/// The output columns of the first statement's final select, outside any CTE, in the
+/// same form as [`cte_output_columns`].
+pub fn final_output_columns(tree: &Tree, text: &str) -> Option<Vec<String>> {
+    select_output_columns(final_select(tree)?, text)
+}
+
+fn final_select(tree: &Tree) -> Option<Node<'_>> {
+    let statement = find_descendant(tree.root_node(), &|n| n.kind() == "query_statement")?;
+    let query = statement.named_child(0).filter(|q| q.kind() == "query_expr")?;
+    let mut cursor = query.walk();
+    let body = query.named_children(&mut cursor).find(|c| c.kind() != "cte_clause")?;
+    find_descendant(body, &|n| n.kind() == "select")
+}
+
+/// The columns a model's SQL outputs, when its final select names every one of them.
+/// None when it selects a star, builds its select list with jinja or doesn't parse.
+pub fn model_output_columns(text: &str) -> Option<Vec<String>> {
+    let preprocessed = crate::jinja::preprocess_for_parsing(text);
+    let tree = crate::parser::DbtParser::new().ok()?.parse(&preprocessed, None)?;
+    if tree.root_node().has_error() {
+        return None;
+    }
+    // Jinja in the select list (a star macro, a column loop) adds columns we can't see;
+    // preprocessing keeps byte offsets, so the original text shows where it was
+    let select = final_select(&tree)?;
+    let mut cursor = select.walk();
+    let list_end = select.named_children(&mut cursor)
+        .find(|c| c.kind() == "from_clause")
+        .map_or(select.end_byte(), |from| from.start_byte());
+    let list = text.get(select.start_byte()..list_end)?;
+    if list.contains("{{") || list.contains("{%") {
+        return None;
+    }
+    final_output_columns(&tree, &preprocessed).filter(|columns| !columns.iter().any(|c| c.contains('*')))
+}
+
+fn select_output_columns(select: Node, text: &str) -> Option<Vec<String>> {
+    let mut cursor = select.walk();
+    let list = select.named_children(&mut cursor).find(|c| c.kind() == "select_list")?;
+    let mut list_cursor = list.walk();
+    let columns = list.named_children(&mut list_cursor)
+        .filter_map(|item| match item.kind() {
+            "select_all" => Some(node_text(item, text).split_whitespace().collect::<Vec<_>>().join(" ")),
+            "select_expression" => alias_of(item, text)
+                .or_else(|| {
+                    let expr = item.named_child(0).filter(|e| e.kind() == "identifier")?;
+                    node_text(expr, text).rsplit('.').next()
+                })
+                .map(str::to_string),
+            _ => None,
+        })
+        .collect();
+    Some(columns)
+}
+
+/// Resolves the column reference at `byte_idx` to the select item that produces it in
+/// a CTE of the same query. The column's table comes from its qualifier (`o.amount`)
+/// or, unqualified, from a FROM clause with a single table. Returns None whenever the
+/// column can't be attributed unambiguously.
+pub fn resolve_column_definition(tree: &Tree, text: &str, byte_idx: usize) -> Option<Range<usize>> {
+    let ident = tree.root_node().descendant_for_byte_range(byte_idx, byte_idx)?;
+    if ident.kind() != "identifier" || is_name_field(ident) {
+        return None;
+    }
+
+    let full = node_text(ident, text);
+    let (qualifier, column) = match full.rsplit_once('.') {
+        // The cursor on the qualifier itself is an alias, not a column
+        Some((q, _)) if byte_idx <= ident.start_byte() + q.len() => return None,
+        Some((q, c)) => (Some(q), c),
+        None => (None, full),
+    };
+
+    let mut select = ident.parent();
+    while let Some(node) = select.filter(|n| n.kind() != "select") {
+        select = node.parent();
+    }
+    let select = select?;
+    let mut cursor = select.walk();
+    let from_clause = select.named_children(&mut cursor).find(|c| c.kind() == "from_clause")?;
+    let mut sources = Vec::new();
+    collect_from_sources(from_clause, text, &mut sources);
+
+    let table = match qualifier {
+        Some(q) => sources.iter()
+            .find(|s| match &s.alias {
+                Some(alias) => alias.eq_ignore_ascii_case(q),
+                None => s.table.as_deref().is_some_and(|t| t.eq_ignore_ascii_case(q)),
+            })?
+            .table.clone()?,
+        None => match sources.as_slice() {
+            [only] => only.table.clone()?,
+            _ => return None,
+        },
+    };
+
+    let cte_select = find_cte_select(tree.root_node(), text, &table)?;
+    select_item_for(cte_select, text, column)
+}
+
+/// A column read through a table alias (`o.amount`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualifiedColumn {
+    /// The column name, without quotes.
+    pub column: String,
+    /// Byte range of the column name after the dot.
+    pub range: Range<usize>,
+    /// Byte range of the table name in the FROM/JOIN item that defines the alias.
+    pub table_range: Range<usize>,
+}
+
+/// Every `alias.column` whose alias a FROM/JOIN item of its own select defines.
+pub fn qualified_columns(tree: &Tree, text: &str) -> Vec<QualifiedColumn> {
+    let mut idents = Vec::new();
+    collect_nodes(tree.root_node(), &|n| n.kind() == "identifier" && !is_name_field(n), &mut idents);
+    idents.into_iter()
+        .filter_map(|ident| {
+            let (qualifier, column) = node_text(ident, text).split_once('.').filter(|(_, c)| !c.contains('.'))?;
+            let select = enclosing_select(ident)?;
+            let mut cursor = select.walk();
+            let from_clause = select.named_children(&mut cursor).find(|c| c.kind() == "from_clause")?;
+            let mut sources = Vec::new();
+            collect_from_sources(from_clause, text, &mut sources);
+            let table_range = sources.into_iter()
+                .find(|s| s.alias.as_deref().is_some_and(|a| a.eq_ignore_ascii_case(qualifier)))?
+                .table_range?;
+            let start = ident.start_byte() + qualifier.len() + 1;
+            Some(QualifiedColumn {
+                column: column.trim_matches(['`', '"']).to_string(),
+                range: start..ident.end_byte(),
+                table_range,
+            })
+        })
+        .collect()
+}
+
+/// A CTE's name in its definition and in each FROM/JOIN item that reads from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CteOccurrences {
+    pub name: String,
+    pub definition: Range<usize>,
+    pub usages: Vec<Range<usize>>,
+    /// Names of the other CTEs in the same WITH clause.
+    pub siblings: Vec<String>,
+}
+
+fn collect_nodes<'a>(node: Node<'a>, pred: &dyn Fn(Node) -> bool, out: &mut Vec<Node<'a>>) {
+    if pred(node) {
+        out.push(node);
+    }
+    let mut cursor = node.walk();
+    let children: Vec<Node<'a>> = node.named_children(&mut cursor).collect();
+    for child in children {
+        collect_nodes(child, pred, out);
+    }
+}
+
+/// The occurrences of the CTE whose name is at `byte_idx`, either on its definition
+/// or where a FROM/JOIN reads from it. Matches whole identifiers only, so `orders`
+/// doesn't pick up `orders_enriched`, strings or comments.
+pub fn cte_occurrences(tree: &Tree, text: &str, byte_idx: usize) -> Option<CteOccurrences> {
+    let ident = tree.root_node().descendant_for_byte_range(byte_idx, byte_idx)?;
+    if ident.kind() != "identifier" || !is_name_field(ident) {
+        return None;
+    }
+    let name = node_text(ident, text).to_string();
+
+    let cte = find_descendant(tree.root_node(), &|n| {
+        n.kind() == "cte" && n.child_by_field_name("alias_name").is_some_and(|a| node_text(a, text).eq_ignore_ascii_case(&name))
+    })?;
+    let definition = cte.child_by_field_name("alias_name")?.byte_range();
+    // The cursor on an alias (`from t as orders`) isn't on the CTE
+    let reads_table = ident.parent()
+        .filter(|p| p.kind() == "from_item")
+        .and_then(|p| p.child_by_field_name("table_name"))
+        .is_some_and(|t| t.id() == ident.id());
+    if definition != ident.byte_range() && !reads_table {
+        return None;
+    }
+
+    let mut tables = Vec::new();
+    collect_nodes(tree.root_node(), &|n| {
+        n.kind() == "from_item" && n.child_by_field_name("table_name").is_some_and(|t| node_text(t, text).eq_ignore_ascii_case(&name))
+    }, &mut tables);
+    let usages = tables.iter()
+        .filter_map(|item| item.child_by_field_name("table_name"))
+        .map(|t| t.byte_range())
+        .collect();
+    let mut cursor = cte.walk();
+    let siblings = cte.parent()
+        .map(|clause| clause.named_children(&mut cursor)
+            .filter(|c| c.kind() == "cte" && c.id() != cte.id())
+            .filter_map(|c| c.child_by_field_name("alias_name"))
+            .map(|a| node_text(a, text).to_string())
+            .collect())
+        .unwrap_or_default();
+    Some(CteOccurrences { name, definition, usages, siblings })
+}
+
+/// A table alias's definition and the qualifiers (`c` in `c.id`, `c.*`) that use it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AliasOccurrences {
+    pub name: String,
+    pub definition: Range<usize>,
+    pub usages: Vec<Range<usize>>,
+}
+
+fn enclosing_select(node: Node) -> Option<Node> {
+    let mut current = node.parent();
+    while let Some(n) = current.filter(|n| n.kind() != "select") {
+        current = n.parent();
+    }
+    current
+}
+
+/// The alias defined in a FROM/JOIN item directly under `select` (not in subqueries).
+fn alias_definition_in<'a>(select: Node<'a>, text: &str, name: &str) -> Option<Node<'a>> {
+    let mut cursor = select.walk();
+    let from_clause = select.named_children(&mut cursor).find(|c| c.kind() == "from_clause")?;
+    let mut items = Vec::new();
+    collect_nodes(from_clause, &|n| n.kind() == "from_item", &mut items);
+    items.into_iter()
+        .filter(|item| enclosing_select(*item).is_some_and(|s| s.id() == select.id()))
+        .find_map(|item| {
+            let mut item_cursor = item.walk();
+            let alias = item.named_children(&mut item_cursor)
+                .find(|c| c.kind() == "as_alias")
+                .and_then(|a| a.child_by_field_name("alias_name"))
+                .filter(|a| node_text(*a, text).eq_ignore_ascii_case(name));
+            alias
+        })
+}
+
+/// The table alias at `byte_idx`, on its definition (`as c`) or on a qualifier using it
+/// (`c.id`), with every use in the select statement that defines it. Subqueries of that
+/// statement are included, other CTEs and statements are not.
+pub fn alias_occurrences(tree: &Tree, text: &str, byte_idx: usize) -> Option<AliasOccurrences> {
+    let ident = tree.root_node().descendant_for_byte_range(byte_idx, byte_idx)?;
+    if ident.kind() != "identifier" {
+        return None;
+    }
+    let full = node_text(ident, text);
+    let on_definition = ident.parent().is_some_and(|p| p.kind() == "as_alias")
+        && ident.parent().and_then(|p| p.parent()).is_some_and(|p| p.kind() == "from_item");
+
+    let (name, definition, scope) = if on_definition {
+        (full.to_string(), ident.byte_range(), enclosing_select(ident)?)
+    } else {
+        let (qualifier, _) = full.split_once('.')?;
+        if byte_idx > ident.start_byte() + qualifier.len() {
+            return None;
+        }
+        // The nearest enclosing select defining the alias; subqueries may use outer aliases
+        let mut select = enclosing_select(ident);
+        loop {
+            let current = select?;
+            if let Some(def) = alias_definition_in(current, text, qualifier) {
+                break (qualifier.to_string(), def.byte_range(), current);
+            }
+            select = enclosing_select(current);
+        }
+    };
+
+    let mut nodes = Vec::new();
+    collect_nodes(scope, &|n| n.kind() == "identifier" || n.kind() == "select_all", &mut nodes);
+    let usages = nodes.into_iter()
+        .filter(|n| node_text(*n, text).split_once('.').is_some_and(|(q, _)| q.eq_ignore_ascii_case(&name)))
+        .map(|n| n.start_byte()..n.start_byte() + name.len())
+        .collect();
+    Some(AliasOccurrences { name, definition, usages })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(text: &str, needle: &str) -> Option<String> {
+        let tree = crate::parser::DbtParser::new().unwrap().parse(text, None).unwrap();
+        let offset = text.rfind(needle).unwrap() + needle.len() - 1;
+        resolve_column_definition(&tree, text, offset).map(|r| text[r].to_string())
+    }
+
+    #[test]
+    fn test_resolve_column_definition() {
+        let single = "with orders as (select sum(x) as total_amount from t)\nselect total_amount from orders";
+        assert_eq!(resolve(single, "select total_amount").as_deref(), Some("sum(x) as total_amount"));
+
+        let joined = "with orders as (select sum(x) as total_amount, id from t),\n\
+            users as (select id, name from u)\n\
+            select o.total_amount, o.id, u.name, id from orders as o join users u on o.id = u.id";
+        assert_eq!(resolve(joined, "o.id").as_deref(), Some("id"));
+        assert_eq!(resolve(joined, "u.name").as_deref(), Some("name"));
+        // Unqualified with two tables in the FROM clause is ambiguous
+        assert_eq!(resolve(joined, ", id"), None);
+        // The qualifier is an alias, not a column
+        assert_eq!(resolve(joined, " o"), None);
+    }
+
+    #[test]
+    fn test_alias_occurrences() {
+        let text = "with a as (select c.id from x as c),\n\
+            b as (select c.*, c.id from y join z as c on c.id = y.id)\n\
+            select 1";
+        let tree = crate::parser::DbtParser::new().unwrap().parse(text, None).unwrap();
+        let second = text.find("b as").unwrap();
+        let found = alias_occurrences(&tree, text, text.rfind("as c").unwrap() + 3).unwrap();
+        assert_eq!(found.name, "c");
+        // Only the uses in the second CTE
+        let usages: Vec<usize> = found.usages.iter().map(|r| r.start).collect();
+        assert_eq!(usages, vec![second + 13, second + 18, second + 45]);
+        assert_eq!(alias_occurrences(&tree, text, second + 18), Some(found));
+        // On the column part of a qualified name
+        assert!(alias_occurrences(&tree, text, second + 21).is_none());
+    }
+
+    #[test]
+    fn test_cte_occurrences() {
+        let text = "with orders as (select 1 as id),\n\
+            orders_enriched as (select * from orders)\n\
+            select 'orders' from orders_enriched join orders as o on true";
+        let tree = crate::parser::DbtParser::new().unwrap().parse(text, None).unwrap();
+        let found = cte_occurrences(&tree, text, text.find("orders").unwrap()).unwrap();
+        let usages: Vec<usize> = found.usages.iter().map(|r| r.start).collect();
+        assert_eq!(found.definition, 5..11);
+        assert_eq!(usages, vec![text.find("from orders)").unwrap() + 5, text.rfind("orders as o").unwrap()]);
+        assert_eq!(found.siblings, vec!["orders_enriched"]);
+
+        // From a usage the same set comes back
+        assert_eq!(cte_occurrences(&tree, text, usages[1] + 2), Some(found));
+        // Not a CTE name
+        assert!(cte_occurrences(&tree, text, text.rfind("o on").unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_cte_output_columns() {
+        let text = "with orders as (select o.id, sum(x) as total, o.*, count(*) from t as o group by 1)\nselect * from orders";
+        let tree = crate::parser::DbtParser::new().unwrap().parse(text, None).unwrap();
+        assert_eq!(cte_output_columns(&tree, text, "orders").unwrap(), vec!["id", "total", "o.*"]);
+        assert!(cte_output_columns(&tree, text, "missing").is_none());
+    }
+
+    #[test]
+    fn test_final_output_columns() {
+        let text = "with orders as (select id from t)\nselect o.id, o.amount * 2 as doubled, c.* from orders as o join c using (id)";
+        let tree = crate::parser::DbtParser::new().unwrap().parse(text, None).unwrap();
+        assert_eq!(final_output_columns(&tree, text).unwrap(), vec!["id", "doubled", "c.*"]);
+    }
+
+    #[test]
+    fn test_model_output_columns_with_jinja() {
+        assert_eq!(model_output_columns("select id, amount from {{ ref('x') }}").unwrap(), vec!["id", "amount"]);
+        // A star macro, alone or after named columns, and a column loop hide what the model outputs
+        assert!(model_output_columns("select {{ dbt_utils.star(ref('x')) }} from {{ ref('x') }}").is_none());
+        assert!(model_output_columns("select id, {{ dbt_utils.star(ref('x'), except=['id']) }} from {{ ref('x') }}").is_none());
+        assert!(model_output_columns("\
+select
+    id,
+    {% for kind in ['a', 'b'] %}
+    sum(amount) as {{ kind }}_total,
+    {% endfor %}
+    count(*) as n
+from {{ ref('x') }}
+group by 1").is_none());
+    }
+}
@@ -0,0 +1,321 @@
+use crate::jinja::DbtRef;
+use crate::position::PositionEncoding;
+use crate::project::ProjectManifest;
+use crate::profiles::Target;
+use crate::relation::{model_relation, ref_relation};
+use regex::Regex;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, Range};
+
+/// Compiles a model and returns its compiled SQL. Arguments: the model's URI, then
+/// optionally `"force"` to compile even when the compiled file is up to date.
+pub const SHOW_COMPILED_SQL: &str = "dbt-lsp.showCompiledSql";
+
+/// Opens a model's artifact from `target/`. Arguments: the model's URI, then optionally
+/// "compiled" (the default) or "run". Returns the artifact's URI and whether it exists and
+/// is older than the model, so clients can offer to compile first.
+pub const OPEN_TARGET_ARTIFACT: &str = "dbt-lsp.openTargetArtifact";
+
+/// Runs a model. Arguments: the model's URI, then optionally `"--full-refresh"`. dbt's
+/// output is streamed to the client's log; returns whether the run passed.
+pub const RUN_MODEL: &str = "dbt-lsp.runModel";
+
+/// Runs a model's tests. Argument: the model's URI. Failing tests are reported as
+/// diagnostics on the yml entry declaring them, replacing those of the previous run.
+pub const TEST_MODEL: &str = "dbt-lsp.testModel";
+
+/// Clears the diagnostics reported by `dbt-lsp.testModel`. Argument: optionally a model's
+/// URI, to clear only that model's results.
+pub const CLEAR_TEST_RESULTS: &str = "dbt-lsp.clearTestResults";
+
+/// Reloads a project's manifest from disk. Argument: optionally the project root (path or
+/// URI); all workspace roots otherwise.
+pub const RESCAN_PROJECT: &str = "dbt-lsp.rescanProject";
+
+/// Returns a model's lineage as a markdown tree. Arguments: the model's URI, then
+/// optionally "upstream", "downstream" or "both" (the default) and a maximum depth.
+pub const SHOW_LINEAGE: &str = "dbt-lsp.showLineage";
+
+/// Lists everything downstream of a model or source for impact analysis. Arguments: a
+/// model name or URI, or `source:<source>.<table>`, then optionally a maximum depth.
+pub const DOWNSTREAM: &str = "dbt-lsp.downstream";
+
+/// Runs a model's SQL against the warehouse and returns the first rows. Arguments: the
+/// model's URI, then optionally a row limit. Only available with `enablePreview` set.
+pub const PREVIEW_MODEL: &str = "dbt-lsp.previewModel";
+
+/// Error code of a preview that couldn't be rendered, run or read back. The error's data
+/// holds the failing `stage` and the command's `output` when there is one.
+pub const PREVIEW_FAILED: i64 = -32001;
+
+/// Exports the project's DAG. Arguments: optionally a file to write it to (DOT for `.dot`
+/// and `.gv`, JSON otherwise) and the project root, needed when several are loaded.
+/// Without a file, returns both the DOT text and the `{nodes, edges}` JSON.
+pub const EXPORT_GRAPH: &str = "dbt-lsp.exportGraph";
+
+/// Reports project counts and hygiene problems (orphan models, undocumented models, unused
+/// sources and macros) as markdown and JSON. Argument: optionally the project root.
+pub const PROJECT_STATS: &str = "dbt-lsp.projectStats";
+
+/// Lines of dbt's output shown when it fails.
+const OUTPUT_TAIL_LINES: usize = 15;
+
+/// Where dbt writes the `kind` ("compiled" or "run") artifact for the project file at
+/// `path`: `target/<kind>/<project>/<path relative to the project root>`.
+pub fn artifact_path(manifest: &ProjectManifest, path: &Path, kind: &str) -> Option<PathBuf> {
+    let relative = path.strip_prefix(&manifest.root_dir).ok()?;
+    Some(manifest.root_dir.join(&manifest.config.target_path).join(kind).join(&manifest.config.name).join(relative))
+}
+
+/// Whether `artifact` exists and was written no earlier than `source` was last changed.
+pub fn is_fresh(artifact: &Path, source: &Path) -> bool {
+    let modified = |p: &Path| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+    match (modified(artifact), modified(source)) {
+        (Some(artifact), Some(source)) => artifact >= source,
+        (Some(_), None) => true,
+        _ => false,
+    }
+}
+
+/// The last lines of a dbt invocation's output, for error messages. dbt logs errors to
+/// stdout, so both streams are considered.
+pub fn output_tail(output: &std::process::Output) -> String {
+    let text = format!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+    let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+    lines[lines.len().saturating_sub(OUTPUT_TAIL_LINES)..].join("\n")
+}
+
+/// Starts `dbt <args>` in the project root with its output piped.
+pub fn spawn_dbt(executable: &str, root: &Path, args: &[&str]) -> std::io::Result<tokio::process::Child> {
+    tokio::process::Command::new(executable)
+        .args(args)
+        .current_dir(root)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+}
+
+/// Runs `dbt <args>` in the project root and waits for it to finish.
+pub async fn run_dbt(executable: &str, root: &Path, args: &[&str]) -> std::io::Result<std::process::Output> {
+    spawn_dbt(executable, root, args)?.wait_with_output().await
+}
+
+fn re_this_expression() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"\{\{\s*-?\s*this\s*-?\s*\}\}").unwrap())
+}
+
+fn re_incremental_block() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"(?s)\{%-?\s*if\s+is_incremental\(\)\s*-?%\}.*?\{%-?\s*endif\s*-?%\}").unwrap())
+}
+
+/// The query a preview of `model` runs: its SQL with refs, sources and `{{ this }}`
+/// replaced by relation names (wrapped in `quote`), the config call and jinja comments
+/// dropped, and `is_incremental()` blocks left out as on a first run, limited to `limit`
+/// rows. Errors describe the first thing that needs dbt to render.
+pub fn preview_sql(manifest: &ProjectManifest, model: &str, text: &str, target: &Target, quote: &str, limit: usize) -> Result<String, String> {
+    let mut removed: Vec<std::ops::Range<usize>> = crate::jinja::comment_spans(text);
+    removed.extend(re_incremental_block().find_iter(text).map(|m| m.range()));
+    removed.extend(crate::jinja::parse_config(text).map(|c| c.range));
+
+    let mut edits: Vec<(std::ops::Range<usize>, String)> = removed.iter().map(|r| (r.clone(), String::new())).collect();
+    let kept = |range: &std::ops::Range<usize>| !removed.iter().any(|r| r.start <= range.start && range.end <= r.end);
+    for (dbt_ref, range) in crate::jinja::extract_refs(text) {
+        if !matches!(dbt_ref, DbtRef::Model(_) | DbtRef::VersionedModel(..) | DbtRef::PackageModel(..) | DbtRef::Source(..)) || !kept(&range) {
+            continue;
+        }
+        let relation = ref_relation(manifest, &dbt_ref, target).ok_or_else(|| format!("Can't resolve {}", &text[range.clone()]))?;
+        edits.push((range, format!("{}{}{}", quote, relation, quote)));
+    }
+    for m in re_this_expression().find_iter(text).filter(|m| kept(&m.range())) {
+        let relation = model_relation(manifest, model, target).ok_or_else(|| format!("Can't resolve the relation of {}", model))?;
+        edits.push((m.range(), format!("{}{}{}", quote, relation, quote)));
+    }
+
+    edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+    let mut sql = text.to_string();
+    let mut end = usize::MAX;
+    for (range, replacement) in edits {
+        // Ranges nested in one already applied (a comment inside an incremental block)
+        if range.end > end {
+            continue;
+        }
+        sql.replace_range(range.clone(), &replacement);
+        end = range.start;
+    }
+
+    if let Some(start) = sql.find("{{").or_else(|| sql.find("{%")) {
+        let snippet: String = sql[start..].chars().take(40).collect();
+        return Err(format!("Previews can't render jinja that needs dbt: {}", snippet.trim()));
+    }
+    let sql = sql.trim().trim_end_matches(';').trim_end();
+    Ok(format!("select * from (\n{}\n) limit {}", sql, limit))
+}
+
+/// Runs `command` through the platform shell in `root` with `input` on its stdin.
+pub async fn run_shell(command: &str, root: &Path, input: &str) -> std::io::Result<std::process::Output> {
+    use tokio::io::AsyncWriteExt;
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    let mut child = tokio::process::Command::new(shell)
+        .arg(flag)
+        .arg(command)
+        .current_dir(root)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A command that doesn't read its input closes the pipe early; its exit status says more
+        let _ = stdin.write_all(input.as_bytes()).await;
+    }
+    child.wait_with_output().await
+}
+
+/// The error returned for a failed preview.
+pub fn preview_error(stage: &str, message: String, output: Option<String>) -> tower_lsp::jsonrpc::Error {
+    tower_lsp::jsonrpc::Error {
+        code: tower_lsp::jsonrpc::ErrorCode::ServerError(PREVIEW_FAILED),
+        message: message.into(),
+        data: Some(serde_json::json!({ "stage": stage, "output": output })),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RunResults {
+    results: Vec<RunResult>,
+}
+
+/// One node's entry in `target/run_results.json`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RunResult {
+    pub unique_id: String,
+    pub status: String,
+    #[serde(default)]
+    pub failures: Option<u64>,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// Reads the results of the last dbt invocation in the project, when it wrote them no
+/// earlier than `since`. An older file is left over from a previous invocation.
+pub fn read_run_results(manifest: &ProjectManifest, since: std::time::SystemTime) -> Option<Vec<RunResult>> {
+    let path = manifest.root_dir.join(&manifest.config.target_path).join("run_results.json");
+    // Some filesystems keep mtimes to the second only
+    let since = since.duration_since(std::time::UNIX_EPOCH).ok()?.as_secs();
+    let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+    if modified.duration_since(std::time::UNIX_EPOCH).ok()?.as_secs() < since {
+        return None;
+    }
+    let text = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str::<RunResults>(&text).ok().map(|r| r.results)
+}
+
+/// The test's name from its unique id, `test.<package>.<name>[.<hash>]`.
+fn test_name(unique_id: &str) -> Option<&str> {
+    unique_id.strip_prefix("test.")?.split('.').nth(1)
+}
+
+/// Where a failing test of `model` is reported: the `name:` of the column it's declared
+/// on, else the model's yml entry, else the first line of the model file.
+fn test_location(manifest: &ProjectManifest, model: &str, model_path: &Path, test: &str, encoding: PositionEncoding) -> (PathBuf, Range) {
+    let Some(entry) = manifest.model_entries.get(model).map(|e| e.clone()) else {
+        return (model_path.to_path_buf(), Range::default());
+    };
+    let text = std::fs::read_to_string(&entry.path).unwrap_or_default();
+    let rope = ropey::Rope::from_str(&text);
+    let at = |line: usize, column: usize, len: usize| crate::position::line_span_to_range(&rope, line, column, len, encoding);
+
+    // Generic test names embed the model and column: `not_null_orders_order_id`
+    let mut columns: Vec<&str> = entry.columns.iter().map(|c| c.name.as_str()).collect();
+    columns.sort_by_key(|c| std::cmp::Reverse(c.len()));
+    let column = columns.into_iter().find(|c| {
+        let suffix = format!("_{}_{}", model, c);
+        test.ends_with(&suffix) || test.contains(&format!("{}_", suffix))
+    });
+    if let Some(column) = column {
+        let keys = crate::yaml::scan_keys(&text);
+        if let Some(key) = crate::yaml::find_named_item(&keys, &["models", model, "columns"], column) {
+            return (entry.path.clone(), at(key.line, key.value_column, column.len()));
+        }
+    }
+    (entry.path.clone(), at(entry.line, entry.column, model.len()))
+}
+
+/// Diagnostics for the tests in `results` that didn't pass, with the file each belongs on.
+pub fn test_failure_diagnostics(manifest: &ProjectManifest, model: &str, model_path: &Path, results: &[RunResult], encoding: PositionEncoding) -> Vec<(PathBuf, Diagnostic)> {
+    results.iter()
+        .filter(|r| matches!(r.status.as_str(), "fail" | "warn" | "error"))
+        .filter_map(|r| {
+            let name = test_name(&r.unique_id)?;
+            let message = match (r.status.as_str(), r.failures) {
+                ("error", _) => format!("Test '{}' errored: {}", name, r.message.as_deref().unwrap_or("unknown error")),
+                (_, Some(failures)) => format!("Test '{}' failed with {} failing row{}", name, failures, if failures == 1 { "" } else { "s" }),
+                _ => format!("Test '{}' failed", name),
+            };
+            let (path, range) = test_location(manifest, model, model_path, name, encoding);
+            Some((path, Diagnostic {
+                range,
+                severity: Some(DiagnosticSeverity::WARNING),
+                source: Some("dbt test".to_string()),
+                message,
+                ..Default::default()
+            }))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_artifact_path() {
+        let root = std::env::temp_dir().join(format!("dbt-lsp-artifact-path-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("models").join("staging")).unwrap();
+        std::fs::write(root.join("dbt_project.yml"), "name: shop\ntarget-path: build\n").unwrap();
+        let manifest = ProjectManifest::new(root.clone()).unwrap();
+        let model = root.join("models").join("staging").join("stg_orders.sql");
+        assert_eq!(
+            artifact_path(&manifest, &model, "compiled").unwrap(),
+            root.join("build").join("compiled").join("shop").join("models").join("staging").join("stg_orders.sql")
+        );
+
+        // Fresh only once written after the model
+        let artifact = root.join("compiled.sql");
+        assert!(!is_fresh(&artifact, &model));
+        std::fs::write(&model, "select 1").unwrap();
+        std::fs::write(&artifact, "select 1").unwrap();
+        assert!(is_fresh(&artifact, &model));
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_preview_sql() {
+        let root = std::env::temp_dir().join(format!("dbt-lsp-preview-sql-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("models")).unwrap();
+        std::fs::write(root.join("dbt_project.yml"), "name: shop\n").unwrap();
+        std::fs::write(root.join("models").join("sources.yml"), "sources:\n  - name: raw\n    schema: landing\n    tables:\n      - name: orders\n").unwrap();
+        std::fs::write(root.join("models").join("stg_orders.sql"), "select 1").unwrap();
+        let manifest = ProjectManifest::new(root.clone()).unwrap();
+        manifest.scan_models();
+        manifest.scan_sources();
+        let target = Target { database: Some("analytics".to_string()), schema: Some("dev".to_string()), ..Target::default() };
+
+        let text = "{{ config(materialized='incremental') }}\n{# raw orders #}\nselect * from {{ source('raw', 'orders') }}\njoin {{ ref('stg_orders') }} using (id)\n{% if is_incremental() %}\nwhere id > (select max(id) from {{ this }})\n{% endif %};\n";
+        assert_eq!(
+            preview_sql(&manifest, "fct_orders", text, &target, "`", 10).unwrap(),
+            "select * from (\nselect * from `analytics.landing.orders`\njoin `analytics.dev.stg_orders` using (id)\n) limit 10"
+        );
+        assert!(preview_sql(&manifest, "fct_orders", "select * from {{ ref('missing') }}", &target, "", 10).unwrap_err().contains("ref('missing')"));
+        assert!(preview_sql(&manifest, "fct_orders", "select {{ dbt_utils.star(ref('stg_orders')) }}", &target, "", 10).is_err());
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,445 @@
+use crate::project::{ColumnOrigin, ProjectManifest};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use tower_lsp::lsp_types::{CompletionItem, CompletionItemKind, CompletionTextEdit, InsertTextFormat, Range, TextEdit};
+
+/// Lines of a model's SQL shown when its completion item is resolved.
+const RESOLVE_PREVIEW_LINES: usize = 10;
+
+/// What the cursor is positioned on, as far as completion is concerned.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompletionContext {
+    /// Inside the quotes of `ref('...')`, with the part of the name typed so far.
+    RefName { prefix: String },
+    /// Inside the second argument of `ref('package', '...')`.
+    PackageRefName { package: String, prefix: String },
+    /// Inside the first argument of `source('...')`.
+    SourceName { prefix: String },
+    /// Inside the second argument of `source('src', '...')`.
+    SourceTable { source: String, prefix: String },
+    /// Inside the quotes of `var('...')`.
+    VarName { prefix: String },
+    /// Inside the quotes of `doc('...')`, in SQL or in a yml description.
+    DocName { prefix: String },
+    /// Right after `alias.`, with the part of the column typed so far.
+    AliasColumn { alias: String, prefix: String },
+    /// Plain SQL, or anywhere without a more specific context.
+    Other,
+}
+
+impl CompletionContext {
+    /// The part of the name typed so far, for contexts that complete a name.
+    pub fn typed_prefix(&self) -> Option<&str> {
+        match self {
+            CompletionContext::RefName { prefix }
+            | CompletionContext::PackageRefName { prefix, .. }
+            | CompletionContext::SourceName { prefix }
+            | CompletionContext::SourceTable { prefix, .. }
+            | CompletionContext::VarName { prefix }
+            | CompletionContext::DocName { prefix }
+            | CompletionContext::AliasColumn { prefix, .. } => Some(prefix),
+            CompletionContext::Other => None,
+        }
+    }
+}
+
+/// Stored in `CompletionItem.data` so completionItem/resolve can find the item again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ItemData {
+    /// Root of the project the item came from.
+    pub root: PathBuf,
+    #[serde(flatten)]
+    pub item: ItemRef,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ItemRef {
+    Model { name: String },
+    Seed { name: String },
+    Snapshot { name: String },
+    SourceTable { source: String, table: String },
+    Column { target: String, column: String },
+}
+
+fn item_data(manifest: &ProjectManifest, item: ItemRef) -> Option<serde_json::Value> {
+    serde_json::to_value(ItemData { root: manifest.root_dir.clone(), item }).ok()
+}
+
+fn re_open_ref() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r#"\bref\s*\(\s*['"]([a-zA-Z0-9_\.]*)$"#).unwrap())
+}
+
+fn re_open_package_ref() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r#"\bref\s*\(\s*['"]([a-zA-Z0-9_]+)['"]\s*,\s*['"]([a-zA-Z0-9_\.]*)$"#).unwrap())
+}
+
+fn re_open_source() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r#"\bsource\s*\(\s*['"]([a-zA-Z0-9_\.]*)$"#).unwrap())
+}
+
+fn re_open_source_table() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r#"\bsource\s*\(\s*['"]([a-zA-Z0-9_\.]+)['"]\s*,\s*['"]([a-zA-Z0-9_\.]*)$"#).unwrap())
+}
+
+fn re_open_var() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r#"\bvar\s*\(\s*['"]([a-zA-Z0-9_]*)$"#).unwrap())
+}
+
+fn re_open_doc() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r#"\bdoc\s*\(\s*['"]([a-zA-Z0-9_]*)$"#).unwrap())
+}
+
+fn re_alias_dot() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r#"(?:^|[^a-zA-Z0-9_\.'"])([a-zA-Z_][a-zA-Z0-9_]*)\.([a-zA-Z0-9_]*)$"#).unwrap())
+}
+
+/// Determines the completion context from the text of the current line up to the cursor.
+pub fn completion_context(line_prefix: &str) -> CompletionContext {
+    if let Some(cap) = re_open_ref().captures(line_prefix) {
+        return CompletionContext::RefName { prefix: cap[1].to_string() };
+    }
+    if let Some(cap) = re_open_package_ref().captures(line_prefix) {
+        return CompletionContext::PackageRefName { package: cap[1].to_string(), prefix: cap[2].to_string() };
+    }
+    if let Some(cap) = re_open_source().captures(line_prefix) {
+        return CompletionContext::SourceName { prefix: cap[1].to_string() };
+    }
+    if let Some(cap) = re_open_source_table().captures(line_prefix) {
+        return CompletionContext::SourceTable { source: cap[1].to_string(), prefix: cap[2].to_string() };
+    }
+    if let Some(cap) = re_open_var().captures(line_prefix) {
+        return CompletionContext::VarName { prefix: cap[1].to_string() };
+    }
+    if let Some(cap) = re_open_doc().captures(line_prefix) {
+        return CompletionContext::DocName { prefix: cap[1].to_string() };
+    }
+    if let Some(cap) = re_alias_dot().captures(line_prefix) {
+        return CompletionContext::AliasColumn { alias: cap[1].to_string(), prefix: cap[2].to_string() };
+    }
+    CompletionContext::Other
+}
+
+/// Models, seeds and snapshots whose name starts with `prefix`, then models from installed
+/// packages not in `exclude_packages` that no project node shadows, then the names of
+/// installed and declared packages for `ref('package', 'model')`. Documentation is
+/// filled in by [`resolve_documentation`] when the client asks for it.
+pub fn ref_items(manifest: &ProjectManifest, prefix: &str, exclude_packages: &[String]) -> Vec<CompletionItem> {
+    let item = |name: &str, detail: &str, data: ItemRef| CompletionItem {
+        label: name.to_string(),
+        kind: Some(CompletionItemKind::FILE),
+        detail: Some(detail.to_string()),
+        data: item_data(manifest, data),
+        ..CompletionItem::default()
+    };
+
+    let mut items = Vec::new();
+    for model in manifest.models.iter().filter(|m| m.key().starts_with(prefix)) {
+        items.push(item(model.key(), "dbt model", ItemRef::Model { name: model.key().clone() }));
+    }
+    for seed in manifest.seeds.iter().filter(|s| s.key().starts_with(prefix)) {
+        items.push(item(seed.key(), "dbt seed", ItemRef::Seed { name: seed.key().clone() }));
+    }
+    for snapshot in manifest.snapshots.iter().filter(|s| s.key().starts_with(prefix)) {
+        items.push(item(snapshot.key(), "dbt snapshot", ItemRef::Snapshot { name: snapshot.key().clone() }));
+    }
+    let mut package_items: Vec<CompletionItem> = manifest.package_models.iter()
+        .filter(|m| m.key().1.starts_with(prefix) && !exclude_packages.contains(&m.key().0))
+        // One item per name, from the package a plain ref resolves to
+        .filter(|m| manifest.defining_packages(&m.key().1).first() == Some(&m.key().0))
+        .map(|m| CompletionItem {
+            label: m.key().1.clone(),
+            kind: Some(CompletionItemKind::FILE),
+            detail: Some(format!("model in package '{}'", m.key().0)),
+            ..CompletionItem::default()
+        })
+        .collect();
+    package_items.retain(|p| !items.iter().any(|i| i.label == p.label));
+    items.extend(package_items);
+    items.sort_by(|a, b| a.label.cmp(&b.label));
+
+    let mut packages: Vec<(String, &str)> = manifest.packages.iter()
+        .map(|p| (p.key().clone(), "dbt package"))
+        .chain(manifest.missing_packages().into_iter().map(|p| (p.name, "dbt package (not installed)")))
+        .filter(|(name, _)| name.starts_with(prefix) && !exclude_packages.contains(name))
+        .collect();
+    packages.sort();
+    items.extend(packages.into_iter().map(|(name, detail)| CompletionItem {
+        label: name,
+        kind: Some(CompletionItemKind::MODULE),
+        detail: Some(detail.to_string()),
+        ..CompletionItem::default()
+    }));
+    items
+}
+
+/// Models of `package` starting with `prefix`, for `ref('package', '...')`. The project's
+/// own name lists its models.
+pub fn package_ref_items(manifest: &ProjectManifest, package: &str, prefix: &str) -> Vec<CompletionItem> {
+    let mut names: Vec<String> = if package == manifest.config.name {
+        manifest.models.iter().map(|m| m.key().clone()).collect()
+    } else {
+        manifest.package_models.iter().filter(|m| m.key().0 == package).map(|m| m.key().1.clone()).collect()
+    };
+    names.retain(|name| name.starts_with(prefix));
+    names.sort();
+    names.into_iter()
+        .map(|name| CompletionItem {
+            label: name,
+            kind: Some(CompletionItemKind::FILE),
+            detail: Some(format!("model in package '{}'", package)),
+            ..CompletionItem::default()
+        })
+        .collect()
+}
+
+/// Source names starting with `prefix`. The item text is just the name, so it fits
+/// between the quotes already typed.
+pub fn source_name_items(manifest: &ProjectManifest, prefix: &str) -> Vec<CompletionItem> {
+    manifest.source_names().into_iter()
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| CompletionItem {
+            label: name,
+            kind: Some(CompletionItemKind::MODULE),
+            detail: Some("dbt source".to_string()),
+            ..CompletionItem::default()
+        })
+        .collect()
+}
+
+/// Tables of `source` starting with `prefix`.
+pub fn source_table_items(manifest: &ProjectManifest, source: &str, prefix: &str) -> Vec<CompletionItem> {
+    manifest.source_tables(source).into_iter()
+        .filter(|table| table.starts_with(prefix))
+        .map(|table| CompletionItem {
+            label: table.clone(),
+            kind: Some(CompletionItemKind::CLASS),
+            detail: Some(format!("table in source '{}'", source)),
+            data: item_data(manifest, ItemRef::SourceTable { source: source.to_string(), table }),
+            ..CompletionItem::default()
+        })
+        .collect()
+}
+
+/// Vars from dbt_project.yml starting with `prefix`, with their value as the detail.
+pub fn var_items(manifest: &ProjectManifest, prefix: &str) -> Vec<CompletionItem> {
+    let mut items: Vec<CompletionItem> = manifest.vars.iter()
+        .filter(|v| v.key().starts_with(prefix))
+        .map(|v| {
+            // Lists and mappings are shown inline so the detail stays on one line
+            let value = if v.value.is_mapping() || v.value.is_sequence() {
+                serde_json::to_string(&v.value).unwrap_or_default()
+            } else {
+                serde_yaml::to_string(&v.value).unwrap_or_default().trim_end().to_string()
+            };
+            CompletionItem {
+                label: v.key().clone(),
+                kind: Some(CompletionItemKind::VARIABLE),
+                detail: Some(value),
+                ..CompletionItem::default()
+            }
+        })
+        .collect();
+    items.sort_by(|a, b| a.label.cmp(&b.label));
+    items
+}
+
+/// Docs blocks starting with `prefix`, with the first line of the body as the detail.
+pub fn doc_items(manifest: &ProjectManifest, prefix: &str) -> Vec<CompletionItem> {
+    let mut items: Vec<CompletionItem> = manifest.docs.iter()
+        .filter(|d| d.key().starts_with(prefix))
+        .map(|d| CompletionItem {
+            label: d.key().clone(),
+            kind: Some(CompletionItemKind::TEXT),
+            detail: d.body.lines().map(str::trim).find(|l| !l.is_empty()).map(str::to_string),
+            ..CompletionItem::default()
+        })
+        .collect();
+    items.sort_by(|a, b| a.label.cmp(&b.label));
+    items
+}
+
+/// Columns of `target` (as stored in an alias) starting with `prefix`: from catalog.json
+/// when it is current, else a seed's CSV header when `seed_header` is given, else the
+/// columns documented in yml or a model's select list. Unknown targets get no items.
+pub fn column_items(manifest: &ProjectManifest, target: &str, seed_header: Option<&[String]>, prefix: &str) -> Vec<CompletionItem> {
+    let columns = match (manifest.relation_columns(target), seed_header) {
+        (Some((columns, ColumnOrigin::Catalog)), _) => columns,
+        (_, Some(header)) => manifest.seed_columns(target, header),
+        (columns, None) => columns.map(|(columns, _)| columns).unwrap_or_default(),
+    };
+    columns.into_iter()
+        .filter(|c| c.name.starts_with(prefix))
+        .map(|c| CompletionItem {
+            label: c.name.clone(),
+            kind: Some(CompletionItemKind::FIELD),
+            detail: c.data_type,
+            data: item_data(manifest, ItemRef::Column { target: target.to_string(), column: c.name }),
+            ..CompletionItem::default()
+        })
+        .collect()
+}
+
+/// Markdown documentation for a resolved completion item: the description from yml,
+/// where the item is defined, and for models the start of the SQL.
+pub fn resolve_documentation(manifest: &ProjectManifest, item: &ItemRef) -> Option<String> {
+    let relative = |path: &std::path::Path| path.strip_prefix(&manifest.root_dir).unwrap_or(path).display().to_string();
+    let mut parts = Vec::new();
+    match item {
+        ItemRef::Model { name } => {
+            let path = manifest.models.get(name)?.clone();
+            if let Some(description) = manifest.model_entries.get(name).and_then(|e| e.description.clone()) {
+                parts.push(manifest.render_docs(&description));
+            }
+            parts.push(format!("`{}`", relative(&path)));
+            if let Ok(sql) = std::fs::read_to_string(&path) {
+                let preview: Vec<&str> = sql.lines().take(RESOLVE_PREVIEW_LINES).collect();
+                parts.push(format!("```sql\n{}\n```", preview.join("\n")));
+            }
+        }
+        ItemRef::Seed { name } => parts.push(format!("`{}`", relative(&manifest.seeds.get(name)?))),
+        ItemRef::Snapshot { name } => parts.push(format!("`{}`", relative(&manifest.snapshots.get(name)?.path))),
+        ItemRef::SourceTable { source, table } => {
+            let def = manifest.sources.get(&format!("{}.{}", source, table))?;
+            if let Some(description) = &def.description {
+                parts.push(manifest.render_docs(description));
+            }
+            parts.push(format!("`{}`", relative(&def.path)));
+        }
+        ItemRef::Column { target, column } => {
+            parts.push(manifest.relation_columns(target)?.0.into_iter().find(|c| &c.name == column)?.description?);
+        }
+    }
+    Some(parts.join("\n\n"))
+}
+
+fn snippet(label: &str, body: &str, detail: &str) -> CompletionItem {
+    CompletionItem {
+        label: label.to_string(),
+        kind: Some(CompletionItemKind::SNIPPET),
+        insert_text: Some(body.to_string()),
+        insert_text_format: Some(InsertTextFormat::SNIPPET),
+        detail: Some(detail.to_string()),
+        ..CompletionItem::default()
+    }
+}
+
+const SNAPSHOT_SKELETON: &str = "\
+{% snapshot ${1:name} %}
+
+{{
+    config(
+      target_schema='${2:snapshots}',
+      unique_key='${3:id}',
+      strategy='timestamp',
+      updated_at='${4:updated_at}',
+    )
+}}
+
+select * from $0
+
+{% endsnapshot %}";
+
+/// Snippets offered outside any more specific context. The jinja block snippets only
+/// apply where a new statement can start (`line_prefix`, less the word being typed,
+/// empty or ending in whitespace); macro and snapshot skeletons only in files under
+/// their paths.
+pub fn snippet_items(line_prefix: &str, in_macro_paths: bool, in_snapshot_paths: bool) -> Vec<CompletionItem> {
+    let mut items = vec![
+        snippet("ref", "{{ ref('$1') }}", "Expand to ref() tag"),
+        snippet("source", "{{ source('$1', '$2') }}", "Expand to source() tag"),
+    ];
+    let before_word = line_prefix.trim_end_matches(|c: char| c.is_alphanumeric() || c == '_');
+    if !(before_word.is_empty() || before_word.ends_with(char::is_whitespace)) {
+        return items;
+    }
+    items.extend([
+        snippet("config", "{{ config(materialized='$1') }}", "config() block"),
+        snippet("if is_incremental", "{% if is_incremental() %}\n$0\n{% endif %}", "if is_incremental() block"),
+        snippet("for", "{% for $1 in $2 %}\n$0\n{% endfor %}", "for loop"),
+        snippet("set", "{% set $1 = $2 %}", "set a variable"),
+    ]);
+    if in_macro_paths {
+        items.push(snippet("macro", "{% macro ${1:name}(${2:args}) %}\n$0\n{% endmacro %}", "macro skeleton"));
+    }
+    if in_snapshot_paths {
+        items.push(snippet("snapshot", SNAPSHOT_SKELETON, "snapshot skeleton"));
+    }
+    items
+}
+
+/// Makes each item replace `range` (the partially typed name) with its label, so
+/// accepting `stg_orders` after `stg_ord` doesn't leave `stg_ordstg_orders`.
+pub fn with_text_edit(items: Vec<CompletionItem>, range: Range) -> Vec<CompletionItem> {
+    items.into_iter()
+        .map(|item| CompletionItem {
+            text_edit: Some(CompletionTextEdit::Edit(TextEdit { range, new_text: item.label.clone() })),
+            ..item
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snippets_only_at_statement_position() {
+        let labels = |prefix: &str, in_macros: bool, in_snapshots: bool| -> Vec<String> {
+            snippet_items(prefix, in_macros, in_snapshots).into_iter().map(|i| i.label).collect()
+        };
+        assert_eq!(labels("select o.stg", true, true), vec!["ref", "source"]);
+        assert_eq!(labels("coalesce(con", false, false), vec!["ref", "source"]);
+        // A partly typed snippet name still counts as statement position
+        assert_eq!(labels("  con", false, false), vec!["ref", "source", "config", "if is_incremental", "for", "set"]);
+        assert_eq!(labels("mac", true, false).last().map(String::as_str), Some("macro"));
+        assert_eq!(labels("", false, false), vec!["ref", "source", "config", "if is_incremental", "for", "set"]);
+        assert_eq!(labels("    ", true, false).last().map(String::as_str), Some("macro"));
+        assert_eq!(labels("from ", false, true).last().map(String::as_str), Some("snapshot"));
+    }
+
+    #[test]
+    fn test_completion_context() {
+        assert_eq!(completion_context("select * from {{ ref('stg_"), CompletionContext::RefName { prefix: "stg_".to_string() });
+        assert_eq!(completion_context("from {{ ref(\""), CompletionContext::RefName { prefix: String::new() });
+        // Before the opening quote there is nothing to complete into yet
+        assert_eq!(completion_context("select * from {{ ref("), CompletionContext::Other);
+        assert_eq!(completion_context("select stg_"), CompletionContext::Other);
+        // A closed ref earlier on the line doesn't count
+        assert_eq!(completion_context("from {{ ref('a') }} join b"), CompletionContext::Other);
+        assert_eq!(
+            completion_context("from {{ ref('dbt_date', 'dim_"),
+            CompletionContext::PackageRefName { package: "dbt_date".to_string(), prefix: "dim_".to_string() }
+        );
+
+        assert_eq!(completion_context("from {{ source('ra"), CompletionContext::SourceName { prefix: "ra".to_string() });
+        assert_eq!(
+            completion_context("from {{ source('raw', 'pay"),
+            CompletionContext::SourceTable { source: "raw".to_string(), prefix: "pay".to_string() }
+        );
+        // Between the arguments neither stage applies
+        assert_eq!(completion_context("from {{ source('raw', "), CompletionContext::Other);
+
+        assert_eq!(completion_context("where d > '{{ var('st"), CompletionContext::VarName { prefix: "st".to_string() });
+
+        assert_eq!(
+            completion_context("        description: '{{ doc(\"orders_"),
+            CompletionContext::DocName { prefix: "orders_".to_string() }
+        );
+
+        assert_eq!(completion_context("select c."), CompletionContext::AliasColumn { alias: "c".to_string(), prefix: String::new() });
+        assert_eq!(completion_context("  o.cust"), CompletionContext::AliasColumn { alias: "o".to_string(), prefix: "cust".to_string() });
+        // Numbers and the middle of a dotted name aren't aliases
+        assert_eq!(completion_context("select 1."), CompletionContext::Other);
+        assert_eq!(completion_context("from db.schema."), CompletionContext::Other);
+    }
+}
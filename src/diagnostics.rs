@@ -1,17 +1,342 @@
 use crate::jinja::DbtRef;
+use crate::position::PositionEncoding;
 use crate::project::ProjectManifest;
-use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, Position, Range};
+use crate::settings::Settings;
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, DiagnosticTag, NumberOrString, Range};
 use ropey::Rope;
-use sqlparser::dialect::BigQueryDialect;
 use sqlparser::parser::Parser;
 use std::sync::OnceLock;
 use regex::Regex;
 
+/// Code of the diagnostic for a `ref()` to a model, seed or snapshot the project lacks.
+pub const UNKNOWN_MODEL: &str = "unknown-model";
+
+/// Code of the diagnostic for a `source()` naming a table its (known) source lacks.
+pub const UNKNOWN_SOURCE_TABLE: &str = "unknown-source-table";
+
+/// Code of the diagnostic for a `source()` whose source the project doesn't declare.
+pub const UNKNOWN_SOURCE: &str = "unknown-source";
+
+/// Code of the error on a `ref(name, v=N)` naming a version the model doesn't declare.
+pub const UNKNOWN_MODEL_VERSION: &str = "unknown-model-version";
+
+/// Code of the warning on a `ref(name, v=N)` pinning a version older than the latest.
+pub const OLD_MODEL_VERSION: &str = "old-model-version";
+
+/// Code of the error on a `ref()` to a private model of another group.
+pub const PRIVATE_REF: &str = "private-ref";
+
+/// Code of the warning on a seed `column_types` key that isn't a column of the CSV.
+pub const UNKNOWN_SEED_COLUMN: &str = "unknown-seed-column";
+
+/// Code of the error on a generic test in yml that no builtin or `{% test %}` block defines.
+pub const UNKNOWN_TEST: &str = "unknown-test";
+
+/// Code of the information on a plain `ref()` that more than one package defines.
+pub const AMBIGUOUS_REF: &str = "ambiguous-ref";
+
+/// Code of the hint on a CTE that nothing in the model reads from.
+pub const UNUSED_CTE: &str = "unused-cte";
+
+/// Code of the warning on an `alias.column` the aliased model or source doesn't have.
+pub const UNKNOWN_COLUMN: &str = "unknown-column";
+
+/// Code of the error on each file of a model name that more than one file defines.
+pub const DUPLICATE_MODEL: &str = "duplicate-model";
+
+/// Code of the error on a docs block whose name an earlier block already has.
+pub const DUPLICATE_DOCS: &str = "duplicate-docs";
+
+/// Code of the warning on a packages.yml entry that isn't installed.
+pub const MISSING_PACKAGE: &str = "missing-package";
+
+/// Widely used hub packages. A macro call qualified with one of these names most likely
+/// means the package is missing rather than that the namespace is something else.
+const KNOWN_PACKAGES: &[&str] = &[
+    "dbt_utils", "dbt_date", "dbt_expectations", "codegen", "audit_helper", "dbt_external_tables",
+    "dbt_project_evaluator", "dbt_artifacts", "elementary", "fivetran_utils", "spark_utils", "dbt_constraints",
+];
+
+/// The package a `pkg.macro()` call needs when that package isn't installed but is
+/// declared in packages.yml or is a well-known one.
+fn missing_macro_package<'a>(manifest: &ProjectManifest, name: &'a str) -> Option<&'a str> {
+    let (namespace, _) = name.split_once('.')?;
+    let missing = !manifest.has_package(namespace)
+        && (manifest.declared_packages.contains_key(namespace) || KNOWN_PACKAGES.contains(&namespace));
+    missing.then_some(namespace)
+}
+
+/// Warnings on the packages.yml entries that aren't installed under dbt_packages/.
+pub fn package_diagnostics(manifest: &ProjectManifest, encoding: PositionEncoding) -> Vec<Diagnostic> {
+    let missing = manifest.missing_packages();
+    if missing.is_empty() {
+        return Vec::new();
+    }
+    let rope = Rope::from_str(&std::fs::read_to_string(manifest.root_dir.join("packages.yml")).unwrap_or_default());
+    missing.into_iter()
+        .map(|p| Diagnostic {
+            range: crate::position::line_span_to_range(&rope, p.line, p.column, p.spec.len(), encoding),
+            severity: Some(DiagnosticSeverity::WARNING),
+            code: Some(NumberOrString::String(MISSING_PACKAGE.to_string())),
+            source: Some("dbt-lsp".to_string()),
+            message: format!("Package '{}' is declared but not installed — run `dbt deps`.", p.name),
+            ..Diagnostic::default()
+        })
+        .collect()
+}
+
+/// An error at the top of the model file at `path` when another file defines a model of
+/// the same name, naming the other files.
+pub fn duplicate_model_diagnostics(manifest: &ProjectManifest, path: &std::path::Path) -> Vec<Diagnostic> {
+    let Some(name) = manifest.model_name_for_path(path) else { return Vec::new() };
+    let files = manifest.model_files(&name);
+    if files.len() < 2 {
+        return Vec::new();
+    }
+    let others: Vec<String> = files.iter()
+        .filter(|p| *p != path)
+        .map(|p| p.strip_prefix(&manifest.root_dir).unwrap_or(p).display().to_string())
+        .collect();
+    vec![Diagnostic {
+        range: Range::default(),
+        severity: Some(DiagnosticSeverity::ERROR),
+        code: Some(NumberOrString::String(DUPLICATE_MODEL.to_string())),
+        source: Some("dbt-lsp".to_string()),
+        message: format!("Duplicate model name '{}': also defined at {}. dbt requires model names to be unique.", name, others.join(", ")),
+        ..Diagnostic::default()
+    }]
+}
+
+/// An error on the name of each docs block in the file at `path` that repeats the name
+/// of an earlier block (by path and line), pointing at the first definition.
+pub fn duplicate_docs_diagnostics(manifest: &ProjectManifest, path: &std::path::Path, encoding: PositionEncoding) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut rope = None;
+    for entry in manifest.duplicate_docs.iter() {
+        let Some((first, later)) = entry.value().split_first() else { continue };
+        for block in later.iter().filter(|b| b.path == path) {
+            let rope = rope.get_or_insert_with(|| Rope::from_str(&std::fs::read_to_string(path).unwrap_or_default()));
+            let first_path = first.path.strip_prefix(&manifest.root_dir).unwrap_or(&first.path);
+            diagnostics.push(Diagnostic {
+                range: crate::position::line_span_to_range(rope, block.line, block.column, entry.key().len(), encoding),
+                severity: Some(DiagnosticSeverity::ERROR),
+                code: Some(NumberOrString::String(DUPLICATE_DOCS.to_string())),
+                source: Some("dbt-lsp".to_string()),
+                message: format!("Docs block '{}' is already defined at {}:{}.", entry.key(), first_path.display(), first.line + 1),
+                ..Diagnostic::default()
+            });
+        }
+    }
+    diagnostics.sort_by_key(|d| d.range.start.line);
+    diagnostics
+}
+
+/// Errors on the refs in the yml file at `path` — exposures' `depends_on` and semantic
+/// models' `model:` — that name a model or source the project lacks, as for the same calls in SQL.
+pub fn yml_ref_diagnostics(manifest: &ProjectManifest, path: &std::path::Path, encoding: PositionEncoding) -> Vec<Diagnostic> {
+    let mut refs: Vec<(DbtRef, std::ops::Range<usize>)> = manifest.exposures.iter()
+        .filter(|e| e.path == path)
+        .flat_map(|e| e.depends_on.clone())
+        .collect();
+    refs.extend(manifest.semantic_models.iter().filter(|m| m.path == path).filter_map(|m| m.model.clone()));
+    refs.sort_by_key(|(_, range)| range.start);
+    if refs.is_empty() {
+        return Vec::new();
+    }
+    let Ok(text) = std::fs::read_to_string(path) else { return Vec::new() };
+    ref_diagnostics(&refs, manifest, &Rope::from_str(&text), encoding)
+}
+
+/// Errors on the refs in the model at `path` to private models outside its group, which
+/// dbt rejects. Protected and public models may be ref'd from anywhere in the project.
+pub fn access_diagnostics(refs: &[(DbtRef, std::ops::Range<usize>)], manifest: &ProjectManifest, path: &std::path::Path, rope: &Rope, encoding: PositionEncoding) -> Vec<Diagnostic> {
+    let Some(model) = manifest.model_name_for_path(path) else { return Vec::new() };
+    let project = &manifest.config.name;
+    let mut group = None;
+    let mut diagnostics = Vec::new();
+    for (dbt_ref, range) in refs {
+        let Some(target) = manifest.ref_target_name(dbt_ref).filter(|t| *t != model && manifest.models.contains_key(t)) else { continue };
+        let (target_group, access) = crate::relation::model_access(manifest, &target);
+        if access != "private" {
+            continue;
+        }
+        let group = group.get_or_insert_with(|| crate::relation::model_access(manifest, &model).0);
+        let message = match &target_group {
+            Some(target_group) if group.as_ref() == Some(target_group) => continue,
+            // As dbt words it
+            Some(target_group) => format!(
+                "Node model.{}.{} attempted to reference node model.{}.{}, which is not allowed because the referenced node is private to the '{}' group.",
+                project, model, project, target, target_group
+            ),
+            None => format!("Model '{}' is private but in no group, so no other model can ref it.", target),
+        };
+        diagnostics.push(Diagnostic {
+            range: crate::position::byte_range_to_range(rope, range, encoding),
+            severity: Some(DiagnosticSeverity::ERROR),
+            code: Some(NumberOrString::String(PRIVATE_REF.to_string())),
+            source: Some("dbt-lsp".to_string()),
+            message,
+            ..Diagnostic::default()
+        });
+    }
+    diagnostics
+}
+
+/// Warnings on the `column_types` keys in the yml file at `path`, a seed's properties or
+/// dbt_project.yml, that are a column of none of the seeds they apply to.
+pub fn seed_column_type_diagnostics(manifest: &ProjectManifest, path: &std::path::Path, encoding: PositionEncoding) -> Vec<Diagnostic> {
+    let is_project_file = path == manifest.root_dir.join("dbt_project.yml");
+    if !is_project_file && !manifest.seed_entries.iter().any(|e| e.path == path) {
+        return Vec::new();
+    }
+    let Ok(text) = std::fs::read_to_string(path) else { return Vec::new() };
+    let rope = Rope::from_str(&text);
+    let mut headers: std::collections::HashMap<String, Option<Vec<String>>> = std::collections::HashMap::new();
+    let mut diagnostics = Vec::new();
+    for key in crate::yaml::scan_keys(&text) {
+        let in_column_types = key.path.last().is_some_and(|p| p.trim_start_matches('+') == "column_types");
+        if !in_column_types || key.path.first().map(String::as_str) != Some("seeds") {
+            continue;
+        }
+        let seeds: Vec<String> = if is_project_file {
+            // `seeds: project: folder: ... [seed:] +column_types:` applies to the seeds below
+            if key.path.len() < 3 || key.path[1] != manifest.config.name {
+                continue;
+            }
+            let prefix = &key.path[2..key.path.len() - 1];
+            manifest.seeds.iter()
+                .filter(|s| {
+                    let mut segments = crate::relation::folders_under(s.value(), &manifest.root_dir, &manifest.config.seed_paths);
+                    segments.push(s.key().clone());
+                    segments.starts_with(prefix)
+                })
+                .map(|s| s.key().clone())
+                .collect()
+        } else {
+            key.path.get(1).cloned().into_iter().collect()
+        };
+        let seed_headers: Vec<Vec<String>> = seeds.iter()
+            .filter_map(|seed| headers.entry(seed.clone()).or_insert_with(|| {
+                manifest.seeds.get(seed).and_then(|p| crate::hover::read_seed_preview(&p)).map(|p| p.header)
+            }).clone())
+            .collect();
+        if seed_headers.is_empty() || seed_headers.iter().flatten().any(|c| c.eq_ignore_ascii_case(&key.key)) {
+            continue;
+        }
+        let message = match (seeds.as_slice(), seed_headers.as_slice()) {
+            ([seed], [header]) => format!("Seed '{}' has no column '{}' (its columns: {}).", seed, key.key, header.join(", ")),
+            _ => format!("None of the seeds this config applies to has a column '{}'.", key.key),
+        };
+        diagnostics.push(Diagnostic {
+            range: crate::position::line_span_to_range(&rope, key.line, key.key_column, key.key.len(), encoding),
+            severity: Some(DiagnosticSeverity::WARNING),
+            code: Some(NumberOrString::String(UNKNOWN_SEED_COLUMN.to_string())),
+            source: Some("dbt-lsp".to_string()),
+            message,
+            ..Diagnostic::default()
+        });
+    }
+    diagnostics
+}
+
+/// Errors on the generic tests listed in the yml file at `path` that resolve to neither a
+/// dbt builtin nor a `{% test %}` block of the project or an installed package.
+pub fn generic_test_diagnostics(manifest: &ProjectManifest, path: &std::path::Path, encoding: PositionEncoding) -> Vec<Diagnostic> {
+    let Some(tests) = manifest.generic_tests.get(path) else { return Vec::new() };
+    let mut diagnostics = Vec::new();
+    let mut rope = None;
+    for test in tests.iter().filter(|t| manifest.resolve_generic_test(&t.name).is_none()) {
+        let message = match test.name.split_once('.') {
+            Some((pkg, name)) if manifest.has_package(pkg) => format!("Package '{}' has no generic test '{}'.", pkg, name),
+            Some((pkg, name)) if manifest.declared_packages.contains_key(pkg) => {
+                format!("Test '{}' is from package '{}', which is declared but not installed — run `dbt deps`.", name, pkg)
+            }
+            Some((pkg, name)) => format!("Test '{}' is from package '{}', which is not installed.", name, pkg),
+            None => format!("Unknown generic test '{}': not a dbt builtin and no `{{% test {} %}}` block in the project or its packages.", test.name, test.name),
+        };
+        let rope = rope.get_or_insert_with(|| Rope::from_str(&std::fs::read_to_string(path).unwrap_or_default()));
+        diagnostics.push(Diagnostic {
+            range: crate::position::line_span_to_range(rope, test.line, test.column, test.name.len(), encoding),
+            severity: Some(DiagnosticSeverity::ERROR),
+            code: Some(NumberOrString::String(UNKNOWN_TEST.to_string())),
+            source: Some("dbt-lsp".to_string()),
+            message,
+            ..Diagnostic::default()
+        });
+    }
+    diagnostics
+}
+
+/// Stored in the `data` of [`UNKNOWN_MODEL`] diagnostics for the quick fixes.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct UnknownModel {
+    pub name: String,
+    /// Nearest existing names, best first.
+    #[serde(default)]
+    pub candidates: Vec<String>,
+}
+
+/// Stored in the `data` of [`AMBIGUOUS_REF`] diagnostics.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AmbiguousRef {
+    pub name: String,
+    /// The defining packages, the project first.
+    pub packages: Vec<String>,
+}
+
+/// Stored in the `data` of [`UNKNOWN_SOURCE_TABLE`] and [`UNKNOWN_SOURCE`] diagnostics.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct UnknownSourceTable {
+    pub source: String,
+    pub table: String,
+    #[serde(default)]
+    pub candidates: Vec<String>,
+}
+
+/// Stored in the `data` of [`UNKNOWN_COLUMN`] diagnostics.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct UnknownColumn {
+    /// The model (`orders`) or source table (`raw.orders`).
+    pub target: String,
+    pub column: String,
+    #[serde(default)]
+    pub candidates: Vec<String>,
+}
+
+/// Levenshtein distance between `a` and `b`, in chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb { diagonal } else { 1 + diagonal.min(above).min(row[j]) };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+/// Up to three of `names` close to `name`: within two edits, or starting with it.
+fn suggestions(name: &str, names: impl Iterator<Item = String>) -> Vec<String> {
+    let mut scored: Vec<(usize, String)> = names
+        .map(|candidate| (edit_distance(name, &candidate), candidate))
+        .filter(|(distance, candidate)| *distance <= 2 || candidate.starts_with(name))
+        .collect();
+    scored.sort();
+    scored.dedup();
+    scored.into_iter().take(3).map(|(_, candidate)| candidate).collect()
+}
+
 pub fn validate_refs(
     refs: &[(DbtRef, std::ops::Range<usize>)],
     manifest: Option<&ProjectManifest>,
     rope: &Rope,
-    _tree: Option<&tree_sitter::Tree>,
+    tree: Option<&tree_sitter::Tree>,
+    encoding: PositionEncoding,
+    settings: &Settings,
 ) -> (Vec<Diagnostic>, std::collections::HashMap<String, crate::state::CteDefinition>, std::collections::HashMap<String, crate::state::AliasDefinition>) {
     let mut diagnostics = Vec::new();
     let mut ctes = std::collections::HashMap::new();
@@ -54,68 +379,228 @@ pub fn validate_refs(
                 continue;
             }
             if let Some(source_match) = cap.name("source") {
-                if let Some(full_match) = cap.get(0) {
-                     let source_text = source_match.as_str();
-                     let target_name = extract_target_name(source_text);
-                     aliases.insert(alias, crate::state::AliasDefinition {
-                         reference_range: source_match.range(), // Point to "source" (e.g. "{{ ref(...) }}") not full "from ... w"
-                         target_name,
-                     });
-                }
+                let source_text = source_match.as_str();
+                let target_name = extract_target_name(source_text);
+                aliases.insert(alias, crate::state::AliasDefinition {
+                    reference_range: source_match.range(), // Point to "source" (e.g. "{{ ref(...) }}") not full "from ... w"
+                    target_name,
+                });
             }
         }
     }
 
-    let preprocessed = crate::jinja::preprocess_for_parsing(&text);
-    if let Err(e) = Parser::parse_sql(&BigQueryDialect {}, &preprocessed) {
-        if let Some(diag) = parse_sqlparser_error(e, rope) {
-            diagnostics.push(diag);
+    if settings.sql_diagnostics {
+        // A tree with errors may have lost the FROM that reads the CTE
+        if let Some(tree) = tree.filter(|t| !t.root_node().has_error()) {
+            diagnostics.extend(unused_cte_diagnostics(tree, &text, &ctes, rope, encoding));
+        }
+        let preprocessed = crate::jinja::preprocess_for_parsing(&text);
+        if let Err(e) = Parser::parse_sql(&*settings.sql_dialect(manifest.and_then(|m| m.target.as_ref())), &preprocessed) {
+            if let Some(diag) = parse_sqlparser_error(e, rope, encoding) {
+                diagnostics.push(diag);
+            }
         }
     }
 
     // 2. Ref Validation (Semantic)
     if let Some(manifest) = manifest {
-        for (dbt_ref, range) in refs {
-            let is_valid = match dbt_ref {
-                DbtRef::Model(name) => manifest.models.contains_key(name) || manifest.seeds.contains_key(name),
-                DbtRef::Source(src, tbl) => manifest.sources.contains_key(&format!("{}.{}", src, tbl)),
-                DbtRef::Macro(name) => manifest.macros.contains_key(name),
-            };
+        diagnostics.extend(ref_diagnostics(refs, manifest, rope, encoding));
+    }
 
-            if !is_valid {
-                let start_line = rope.byte_to_line(range.start);
-                let start_char = range.start - rope.line_to_byte(start_line);
-                let end_line = rope.byte_to_line(range.end);
-                let end_char = range.end - rope.line_to_byte(end_line);
+    if let (Some(manifest), Some(tree)) = (manifest, tree.filter(|t| !t.root_node().has_error())) {
+        diagnostics.extend(unknown_column_diagnostics(tree, &text, refs, manifest, rope, encoding));
+    }
 
-                let msg = match dbt_ref {
-                    DbtRef::Model(name) => format!("Model/Seed '{}' not found in project.", name),
-                    DbtRef::Source(s, t) => format!("Source '{}.{}' not found.", s, t),
-                    DbtRef::Macro(name) => format!("Macro '{}' not found in project.", name),
-                };
+    (diagnostics, ctes, aliases)
+}
 
+/// Errors on the refs, sources, macros, docs and vars in `refs` that the project lacks,
+/// and information on refs that several packages define.
+pub fn ref_diagnostics(refs: &[(DbtRef, std::ops::Range<usize>)], manifest: &ProjectManifest, rope: &Rope, encoding: PositionEncoding) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for (dbt_ref, range) in refs {
+        if let DbtRef::Model(name) = dbt_ref {
+            let packages = manifest.defining_packages(name);
+            if packages.len() > 1 {
+                diagnostics.push(Diagnostic {
+                    range: crate::position::byte_range_to_range(rope, range, encoding),
+                    severity: Some(DiagnosticSeverity::INFORMATION),
+                    code: Some(NumberOrString::String(AMBIGUOUS_REF.to_string())),
+                    source: Some("dbt-lsp".to_string()),
+                    message: format!("Model '{}' is defined in several packages ({}); qualify the ref to pick one.", name, packages.join(", ")),
+                    data: serde_json::to_value(AmbiguousRef { name: name.clone(), packages }).ok(),
+                    ..Diagnostic::default()
+                });
+            }
+        }
+        // dbt warns about the same at parse time
+        if let DbtRef::VersionedModel(name, version) = dbt_ref {
+            let latest = manifest.model_entries.get(name).and_then(|e| e.latest_version.clone());
+            let pinned = manifest.resolve_model_version(name, Some(version)).is_some_and(|m| manifest.has_ref_target(&m));
+            if let Some(latest) = latest.filter(|latest| pinned && crate::project::compare_versions(version, latest).is_lt()) {
                 diagnostics.push(Diagnostic {
-                    range: Range {
-                        start: Position::new(start_line as u32, start_char as u32),
-                        end: Position::new(end_line as u32, end_char as u32),
-                    },
-                    severity: Some(DiagnosticSeverity::ERROR),
-                    code: None,
-                    code_description: None,
+                    range: crate::position::byte_range_to_range(rope, range, encoding),
+                    severity: Some(DiagnosticSeverity::WARNING),
+                    code: Some(NumberOrString::String(OLD_MODEL_VERSION.to_string())),
                     source: Some("dbt-lsp".to_string()),
-                    message: msg,
-                    related_information: None,
-                    tags: None,
-                    data: None,
+                    message: format!("Ref pins version {} of model '{}', but the latest version is {}.", version, name, latest),
+                    ..Diagnostic::default()
                 });
             }
         }
+        let is_valid = match dbt_ref {
+            DbtRef::Model(name) => manifest.has_ref_target(name),
+            DbtRef::PackageModel(pkg, name) => manifest.resolve_package_model(pkg, name).is_some(),
+            DbtRef::VersionedModel(name, version) => manifest.resolve_model_version(name, Some(version)).is_some_and(|m| manifest.has_ref_target(&m)),
+            DbtRef::Source(src, tbl) => manifest.sources.contains_key(&format!("{}.{}", src, tbl)),
+            DbtRef::Macro(name) if manifest.is_indexed_macro_name(name) => manifest.resolve_macro(name).is_some(),
+            DbtRef::Macro(name) => missing_macro_package(manifest, name).is_none(),
+            DbtRef::Doc(name) => manifest.docs.contains_key(name),
+            DbtRef::Metric(name) => manifest.metrics.contains_key(name),
+            DbtRef::Var(name, default) => default.is_some() || manifest.var(name).is_some(),
+            // Resolved from the environment dbt runs in, which we can't see
+            DbtRef::This | DbtRef::EnvVar(..) => true,
+        };
+
+        if !is_valid {
+            let msg = match dbt_ref {
+                DbtRef::Model(name) if manifest.analyses.contains_key(name) => format!("'{}' is an analysis; analyses can't be ref'd.", name),
+                DbtRef::Model(name) => format!("Model/Seed '{}' not found in project.", name),
+                DbtRef::PackageModel(pkg, name) if manifest.has_package(pkg) => format!("Model '{}' not found in package '{}'.", name, pkg),
+                DbtRef::PackageModel(pkg, _) if manifest.declared_packages.contains_key(pkg) => {
+                    format!("Package '{}' is declared but not installed — run `dbt deps`.", pkg)
+                }
+                DbtRef::PackageModel(pkg, _) => format!("Package '{}' is not installed (not found in dbt_packages/).", pkg),
+                DbtRef::VersionedModel(name, _) if !manifest.has_ref_target(name) => format!("Model/Seed '{}' not found in project.", name),
+                DbtRef::VersionedModel(name, version) => match manifest.resolve_model_version(name, Some(version)) {
+                    Some(defined_in) => format!("Version {} of model '{}' is defined in '{}', which doesn't exist.", version, name, defined_in),
+                    None => {
+                        let versions: Vec<String> = manifest.model_entries.get(name)
+                            .map(|e| e.versions.iter().map(|v| v.version.clone()).collect())
+                            .unwrap_or_default();
+                        if versions.is_empty() {
+                            format!("Model '{}' is not versioned.", name)
+                        } else {
+                            format!("Model '{}' has no version {} (versions: {}).", name, version, versions.join(", "))
+                        }
+                    }
+                },
+                DbtRef::Source(s, t) => format!("Source '{}.{}' not found.", s, t),
+                DbtRef::Macro(name) => match name.split_once('.') {
+                    Some((pkg, rest)) if manifest.declared_packages.contains_key(pkg) && !manifest.has_package(pkg) => {
+                        format!("Macro '{}' is from package '{}', which is declared but not installed — run `dbt deps`.", rest, pkg)
+                    }
+                    Some((pkg, rest)) if !manifest.has_package(pkg) => {
+                        format!("Macro '{}' is from package '{}', which is not installed — add it to packages.yml and run `dbt deps`.", rest, pkg)
+                    }
+                    Some((pkg, rest)) if pkg != manifest.config.name => format!("Macro '{}' not found in package '{}'.", rest, pkg),
+                    _ => format!("Macro '{}' not found in project.", name),
+                },
+                DbtRef::Doc(name) => format!("Docs block '{}' not found in project.", name),
+                DbtRef::Metric(name) => format!("Metric '{}' not found in project.", name),
+                DbtRef::Var(name, _) => format!("Var '{}' is not defined in dbt_project.yml and has no default.", name),
+                DbtRef::This | DbtRef::EnvVar(..) => continue,
+            };
+            // The var may still be passed with --vars at run time
+            let severity = match dbt_ref {
+                DbtRef::Var(..) => DiagnosticSeverity::WARNING,
+                _ => DiagnosticSeverity::ERROR,
+            };
+
+            let (code, data) = match dbt_ref {
+                DbtRef::Model(name) | DbtRef::VersionedModel(name, _) if !manifest.has_ref_target(name) => {
+                    let names = manifest.models.iter().map(|m| m.key().clone())
+                        .chain(manifest.seeds.iter().map(|s| s.key().clone()))
+                        .chain(manifest.snapshots.iter().map(|s| s.key().clone()));
+                    let data = UnknownModel { name: name.clone(), candidates: suggestions(name, names) };
+                    (Some(UNKNOWN_MODEL), serde_json::to_value(data).ok())
+                }
+                DbtRef::Source(src, tbl) => {
+                    let tables = manifest.source_tables(src);
+                    if tables.is_empty() {
+                        let data = UnknownSourceTable { source: src.clone(), table: tbl.clone(), candidates: Vec::new() };
+                        (Some(UNKNOWN_SOURCE), serde_json::to_value(data).ok())
+                    } else {
+                        let data = UnknownSourceTable { source: src.clone(), table: tbl.clone(), candidates: suggestions(tbl, tables.into_iter()) };
+                        (Some(UNKNOWN_SOURCE_TABLE), serde_json::to_value(data).ok())
+                    }
+                }
+                DbtRef::VersionedModel(..) => (Some(UNKNOWN_MODEL_VERSION), None),
+                _ => (None, None),
+            };
+            let code = code.map(|c| NumberOrString::String(c.to_string()));
+
+            diagnostics.push(Diagnostic {
+                range: crate::position::byte_range_to_range(rope, range, encoding),
+                severity: Some(severity),
+                code,
+                code_description: None,
+                source: Some("dbt-lsp".to_string()),
+                message: msg,
+                related_information: None,
+                tags: None,
+                data,
+            });
+        }
     }
+    diagnostics
+}
 
-    (diagnostics, ctes, aliases)
+/// A warning on each `alias.column` whose alias reads a ref or source with a complete
+/// column list (catalog.json or a select list) that lacks the column. Columns only
+/// documented in yml may be partial, so they aren't checked against.
+fn unknown_column_diagnostics(
+    tree: &tree_sitter::Tree,
+    text: &str,
+    refs: &[(DbtRef, std::ops::Range<usize>)],
+    manifest: &ProjectManifest,
+    rope: &Rope,
+    encoding: PositionEncoding,
+) -> Vec<Diagnostic> {
+    let mut known: std::collections::HashMap<String, Option<(Vec<String>, crate::project::ColumnOrigin)>> = std::collections::HashMap::new();
+    let mut diagnostics = Vec::new();
+    for col in crate::columns::qualified_columns(tree, text) {
+        let target = refs.iter()
+            .find(|(_, range)| range.contains(&col.table_range.start))
+            .and_then(|(dbt_ref, _)| match dbt_ref {
+                DbtRef::Model(name) => Some(name.clone()),
+                DbtRef::VersionedModel(..) => manifest.ref_target_name(dbt_ref),
+                DbtRef::Source(src, tbl) => Some(format!("{}.{}", src, tbl)),
+                _ => None,
+            });
+        let Some(target) = target else { continue };
+        let columns = known.entry(target.clone()).or_insert_with(|| {
+            manifest.relation_columns(&target)
+                .filter(|(_, origin)| *origin != crate::project::ColumnOrigin::Documented)
+                .map(|(columns, origin)| (columns.into_iter().map(|c| c.name).collect(), origin))
+        });
+        let Some((names, origin)) = columns.as_ref() else { continue };
+        if names.iter().any(|n| n.eq_ignore_ascii_case(&col.column)) {
+            continue;
+        }
+        let from = match origin {
+            crate::project::ColumnOrigin::Catalog => "catalog.json",
+            _ => "its select list",
+        };
+        let data = UnknownColumn {
+            target: target.clone(),
+            column: col.column.clone(),
+            candidates: suggestions(&col.column.to_lowercase(), names.iter().map(|n| n.to_lowercase())),
+        };
+        diagnostics.push(Diagnostic {
+            range: crate::position::byte_range_to_range(rope, &col.range, encoding),
+            severity: Some(DiagnosticSeverity::WARNING),
+            code: Some(NumberOrString::String(UNKNOWN_COLUMN.to_string())),
+            source: Some("dbt-lsp".to_string()),
+            message: format!("Column '{}' not found in '{}' (according to {}).", col.column, target, from),
+            data: serde_json::to_value(data).ok(),
+            ..Diagnostic::default()
+        });
+    }
+    diagnostics
 }
 
-fn parse_sqlparser_error(err: sqlparser::parser::ParserError, _rope: &Rope) -> Option<Diagnostic> {
+fn parse_sqlparser_error(err: sqlparser::parser::ParserError, rope: &Rope, encoding: PositionEncoding) -> Option<Diagnostic> {
     let msg = format!("{}", err);
     
     // sqlparser errors can have various formats:
@@ -124,14 +609,20 @@ fn parse_sqlparser_error(err: sqlparser::parser::ParserError, _rope: &Rope) -> O
 
     let (mut line, mut col) = (0, 0);
     if let Some(cap) = re.captures(&msg) {
-        line = cap[1].parse::<u32>().unwrap_or(1).saturating_sub(1);
-        col = cap[2].parse::<u32>().unwrap_or(1).saturating_sub(1);
+        line = cap[1].parse::<usize>().unwrap_or(1).saturating_sub(1);
+        col = cap[2].parse::<usize>().unwrap_or(1).saturating_sub(1);
     }
 
+    // sqlparser counts columns in chars; convert so multi-byte text earlier on the line doesn't shift the marker
+    let line = line.min(rope.len_lines().saturating_sub(1));
+    let line_chars = rope.line(line).len_chars();
+    let start_char = rope.line_to_char(line) + col.min(line_chars);
+    let end_char = (start_char + 1).min(rope.len_chars()); // Highlight at least one char
+
     Some(Diagnostic {
         range: Range {
-            start: Position::new(line, col),
-            end: Position::new(line, col + 1), // Highlight at least one char
+            start: crate::position::char_to_position(rope, start_char, encoding),
+            end: crate::position::char_to_position(rope, end_char, encoding),
         },
         severity: Some(DiagnosticSeverity::ERROR),
         message: msg,
@@ -140,12 +631,34 @@ fn parse_sqlparser_error(err: sqlparser::parser::ParserError, _rope: &Rope) -> O
     })
 }
 
+/// A hint, faded as unnecessary, on each CTE no FROM or JOIN reads from.
+fn unused_cte_diagnostics(
+    tree: &tree_sitter::Tree,
+    text: &str,
+    ctes: &std::collections::HashMap<String, crate::state::CteDefinition>,
+    rope: &Rope,
+    encoding: PositionEncoding,
+) -> Vec<Diagnostic> {
+    let mut unused: Vec<crate::columns::CteOccurrences> = ctes.values()
+        .filter_map(|cte| crate::columns::cte_occurrences(tree, text, cte.name_range.start))
+        .filter(|occurrences| occurrences.usages.is_empty())
+        .collect();
+    unused.sort_by_key(|o| o.definition.start);
+    unused.into_iter().map(|o| Diagnostic {
+        range: crate::position::byte_range_to_range(rope, &o.definition, encoding),
+        severity: Some(DiagnosticSeverity::HINT),
+        code: Some(NumberOrString::String(UNUSED_CTE.to_string())),
+        source: Some("dbt-lsp".to_string()),
+        message: format!("CTE '{}' is never used.", o.name),
+        tags: Some(vec![DiagnosticTag::UNNECESSARY]),
+        ..Diagnostic::default()
+    }).collect()
+}
+
 fn find_closing_paren(text: &str, start_idx: usize) -> Option<usize> {
     let mut depth = 1;
     let mut in_quote = None;
-    let mut chars = text[start_idx..].char_indices();
-    
-    while let Some((idx, c)) = chars.next() {
+    for (idx, c) in text[start_idx..].char_indices() {
         if let Some(q) = in_quote {
             if c == q {
                 in_quote = None;
@@ -185,3 +698,16 @@ fn extract_target_name(source: &str) -> String {
     let cleaned = source.replace("{{", "").replace("}}", "").trim().to_string();
     cleaned.trim_matches(|c| c == '"' || c == '`').to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suggestions() {
+        let names = ["stg_payments", "stg_orders", "stg_payment_methods", "customers"].map(String::from);
+        assert_eq!(edit_distance("stg_payment", "stg_payments"), 1);
+        assert_eq!(suggestions("stg_payment", names.clone().into_iter()), vec!["stg_payments", "stg_payment_methods"]);
+        assert_eq!(suggestions("custmers", names.into_iter()), vec!["customers"]);
+    }
+}
@@ -0,0 +1,529 @@
+use crate::position::{byte_range_to_range, PositionEncoding};
+use crate::state::DocumentState;
+use regex::Regex;
+use ropey::Rope;
+use std::ops::Range;
+use std::sync::OnceLock;
+use tower_lsp::lsp_types::{FormattingOptions, TextEdit};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Word,
+    Str,
+    Punct,
+    LineComment,
+    BlockComment,
+    /// `{{ ... }}`, which stands in for a value or identifier.
+    JinjaExpr,
+    /// `{% ... %}` and `{# ... #}`, which the printer keeps on their own lines.
+    JinjaStmt,
+}
+
+#[derive(Debug, Clone)]
+struct Token<'a> {
+    text: &'a str,
+    kind: Kind,
+    newlines_before: usize,
+    space_before: bool,
+}
+
+/// Reserved words lowercased by the formatter. Words that are also common column names
+/// are left alone.
+const KEYWORDS: &[&str] = &[
+    "select", "from", "where", "group", "order", "by", "having", "limit", "qualify", "join", "left",
+    "right", "inner", "full", "outer", "cross", "natural", "on", "using", "as", "and", "or", "not",
+    "in", "is", "null", "case", "when", "then", "else", "end", "distinct", "union", "all",
+    "intersect", "except", "with", "recursive", "over", "partition", "between", "like", "ilike",
+    "exists", "asc", "desc", "true", "false", "interval", "lateral",
+];
+
+const JOIN_MODIFIERS: &[&str] = &["left", "right", "inner", "full", "outer", "cross", "natural"];
+
+const OPERATORS: &[&str] = &["::", "<=", ">=", "<>", "!=", "||", "=>", "->"];
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$' || c == '@'
+}
+
+/// Splits `text` into SQL tokens, keeping jinja tags, comments and strings whole.
+/// None when a tag, comment or string is left open.
+fn tokenize(text: &str) -> Option<Vec<Token<'_>>> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    loop {
+        let ws_len = text[pos..].len() - text[pos..].trim_start().len();
+        let whitespace = &text[pos..pos + ws_len];
+        pos += ws_len;
+        let rest = &text[pos..];
+        let Some(first) = rest.chars().next() else { break };
+
+        let (len, kind) = if rest.starts_with("{{") {
+            (rest.find("}}")? + 2, Kind::JinjaExpr)
+        } else if rest.starts_with("{%") {
+            (rest.find("%}")? + 2, Kind::JinjaStmt)
+        } else if rest.starts_with("{#") {
+            (rest.find("#}")? + 2, Kind::JinjaStmt)
+        } else if rest.starts_with("--") || first == '#' {
+            let line = &rest[..rest.find('\n').unwrap_or(rest.len())];
+            (line.trim_end().len(), Kind::LineComment)
+        } else if rest.starts_with("/*") {
+            (rest.find("*/")? + 2, Kind::BlockComment)
+        } else if matches!(first, '\'' | '"' | '`') {
+            let bytes = rest.as_bytes();
+            let mut i = 1;
+            loop {
+                match bytes.get(i)? {
+                    b'\\' => i += 2,
+                    b if *b == first as u8 => break,
+                    _ => i += 1,
+                }
+            }
+            (i + 1, Kind::Str)
+        } else if is_word_char(first) {
+            (rest.find(|c: char| !is_word_char(c)).unwrap_or(rest.len()), Kind::Word)
+        } else {
+            let len = OPERATORS.iter().find(|op| rest.starts_with(**op)).map_or(first.len_utf8(), |op| op.len());
+            (len, Kind::Punct)
+        };
+        tokens.push(Token {
+            text: &rest[..len],
+            kind,
+            newlines_before: whitespace.matches('\n').count(),
+            space_before: !whitespace.is_empty(),
+        });
+        pos += len;
+    }
+    Some(tokens)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Clause {
+    None,
+    Select,
+    With,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FrameKind {
+    /// A parenthesised subquery or CTE body, printed as an indented block.
+    Block,
+    Inline,
+    /// `case ... end`; `breaking` puts each `when`/`else` on its own line.
+    Case { breaking: bool },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    kind: FrameKind,
+    indent: usize,
+    line_base: usize,
+    clause: Clause,
+}
+
+struct Printer<'a> {
+    unit: &'a str,
+    out: String,
+    /// Indent of clause keywords at the current nesting level.
+    indent: usize,
+    /// Indent of the current logical line; broken continuation lines go one deeper.
+    line_base: usize,
+    clause: Clause,
+    stack: Vec<Frame>,
+    /// The next token starts a select-list item.
+    need_item: bool,
+    /// The next token must start a line at this indent.
+    pending: Option<usize>,
+    /// The previous token was a comment or jinja tag on a line of its own.
+    neutral_prev: bool,
+    last_word: String,
+}
+
+impl<'a> Printer<'a> {
+    fn new(unit: &'a str, base: usize) -> Self {
+        Printer {
+            unit,
+            out: String::new(),
+            indent: base,
+            line_base: base,
+            clause: Clause::None,
+            stack: Vec::new(),
+            need_item: false,
+            pending: None,
+            neutral_prev: false,
+            last_word: String::new(),
+        }
+    }
+
+    /// Clause keywords only break lines at statement level, not inside inline parens
+    /// (`extract(day from x)`, `over (order by y)`) or case expressions.
+    fn statement_level(&self) -> bool {
+        self.stack.last().is_none_or(|f| f.kind == FrameKind::Block)
+    }
+
+    fn clause_start(&self, word: &str, next: Option<&Token>) -> Option<Clause> {
+        // The last part of a qualified name (`a.end`, `t.from`) is a column, not a keyword
+        if self.last_word == "." {
+            return None;
+        }
+        let next_lower = next.map(|t| t.text.to_ascii_lowercase()).unwrap_or_default();
+        // Functions that share a name with a keyword: left(x, 2), `* except(col)`
+        if next_lower == "(" && matches!(word, "left" | "right" | "except") {
+            return None;
+        }
+        match word {
+            "select" => Some(Clause::Select),
+            "with" if self.clause == Clause::None => Some(Clause::With),
+            "from" | "where" | "having" | "qualify" | "limit" | "window" | "union" | "intersect" | "except" => Some(Clause::Other),
+            "group" | "order" if next_lower == "by" => Some(Clause::Other),
+            "join" | "left" | "right" | "inner" | "full" | "cross" | "natural" if !JOIN_MODIFIERS.contains(&self.last_word.as_str()) => Some(Clause::Other),
+            _ => None,
+        }
+    }
+
+    fn newline(&mut self, indent: usize, blank: bool) {
+        let trimmed = self.out.trim_end_matches([' ', '\t']).len();
+        self.out.truncate(trimmed);
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+        if blank && !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+        self.out.push_str(&self.unit.repeat(indent));
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.out.trim_end_matches([' ', '\t']).ends_with('\n') || self.out.trim().is_empty()
+    }
+
+    fn push(&mut self, tokens: &[Token], i: usize) {
+        let tok = &tokens[i];
+        let lower = tok.text.to_ascii_lowercase();
+        let next = tokens.get(i + 1);
+        let prev = i.checked_sub(1).map(|p| &tokens[p]);
+        let own_line = tok.newlines_before > 0 && i > 0;
+        let mut break_to: Option<usize> = None;
+        let mut neutral = false;
+        let mut starts_select = false;
+
+        match tok.kind {
+            Kind::Word => {
+                let clause = if self.statement_level() { self.clause_start(&lower, next) } else { None };
+                if let Some(clause) = clause {
+                    break_to = Some(self.indent);
+                    self.line_base = self.indent;
+                    self.clause = clause;
+                    self.need_item = false;
+                    starts_select = clause == Clause::Select;
+                } else if matches!(lower.as_str(), "when" | "else" | "end") && self.last_word != "." {
+                    if let Some(frame) = self.stack.last().copied().filter(|f| matches!(f.kind, FrameKind::Case { .. })) {
+                        let breaking = frame.kind == FrameKind::Case { breaking: true };
+                        if lower == "end" {
+                            self.stack.pop();
+                            self.line_base = frame.line_base;
+                            break_to = breaking.then_some(frame.line_base);
+                        } else if breaking {
+                            break_to = Some(frame.line_base + 1);
+                            self.line_base = frame.line_base + 1;
+                        }
+                    }
+                }
+            }
+            Kind::LineComment | Kind::JinjaStmt if own_line => neutral = true,
+            _ => {}
+        }
+
+        // Select items, unless the token continues the previous one
+        let continues = matches!(tok.text, "," | ")" | ";" | ".") || (lower == "distinct" && self.last_word == "select");
+        if self.need_item && break_to.is_none() && !continues {
+            // A tag glued to the previous item (`{% if not loop.last %},{% endif %}`) stays on its line
+            if neutral || matches!(tok.kind, Kind::LineComment | Kind::JinjaStmt) {
+                if own_line {
+                    break_to = Some(self.indent + 1);
+                }
+            } else {
+                break_to = Some(self.indent + 1);
+                self.line_base = self.indent + 1;
+                self.need_item = false;
+            }
+        }
+        if break_to.is_none() {
+            break_to = self.pending;
+        }
+        if break_to.is_none() && own_line && !continues {
+            break_to = Some(if neutral || self.neutral_prev { self.line_base } else { self.line_base + 1 });
+        }
+        self.pending = None;
+
+        let text = if tok.kind == Kind::Word && KEYWORDS.contains(&lower.as_str())
+            && prev.is_none_or(|p| p.text != ".") && next.is_none_or(|n| n.text != ".") {
+            lower.clone()
+        } else {
+            tok.text.to_string()
+        };
+
+        match break_to {
+            Some(indent) => self.newline(indent, tok.newlines_before >= 2),
+            None if self.at_line_start() => {}
+            None => {
+                // Jinja tags keep whatever spacing they had after a comma
+                let after_comma = prev.is_some_and(|p| p.text == ",") && tok.kind != Kind::JinjaStmt;
+                let before_separator = matches!(tok.text, "," | ";");
+                if (tok.space_before || after_comma) && !before_separator {
+                    self.out.push(' ');
+                }
+            }
+        }
+        self.out.push_str(&text);
+
+        match (tok.kind, tok.text) {
+            (Kind::Punct, "(") => {
+                let next_word = tokens[i + 1..].iter()
+                    .find(|t| !matches!(t.kind, Kind::LineComment | Kind::BlockComment))
+                    .map(|t| t.text.to_ascii_lowercase());
+                let subquery = matches!(next_word.as_deref(), Some("select" | "with"));
+                let cte_body = self.statement_level() && self.clause == Clause::With && self.last_word == "as";
+                let frame = Frame { kind: FrameKind::Inline, indent: self.indent, line_base: self.line_base, clause: self.clause };
+                if subquery || cte_body {
+                    self.stack.push(Frame { kind: FrameKind::Block, ..frame });
+                    self.indent += 1;
+                    self.line_base = self.indent;
+                    self.clause = Clause::None;
+                    self.pending = Some(self.indent);
+                } else {
+                    self.stack.push(frame);
+                }
+            }
+            (Kind::Punct, ")") => {
+                while let Some(frame) = self.stack.pop() {
+                    if matches!(frame.kind, FrameKind::Case { .. }) {
+                        continue;
+                    }
+                    if frame.kind == FrameKind::Block {
+                        // Move the paren onto its own line at the block's outer indent
+                        let paren = self.out.pop();
+                        self.newline(frame.indent, false);
+                        self.out.extend(paren);
+                        self.indent = frame.indent;
+                        self.clause = frame.clause;
+                    }
+                    self.line_base = frame.line_base;
+                    break;
+                }
+            }
+            (Kind::Punct, ",") if self.statement_level() && self.clause == Clause::Select => self.need_item = true,
+            (Kind::Punct, ",") if self.statement_level() && self.clause == Clause::With => self.pending = Some(self.indent),
+            (Kind::Punct, ";") if self.stack.is_empty() => {
+                self.clause = Clause::None;
+                self.pending = Some(self.indent);
+            }
+            (Kind::Word, _) if lower == "case" => {
+                let breaking = self.statement_level();
+                self.stack.push(Frame { kind: FrameKind::Case { breaking }, indent: self.indent, line_base: self.line_base, clause: self.clause });
+            }
+            (Kind::LineComment, _) => {
+                self.pending = Some(if neutral { self.line_base } else { self.line_base + 1 });
+            }
+            _ => {}
+        }
+
+        if starts_select {
+            self.need_item = true;
+        }
+        self.neutral_prev = neutral;
+        if !matches!(tok.kind, Kind::LineComment | Kind::BlockComment | Kind::JinjaStmt) {
+            self.last_word = lower;
+        }
+    }
+}
+
+/// Token streams match when only whitespace and keyword case differ, and every line
+/// comment still ends its line.
+fn same_tokens(before: &[Token], after: &[Token]) -> bool {
+    before.len() == after.len()
+        && before.iter().zip(after).all(|(a, b)| {
+            a.kind == b.kind && if a.kind == Kind::Word { a.text.eq_ignore_ascii_case(b.text) } else { a.text == b.text }
+        })
+        && after.windows(2).all(|w| w[0].kind != Kind::LineComment || w[1].newlines_before > 0)
+}
+
+/// One indent level as configured by the client.
+fn indent_unit(options: &FormattingOptions) -> String {
+    if options.insert_spaces {
+        " ".repeat(options.tab_size as usize)
+    } else {
+        "\t".to_string()
+    }
+}
+
+/// Formats the SQL in `text` around its jinja, starting at indent level `base`: lowercase
+/// keywords, one clause per line, one select item per line and indented CTE bodies and
+/// subqueries. Jinja tags are copied verbatim and lines end in `newline`. None when the text
+/// can't be tokenized or the result wouldn't keep every token (jinja included) in order.
+fn format_at(text: &str, unit: &str, base: usize, newline: &str) -> Option<String> {
+    let text = text.replace("\r\n", "\n");
+    let tokens = tokenize(&text)?;
+    let mut printer = Printer::new(unit, base);
+    for i in 0..tokens.len() {
+        printer.push(&tokens, i);
+    }
+    let mut out = printer.out.trim_end().to_string();
+    if text.ends_with('\n') {
+        out.push('\n');
+    }
+    same_tokens(&tokens, &tokenize(&out)?).then(|| out.replace('\n', newline))
+}
+
+/// The line ending `text` uses: CRLF when its first line ends in one.
+fn line_ending(text: &str) -> &'static str {
+    match text.find('\n') {
+        Some(i) if text[..i].ends_with('\r') => "\r\n",
+        _ => "\n",
+    }
+}
+
+/// Formats a whole model file. See [`format_at`].
+pub fn format_document(text: &str, options: &FormattingOptions) -> Option<String> {
+    format_at(text, &indent_unit(options), 0, line_ending(text))
+}
+
+fn re_jinja_tag() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"(?s)\{\{.*?\}\}|\{%.*?%\}|\{#.*?#\}").unwrap())
+}
+
+/// Grows `range` until no jinja tag or if/for/macro block straddles its edges. None
+/// when it would contain a block tag without its partner.
+fn expand_over_jinja(text: &str, mut range: Range<usize>) -> Option<Range<usize>> {
+    let (blocks, unmatched) = crate::symbols::block_ranges(text);
+    let tags: Vec<Range<usize>> = re_jinja_tag().find_iter(text).map(|m| m.range()).collect();
+    loop {
+        let straddling = blocks.iter().chain(&tags)
+            .find(|b| b.start < range.end && range.start < b.end && !(range.start <= b.start && b.end <= range.end));
+        match straddling {
+            Some(b) => range = range.start.min(b.start)..range.end.max(b.end),
+            None => break,
+        }
+    }
+    (!unmatched.iter().any(|tag| range.start <= tag.start && tag.end <= range.end)).then_some(range)
+}
+
+/// Indent level of the line containing `byte_idx`, counting a tab or `tab_size` spaces
+/// as one level, and the line's leading whitespace.
+fn line_indent<'t>(text: &'t str, byte_idx: usize, options: &FormattingOptions) -> (usize, &'t str) {
+    let line_start = text[..byte_idx].rfind('\n').map_or(0, |i| i + 1);
+    let line = &text[line_start..];
+    let leading = &line[..line.len() - line.trim_start_matches([' ', '\t']).len()];
+    let spaces = leading.chars().filter(|c| *c == ' ').count();
+    let tabs = leading.chars().filter(|c| *c == '\t').count();
+    (tabs + spaces / options.tab_size.max(1) as usize, leading)
+}
+
+/// Formats the smallest CTE body or top-level statement containing `range`, widened over
+/// any jinja block it cuts through. Returns the byte range replaced and its new text.
+/// None when no such region exists or it can't be formatted safely.
+pub fn format_range(doc: &DocumentState, range: Range<usize>, options: &FormattingOptions) -> Option<(Range<usize>, String)> {
+    let text = doc.text.to_string();
+    let covers = |r: &Range<usize>| r.start <= range.start && range.end <= r.end;
+    let cte_body = doc.ctes.values().map(|c| c.body_range.clone()).filter(covers).min_by_key(|r| r.len());
+    let statement = doc.tree.as_ref().and_then(|tree| {
+        let root = tree.root_node();
+        let mut cursor = root.walk();
+        let found = root.named_children(&mut cursor).map(|n| n.byte_range()).find(covers);
+        found
+    });
+    let unit = indent_unit(options);
+    let newline = line_ending(&text);
+
+    if let Some(body) = cte_body.filter(|b| statement.as_ref().is_none_or(|s| s.len() >= b.len())) {
+        let expanded = expand_over_jinja(&text, body.clone())?;
+        if expanded != body {
+            return None;
+        }
+        let (outer, leading) = line_indent(&text, body.start, options);
+        let formatted = format_at(text[body.clone()].trim(), &unit, outer + 1, newline)?;
+        let replacement = format!("{}{}{}{}{}", newline, unit.repeat(outer + 1), formatted.trim_start(), newline, leading);
+        return Some((body, replacement));
+    }
+
+    let statement = expand_over_jinja(&text, statement?)?;
+    let slice = &text[statement.clone()];
+    let trimmed = slice.trim_start().len();
+    let start = statement.start + slice.len() - trimmed;
+    let end = start + slice.trim().len();
+    let formatted = format_at(&text[start..end], &unit, 0, newline)?;
+    Some((start..end, formatted.trim_start().to_string()))
+}
+
+/// A single edit turning `rope[range]` into `replacement`, trimmed to the part that
+/// actually changes. None when nothing does.
+pub fn minimal_edit(rope: &Rope, range: std::ops::Range<usize>, replacement: &str, encoding: PositionEncoding) -> Option<TextEdit> {
+    let original = rope.byte_slice(range.clone()).to_string();
+    let prefix = original.char_indices().zip(replacement.chars())
+        .find(|((_, a), b)| a != b)
+        .map_or(original.len().min(replacement.len()), |((i, _), _)| i);
+    let suffix = original[prefix..].chars().rev().zip(replacement[prefix..].chars().rev())
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a.len_utf8())
+        .sum::<usize>();
+    if prefix == original.len() && original.len() == replacement.len() {
+        return None;
+    }
+    let changed = range.start + prefix..range.end - suffix;
+    Some(TextEdit {
+        range: byte_range_to_range(rope, &changed, encoding),
+        new_text: replacement[prefix..replacement.len() - suffix].to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> FormattingOptions {
+        FormattingOptions { tab_size: 4, insert_spaces: true, ..Default::default() }
+    }
+
+    #[test]
+    fn test_format_model() {
+        let text = "{{ config(materialized='table') }}\nWITH orders AS (SELECT id, amount FROM {{ ref('orders') }} WHERE amount > 0),\n\npayments as (select * from {{ source('raw', 'payments') }})\nSELECT o.id, CASE WHEN p.id IS NULL THEN 0 ELSE 1 END AS paid, sum(o.amount) as total -- note\nFROM orders o LEFT JOIN payments p ON o.id = p.order_id\n{% if is_incremental() %}\nwhere o.id > (select max(id) from {{ this }})\n{% endif %}\ngroup by 1, 2\n";
+        let expected = "{{ config(materialized='table') }}\nwith orders as (\n    select\n        id,\n        amount\n    from {{ ref('orders') }}\n    where amount > 0\n),\n\npayments as (\n    select\n        *\n    from {{ source('raw', 'payments') }}\n)\nselect\n    o.id,\n    case\n        when p.id is null then 0\n        else 1\n    end as paid,\n    sum(o.amount) as total -- note\nfrom orders o\nleft join payments p on o.id = p.order_id\n{% if is_incremental() %}\nwhere o.id > (\n    select\n        max(id)\n    from {{ this }}\n)\n{% endif %}\ngroup by 1, 2\n";
+        let formatted = format_document(text, &options()).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format_document(&formatted, &options()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_tabs_and_glued_jinja() {
+        let tabs = FormattingOptions { tab_size: 4, insert_spaces: false, ..Default::default() };
+        assert_eq!(format_document("select a, b{{ suffix }} from t", &tabs).unwrap(), "select\n\ta,\n\tb{{ suffix }}\nfrom t");
+    }
+
+    #[test]
+    fn test_keyword_column_names_and_glued_tags() {
+        assert_eq!(format_document("select a.end, a.from from t", &options()).unwrap(), "select\n    a.end,\n    a.from\nfrom t");
+        let text = "select\n    {% for c in cols %}\n    {{ c }}{% if not loop.last %},{% endif %}\n    {% endfor %}\nfrom t\n";
+        assert_eq!(format_document(text, &options()).unwrap(), text);
+    }
+
+    #[test]
+    fn test_keeps_crlf() {
+        assert_eq!(format_document("SELECT a, b\r\nFROM t\r\n", &options()).unwrap(), "select\r\n    a,\r\n    b\r\nfrom t\r\n");
+    }
+
+    #[test]
+    fn test_minimal_edit() {
+        let rope = Rope::from_str("select a\nFROM t\n");
+        let edit = minimal_edit(&rope, 0..rope.len_bytes(), "select a\nfrom t\n", PositionEncoding::Utf16).unwrap();
+        assert_eq!((edit.range.start.line, edit.range.start.character, edit.range.end.character), (1, 0, 4));
+        assert_eq!(edit.new_text, "from");
+        assert_eq!(minimal_edit(&rope, 0..rope.len_bytes(), "select a\nFROM t\n", PositionEncoding::Utf16), None);
+    }
+
+    #[test]
+    fn test_refuses_unclosed_jinja() {
+        assert_eq!(format_document("select {{ ref('a') from t", &options()), None);
+        assert_eq!(format_document("select 'open from t", &options()), None);
+    }
+}
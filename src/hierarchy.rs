@@ -0,0 +1,256 @@
+use crate::jinja::DbtRef;
+use crate::position::{byte_range_to_range, PositionEncoding};
+use crate::project::ProjectManifest;
+use crate::state::DocumentState;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tower_lsp::lsp_types::{CallHierarchyIncomingCall, CallHierarchyItem, CallHierarchyOutgoingCall, Position, Range, SymbolKind, Url};
+
+/// A node of the model DAG.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DagNode {
+    Model { name: String },
+    Seed { name: String },
+    Snapshot { name: String },
+    /// A singular test. Tests aren't ref'd, so they only appear downstream.
+    Test { name: String },
+    Source { source: String, table: String },
+    /// An exposure from yml. Nothing depends on exposures, so they only appear downstream.
+    Exposure { name: String },
+}
+
+/// Stored in `CallHierarchyItem.data` so incoming/outgoing calls can find the node again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeData {
+    /// Root of the project the node belongs to.
+    pub root: PathBuf,
+    #[serde(flatten)]
+    pub node: DagNode,
+}
+
+impl DagNode {
+    /// The node a ref or source call points at, if the manifest knows it.
+    pub fn from_ref(manifest: &ProjectManifest, dbt_ref: &DbtRef) -> Option<Self> {
+        let resolved = manifest.ref_target_name(dbt_ref);
+        let name = match dbt_ref {
+            DbtRef::Model(_) | DbtRef::VersionedModel(..) => resolved.as_ref()?,
+            DbtRef::PackageModel(pkg, name) if *pkg == manifest.config.name => name,
+            DbtRef::Source(src, tbl) => {
+                manifest.sources.get(&format!("{}.{}", src, tbl))?;
+                return Some(DagNode::Source { source: src.clone(), table: tbl.clone() });
+            }
+            _ => return None,
+        };
+        if manifest.models.contains_key(name) {
+            Some(DagNode::Model { name: name.clone() })
+        } else if manifest.seeds.contains_key(name) {
+            Some(DagNode::Seed { name: name.clone() })
+        } else if manifest.snapshots.contains_key(name) {
+            Some(DagNode::Snapshot { name: name.clone() })
+        } else {
+            None
+        }
+    }
+
+    /// The node a manifest.json unique id such as `model.shop.orders` or
+    /// `source.shop.raw.orders` names, if it is still in the project.
+    pub fn from_unique_id(manifest: &ProjectManifest, unique_id: &str) -> Option<Self> {
+        let mut parts = unique_id.splitn(3, '.');
+        let (kind, package, rest) = (parts.next()?, parts.next()?, parts.next()?);
+        if package != manifest.config.name {
+            return None;
+        }
+        let dbt_ref = match kind {
+            "source" => {
+                let (src, tbl) = rest.split_once('.')?;
+                DbtRef::Source(src.to_string(), tbl.to_string())
+            }
+            "model" | "seed" | "snapshot" => DbtRef::Model(rest.to_string()),
+            _ => return None,
+        };
+        Self::from_ref(manifest, &dbt_ref)
+    }
+
+    /// The model, snapshot or singular test defined by the file at `path`.
+    pub fn for_file(manifest: &ProjectManifest, path: &Path) -> Option<Self> {
+        if let Some(name) = manifest.model_name_for_path(path) {
+            return Some(DagNode::Model { name });
+        }
+        if let Some(test) = manifest.singular_tests.iter().find(|t| t.value() == path) {
+            return Some(DagNode::Test { name: test.key().clone() });
+        }
+        manifest.snapshots.iter()
+            .find(|s| s.path == path)
+            .map(|s| DagNode::Snapshot { name: s.key().clone() })
+    }
+
+    /// The file defining the node, and the zero-based line and column of its definition.
+    pub fn location(&self, manifest: &ProjectManifest) -> Option<(PathBuf, usize, usize)> {
+        match self {
+            DagNode::Model { name } => manifest.models.get(name).map(|p| (p.value().clone(), 0, 0)),
+            DagNode::Seed { name } => manifest.seeds.get(name).map(|p| (p.value().clone(), 0, 0)),
+            DagNode::Snapshot { name } => manifest.snapshots.get(name).map(|s| (s.path.clone(), s.line, 0)),
+            DagNode::Test { name } => manifest.singular_tests.get(name).map(|p| (p.value().clone(), 0, 0)),
+            DagNode::Source { source, table } => manifest.sources.get(&format!("{}.{}", source, table))
+                .map(|s| (s.path.clone(), s.line, s.column)),
+            DagNode::Exposure { name } => manifest.exposures.get(name).map(|e| (e.path.clone(), e.line, e.column)),
+        }
+    }
+}
+
+/// The call hierarchy item for `node`. Sources and exposures point at their yml entry;
+/// an exposure's detail has its type and owner.
+pub fn item(manifest: &ProjectManifest, documents: &DashMap<Url, DocumentState>, node: &DagNode, encoding: PositionEncoding) -> Option<CallHierarchyItem> {
+    let (path, line, column) = node.location(manifest)?;
+    let (name, kind, detail) = match node {
+        DagNode::Model { name } => (name.clone(), SymbolKind::FILE, "model".to_string()),
+        DagNode::Seed { name } => (name.clone(), SymbolKind::FILE, "seed".to_string()),
+        DagNode::Snapshot { name } => (name.clone(), SymbolKind::FILE, "snapshot".to_string()),
+        DagNode::Test { name } => (name.clone(), SymbolKind::FILE, "test".to_string()),
+        DagNode::Source { source, table } => (format!("{}.{}", source, table), SymbolKind::STRUCT, "source".to_string()),
+        DagNode::Exposure { name } => {
+            let exposure = manifest.exposures.get(name)?;
+            let detail = std::iter::once("exposure")
+                .chain(exposure.exposure_type.as_deref())
+                .chain(exposure.owner.as_deref())
+                .collect::<Vec<_>>()
+                .join(" · ");
+            (name.clone(), SymbolKind::INTERFACE, detail)
+        }
+    };
+    // Only yml entries have a column, which the file's text converts to the client's encoding
+    let position = match column {
+        0 => Position::new(line as u32, 0),
+        _ => crate::position::file_span_to_range(documents, &path, line, column, 0, encoding).start,
+    };
+    let data = NodeData { root: manifest.root_dir.clone(), node: node.clone() };
+    Some(CallHierarchyItem {
+        name,
+        kind,
+        tags: None,
+        detail: Some(detail),
+        uri: Url::from_file_path(&path).ok()?,
+        range: Range::new(position, position),
+        selection_range: Range::new(position, position),
+        data: serde_json::to_value(data).ok(),
+    })
+}
+
+/// The refs and sources of the node's file, or of an exposure's `depends_on`.
+fn node_refs(manifest: &ProjectManifest, node: &DagNode) -> Option<crate::project::IndexedFile> {
+    match node {
+        DagNode::Exposure { name } => manifest.exposure_file(name),
+        DagNode::Model { .. } | DagNode::Snapshot { .. } | DagNode::Test { .. } => {
+            let (path, _, _) = node.location(manifest)?;
+            manifest.references.get(&path).map(|file| file.clone())
+        }
+        _ => None,
+    }
+}
+
+/// The models, seeds, snapshots and sources `node` refs (an exposure: depends on), each
+/// with its call sites in the node's file, in order of first use. Seeds and sources are
+/// leaves. Needs the reference index.
+pub fn outgoing_calls(manifest: &ProjectManifest, documents: &DashMap<Url, DocumentState>, node: &DagNode, encoding: PositionEncoding) -> Vec<CallHierarchyOutgoingCall> {
+    let Some(file) = node_refs(manifest, node) else { return Vec::new() };
+
+    let mut calls: Vec<(DagNode, Vec<Range>)> = Vec::new();
+    for (dbt_ref, span) in &file.refs {
+        let Some(target) = DagNode::from_ref(manifest, dbt_ref) else { continue };
+        let range = byte_range_to_range(&file.text, span, encoding);
+        match calls.iter_mut().find(|(n, _)| *n == target) {
+            Some((_, ranges)) => ranges.push(range),
+            None => calls.push((target, vec![range])),
+        }
+    }
+    calls.sort_by_key(|(_, ranges)| ranges[0].start);
+    calls.into_iter()
+        .filter_map(|(target, from_ranges)| Some(CallHierarchyOutgoingCall { to: item(manifest, documents, &target, encoding)?, from_ranges }))
+        .collect()
+}
+
+/// The models, snapshots, singular tests and exposures that ref `node`, each with its call
+/// sites in that file, ordered by path. Needs the reference index.
+pub fn incoming_calls(manifest: &ProjectManifest, documents: &DashMap<Url, DocumentState>, node: &DagNode, encoding: PositionEncoding) -> Vec<CallHierarchyIncomingCall> {
+    let mut calls: Vec<(PathBuf, CallHierarchyIncomingCall)> = Vec::new();
+    for file in manifest.references.iter() {
+        let from_ranges: Vec<Range> = file.refs.iter()
+            .filter(|(dbt_ref, _)| DagNode::from_ref(manifest, dbt_ref).as_ref() == Some(node))
+            .map(|(_, span)| byte_range_to_range(&file.text, span, encoding))
+            .collect();
+        if from_ranges.is_empty() {
+            continue;
+        }
+        let Some(from) = DagNode::for_file(manifest, file.key()).and_then(|n| item(manifest, documents, &n, encoding)) else { continue };
+        calls.push((file.key().clone(), CallHierarchyIncomingCall { from, from_ranges }));
+    }
+    for (exposure, path) in dependent_exposures(manifest, node) {
+        let Some(file) = manifest.exposure_file(&exposure) else { continue };
+        let from_ranges: Vec<Range> = file.refs.iter()
+            .filter(|(dbt_ref, _)| DagNode::from_ref(manifest, dbt_ref).as_ref() == Some(node))
+            .map(|(_, span)| byte_range_to_range(&file.text, span, encoding))
+            .collect();
+        let Some(from) = item(manifest, documents, &DagNode::Exposure { name: exposure }, encoding) else { continue };
+        calls.push((path, CallHierarchyIncomingCall { from, from_ranges }));
+    }
+    calls.sort_by(|a, b| a.0.cmp(&b.0));
+    calls.into_iter().map(|(_, call)| call).collect()
+}
+
+/// The nodes `node` refs, once each in order of first use. Needs the reference index, or
+/// a current manifest.json.
+pub fn upstream(manifest: &ProjectManifest, node: &DagNode) -> Vec<DagNode> {
+    if let DagNode::Exposure { name } = node {
+        let mut nodes: Vec<DagNode> = Vec::new();
+        for (dbt_ref, _) in manifest.exposures.get(name).map(|e| e.depends_on.clone()).unwrap_or_default() {
+            if let Some(target) = DagNode::from_ref(manifest, &dbt_ref).filter(|t| !nodes.contains(t)) {
+                nodes.push(target);
+            }
+        }
+        return nodes;
+    }
+    if !matches!(node, DagNode::Model { .. } | DagNode::Snapshot { .. } | DagNode::Test { .. }) {
+        return Vec::new();
+    }
+    let Some((path, _, _)) = node.location(manifest) else { return Vec::new() };
+    let Some(file) = manifest.references.get(&path) else {
+        // Until the reference index has the file, manifest.json's edges are used if current
+        return manifest.artifact.as_ref()
+            .filter(|artifact| artifact.covers(&path))
+            .and_then(|artifact| artifact.node_for_file(&path))
+            .map(|n| n.depends_on.iter().filter_map(|id| DagNode::from_unique_id(manifest, id)).collect())
+            .unwrap_or_default();
+    };
+    let mut nodes: Vec<DagNode> = Vec::new();
+    for (dbt_ref, _) in &file.refs {
+        if let Some(target) = DagNode::from_ref(manifest, dbt_ref).filter(|t| !nodes.contains(t)) {
+            nodes.push(target);
+        }
+    }
+    nodes
+}
+
+/// The exposures whose `depends_on` names `node`, with their yml file.
+fn dependent_exposures(manifest: &ProjectManifest, node: &DagNode) -> Vec<(String, PathBuf)> {
+    manifest.exposures.iter()
+        .filter(|e| e.depends_on.iter().any(|(dbt_ref, _)| DagNode::from_ref(manifest, dbt_ref).as_ref() == Some(node)))
+        .map(|e| (e.key().clone(), e.path.clone()))
+        .collect()
+}
+
+/// The models, snapshots, singular tests and exposures that ref `node`, ordered by path.
+/// Needs the reference index.
+pub fn downstream(manifest: &ProjectManifest, node: &DagNode) -> Vec<DagNode> {
+    let mut nodes: Vec<(PathBuf, usize, DagNode)> = manifest.references.iter()
+        .filter(|file| file.refs.iter().any(|(dbt_ref, _)| DagNode::from_ref(manifest, dbt_ref).as_ref() == Some(node)))
+        .filter_map(|file| Some((file.key().clone(), 0, DagNode::for_file(manifest, file.key())?)))
+        .collect();
+    for (name, path) in dependent_exposures(manifest, node) {
+        let line = manifest.exposures.get(&name).map_or(0, |e| e.line);
+        nodes.push((path, line, DagNode::Exposure { name }));
+    }
+    nodes.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+    nodes.into_iter().map(|(_, _, node)| node).collect()
+}
@@ -0,0 +1,27 @@
+use crate::position::{byte_to_position, PositionEncoding};
+use crate::project::ProjectManifest;
+use crate::profiles::Target;
+use crate::relation::ref_relation;
+use crate::state::DocumentState;
+use std::ops::Range;
+use tower_lsp::lsp_types::{InlayHint, InlayHintLabel};
+
+/// A `→ relation` hint at the end of each ref() and source() span that ends inside `range`.
+pub fn relation_hints(doc: &DocumentState, manifest: &ProjectManifest, target: &Target, range: Range<usize>, encoding: PositionEncoding) -> Vec<InlayHint> {
+    doc.refs.iter()
+        .filter(|(_, span)| range.start <= span.end && span.end <= range.end)
+        .filter_map(|(dbt_ref, span)| {
+            let relation = ref_relation(manifest, dbt_ref, target)?;
+            Some(InlayHint {
+                position: byte_to_position(&doc.text, span.end, encoding),
+                label: InlayHintLabel::String(format!("→ {}", relation)),
+                kind: None,
+                text_edits: None,
+                tooltip: None,
+                padding_left: Some(true),
+                padding_right: None,
+                data: None,
+            })
+        })
+        .collect()
+}
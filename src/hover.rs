@@ -0,0 +1,583 @@
+use crate::jinja::ModelConfig;
+use crate::profiles::Target;
+use crate::project::{ColumnDoc, ColumnOrigin, MetricDef, ProjectManifest, SourceDef};
+use std::io::BufRead;
+use regex::Regex;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+/// Seeds above this size only have their header read for hover.
+const SEED_SAMPLE_MAX_BYTES: u64 = 5 * 1024 * 1024;
+const SEED_SAMPLE_ROWS: usize = 5;
+
+/// Cuts markdown down to `max_chars` characters, closing a code fence left open by the
+/// cut and noting that the content was truncated.
+pub fn truncate(markdown: String, max_chars: usize) -> String {
+    let Some((cut, _)) = markdown.char_indices().nth(max_chars) else { return markdown };
+    let mut out = markdown[..cut].to_string();
+    if out.matches("```").count() % 2 == 1 {
+        out.push_str("\n```");
+    }
+    out.push_str("\n\n_… truncated_");
+    out
+}
+
+/// Makes free text safe to put in a markdown table cell.
+fn table_cell(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ").replace('|', "\\|")
+}
+
+/// The names of data tests as inline code, e.g. "`unique`, `not_null`".
+fn tests_list(tests: &[String]) -> String {
+    tests.iter().map(|t| format!("`{}`", t)).collect::<Vec<_>>().join(", ")
+}
+
+/// Renders documented columns as a markdown table; the type column is only shown when
+/// at least one column declares a `data_type`.
+pub fn columns_table(columns: &[ColumnDoc]) -> String {
+    let with_types = columns.iter().any(|c| c.data_type.is_some());
+    let mut out = if with_types {
+        "| Column | Type | Description |\n|---|---|---|\n".to_string()
+    } else {
+        "| Column | Description |\n|---|---|\n".to_string()
+    };
+    for col in columns {
+        let description = col.description.as_deref().map(table_cell).unwrap_or_default();
+        if with_types {
+            let data_type = col.data_type.as_deref().map(table_cell).unwrap_or_default();
+            out.push_str(&format!("| `{}` | {} | {} |\n", col.name, data_type, description));
+        } else {
+            out.push_str(&format!("| `{}` | {} |\n", col.name, description));
+        }
+    }
+    out
+}
+
+/// The line heading an `alias.column` hover when the relation's columns are known: the
+/// column's type and where it comes from, then its description.
+pub fn column_markdown(column: &ColumnDoc, target: &str, origin: ColumnOrigin) -> String {
+    let mut out = format!("**Column** `{}` of `{}`", column.name, target);
+    if let Some(data_type) = &column.data_type {
+        let from = match origin {
+            ColumnOrigin::Catalog => "catalog.json",
+            ColumnOrigin::Documented => "yml",
+            ColumnOrigin::SelectList => "select list",
+        };
+        out.push_str(&format!(": `{}` (from {})", data_type, from));
+    }
+    if let Some(description) = &column.description {
+        out.push_str(&format!("\n\n{}", description));
+    }
+    if !column.tests.is_empty() {
+        out.push_str(&format!("\n\nTests: {}", tests_list(&column.tests)));
+    }
+    out
+}
+
+/// Hover for `ref('name')` to a model: its materialization, then its yml description and
+/// columns when documented, otherwise the file it lives in. A versioned model also shows
+/// the version referenced, `version` or the latest.
+pub fn model_markdown(manifest: &ProjectManifest, name: &str, version: Option<&str>) -> String {
+    let mut out = format!("**Model**: `{}`", name);
+    let latest = manifest.model_entries.get(name).and_then(|e| e.latest_version.clone());
+    if let Some(latest) = latest {
+        match version.filter(|v| *v != latest) {
+            Some(version) => out.push_str(&format!(" — version {} (latest: {})", version, latest)),
+            None => out.push_str(&format!(" — version {} (latest)", latest)),
+        }
+    }
+    let file = manifest.resolve_model_version(name, version).unwrap_or_else(|| name.to_string());
+    match crate::relation::model_materialization(manifest, &file) {
+        Some((materialized, "default")) => out.push_str(&format!("\n\nmaterialized: `{}`", materialized)),
+        Some((materialized, origin)) => out.push_str(&format!("\n\nmaterialized: `{}` (from {})", materialized, origin)),
+        None => {}
+    }
+    if manifest.models.contains_key(&file) {
+        match crate::relation::model_access(manifest, &file) {
+            (Some(group), access) => {
+                let owner = manifest.groups.get(&group).and_then(|g| g.owner.clone());
+                out.push_str(&format!("\n\ngroup: `{}`{} · access: `{}`", group, owner.map(|o| format!(" ({})", o)).unwrap_or_default(), access));
+            }
+            (None, access) if access != "protected" => out.push_str(&format!("\n\naccess: `{}`", access)),
+            (None, _) => {}
+        }
+    }
+    match manifest.model_entries.get(name) {
+        Some(entry) => {
+            if let Some(description) = &entry.description {
+                out.push_str("\n\n");
+                out.push_str(&manifest.render_docs(description));
+            }
+            if !entry.tests.is_empty() {
+                out.push_str(&format!("\n\nTests: {}", tests_list(&entry.tests)));
+            }
+            if !entry.columns.is_empty() {
+                out.push_str("\n\n");
+                out.push_str(&columns_table(&manifest.render_column_docs(&entry.columns)));
+            }
+        }
+        None => {
+            if let Some(path) = manifest.models.get(&file) {
+                let relative = path.strip_prefix(&manifest.root_dir).unwrap_or(path.value());
+                out.push_str(&format!("\n\n`{}`", relative.display()));
+            }
+        }
+    }
+    out
+}
+
+/// The physical relation behind a ref, noting when it was resolved without profiles.yml
+/// or with profile values that are jinja.
+pub fn relation_markdown(relation: &str, profile_target: Option<&Target>) -> String {
+    match profile_target {
+        None => format!("\n\n**Relation**: `{}` _(profiles.yml unavailable)_", relation),
+        Some(target) if !target.templated.is_empty() => {
+            format!("\n\n**Relation**: `{}` _(unrendered in profiles.yml: {})_", relation, target.templated.join(", "))
+        }
+        Some(_) => format!("\n\n**Relation**: `{}`", relation),
+    }
+}
+
+/// Hover for `source('src', 'tbl')`: description, physical relation, loading metadata and
+/// documented columns from the sources yml.
+pub fn source_markdown(src: &str, tbl: &str, def: &SourceDef, default_database: Option<&str>) -> String {
+    let mut out = format!("**Source**: `{}.{}`", src, tbl);
+    if let Some(description) = &def.description {
+        out.push_str("\n\n");
+        out.push_str(description);
+    }
+
+    let relation = crate::relation::source_relation(def, default_database);
+    let mut details = vec![format!("- Relation: `{}`", relation)];
+    if def.identifier != tbl {
+        details.push(format!("- Identifier: `{}` (table `{}`)", def.identifier, tbl));
+    }
+    if let Some(loader) = &def.loader {
+        details.push(format!("- Loader: {}", loader));
+    }
+    if let Some(field) = &def.loaded_at_field {
+        details.push(format!("- Loaded at: `{}`", field));
+    }
+    if let Some(freshness) = &def.freshness {
+        details.push(format!("- Freshness: {}", freshness));
+    }
+    if !def.tests.is_empty() {
+        details.push(format!("- Tests: {}", tests_list(&def.tests)));
+    }
+    out.push_str("\n\n");
+    out.push_str(&details.join("\n"));
+
+    if !def.columns.is_empty() {
+        out.push_str("\n\n");
+        out.push_str(&columns_table(&def.columns));
+    }
+    out
+}
+
+/// Hover for `metric('name')`: label and description, then what it computes and the
+/// dimensions it can be grouped by.
+pub fn metric_markdown(name: &str, metric: &MetricDef, dimensions: &[String]) -> String {
+    let mut out = format!("**Metric**: `{}`", name);
+    if let Some(label) = &metric.label {
+        out.push_str(&format!(" — {}", label));
+    }
+    if let Some(description) = &metric.description {
+        out.push_str("\n\n");
+        out.push_str(description);
+    }
+
+    let mut details = Vec::new();
+    if let Some(metric_type) = &metric.metric_type {
+        details.push(format!("- Type: {}", metric_type));
+    }
+    if let Some(expression) = &metric.expression {
+        details.push(format!("- Expression: `{}`", expression));
+    }
+    if !dimensions.is_empty() {
+        details.push(format!("- Dimensions: {}", dimensions.iter().map(|d| format!("`{}`", d)).collect::<Vec<_>>().join(", ")));
+    }
+    if !details.is_empty() {
+        out.push_str("\n\n");
+        out.push_str(&details.join("\n"));
+    }
+    out
+}
+
+fn re_macro_open() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"(?s)\{%-?\s*(?:macro|test)\s+([a-zA-Z0-9_]+\s*\(.*?\))\s*-?%\}").unwrap())
+}
+
+fn re_endmacro() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"\{%-?\s*end(?:macro|test)\s*-?%\}").unwrap())
+}
+
+/// Hover for a macro call: the signature, then the definition from its opener to its
+/// `{% endmacro %}`, capped at `max_lines` lines. `line` is where the macro's opener is;
+/// macros from installed packages are labelled with the package.
+pub fn macro_markdown(name: &str, package: Option<&str>, content: &str, line: usize, max_lines: usize) -> String {
+    let mut out = format!("**Macro**: `{}`", name);
+    if let Some(package) = package {
+        out.push_str(&format!(" (package `{}`)", package));
+    }
+    let start = content.split_inclusive('\n').take(line).map(str::len).sum::<usize>();
+    let Some(open) = re_macro_open().captures(&content[start..]) else { return out };
+    let (Some(full), Some(signature)) = (open.get(0), open.get(1)) else { return out };
+    let body_start = start + full.start();
+    let body_end = re_endmacro()
+        .find(&content[start + full.end()..])
+        .map_or(content.len(), |m| start + full.end() + m.end());
+
+    let signature: String = signature.as_str().split_whitespace().collect::<Vec<_>>().join(" ");
+    out.push_str(&format!("\n\n```jinja\n{}\n```", signature));
+
+    let lines: Vec<&str> = content[body_start..body_end].lines().collect();
+    out.push_str("\n\n```jinja\n");
+    out.push_str(&lines[..lines.len().min(max_lines)].join("\n"));
+    out.push_str("\n```");
+    if lines.len() > max_lines {
+        out.push_str(&format!("\n\n_… {} more lines_", lines.len() - max_lines));
+    }
+    out
+}
+
+/// Hover for a generic test in yml: its `{% test %}` block like a macro's (`definition` is
+/// the package, file content and line), or a note that dbt defines it.
+pub fn generic_test_markdown(name: &str, definition: Option<(Option<&str>, &str, usize)>, max_lines: usize) -> String {
+    match definition {
+        Some((package, content, line)) => macro_markdown(name, package, content, line, max_lines).replacen("**Macro**", "**Generic test**", 1),
+        None => format!("**Generic test**: `{}` (built into dbt)", name),
+    }
+}
+
+/// Hover for `var('name', default)`: the project value (mappings and lists as a yaml
+/// block), else the inline default, else a warning that it's undefined.
+pub fn var_markdown(name: &str, value: Option<&serde_yaml::Value>, default: Option<&str>) -> String {
+    let mut out = format!("**Var**: `{}`", name);
+    match value {
+        Some(value) if value.is_mapping() || value.is_sequence() => {
+            let yaml = serde_yaml::to_string(value).unwrap_or_default();
+            out.push_str(&format!("\n\n```yaml\n{}\n```", yaml.trim_end()));
+        }
+        Some(value) => {
+            let scalar = serde_yaml::to_string(value).unwrap_or_default();
+            out.push_str(&format!(" = `{}`", scalar.trim_end()));
+        }
+        None => match default {
+            Some(default) => out.push_str(&format!(" = `{}` _(default)_", default)),
+            None => out.push_str("\n\n⚠️ **Undefined**: not set in dbt_project.yml and no default given"),
+        },
+    }
+    if let (Some(_), Some(default)) = (value, default) {
+        out.push_str(&format!("\n\nDefault: `{}`", default));
+    }
+    out
+}
+
+const CTE_PREVIEW_LINES: usize = 15;
+
+/// Hover for a CTE (or an alias of one): its output columns, then the start of its body.
+/// `columns` is None when the select list couldn't be read from the tree.
+pub fn cte_markdown(title: &str, columns: Option<&[String]>, body: &str, full_body: bool) -> String {
+    let body = body.trim_matches('\n');
+    if full_body {
+        return format!("{}\n```sql\n{}\n```", title, body);
+    }
+
+    let mut out = title.to_string();
+    match columns {
+        Some(columns) if !columns.is_empty() => {
+            let names: Vec<String> = columns.iter().map(|c| format!("`{}`", c)).collect();
+            out.push_str(&format!("\n\n**Columns**: {}", names.join(", ")));
+        }
+        _ => out.push_str("\n\n_Output columns could not be determined._"),
+    }
+
+    let lines: Vec<&str> = body.lines().collect();
+    out.push_str("\n\n```sql\n");
+    out.push_str(&lines[..lines.len().min(CTE_PREVIEW_LINES)].join("\n"));
+    if lines.len() > CTE_PREVIEW_LINES {
+        out.push_str("\n-- …");
+    }
+    out.push_str("\n```");
+    out
+}
+
+/// Hover for a model's `config()` call: its arguments, then the folder-level configs
+/// from dbt_project.yml it doesn't override. Tags from both places apply.
+pub fn config_markdown(config: &ModelConfig, folder_config: &[(&str, serde_yaml::Value)]) -> String {
+    let mut rows: Vec<(String, String, &str)> = config.entries.iter()
+        .map(|e| (e.key.clone(), e.value.clone(), "`config()`"))
+        .collect();
+    for (key, value) in folder_config {
+        if *key != "tags" && config.get(key).is_some() {
+            continue;
+        }
+        let value = match value {
+            serde_yaml::Value::String(s) => s.clone(),
+            other => serde_json::to_string(other).unwrap_or_default(),
+        };
+        rows.push((key.to_string(), value, "dbt_project.yml"));
+    }
+    if !rows.iter().any(|(key, _, _)| key == "materialized") {
+        rows.push(("materialized".to_string(), "view".to_string(), "default"));
+    }
+
+    let mut out = "**Model config**\n\n| Key | Value | From |\n|---|---|---|\n".to_string();
+    for (key, value, origin) in rows {
+        out.push_str(&format!("| `{}` | `{}` | {} |\n", key, table_cell(&value), origin));
+    }
+    out
+}
+
+/// Whether an environment variable's value should never be shown.
+fn is_secret_name(name: &str) -> bool {
+    let upper = name.to_uppercase();
+    upper.starts_with("DBT_ENV_SECRET_")
+        || upper.ends_with("_TOKEN")
+        || upper.ends_with("_SECRET")
+        || upper.ends_with("_PASSWORD")
+        || upper.contains("KEY")
+}
+
+/// Hover for `env_var('NAME', default)`, resolved against `value` (the server's own
+/// environment). Secret-looking names are masked.
+pub fn env_var_markdown(name: &str, value: Option<&str>, default: Option<&str>) -> String {
+    let mut out = format!("**Env var**: `{}`", name);
+    match (value, default) {
+        (Some(_), _) if is_secret_name(name) => out.push_str(" = `••••` _(set, masked)_"),
+        (Some(value), _) => out.push_str(&format!(" = `{}`", value)),
+        (None, Some(default)) => out.push_str(&format!(" = `{}` _(unset, default)_", default)),
+        (None, None) => out.push_str("\n\n💡 Unset in the language server's environment and no default given; `dbt compile` will fail unless it is set."),
+    }
+    if let (Some(_), Some(default)) = (value, default) {
+        out.push_str(&format!("\n\nDefault: `{}`", default));
+    }
+    out
+}
+
+/// The header and first rows of a seed CSV, cached per path until the file changes.
+#[derive(Debug, Clone)]
+pub struct SeedPreview {
+    pub modified: Option<SystemTime>,
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    /// The file was too large to sample rows from.
+    pub header_only: bool,
+}
+
+/// Picks the delimiter that occurs most often outside quotes in the header line.
+fn sniff_delimiter(header: &str) -> char {
+    let mut counts = [(',', 0), (';', 0), ('\t', 0), ('|', 0)];
+    let mut in_quotes = false;
+    for c in header.chars() {
+        if c == '"' {
+            in_quotes = !in_quotes;
+        } else if !in_quotes {
+            if let Some(entry) = counts.iter_mut().find(|(d, _)| *d == c) {
+                entry.1 += 1;
+            }
+        }
+    }
+    counts.iter().max_by_key(|(_, n)| *n).filter(|(_, n)| *n > 0).map_or(',', |(d, _)| *d)
+}
+
+fn split_csv_line(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            c if c == delimiter && !in_quotes => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields.into_iter().map(|f| f.trim().to_string()).collect()
+}
+
+pub fn read_seed_preview(path: &Path) -> Option<SeedPreview> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let header_only = metadata.len() > SEED_SAMPLE_MAX_BYTES;
+    let mut lines = std::io::BufReader::new(std::fs::File::open(path).ok()?).lines();
+
+    let first = lines.next()?.ok()?;
+    let first = first.strip_prefix('\u{feff}').unwrap_or(&first);
+    let delimiter = sniff_delimiter(first);
+    let header = split_csv_line(first, delimiter);
+    let rows = if header_only {
+        Vec::new()
+    } else {
+        lines
+            .map_while(|l| l.ok())
+            .filter(|l| !l.trim().is_empty())
+            .take(SEED_SAMPLE_ROWS)
+            .map(|l| split_csv_line(&l, delimiter))
+            .collect()
+    };
+
+    Some(SeedPreview { modified: metadata.modified().ok(), header, rows, header_only })
+}
+
+/// Hover for `ref('seed')`: the CSV header and a few rows as a markdown table, then the
+/// columns' types and descriptions when any are configured.
+pub fn seed_markdown(name: &str, preview: &SeedPreview, columns: &[ColumnDoc]) -> String {
+    let mut out = format!("**Seed**: `{}`", name);
+    if preview.header.is_empty() {
+        return out;
+    }
+    out.push_str("\n\n| ");
+    out.push_str(&preview.header.iter().map(|h| table_cell(h)).collect::<Vec<_>>().join(" | "));
+    out.push_str(" |\n|");
+    out.push_str(&"---|".repeat(preview.header.len()));
+    for row in &preview.rows {
+        let cells: Vec<String> = (0..preview.header.len()).map(|i| row.get(i).map(|c| table_cell(c)).unwrap_or_default()).collect();
+        out.push_str("\n| ");
+        out.push_str(&cells.join(" | "));
+        out.push_str(" |");
+    }
+    if preview.header_only {
+        out.push_str("\n\n_Large seed: sample rows omitted._");
+    }
+    if columns.iter().any(|c| c.data_type.is_some() || c.description.is_some()) {
+        out.push_str("\n\n");
+        out.push_str(&columns_table(columns));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_columns_table() {
+        let columns = vec![
+            ColumnDoc { name: "id".to_string(), description: Some("Primary\nkey | unique".to_string()), ..ColumnDoc::default() },
+            ColumnDoc { name: "amount".to_string(), data_type: Some("numeric".to_string()), ..ColumnDoc::default() },
+        ];
+        assert_eq!(
+            columns_table(&columns),
+            "| Column | Type | Description |\n|---|---|---|\n| `id` |  | Primary key \\| unique |\n| `amount` | numeric |  |\n"
+        );
+    }
+
+    #[test]
+    fn test_column_markdown() {
+        let column = ColumnDoc { name: "AMOUNT".to_string(), description: Some("Order total".to_string()), data_type: Some("NUMBER(38,2)".to_string()), ..ColumnDoc::default() };
+        assert_eq!(column_markdown(&column, "orders", ColumnOrigin::Catalog), "**Column** `AMOUNT` of `orders`: `NUMBER(38,2)` (from catalog.json)\n\nOrder total");
+        let untyped = ColumnDoc { data_type: None, description: None, ..column };
+        assert_eq!(column_markdown(&untyped, "orders", ColumnOrigin::SelectList), "**Column** `AMOUNT` of `orders`");
+        let tested = ColumnDoc { tests: vec!["unique".to_string(), "not_null".to_string()], ..untyped };
+        assert_eq!(column_markdown(&tested, "orders", ColumnOrigin::Documented), "**Column** `AMOUNT` of `orders`\n\nTests: `unique`, `not_null`");
+    }
+
+    #[test]
+    fn test_source_markdown_with_identifier() {
+        let def = SourceDef {
+            description: Some("Raw payments".to_string()),
+            database: Some("raw_db".to_string()),
+            schema: "stripe".to_string(),
+            identifier: "payments_v2".to_string(),
+            freshness: Some("warn after 12 hour".to_string()),
+            ..SourceDef::default()
+        };
+        let markdown = source_markdown("stripe", "payments", &def, Some("ignored"));
+        assert!(markdown.starts_with("**Source**: `stripe.payments`\n\nRaw payments\n\n"));
+        assert!(markdown.contains("- Relation: `raw_db.stripe.payments_v2`"));
+        assert!(markdown.contains("- Identifier: `payments_v2` (table `payments`)"));
+        assert!(markdown.contains("- Freshness: warn after 12 hour"));
+    }
+
+    #[test]
+    fn test_metric_markdown() {
+        let metric = MetricDef {
+            label: Some("Revenue".to_string()),
+            description: Some("Sum of order amounts".to_string()),
+            metric_type: Some("simple".to_string()),
+            expression: Some("order_total".to_string()),
+            ..MetricDef::default()
+        };
+        let dimensions = vec!["order_date".to_string(), "status".to_string()];
+        assert_eq!(
+            metric_markdown("revenue", &metric, &dimensions),
+            "**Metric**: `revenue` — Revenue\n\nSum of order amounts\n\n- Type: simple\n- Expression: `order_total`\n- Dimensions: `order_date`, `status`"
+        );
+        assert_eq!(metric_markdown("bare", &MetricDef::default(), &[]), "**Metric**: `bare`");
+    }
+
+    #[test]
+    fn test_seed_preview_with_bom_and_semicolons() {
+        let path = std::env::temp_dir().join(format!("dbt-lsp-seed-{}.csv", std::process::id()));
+        std::fs::write(&path, "\u{feff}id;name;note\n1;\"Smith; J\";ok\n\n2;Doe\n").unwrap();
+        let preview = read_seed_preview(&path).unwrap();
+        assert_eq!(preview.header, vec!["id", "name", "note"]);
+        assert_eq!(preview.rows, vec![vec!["1", "Smith; J", "ok"], vec!["2", "Doe"]]);
+        assert_eq!(
+            seed_markdown("people", &preview, &[]),
+            "**Seed**: `people`\n\n| id | name | note |\n|---|---|---|\n| 1 | Smith; J | ok |\n| 2 | Doe |  |"
+        );
+        let columns = vec![ColumnDoc { name: "id".to_string(), data_type: Some("integer".to_string()), ..ColumnDoc::default() }];
+        assert!(seed_markdown("people", &preview, &columns).ends_with("| 2 | Doe |  |\n\n| Column | Type | Description |\n|---|---|---|\n| `id` | integer |  |\n"));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_macro_markdown_stops_at_its_endmacro() {
+        let content = "{% macro first() %}1{% endmacro %}\n\n{% macro cents(col,\n    scale=2) %}\n  ({{ col }} / 100)::numeric(16, {{ scale }})\n{%- endmacro %}\n{% macro after() %}x{% endmacro %}\n";
+        let markdown = macro_markdown("cents", None, content, 2, 40);
+        assert!(markdown.contains("```jinja\ncents(col, scale=2)\n```"));
+        assert!(markdown.ends_with("::numeric(16, {{ scale }})\n{%- endmacro %}\n```"));
+        assert!(!markdown.contains("after"));
+
+        let truncated = macro_markdown("cents", None, content, 2, 2);
+        assert!(truncated.ends_with("_… 2 more lines_"));
+    }
+
+    #[test]
+    fn test_var_markdown() {
+        let countries: serde_yaml::Value = serde_yaml::from_str("[nl, be]").unwrap();
+        assert_eq!(var_markdown("countries", Some(&countries), None), "**Var**: `countries`\n\n```yaml\n- nl\n- be\n```");
+        let days = serde_yaml::Value::from(30);
+        assert_eq!(var_markdown("days", Some(&days), Some("7")), "**Var**: `days` = `30`\n\nDefault: `7`");
+        assert_eq!(var_markdown("days", None, Some("7")), "**Var**: `days` = `7` _(default)_");
+        assert!(var_markdown("days", None, None).contains("**Undefined**"));
+    }
+
+    #[test]
+    fn test_env_var_markdown_masks_secrets() {
+        assert_eq!(env_var_markdown("DBT_SCHEMA_SUFFIX", Some("_dev"), Some("''")), "**Env var**: `DBT_SCHEMA_SUFFIX` = `_dev`\n\nDefault: `''`");
+        assert!(env_var_markdown("SNOWFLAKE_PASSWORD", Some("hunter2"), None).contains("••••"));
+        assert!(!env_var_markdown("api_key_id", Some("abc"), None).contains("abc"));
+        assert!(env_var_markdown("DBT_TARGET", None, None).contains("Unset"));
+    }
+
+    #[test]
+    fn test_truncate_closes_code_fence() {
+        assert_eq!(truncate("short".to_string(), 10), "short");
+        assert_eq!(truncate("a\n```sql\nselect é".to_string(), 16), "a\n```sql\nselect \n```\n\n_… truncated_");
+    }
+
+    #[test]
+    fn test_config_markdown_merges_folder_config() {
+        let config = crate::jinja::parse_config("{{ config(materialized='incremental', tags=['daily']) }}").unwrap();
+        let folder = vec![
+            ("materialized", serde_yaml::Value::from("table")),
+            ("tags", serde_yaml::from_str("[finance]").unwrap()),
+            ("schema", serde_yaml::Value::from("marts")),
+        ];
+        assert_eq!(config_markdown(&config, &folder), "**Model config**\n\n| Key | Value | From |\n|---|---|---|\n\
+            | `materialized` | `incremental` | `config()` |\n\
+            | `tags` | `['daily']` | `config()` |\n\
+            | `tags` | `[\"finance\"]` | dbt_project.yml |\n\
+            | `schema` | `marts` | dbt_project.yml |\n");
+    }
+}
@@ -4,13 +4,29 @@ use std::sync::OnceLock;
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum DbtRef {
     Model(String),
+    PackageModel(String, String), // package_name, model_name
+    VersionedModel(String, String), // model_name, version
     Source(String, String), // source_name, table_name
     Macro(String),
+    Doc(String),
+    Var(String, Option<String>), // var_name, default expression
+    EnvVar(String, Option<String>), // variable name, default expression
+    Metric(String),
+    This,
 }
 
 fn re_ref() -> &'static Regex {
     static RE: OnceLock<Regex> = OnceLock::new();
-    RE.get_or_init(|| Regex::new(r#"(?xs)\{\{\s*[-]?\s*ref\s*\(\s*['"]([a-zA-Z0-9_\.]+)['"]\s*\)\s*[-]?\s*\}\}"#).unwrap())
+    // Group 1 is the model, or the package when group 2 (the model) is present; group 3 is
+    // the `v=` (or `version=`) kwarg
+    RE.get_or_init(|| Regex::new(r#"(?xs)\{\{\s*[-]?\s*ref\s*\(\s*['"]([a-zA-Z0-9_\.]+)['"]\s*(?:,\s*['"]([a-zA-Z0-9_\.]+)['"]\s*)?(?:,\s*(?:v|version)\s*=\s*['"]?([a-zA-Z0-9_\.]+)['"]?\s*)?\)\s*[-]?\s*\}\}"#).unwrap())
+}
+
+/// A `ref()` call as yml writes it, without the braces: a relationships test's `to:`, an
+/// exposure's `depends_on`, a semantic model's `model:`. Groups as in [`re_ref`].
+fn re_bare_ref() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r#"(?xs)\bref\s*\(\s*['"]([a-zA-Z0-9_\.]+)['"]\s*(?:,\s*['"]([a-zA-Z0-9_\.]+)['"]\s*)?(?:,\s*(?:v|version)\s*=\s*['"]?([a-zA-Z0-9_\.]+)['"]?\s*)?\)"#).unwrap())
 }
 
 fn re_source() -> &'static Regex {
@@ -18,15 +34,271 @@ fn re_source() -> &'static Regex {
     RE.get_or_init(|| Regex::new(r#"(?xs)\{\{\s*[-]?\s*source\s*\(\s*['"]([a-zA-Z0-9_\.]+)['"]\s*,\s*['"]([a-zA-Z0-9_\.]+)['"]\s*\)\s*[-]?\s*\}\}"#).unwrap())
 }
 
+fn re_doc() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r#"(?xs)\{\{\s*[-]?\s*doc\s*\(\s*['"]([a-zA-Z0-9_\.]+)['"]\s*\)\s*[-]?\s*\}\}"#).unwrap())
+}
+
+fn re_var() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    // Group 2 matches the comma that introduces a default value
+    RE.get_or_init(|| Regex::new(r#"\bvar\s*\(\s*['"]([a-zA-Z0-9_\.]+)['"]\s*(,)?"#).unwrap())
+}
+
+fn re_this_word() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"\bthis\b").unwrap())
+}
+
+fn re_config_call() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"(?s)\{\{\s*-?\s*config\s*\((.*?)\)\s*-?\s*\}\}").unwrap())
+}
+
+/// One `key=value` argument of a `config()` call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigEntry {
+    pub key: String,
+    /// The value's source text, with the quotes of a plain string literal removed.
+    pub value: String,
+    pub key_range: std::ops::Range<usize>,
+    pub value_range: std::ops::Range<usize>,
+}
+
+/// The model's `{{ config(...) }}` call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelConfig {
+    /// Byte range of the whole call, braces included.
+    pub range: std::ops::Range<usize>,
+    pub entries: Vec<ConfigEntry>,
+}
+
+impl ModelConfig {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.iter().find(|e| e.key == key).map(|e| e.value.as_str())
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    let is_literal = |q: char| value.len() >= 2 && value.starts_with(q) && value.ends_with(q) && !value[1..value.len() - 1].contains(q);
+    if is_literal('\'') || is_literal('"') {
+        &value[1..value.len() - 1]
+    } else {
+        value
+    }
+}
+
+/// Parses the keyword arguments of the first `{{ config(...) }}` call in a model.
+pub fn parse_config(text: &str) -> Option<ModelConfig> {
+    let cap = re_config_call().captures(text)?;
+    let (call, args) = (cap.get(0)?, cap.get(1)?);
+    let mut entries = Vec::new();
+    let mut pos = args.start();
+    while pos < args.end() {
+        let rest = &text[pos..args.end()];
+        let key_len = rest.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(rest.len());
+        if key_len == 0 {
+            // Skip separators and anything that isn't a keyword argument
+            pos += rest.chars().next().map_or(1, char::len_utf8);
+            continue;
+        }
+        let key_range = pos..pos + key_len;
+        let after_key = text[key_range.end..args.end()].trim_start();
+        let Some(value_text) = after_key.strip_prefix('=') else {
+            pos = key_range.end;
+            continue;
+        };
+        let value_start = args.end() - value_text.trim_start().len();
+        // The call's own closing paren ends the last argument
+        let raw = argument_text(&text[..args.end() + 1], value_start).unwrap_or("");
+        let value_range = value_start..value_start + raw.len();
+        entries.push(ConfigEntry {
+            key: text[key_range.clone()].to_string(),
+            value: unquote(raw).to_string(),
+            key_range,
+            value_range: value_range.clone(),
+        });
+        pos = value_range.end.max(value_start + 1);
+    }
+    Some(ModelConfig { range: call.range(), entries })
+}
+
+fn re_env_var() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r#"\benv_var\s*\(\s*['"]([a-zA-Z0-9_\.]+)['"]\s*(,)?"#).unwrap())
+}
+
+fn re_metric() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r#"\bmetric\s*\(\s*['"]([a-zA-Z0-9_]+)['"]"#).unwrap())
+}
+
+/// Whether `text` defines macros or generic tests rather than being a model's SQL.
 pub fn is_macro_file(text: &str) -> bool {
     static RE: OnceLock<Regex> = OnceLock::new();
-    let re = RE.get_or_init(|| Regex::new(r#"(?s)\{[%-]\s*macro\s+"#).unwrap());
+    let re = RE.get_or_init(|| Regex::new(r#"(?s)\{[%-]\s*(?:macro|test)\s+"#).unwrap());
     re.is_match(text)
 }
 
-fn re_macro_call() -> &'static Regex {
+fn re_jinja_expression() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"(?s)\{\{.*?\}\}|\{%.*?%\}").unwrap())
+}
+
+/// Whether byte `idx` falls strictly inside a `{{ }}` or `{% %}` tag of `text`.
+pub fn splits_jinja(text: &str, idx: usize) -> bool {
+    re_jinja_expression().find_iter(text).any(|m| m.start() < idx && idx < m.end())
+}
+
+fn re_call() -> &'static Regex {
     static RE: OnceLock<Regex> = OnceLock::new();
-    RE.get_or_init(|| Regex::new(r#"(?xs)\{[{%]\s*[-]?\s*(?:do\s+)?([a-zA-Z0-9_\.]+)\s*\("#).unwrap())
+    RE.get_or_init(|| Regex::new(r"([a-zA-Z_][a-zA-Z0-9_]*(?:\.[a-zA-Z_][a-zA-Z0-9_]*)*)\s*\(").unwrap())
+}
+
+/// dbt context functions and jinja globals that look like calls but aren't user macros.
+const BUILTIN_CALLS: &[&str] = &[
+    "ref", "source", "config", "var", "env_var", "doc", "metric", "return", "log", "print",
+    "run_query", "statement", "is_incremental", "load_result", "load_relation", "zip", "range",
+    "dict", "list", "caller", "namespace", "cycler", "joiner", "lipsum", "super", "fromjson",
+    "tojson", "fromyaml", "toyaml", "as_bool", "as_number", "as_text", "as_native", "debug",
+    "set_strict", "set", "try_or_compiler_error", "local_md5", "diff_of_two_dicts",
+    "should_full_refresh", "should_store_failures", "store_result", "store_raw_result",
+    "load_cached_relation", "set_sql_header", "write", "render", "submit_python_job",
+];
+
+/// Objects from the dbt context whose methods are never project macros.
+const BUILTIN_NAMESPACES: &[&str] = &[
+    "adapter", "exceptions", "modules", "dbt", "api", "graph", "this", "target", "flags",
+    "builtins", "model", "context", "loop", "run_started_at", "invocation_id", "selected_resources",
+];
+
+/// Python-style methods commonly called on jinja values (`x.items()`, `cols.append(...)`).
+const VALUE_METHODS: &[&str] = &[
+    "items", "keys", "values", "append", "extend", "get", "update", "pop", "format", "upper",
+    "lower", "strip", "split", "join", "replace", "startswith", "endswith", "render", "include_policy",
+];
+
+/// Keywords that introduce a definition rather than a call (`{% macro name(...) %}`).
+const DEFINITION_KEYWORDS: &[&str] = &["macro", "test", "materialization"];
+
+fn is_macro_call_candidate(name: &str, preceding: &str) -> bool {
+    let before = preceding.trim_end();
+    // Filters (`x | join(',')`) and definitions aren't calls to project macros
+    if before.ends_with('|') || before.ends_with('.') {
+        return false;
+    }
+    let prev_word = before.rsplit(|c: char| !c.is_alphanumeric() && c != '_').next().unwrap_or("");
+    if DEFINITION_KEYWORDS.contains(&prev_word) {
+        return false;
+    }
+
+    match name.split_once('.') {
+        Some((namespace, _)) => {
+            let method = name.rsplit('.').next().unwrap_or(name);
+            !BUILTIN_NAMESPACES.contains(&namespace) && !VALUE_METHODS.contains(&method)
+        }
+        None => !BUILTIN_CALLS.contains(&name),
+    }
+}
+
+/// Byte ranges of quoted strings within a jinja expression, so calls inside string
+/// literals aren't mistaken for macro calls.
+fn string_spans(expr: &str) -> Vec<std::ops::Range<usize>> {
+    let mut spans = Vec::new();
+    let mut open: Option<(char, usize)> = None;
+    for (idx, c) in expr.char_indices() {
+        match open {
+            Some((q, start)) if c == q => {
+                spans.push(start..idx + 1);
+                open = None;
+            }
+            None if c == '\'' || c == '"' => open = Some((c, idx)),
+            _ => {}
+        }
+    }
+    spans
+}
+
+/// Finds macro calls inside `{{ }}` and `{% %}` blocks, returning the macro name (possibly
+/// namespaced, e.g. `my_project.cents_to_dollars`) and the byte range of the name.
+fn extract_macro_calls(text: &str) -> Vec<(String, std::ops::Range<usize>)> {
+    let mut calls = Vec::new();
+    for expr in re_jinja_expression().find_iter(text) {
+        let expr_text = expr.as_str();
+        let strings = string_spans(expr_text);
+        for cap in re_call().captures_iter(expr_text) {
+            let Some(m) = cap.get(1) else { continue };
+            if strings.iter().any(|s| s.contains(&m.start())) {
+                continue;
+            }
+            if is_macro_call_candidate(m.as_str(), &expr_text[..m.start()]) {
+                let start = expr.start() + m.start();
+                calls.push((m.as_str().to_string(), start..start + m.len()));
+            }
+        }
+    }
+    calls
+}
+
+/// The source text of a call argument starting at `start`, up to the `,` or `)` that
+/// ends it at the same nesting depth.
+fn argument_text(text: &str, start: usize) -> Option<&str> {
+    let mut depth = 0;
+    let mut quote: Option<char> = None;
+    for (idx, c) in text[start..].char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '(' | '[' | '{') => depth += 1,
+            (None, ')' | ']' | '}' | ',') if depth == 0 => return Some(text[start..start + idx].trim()),
+            (None, ')' | ']' | '}') => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Finds calls like `var('name')` / `var('name', default)` inside jinja blocks, given a
+/// regex capturing the name (group 1) and an optional comma (group 2). Returns the
+/// name, the default's source text, and the byte range from the function name to the
+/// name's closing quote.
+fn extract_named_calls(text: &str, re: &Regex) -> Vec<(String, Option<String>, std::ops::Range<usize>)> {
+    let mut calls = Vec::new();
+    for expr in re_jinja_expression().find_iter(text) {
+        let expr_text = expr.as_str();
+        let strings = string_spans(expr_text);
+        for cap in re.captures_iter(expr_text) {
+            let (Some(full), Some(name)) = (cap.get(0), cap.get(1)) else { continue };
+            if strings.iter().any(|s| s.contains(&full.start())) || expr_text[..full.start()].trim_end().ends_with('.') {
+                continue;
+            }
+            let default = cap.get(2)
+                .and_then(|comma| argument_text(expr_text, comma.end()))
+                .map(str::to_string);
+            let start = expr.start() + full.start();
+            let end = expr.start() + name.end() + 1;
+            calls.push((name.as_str().to_string(), default, start..end));
+        }
+    }
+    calls
+}
+
+/// Byte ranges of `this` inside jinja blocks (`{{ this }}`, `{{ this.schema }}`,
+/// `{% if this is ... %}`), skipping string literals and attribute accesses like `x.this`.
+fn extract_this_refs(text: &str) -> Vec<std::ops::Range<usize>> {
+    let mut ranges = Vec::new();
+    for expr in re_jinja_expression().find_iter(text) {
+        let expr_text = expr.as_str();
+        let strings = string_spans(expr_text);
+        for m in re_this_word().find_iter(expr_text) {
+            if strings.iter().any(|s| s.contains(&m.start())) || expr_text[..m.start()].trim_end().ends_with('.') {
+                continue;
+            }
+            ranges.push(expr.start() + m.start()..expr.start() + m.end());
+        }
+    }
+    ranges
 }
 
 fn re_generic_jinja() -> &'static Regex {
@@ -55,12 +327,25 @@ fn preserve_newlines_replace(text: &str) -> String {
         if c == '\n' {
             out.push('\n');
         } else {
-            out.push(' ');
+            // Keep the byte length of multi-byte chars so tree-sitter offsets still line up
+            out.extend(std::iter::repeat_n(' ', c.len_utf8()));
         }
     }
     out
 }
 
+/// Replaces a jinja expression with a placeholder identifier, padded with spaces.
+/// The identifier must fit on the first line of the match so that any newlines
+/// inside the expression survive; otherwise the whole match is blanked.
+fn replace_with_ident(full_match: &str, ident: &str) -> String {
+    let first_line_len = full_match.find('\n').unwrap_or(full_match.len());
+    if ident.len() > first_line_len || !full_match.is_char_boundary(ident.len()) {
+        preserve_newlines_replace(full_match)
+    } else {
+        format!("{}{}", ident, preserve_newlines_replace(&full_match[ident.len()..]))
+    }
+}
+
 /// Preprocesses SQL text by replacing Jinja constructs with valid SQL identifiers
 /// so that Tree-sitter can parse the structure.
 /// Cruatilly, this preserves the byte length of the text so that tree-sitter ranges
@@ -71,15 +356,9 @@ pub fn preprocess_for_parsing(text: &str) -> String {
     // 1. Replace Refs: {{ ref('model') }} -> __DBT_REF_model_______
     let result = re_ref().replace_all(&result, |caps: &Captures| {
         let full_match = &caps[0];
-        let model_name = &caps[1];
+        let model_name = caps.get(2).or(caps.get(1)).map_or("", |m| m.as_str());
         let desired_ident = format!("__DBT_REF_{}", model_name);
-        
-        if desired_ident.len() > full_match.len() {
-             preserve_newlines_replace(full_match)
-        } else {
-             let padding = " ".repeat(full_match.len() - desired_ident.len());
-             format!("{}{}", desired_ident, padding)
-        }
+        replace_with_ident(full_match, &desired_ident)
     });
 
     let result = re_source().replace_all(&result, |caps: &Captures| {
@@ -87,13 +366,7 @@ pub fn preprocess_for_parsing(text: &str) -> String {
         let src_name = &caps[1];
         let tbl_name = &caps[2];
         let desired_ident = format!("__DBT_SRC_{}_{}", src_name, tbl_name);
-         
-        if desired_ident.len() > full_match.len() {
-             preserve_newlines_replace(full_match)
-        } else {
-             let padding = " ".repeat(full_match.len() - desired_ident.len());
-             format!("{}{}", desired_ident, padding)
-        }
+        replace_with_ident(full_match, &desired_ident)
     });
 
 
@@ -139,8 +412,16 @@ pub fn extract_refs(text: &str) -> Vec<(DbtRef, std::ops::Range<usize>)> {
     
     for cap in re_ref().captures_iter(text) {
         if let Some(full) = cap.get(0) {
-            if let Some(m) = cap.get(1) {
-                refs.push((DbtRef::Model(m.as_str().to_string()), full.range()));
+            match (cap.get(1), cap.get(2)) {
+                // A package model's versions aren't indexed; it resolves like an unversioned ref
+                (Some(pkg), Some(m)) => {
+                    refs.push((DbtRef::PackageModel(pkg.as_str().to_string(), m.as_str().to_string()), full.range()));
+                }
+                (Some(m), None) => match cap.get(3) {
+                    Some(v) => refs.push((DbtRef::VersionedModel(m.as_str().to_string(), v.as_str().to_string()), full.range())),
+                    None => refs.push((DbtRef::Model(m.as_str().to_string()), full.range())),
+                },
+                _ => {}
             }
         }
     }
@@ -155,20 +436,59 @@ pub fn extract_refs(text: &str) -> Vec<(DbtRef, std::ops::Range<usize>)> {
         }
     }
 
-    for cap in re_macro_call().captures_iter(text) {
-        if let Some(full) = cap.get(0) {
-            if let Some(m) = cap.get(1) {
-                let name = m.as_str();
-                if name != "ref" && name != "source" && name != "config" && name != "var" && name != "env_var" {
-                    refs.push((DbtRef::Macro(name.to_string()), full.range()));
-                }
-            }
+    for cap in re_doc().captures_iter(text) {
+        if let (Some(full), Some(m)) = (cap.get(0), cap.get(1)) {
+            refs.push((DbtRef::Doc(m.as_str().to_string()), full.range()));
         }
     }
+
+    for (name, default, range) in extract_named_calls(text, re_var()) {
+        refs.push((DbtRef::Var(name, default), range));
+    }
+
+    for (name, default, range) in extract_named_calls(text, re_env_var()) {
+        refs.push((DbtRef::EnvVar(name, default), range));
+    }
+
+    // Usually an argument, as in `metrics.calculate(metric('revenue'), ...)`
+    for (name, _, range) in extract_named_calls(text, re_metric()) {
+        refs.push((DbtRef::Metric(name), range));
+    }
+
+    for range in extract_this_refs(text) {
+        refs.push((DbtRef::This, range));
+    }
+
+    for (name, range) in extract_macro_calls(text) {
+        refs.push((DbtRef::Macro(name), range));
+    }
     
     refs
 }
 
+/// Byte ranges of the `{# ... #}` comments in `text`.
+pub fn comment_spans(text: &str) -> Vec<std::ops::Range<usize>> {
+    re_jinja_comment().find_iter(text).map(|m| m.range()).collect()
+}
+
+/// Byte ranges of the quoted model name in every `{{ ref('name') }}` call for `name`.
+pub fn find_model_ref_names(text: &str, name: &str) -> Vec<std::ops::Range<usize>> {
+    quoted_model_names(re_ref(), text, name)
+}
+
+/// [`find_model_ref_names`] for the bare `ref('name')` calls of a yml file.
+pub fn find_yaml_model_ref_names(text: &str, name: &str) -> Vec<std::ops::Range<usize>> {
+    quoted_model_names(re_bare_ref(), text, name)
+}
+
+fn quoted_model_names(re: &Regex, text: &str, name: &str) -> Vec<std::ops::Range<usize>> {
+    re.captures_iter(text)
+        .filter_map(|cap| cap.get(2).or(cap.get(1)))
+        .filter(|m| m.as_str() == name)
+        .map(|m| m.range())
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -183,4 +503,95 @@ mod tests {
         
         println!("Input:  {:?}\nOutput: {:?}", input, output);
     }
+
+    #[test]
+    fn test_extract_macro_calls() {
+        let input = "{% macro helper(x) %}{{ x | join(',') }}{% endmacro %}\n\
+            select {{ cents_to_dollars('amount') }}, {{ my_project.fmt(\"ref(\") }}\n\
+            {% set cols = dbt_utils.star(ref('a')) %}{% do cols.append(adapter.quote('x')) %}\n\
+            {% if is_incremental() %}{{ ref('b') }}{% endif %}";
+        let names: Vec<String> = extract_refs(input)
+            .into_iter()
+            .filter_map(|(r, range)| match r {
+                DbtRef::Macro(name) => {
+                    assert_eq!(&input[range], name.as_str());
+                    Some(name)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names, vec!["cents_to_dollars", "my_project.fmt", "dbt_utils.star"]);
+    }
+
+    #[test]
+    fn test_dbt_context_functions_are_not_macro_calls() {
+        let input = "{{ config(materialized='incremental') }}\n\
+            {% call statement('max_id', fetch_result=True) %}select max(id) from {{ this }}{% endcall %}\n\
+            {% set max_id = load_result('max_id')['data'][0][0] %}\n\
+            select *, '{{ run_started_at.strftime(\"%Y-%m-%d\") }}' as loaded_on, '{{ local_md5(invocation_id) }}' as batch\n\
+            from {{ ref('events') }}\n\
+            {% if is_incremental() and not should_full_refresh() %}where id > {{ max_id }}{% endif %}\n\
+            {% call wrap_in_transaction() %}select 1{% endcall %}";
+        let names: Vec<String> = extract_refs(input)
+            .into_iter()
+            .filter_map(|(r, _)| match r {
+                DbtRef::Macro(name) => Some(name),
+                _ => None,
+            })
+            .collect();
+        // `{% call %}` calls its macro rather than defining one
+        assert_eq!(names, vec!["wrap_in_transaction"]);
+    }
+
+    #[test]
+    fn test_extract_var_calls() {
+        let input = "where d >= '{{ var(\"start_date\") }}'\n\
+            {% if var('full_refresh', false) %}{{ log(\"var('nope')\") }}{% endif %}";
+        let vars: Vec<(DbtRef, &str)> = extract_refs(input)
+            .into_iter()
+            .filter(|(r, _)| matches!(r, DbtRef::Var(..)))
+            .map(|(r, range)| (r, &input[range]))
+            .collect();
+        assert_eq!(vars, vec![
+            (DbtRef::Var("start_date".to_string(), None), "var(\"start_date\""),
+            (DbtRef::Var("full_refresh".to_string(), Some("false".to_string())), "var('full_refresh'"),
+        ]);
+    }
+
+    #[test]
+    fn test_extract_versioned_refs() {
+        let input = "{{ ref('dim_customers', v=2) }} {{ ref(\"dim_customers\", version='1') }} {{ ref('dim_customers') }}";
+        let refs: Vec<DbtRef> = extract_refs(input).into_iter().map(|(r, _)| r).collect();
+        assert_eq!(refs, vec![
+            DbtRef::VersionedModel("dim_customers".to_string(), "2".to_string()),
+            DbtRef::VersionedModel("dim_customers".to_string(), "1".to_string()),
+            DbtRef::Model("dim_customers".to_string()),
+        ]);
+        assert!(preprocess_for_parsing(input).starts_with("__DBT_REF_dim_customers"));
+    }
+
+    #[test]
+    fn test_extract_metric_calls() {
+        let input = "select * from {{ metrics.calculate(metric('revenue'), grain='week') }}\n-- metric('not_jinja')";
+        let metrics: Vec<(DbtRef, &str)> = extract_refs(input)
+            .into_iter()
+            .filter(|(r, _)| matches!(r, DbtRef::Metric(_)))
+            .map(|(r, range)| (r, &input[range]))
+            .collect();
+        assert_eq!(metrics, vec![(DbtRef::Metric("revenue".to_string()), "metric('revenue'")]);
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let text = "{{\n  config(materialized='incremental', unique_key=\"id\",\n    partition_by={'field': 'day', 'data_type': 'date'}, tags=['finance', 'daily'])\n}}\nselect 1";
+        let config = parse_config(text).unwrap();
+        let keys: Vec<&str> = config.entries.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(keys, vec!["materialized", "unique_key", "partition_by", "tags"]);
+        assert_eq!(config.get("materialized"), Some("incremental"));
+        assert_eq!(config.get("unique_key"), Some("id"));
+        assert_eq!(config.get("tags"), Some("['finance', 'daily']"));
+        let partition = &config.entries[2];
+        assert_eq!(&text[partition.value_range.clone()], "{'field': 'day', 'data_type': 'date'}");
+        assert_eq!(&text[config.range.clone()], &text[..text.find("\nselect").unwrap()]);
+    }
 }
@@ -0,0 +1,70 @@
+use crate::jinja::DbtRef;
+use crate::position::PositionEncoding;
+use crate::project::ProjectManifest;
+use crate::references::{declaration, find_references, ReferenceTarget};
+use crate::state::DocumentState;
+use dashmap::DashMap;
+use tower_lsp::lsp_types::{CodeLens, Command, Location, Position, Range, Url};
+
+/// Command behind the dependency lenses. Arguments: the model's URI and "upstream" or
+/// "downstream". Returns the locations, or opens the target when there is only one.
+pub const SHOW_DEPENDENCIES: &str = "dbt.showDependencies";
+
+fn file_location(path: &std::path::Path, line: usize) -> Option<Location> {
+    let position = Position::new(line as u32, 0);
+    Some(Location { uri: Url::from_file_path(path).ok()?, range: Range::new(position, position) })
+}
+
+/// The definitions of the models, seeds, snapshots and sources among a document's `refs`,
+/// once each. Refs the manifest doesn't know are left out.
+pub fn upstream(
+    manifest: &ProjectManifest,
+    documents: &DashMap<Url, DocumentState>,
+    refs: &[(DbtRef, std::ops::Range<usize>)],
+    encoding: PositionEncoding,
+) -> Vec<Location> {
+    let mut locations: Vec<Location> = Vec::new();
+    for (dbt_ref, _) in refs {
+        let resolved = manifest.ref_target_name(dbt_ref);
+        let location = match dbt_ref {
+            DbtRef::Model(_) | DbtRef::VersionedModel(..) => resolved.as_ref().and_then(|name| manifest.models.get(name).and_then(|p| file_location(&p, 0))
+                .or_else(|| manifest.seeds.get(name).and_then(|p| file_location(&p, 0)))
+                .or_else(|| manifest.snapshots.get(name).and_then(|s| file_location(&s.path, s.line)))),
+            DbtRef::PackageModel(pkg, name) => manifest.resolve_package_model(pkg, name).and_then(|p| file_location(&p, 0)),
+            DbtRef::Source(src, tbl) => declaration(manifest, documents, &ReferenceTarget::Source(src.clone(), tbl.clone()), encoding),
+            _ => None,
+        };
+        if let Some(location) = location.filter(|l| !locations.contains(l)) {
+            locations.push(location);
+        }
+    }
+    locations
+}
+
+/// The first ref of `model` in each file that uses it. Needs the reference index.
+pub fn downstream(manifest: &ProjectManifest, model: &str, encoding: PositionEncoding) -> Vec<Location> {
+    let mut locations: Vec<Location> = Vec::new();
+    for location in find_references(manifest, &ReferenceTarget::Model(model.to_string()), encoding) {
+        if !locations.iter().any(|l| l.uri == location.uri) {
+            locations.push(location);
+        }
+    }
+    locations
+}
+
+/// The upstream and downstream lenses shown on the first line of a model.
+pub fn dependency_lenses(uri: &Url, upstream: usize, downstream: usize) -> Vec<CodeLens> {
+    let lens = |title: String, direction: &str| CodeLens {
+        range: Range::new(Position::new(0, 0), Position::new(0, 0)),
+        command: Some(Command {
+            title,
+            command: SHOW_DEPENDENCIES.to_string(),
+            arguments: Some(vec![serde_json::json!(uri), serde_json::json!(direction)]),
+        }),
+        data: None,
+    };
+    vec![
+        lens(format!("⬆ {} upstream", upstream), "upstream"),
+        lens(format!("⬇ {} downstream", downstream), "downstream"),
+    ]
+}
@@ -0,0 +1,319 @@
+use crate::hierarchy::{self, DagNode};
+use crate::project::ProjectManifest;
+use serde::Serialize;
+use std::path::PathBuf;
+use tower_lsp::lsp_types::Url;
+
+/// How many levels `dbt-lsp.showLineage` walks when no depth is given.
+pub const DEFAULT_DEPTH: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Upstream,
+    Downstream,
+    Both,
+}
+
+impl Direction {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "upstream" => Some(Direction::Upstream),
+            "downstream" => Some(Direction::Downstream),
+            "both" => Some(Direction::Both),
+            _ => None,
+        }
+    }
+}
+
+/// The node's name as a markdown link to its definition. Sources link to their yml line.
+fn node_link(manifest: &ProjectManifest, node: &DagNode) -> String {
+    let label = match node {
+        DagNode::Model { name } => name.clone(),
+        DagNode::Seed { name } => format!("seed {}", name),
+        DagNode::Snapshot { name } => format!("snapshot {}", name),
+        DagNode::Test { name } => format!("test {}", name),
+        DagNode::Source { source, table } => format!("source {}.{}", source, table),
+        DagNode::Exposure { name } => format!("exposure {}", name),
+    };
+    let target = node.location(manifest)
+        .and_then(|(path, line, _)| Some((Url::from_file_path(path).ok()?, line)));
+    match target {
+        Some((uri, 0)) => format!("[{}]({})", label, uri),
+        Some((uri, line)) => format!("[{}]({}#L{})", label, uri, line + 1),
+        None => label,
+    }
+}
+
+fn neighbours(manifest: &ProjectManifest, node: &DagNode, direction: Direction) -> Vec<DagNode> {
+    match direction {
+        Direction::Upstream => hierarchy::upstream(manifest, node),
+        _ => hierarchy::downstream(manifest, node),
+    }
+}
+
+/// Appends the neighbours of the last node of `path`, one list item per line. A node
+/// already on the path is marked as a cycle instead of being expanded again, and nodes
+/// cut off by the depth limit get an ellipsis when they have neighbours of their own.
+fn render_children(manifest: &ProjectManifest, path: &mut Vec<DagNode>, direction: Direction, max_depth: usize, out: &mut String) {
+    let arrow = if direction == Direction::Upstream { "←" } else { "→" };
+    let depth = path.len();
+    let Some(node) = path.last().cloned() else { return };
+    for child in neighbours(manifest, &node, direction) {
+        let indent = "  ".repeat(depth);
+        let link = node_link(manifest, &child);
+        if path.contains(&child) {
+            out.push_str(&format!("{}- {} {} ↻ *cycle*\n", indent, arrow, link));
+        } else if depth >= max_depth {
+            let more = if neighbours(manifest, &child, direction).is_empty() { "" } else { " …" };
+            out.push_str(&format!("{}- {} {}{}\n", indent, arrow, link, more));
+        } else {
+            out.push_str(&format!("{}- {} {}\n", indent, arrow, link));
+            path.push(child);
+            render_children(manifest, path, direction, max_depth, out);
+            path.pop();
+        }
+    }
+}
+
+fn tree(manifest: &ProjectManifest, root: &DagNode, direction: Direction, max_depth: usize) -> String {
+    let mut out = format!("- {}\n", node_link(manifest, root));
+    render_children(manifest, &mut vec![root.clone()], direction, max_depth, &mut out);
+    out
+}
+
+/// The lineage of `root` as a nested markdown list, `max_depth` levels deep. With
+/// `Direction::Both` the upstream and downstream trees follow each other under headings.
+/// Needs the reference index.
+pub fn lineage_markdown(manifest: &ProjectManifest, root: &DagNode, direction: Direction, max_depth: usize) -> String {
+    match direction {
+        Direction::Both => format!(
+            "**Upstream**\n\n{}\n**Downstream**\n\n{}",
+            tree(manifest, root, Direction::Upstream, max_depth),
+            tree(manifest, root, Direction::Downstream, max_depth),
+        ),
+        _ => tree(manifest, root, direction, max_depth),
+    }
+}
+
+/// Everything that transitively refs `root`, breadth first, each with its distance from
+/// `root`. Nodes reachable along several paths are listed once, at their shortest distance.
+/// Needs the reference index.
+pub fn downstream_closure(manifest: &ProjectManifest, root: &DagNode, max_depth: Option<usize>) -> Vec<(DagNode, usize)> {
+    let mut found: Vec<(DagNode, usize)> = Vec::new();
+    let mut frontier = vec![root.clone()];
+    let mut depth = 0;
+    while !frontier.is_empty() && max_depth.is_none_or(|max| depth < max) {
+        depth += 1;
+        let mut next = Vec::new();
+        for node in &frontier {
+            for child in hierarchy::downstream(manifest, node) {
+                if child != *root && !found.iter().any(|(n, _)| *n == child) {
+                    found.push((child.clone(), depth));
+                    next.push(child);
+                }
+            }
+        }
+        frontier = next;
+    }
+    found
+}
+
+/// A node of the exported project graph.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GraphNode {
+    /// dbt's unique id: `model.<project>.<name>`, `source.<project>.<source>.<table>`, ...
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub path: PathBuf,
+    /// The model's materialization when its config or dbt_project.yml sets one.
+    pub materialized: Option<String>,
+    pub package: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct GraphEdge {
+    pub from: String,
+    pub to: String,
+}
+
+/// The project's whole DAG, as exported by `dbt-lsp.exportGraph`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ProjectGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+fn node_id(manifest: &ProjectManifest, node: &DagNode) -> String {
+    let project = &manifest.config.name;
+    match node {
+        DagNode::Model { name } => format!("model.{}.{}", project, name),
+        DagNode::Seed { name } => format!("seed.{}.{}", project, name),
+        DagNode::Snapshot { name } => format!("snapshot.{}.{}", project, name),
+        DagNode::Test { name } => format!("test.{}.{}", project, name),
+        DagNode::Source { source, table } => format!("source.{}.{}.{}", project, source, table),
+        DagNode::Exposure { name } => format!("exposure.{}.{}", project, name),
+    }
+}
+
+fn graph_node(manifest: &ProjectManifest, node: &DagNode) -> Option<GraphNode> {
+    let (path, _, _) = node.location(manifest)?;
+    let (name, kind, materialized) = match node {
+        DagNode::Model { name } => {
+            let in_file = manifest.references.get(&path)
+                .and_then(|file| crate::jinja::parse_config(&file.text.to_string()))
+                .and_then(|config| config.get("materialized").map(str::to_string));
+            let materialized = in_file.or_else(|| {
+                crate::relation::model_folder_config(manifest, &path, "materialized").and_then(|v| v.as_str().map(str::to_string))
+            });
+            (name.clone(), "model", materialized)
+        }
+        DagNode::Seed { name } => (name.clone(), "seed", Some("seed".to_string())),
+        DagNode::Snapshot { name } => (name.clone(), "snapshot", Some("snapshot".to_string())),
+        DagNode::Test { name } => (name.clone(), "test", None),
+        DagNode::Source { source, table } => (format!("{}.{}", source, table), "source", None),
+        DagNode::Exposure { name } => (name.clone(), "exposure", None),
+    };
+    Some(GraphNode { id: node_id(manifest, node), name, kind, path, materialized, package: manifest.config.name.clone() })
+}
+
+/// Every model, seed, snapshot, singular test, source and exposure of the project, and an
+/// edge from each node to whatever refs it. Sorted by id. Needs the reference index.
+pub fn project_graph(manifest: &ProjectManifest) -> ProjectGraph {
+    let mut dag_nodes: Vec<DagNode> = Vec::new();
+    dag_nodes.extend(manifest.models.iter().map(|e| DagNode::Model { name: e.key().clone() }));
+    dag_nodes.extend(manifest.seeds.iter().map(|e| DagNode::Seed { name: e.key().clone() }));
+    dag_nodes.extend(manifest.snapshots.iter().map(|e| DagNode::Snapshot { name: e.key().clone() }));
+    dag_nodes.extend(manifest.singular_tests.iter().map(|e| DagNode::Test { name: e.key().clone() }));
+    dag_nodes.extend(manifest.sources.iter().map(|e| DagNode::Source { source: e.source_name.clone(), table: e.table_name.clone() }));
+    dag_nodes.extend(manifest.exposures.iter().map(|e| DagNode::Exposure { name: e.key().clone() }));
+
+    let mut nodes: Vec<GraphNode> = dag_nodes.iter().filter_map(|n| graph_node(manifest, n)).collect();
+    nodes.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let mut edges: Vec<GraphEdge> = Vec::new();
+    for file in manifest.references.iter() {
+        let Some(to) = DagNode::for_file(manifest, file.key()) else { continue };
+        for (dbt_ref, _) in &file.refs {
+            if let Some(from) = DagNode::from_ref(manifest, dbt_ref) {
+                edges.push(GraphEdge { from: node_id(manifest, &from), to: node_id(manifest, &to) });
+            }
+        }
+    }
+    for exposure in manifest.exposures.iter() {
+        let to = DagNode::Exposure { name: exposure.key().clone() };
+        for (dbt_ref, _) in &exposure.depends_on {
+            if let Some(from) = DagNode::from_ref(manifest, dbt_ref) {
+                edges.push(GraphEdge { from: node_id(manifest, &from), to: node_id(manifest, &to) });
+            }
+        }
+    }
+    edges.sort();
+    edges.dedup();
+    ProjectGraph { nodes, edges }
+}
+
+impl ProjectGraph {
+    /// The graph in Graphviz DOT, left to right, with a shape per node type.
+    pub fn to_dot(&self) -> String {
+        let quoted = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut out = String::from("digraph dbt {\n  rankdir=LR;\n");
+        for node in &self.nodes {
+            let shape = match node.kind {
+                "source" => "cylinder",
+                "seed" => "note",
+                "snapshot" => "box3d",
+                "test" => "hexagon",
+                "exposure" => "house",
+                _ => "box",
+            };
+            out.push_str(&format!("  {} [label={}, shape={}];\n", quoted(&node.id), quoted(&node.name), shape));
+        }
+        for edge in &self.edges {
+            out.push_str(&format!("  {} -> {};\n", quoted(&edge.from), quoted(&edge.to)));
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lineage_markdown() {
+        let root = std::env::temp_dir().join(format!("dbt-lsp-lineage-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("models")).unwrap();
+        std::fs::write(root.join("dbt_project.yml"), "name: shop\n").unwrap();
+        std::fs::write(root.join("models").join("sources.yml"), "sources:\n  - name: raw\n    tables:\n      - name: orders\n").unwrap();
+        std::fs::write(root.join("models").join("stg_orders.sql"), "select * from {{ source('raw', 'orders') }}").unwrap();
+        std::fs::write(root.join("models").join("fct_orders.sql"), "select * from {{ ref('stg_orders') }} join {{ ref('loop_a') }}").unwrap();
+        std::fs::write(root.join("models").join("loop_a.sql"), "select * from {{ ref('loop_b') }}").unwrap();
+        std::fs::write(root.join("models").join("loop_b.sql"), "select * from {{ ref('loop_a') }}").unwrap();
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+        manifest.ensure_reference_index();
+        let link = |name: &str| format!("[{}]({})", name, Url::from_file_path(root.join("models").join(format!("{}.sql", name))).unwrap());
+        let fct = DagNode::Model { name: "fct_orders".to_string() };
+
+        let sources = Url::from_file_path(root.join("models").join("sources.yml")).unwrap();
+        assert_eq!(lineage_markdown(&manifest, &fct, Direction::Upstream, 5), format!(
+            "- {}\n  - ← {}\n    - ← [source raw.orders]({}#L4)\n  - ← {}\n    - ← {}\n      - ← {} ↻ *cycle*\n",
+            link("fct_orders"), link("stg_orders"), sources, link("loop_a"), link("loop_b"), link("loop_a"),
+        ));
+        // Cut off at the depth limit, with a marker where there is more
+        assert_eq!(lineage_markdown(&manifest, &fct, Direction::Upstream, 1), format!(
+            "- {}\n  - ← {} …\n  - ← {} …\n",
+            link("fct_orders"), link("stg_orders"), link("loop_a"),
+        ));
+        let stg = DagNode::Model { name: "stg_orders".to_string() };
+        assert_eq!(lineage_markdown(&manifest, &stg, Direction::Downstream, 5), format!("- {}\n  - → {}\n", link("stg_orders"), link("fct_orders")));
+        assert!(lineage_markdown(&manifest, &stg, Direction::Both, 5).starts_with("**Upstream**\n\n"));
+
+        let model = |name: &str| DagNode::Model { name: name.to_string() };
+        let source = DagNode::Source { source: "raw".to_string(), table: "orders".to_string() };
+        assert_eq!(downstream_closure(&manifest, &source, None), vec![(model("stg_orders"), 1), (model("fct_orders"), 2)]);
+        assert_eq!(downstream_closure(&manifest, &source, Some(1)), vec![(model("stg_orders"), 1)]);
+        // The cycle ends once every member has been seen
+        assert_eq!(downstream_closure(&manifest, &model("loop_a"), None), vec![(model("fct_orders"), 1), (model("loop_b"), 1)]);
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_project_graph() {
+        let root = std::env::temp_dir().join(format!("dbt-lsp-project-graph-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("models").join("marts")).unwrap();
+        std::fs::create_dir_all(root.join("seeds")).unwrap();
+        std::fs::write(root.join("dbt_project.yml"), "name: shop\nmodels:\n  shop:\n    marts:\n      +materialized: table\n").unwrap();
+        std::fs::write(root.join("models").join("sources.yml"), "sources:\n  - name: raw\n    tables:\n      - name: orders\n").unwrap();
+        std::fs::write(root.join("seeds").join("countries.csv"), "code,name\n").unwrap();
+        std::fs::write(root.join("models").join("stg_orders.sql"), "{{ config(materialized='view') }}\nselect * from {{ source('raw', 'orders') }}").unwrap();
+        std::fs::write(root.join("models").join("marts").join("fct_orders.sql"), "select * from {{ ref('stg_orders') }} join {{ ref('countries') }} join {{ ref('stg_orders') }}").unwrap();
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+        manifest.ensure_reference_index();
+
+        let graph = project_graph(&manifest);
+        let summary: Vec<(&str, &str, Option<&str>)> = graph.nodes.iter().map(|n| (n.id.as_str(), n.kind, n.materialized.as_deref())).collect();
+        assert_eq!(summary, vec![
+            ("model.shop.fct_orders", "model", Some("table")),
+            ("model.shop.stg_orders", "model", Some("view")),
+            ("seed.shop.countries", "seed", Some("seed")),
+            ("source.shop.raw.orders", "source", None),
+        ]);
+        let edge = |from: &str, to: &str| GraphEdge { from: from.to_string(), to: to.to_string() };
+        assert_eq!(graph.edges, vec![
+            edge("model.shop.stg_orders", "model.shop.fct_orders"),
+            edge("seed.shop.countries", "model.shop.fct_orders"),
+            edge("source.shop.raw.orders", "model.shop.stg_orders"),
+        ]);
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph dbt {\n  rankdir=LR;\n"));
+        assert!(dot.contains("  \"source.shop.raw.orders\" [label=\"raw.orders\", shape=cylinder];\n"));
+        assert!(dot.contains("  \"model.shop.stg_orders\" -> \"model.shop.fct_orders\";\n"));
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,75 @@
+use crate::jinja::DbtRef;
+use crate::position::{byte_range_to_range, PositionEncoding};
+use crate::project::ProjectManifest;
+use crate::state::DocumentState;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tower_lsp::lsp_types::{DocumentLink, Url};
+
+/// Stored in `DocumentLink.data` for links whose target is filled in by documentLink/resolve.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkData {
+    /// Root of the project the link was made in.
+    pub root: PathBuf,
+    pub source: String,
+    pub table: String,
+}
+
+/// A file URL, opened at the zero-based `line` when there is one.
+fn file_target(path: &Path, line: Option<usize>) -> Option<Url> {
+    let mut url = Url::from_file_path(path).ok()?;
+    if let Some(line) = line {
+        url.set_fragment(Some(&format!("L{}", line + 1)));
+    }
+    Some(url)
+}
+
+/// One link per ref, source and macro call in `doc` whose target exists in `manifest`.
+/// Source links carry [`LinkData`] and get their yml line on resolve.
+pub fn document_links(doc: &DocumentState, manifest: &ProjectManifest, encoding: PositionEncoding) -> Vec<DocumentLink> {
+    let mut links = Vec::new();
+    for (dbt_ref, range) in &doc.refs {
+        let (target, tooltip, data) = match dbt_ref {
+            DbtRef::Model(name) | DbtRef::VersionedModel(name, _) => {
+                let resolved = manifest.ref_target_name(dbt_ref).unwrap_or_else(|| name.clone());
+                let target = manifest.models.get(&resolved).map(|p| p.value().clone())
+                    .or_else(|| manifest.seeds.get(&resolved).map(|p| p.value().clone()))
+                    .and_then(|p| file_target(&p, None))
+                    .or_else(|| manifest.snapshots.get(&resolved).and_then(|s| file_target(&s.path, Some(s.line))));
+                (target, format!("Open {}", name), None)
+            }
+            DbtRef::PackageModel(pkg, name) => {
+                let target = manifest.resolve_package_model(pkg, name).and_then(|p| file_target(&p, None));
+                (target, format!("Open {}.{}", pkg, name), None)
+            }
+            DbtRef::Source(src, tbl) => {
+                if !manifest.sources.contains_key(&format!("{}.{}", src, tbl)) {
+                    continue;
+                }
+                let data = LinkData { root: manifest.root_dir.clone(), source: src.clone(), table: tbl.clone() };
+                (None, format!("Open source {}.{}", src, tbl), serde_json::to_value(data).ok())
+            }
+            DbtRef::Macro(name) => {
+                let target = manifest.resolve_macro(name).and_then(|m| file_target(&m.path, Some(m.line)));
+                (target, format!("Open macro {}", name), None)
+            }
+            _ => continue,
+        };
+        if target.is_none() && data.is_none() {
+            continue;
+        }
+        links.push(DocumentLink {
+            range: byte_range_to_range(&doc.text, range, encoding),
+            target,
+            tooltip: Some(tooltip),
+            data,
+        });
+    }
+    links
+}
+
+/// The yml location of the source table behind a resolved link.
+pub fn resolve_target(manifest: &ProjectManifest, data: &LinkData) -> Option<Url> {
+    let source = manifest.sources.get(&format!("{}.{}", data.source, data.table))?;
+    file_target(&source.path, Some(source.line))
+}
@@ -3,8 +3,32 @@ mod state;
 mod parser;
 mod jinja;
 mod diagnostics;
+mod position;
+mod settings;
+mod rename;
+mod yaml;
+mod columns;
+mod hover;
+mod relation;
+mod profiles;
+mod completion;
+mod references;
+mod symbols;
+mod selection;
+mod links;
+mod lenses;
+mod hints;
+mod semantic;
+mod format;
+mod hierarchy;
+mod actions;
+mod commands;
+mod artifacts;
+mod lineage;
+mod stats;
 
 use crate::state::GlobalState;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::*;
@@ -23,51 +47,131 @@ impl LanguageServer for Backend {
             .log_message(MessageType::INFO, "dbt-lsp initializing...")
             .await;
             
-        // Support both deprecated root_uri and modern workspace_folders
-        let root_path = params.root_uri.and_then(|u| u.to_file_path().ok())
-            .or_else(|| {
-                params.workspace_folders.as_ref().and_then(|folders| {
-                    folders.get(0).and_then(|f| f.uri.to_file_path().ok())
-                })
-            });
-
-        if let Some(path) = root_path {
-            self.client.log_message(MessageType::INFO, format!("Initializing at root: {:?}", path)).await;
-            match crate::project::ProjectManifest::load(path) {
-                Ok(manifest) => {
-                    let model_count = manifest.models.len();
-                    let msg = format!("Loaded dbt project: {} with {} models", manifest.config.name, model_count);
-                    self.client.log_message(MessageType::INFO, msg.clone()).await;
-                    self.client.show_message(MessageType::INFO, msg).await;
-                    *self.state.manifest.write().await = Some(Arc::new(manifest));
-                }
-                Err(e) => {
-                    let msg = format!("Failed to load dbt project: {}", e);
-                    self.client.log_message(MessageType::ERROR, msg.clone()).await;
-                    self.client.show_message(MessageType::ERROR, msg).await;
-                }
+        // Every workspace folder may be its own dbt project; fall back to the deprecated root_uri
+        let mut roots: Vec<std::path::PathBuf> = params.workspace_folders.as_ref()
+            .map(|folders| folders.iter().filter_map(|f| f.uri.to_file_path().ok()).collect())
+            .unwrap_or_default();
+        if roots.is_empty() {
+            if let Some(path) = params.root_uri.as_ref().and_then(|u| u.to_file_path().ok()) {
+                roots.push(path);
             }
-        } else {
+        }
+
+        let offered_encodings = params.capabilities.general.as_ref()
+            .and_then(|general| general.position_encodings.as_deref());
+        let encoding = crate::position::PositionEncoding::negotiate(offered_encodings);
+        *self.state.position_encoding.write().await = encoding;
+
+        // Indexing happens in `initialized` so the handshake isn't blocked by the scan
+        if roots.is_empty() {
             self.client.show_message(MessageType::WARNING, "No root directory detected. Manifest loading skipped.").await;
         }
+        for path in &roots {
+            self.client.log_message(MessageType::INFO, format!("Initializing at root: {:?}", path)).await;
+        }
+        *self.state.workspace_roots.write().await = roots;
+        *self.state.client_capabilities.write().await = params.capabilities;
+
+        if let Some(options) = params.initialization_options.as_ref() {
+            self.update_settings(options).await;
+        }
 
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::INCREMENTAL,
+                position_encoding: Some(encoding.kind()),
+                text_document_sync: Some(TextDocumentSyncCapability::Options(
+                    TextDocumentSyncOptions {
+                        open_close: Some(true),
+                        // A change without a range still replaces the whole text, so clients that
+                        // only send full text work as well
+                        change: Some(TextDocumentSyncKind::INCREMENTAL),
+                        // The saved text lets us recover a document whose incremental edits went out of sync
+                        save: Some(TextDocumentSyncSaveOptions::SaveOptions(SaveOptions {
+                            include_text: Some(true),
+                        })),
+                        ..TextDocumentSyncOptions::default()
+                    },
                 )),
                 hover_provider: Some(HoverProviderCapability::Simple(true)),
                 definition_provider: Some(OneOf::Left(true)),
+                references_provider: Some(OneOf::Left(true)),
+                document_highlight_provider: Some(OneOf::Left(true)),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+                inlay_hint_provider: Some(OneOf::Left(true)),
+                call_hierarchy_provider: Some(CallHierarchyServerCapability::Simple(true)),
+                code_action_provider: Some(CodeActionProviderCapability::Options(CodeActionOptions {
+                    code_action_kinds: Some(vec![CodeActionKind::QUICKFIX, CodeActionKind::REFACTOR, CodeActionKind::REFACTOR_EXTRACT, CodeActionKind::REFACTOR_REWRITE]),
+                    ..CodeActionOptions::default()
+                })),
+                document_formatting_provider: Some(OneOf::Left(true)),
+                document_range_formatting_provider: Some(OneOf::Left(true)),
+                semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(SemanticTokensOptions {
+                    legend: crate::semantic::legend(),
+                    range: Some(true),
+                    full: Some(SemanticTokensFullOptions::Bool(true)),
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                })),
+                code_lens_provider: Some(CodeLensOptions { resolve_provider: Some(false) }),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands: vec![
+                        crate::lenses::SHOW_DEPENDENCIES.to_string(),
+                        crate::commands::SHOW_COMPILED_SQL.to_string(),
+                        crate::commands::RUN_MODEL.to_string(),
+                        crate::commands::TEST_MODEL.to_string(),
+                        crate::commands::CLEAR_TEST_RESULTS.to_string(),
+                        crate::commands::RESCAN_PROJECT.to_string(),
+                        crate::commands::SHOW_LINEAGE.to_string(),
+                        crate::commands::DOWNSTREAM.to_string(),
+                        crate::commands::OPEN_TARGET_ARTIFACT.to_string(),
+                        crate::commands::PREVIEW_MODEL.to_string(),
+                        crate::commands::EXPORT_GRAPH.to_string(),
+                        crate::commands::PROJECT_STATS.to_string(),
+                    ],
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                }),
+                document_link_provider: Some(DocumentLinkOptions {
+                    resolve_provider: Some(true),
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                }),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                })),
                 completion_provider: Some(CompletionOptions {
                     trigger_characters: Some(vec!["'".to_string(), "\"".to_string()]),
+                    resolve_provider: Some(true),
                     ..CompletionOptions::default()
                 }),
+                workspace: Some(WorkspaceServerCapabilities {
+                    workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                        supported: Some(true),
+                        change_notifications: Some(OneOf::Left(true)),
+                    }),
+                    file_operations: Some(WorkspaceFileOperationsServerCapabilities {
+                        will_rename: Some(sql_file_operation_filter()),
+                        did_rename: Some(sql_file_operation_filter()),
+                        did_create: Some(project_file_operation_filter()),
+                        did_delete: Some(project_file_operation_filter()),
+                        ..WorkspaceFileOperationsServerCapabilities::default()
+                    }),
+                }),
                 ..ServerCapabilities::default()
             },
             ..InitializeResult::default()
         })
     }
 
+    async fn initialized(&self, _params: InitializedParams) {
+        self.register_file_watchers().await;
+
+        for root in self.project_roots().await {
+            self.index_project(root).await;
+        }
+    }
+
     async fn shutdown(&self) -> Result<()> {
         self.client
             .log_message(MessageType::INFO, "dbt-lsp shutting down...")
@@ -77,98 +181,229 @@ impl LanguageServer for Backend {
 
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
         let uri = params.text_document.uri;
-        let text = params.text_document.text;
-        
-        // 1. Preprocess for parsing (preserves length)
-        let preprocessed = crate::jinja::preprocess_for_parsing(&text);
-        
-        // 2. Parse (using preprocessed text)
-        let tree = if let Ok(mut parser) = crate::parser::DbtParser::new() {
-             parser.parse(&preprocessed, None)
-        } else {
-             None
-        };
-        
-        // 3. Extract Refs (using original text for semantics)
-        let refs = crate::jinja::extract_refs(&text);
-        
-        let rope = ropey::Rope::from_str(&text);
-
-        // 5. Generate and Publish Diagnostics
-        let manifest_guard = self.state.manifest.read().await;
-        let (diagnostics, ctes, aliases) = crate::diagnostics::validate_refs(&refs, manifest_guard.as_deref(), &rope, tree.as_ref());
-        
-        // 4. Update State
-        self.state.documents.insert(uri.clone(), crate::state::DocumentState {
-            text: rope.clone(),
-            tree,
-            refs: refs.clone(),
-            ctes,
-            aliases,
-            diagnostics: Vec::new(), 
-        });
-
-        self.client.publish_diagnostics(uri, diagnostics, None).await;
+        let rope = ropey::Rope::from_str(&params.text_document.text);
+        // The full text: nothing of an earlier copy of the document is kept
+        self.state.documents.remove(&uri);
+        self.analyze_document(uri, rope).await;
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
         let uri = params.text_document.uri;
+        let encoding = *self.state.position_encoding.read().await;
         
         // Scope for mutable access to update text
-        let full_text = {
+        let (rope, lost_sync) = {
             if let Some(mut doc) = self.state.documents.get_mut(&uri) {
+                let was_out_of_sync = doc.out_of_sync;
                 for change in params.content_changes {
                     if let Some(range) = change.range {
-                        let start_char_idx = doc.text.line_to_char(range.start.line as usize) + range.start.character as usize;
-                        let end_char_idx = doc.text.line_to_char(range.end.line as usize) + range.end.character as usize;
-                        
-                        if start_char_idx <= doc.text.len_chars() && end_char_idx <= doc.text.len_chars() {
-                            doc.text.remove(start_char_idx..end_char_idx);
-                            doc.text.insert(start_char_idx, &change.text);
+                        // Once an edit failed to apply, further incremental edits would only corrupt the rope more
+                        if doc.out_of_sync {
+                            continue;
+                        }
+                        let start = crate::position::position_to_char(&doc.text, range.start, encoding);
+                        let end = crate::position::position_to_char(&doc.text, range.end, encoding);
+
+                        match (start, end) {
+                            (Some(start_char_idx), Some(end_char_idx)) if start_char_idx <= end_char_idx => {
+                                doc.text.remove(start_char_idx..end_char_idx);
+                                doc.text.insert(start_char_idx, &change.text);
+                            }
+                            _ => doc.out_of_sync = true,
                         }
                     } else {
+                        // Full sync (or a full replacement within an incremental batch) resynchronises the document
                         doc.text = ropey::Rope::from_str(&change.text);
+                        doc.out_of_sync = false;
                     }
                 }
-                Some(doc.text.to_string())
+                let rope = if doc.out_of_sync { None } else { Some(doc.text.clone()) };
+                (rope, doc.out_of_sync && !was_out_of_sync)
             } else {
-                None
+                (None, false)
             }
         };
 
-        if let Some(text) = full_text {
-             // 1. Preprocess
-             let preprocessed = crate::jinja::preprocess_for_parsing(&text);
-             
-             // 2. Parse
-             let tree = if let Ok(mut parser) = crate::parser::DbtParser::new() {
-                 parser.parse(&preprocessed, None)
-             } else {
-                 None
-             };
-             
-             // 3. Extract Refs
-             let refs = crate::jinja::extract_refs(&text);
-             
-             // 4. Update State (need to re-acquire write lock or update via dashmap)
-             // We need rope for diagnostics, can create from text or clone from state
-             // But avoiding excessive locking/cloning.
-             // We can just get rope from state again or use the text we have.
-             let rope = ropey::Rope::from_str(&text);
-             
-             // 5. Generate and Publish Diagnostics
-             let manifest_guard = self.state.manifest.read().await;
-             let (diagnostics, ctes, aliases) = crate::diagnostics::validate_refs(&refs, manifest_guard.as_deref(), &rope, tree.as_ref());
-             
-             if let Some(mut doc) = self.state.documents.get_mut(&uri) {
-                 doc.tree = tree;
-                 doc.refs = refs.clone();
-                 doc.ctes = ctes;
-                 doc.aliases = aliases;
-             }
+        if lost_sync {
+            self.client.log_message(MessageType::ERROR, format!("Incremental edit out of bounds for {}; ignoring edits until the next full sync or save", uri)).await;
+            self.client.show_message(MessageType::WARNING, "dbt-lsp lost track of this document's contents. Save the file to resynchronise.").await;
+            self.publish_diagnostics(uri.clone(), Vec::new()).await;
+        }
+
+        if let Some(rope) = rope {
+            self.analyze_document(uri, rope).await;
+        }
+    }
+
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        let uri = params.text_document.uri;
+
+        // The saved file may be new to the manifest (e.g. created in the editor after the scan)
+        if let Ok(path) = uri.to_file_path() {
+            if let Some(manifest) = self.state.manifest_for_path(&path).await {
+                manifest.refresh_file(&path);
+                // yml isn't analysed, so its exposures' errors are published from here
+                if is_yaml_uri(&uri) {
+                    self.publish_project_file_diagnostics(&manifest).await;
+                }
+            }
+        }
+
+        // Without the text, a document whose edits went out of sync is re-read from the file just saved
+        let out_of_sync = self.state.documents.get(&uri).is_some_and(|doc| doc.out_of_sync);
+        let saved = params.text.or_else(|| {
+            out_of_sync.then(|| uri.to_file_path().ok().and_then(|path| std::fs::read_to_string(path).ok())).flatten()
+        });
+        let rope = match saved {
+            Some(text) => {
+                let rope = ropey::Rope::from_str(&text);
+                if let Some(mut doc) = self.state.documents.get_mut(&uri) {
+                    doc.text = rope.clone();
+                    doc.out_of_sync = false;
+                }
+                Some(rope)
+            }
+            None => self.state.documents.get(&uri).filter(|doc| !doc.out_of_sync).map(|doc| doc.text.clone()),
+        };
+        if let Some(rope) = rope {
+            self.analyze_document(uri, rope).await;
+        }
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri;
+
+        // Drop the rope, tree and derived maps; a later did_open rebuilds them from scratch.
+        if let Some((_, doc)) = self.state.documents.remove(&uri) {
+            if !doc.diagnostics.is_empty() {
+                self.publish_diagnostics(uri, Vec::new()).await;
+            }
+        }
+    }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        let changes = params.changes.into_iter()
+            .filter_map(|change| Some((change.uri.to_file_path().ok()?, change.typ == FileChangeType::DELETED)))
+            .collect();
+        self.apply_file_changes(changes).await;
+    }
+
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        let previous = self.state.settings.read().await.clone();
+        self.update_settings(&params.settings).await;
+        let current = self.state.settings.read().await.clone();
+
+        if current.affects_indexing(&previous) {
+            // The projects may now live elsewhere in the workspace
+            self.state.manifests.write().await.clear();
+            for root in self.project_roots().await {
+                self.index_project(root).await;
+            }
+        } else if current != previous {
+            self.revalidate_open_documents().await;
+        }
+        if current.inlay_hints != previous.inlay_hints {
+            let supported = self.state.client_capabilities.read().await.workspace.as_ref()
+                .and_then(|w| w.inlay_hint.as_ref())
+                .and_then(|h| h.refresh_support)
+                .unwrap_or(false);
+            if supported {
+                let _ = self.client.inlay_hint_refresh().await;
+            }
+        }
+    }
+
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        for folder in params.event.removed {
+            let Ok(root) = folder.uri.to_file_path() else { continue };
+            self.state.workspace_roots.write().await.retain(|r| r != &root);
+            self.state.manifests.write().await.retain(|project_root, _| !project_root.starts_with(&root));
+            self.client.log_message(MessageType::INFO, format!("Removed workspace folder: {:?}", root)).await;
+        }
+
+        let mut added = false;
+        for folder in params.event.added {
+            let Ok(root) = folder.uri.to_file_path() else { continue };
+            self.state.workspace_roots.write().await.push(root.clone());
+            let project_root = self.state.settings.read().await.project_root(&root);
+            match project_root {
+                Ok(project_root) => {
+                    self.index_project(project_root).await;
+                    added = true;
+                }
+                Err(e) => self.client.show_message(MessageType::ERROR, format!("dbt-lsp: {}", e)).await,
+            }
+        }
+
+        // index_project already re-validates; only removals need an explicit pass
+        if !added {
+            self.revalidate_open_documents().await;
+        }
+    }
+
+    async fn will_rename_files(&self, params: RenameFilesParams) -> Result<Option<WorkspaceEdit>> {
+        let encoding = *self.state.position_encoding.read().await;
+        let mut changes: std::collections::HashMap<Url, Vec<TextEdit>> = std::collections::HashMap::new();
+
+        for file in &params.files {
+            let (Ok(old_uri), Ok(new_uri)) = (Url::parse(&file.old_uri), Url::parse(&file.new_uri)) else { continue };
+            let (Ok(old_path), Ok(new_path)) = (old_uri.to_file_path(), new_uri.to_file_path()) else { continue };
+            if old_path.extension().is_none_or(|e| e != "sql") || new_path.extension().is_none_or(|e| e != "sql") {
+                continue;
+            }
+            let (Some(old_stem), Some(new_stem)) = (old_path.file_stem(), new_path.file_stem()) else { continue };
+            let (old_name, new_name) = (old_stem.to_string_lossy(), new_stem.to_string_lossy());
+            if old_name == new_name {
+                continue;
+            }
+
+            let Some(manifest) = self.state.manifest_for_path(&old_path).await else { continue };
+            if manifest.models.get(old_name.as_ref()).is_none_or(|p| *p != old_path) {
+                continue;
+            }
+
+            let edits = crate::rename::model_ref_edits(&manifest, &self.state.documents, &old_name, &new_name, encoding);
+            for (uri, mut file_edits) in edits {
+                changes.entry(uri).or_default().append(&mut file_edits);
+            }
+        }
+
+        if changes.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(WorkspaceEdit {
+            changes: Some(changes),
+            ..WorkspaceEdit::default()
+        }))
+    }
+
+    async fn did_create_files(&self, params: CreateFilesParams) {
+        // Refs to a new model resolve as soon as the open documents are revalidated
+        let changes = params.files.iter()
+            .filter_map(|file| Some((Url::parse(&file.uri).ok()?.to_file_path().ok()?, false)))
+            .collect();
+        self.apply_file_changes(changes).await;
+    }
+
+    async fn did_delete_files(&self, params: DeleteFilesParams) {
+        let changes = params.files.iter()
+            .filter_map(|file| Some((Url::parse(&file.uri).ok()?.to_file_path().ok()?, true)))
+            .collect();
+        self.apply_file_changes(changes).await;
+    }
 
-             self.client.publish_diagnostics(uri, diagnostics, None).await;
+    async fn did_rename_files(&self, params: RenameFilesParams) {
+        for file in params.files {
+            let (Ok(old_uri), Ok(new_uri)) = (Url::parse(&file.old_uri), Url::parse(&file.new_uri)) else { continue };
+            let (Ok(old_path), Ok(new_path)) = (old_uri.to_file_path(), new_uri.to_file_path()) else { continue };
+            if let Some(manifest) = self.state.manifest_for_path(&old_path).await {
+                manifest.remove_file(&old_path);
+            }
+            if let Some(manifest) = self.state.manifest_for_path(&new_path).await {
+                manifest.refresh_file(&new_path);
+            }
         }
+        self.revalidate_open_documents().await;
     }
 
     async fn goto_definition(
@@ -179,39 +414,47 @@ impl LanguageServer for Backend {
         let position = params.text_document_position_params.position;
 
         self.client.log_message(MessageType::INFO, format!("GotoDef request at {:?} in {}", position, uri)).await;
+        let encoding = *self.state.position_encoding.read().await;
 
         if let Some(doc) = self.state.documents.get(&uri) {
-             let line_idx = position.line as usize;
-             if line_idx >= doc.text.len_lines() {
-                 return Ok(None);
-             }
-
-             let line_start_char = doc.text.line_to_char(line_idx);
-             let char_idx = line_start_char + position.character as usize;
-             
-             if char_idx >= doc.text.len_chars() {
-                  return Ok(None);
-             }
+             let char_idx = match crate::position::position_to_char(&doc.text, position, encoding) {
+                 Some(idx) if idx < doc.text.len_chars() => idx,
+                 _ => return Ok(None),
+             };
              let byte_idx = doc.text.char_to_byte(char_idx);
 
              self.client.log_message(MessageType::INFO, format!("Byte idx: {}. Refs: {}", byte_idx, doc.refs.len())).await;
 
-             // 1. Check for CTEs (local definitions)
+             if is_yaml_uri(&uri) {
+                 return Ok(self.yaml_definition(&uri, &doc.text, char_idx).await);
+             }
+
+             // 1. Check for CTEs and table aliases (local definitions)
              if let Some(word) = get_word_at_pos(&doc.text, char_idx) {
-                 if let Some(cte_def) = doc.ctes.get(&word) {
-                     let range = &cte_def.name_range;
-                     let start_line = doc.text.byte_to_line(range.start);
-                     let start_char = range.start - doc.text.line_to_byte(start_line);
-                     let end_line = doc.text.byte_to_line(range.end);
-                     let end_char = range.end - doc.text.line_to_byte(end_line);
-                     
+                 let cte_def = doc.ctes.get(&word);
+                 let alias_def = doc.aliases.get(&word);
+                 // A name that is both a CTE and an alias means the CTE only on its definition
+                 // or inside the from/join text that introduces the alias
+                 let in_cte_scope = cte_def.is_some_and(|c| c.name_range.contains(&byte_idx))
+                     || alias_def.is_some_and(|a| a.reference_range.contains(&byte_idx));
+
+                 if let Some(alias_def) = alias_def.filter(|_| cte_def.is_none() || !in_cte_scope) {
+                     let range = crate::position::byte_range_to_range(&doc.text, &alias_def.reference_range, encoding);
+
+                     self.client.log_message(MessageType::INFO, format!("Found alias definition: {}", word)).await;
+                     return Ok(Some(GotoDefinitionResponse::Scalar(Location {
+                         uri: uri.clone(),
+                         range,
+                     })));
+                 }
+
+                 if let Some(cte_def) = cte_def {
+                     let range = crate::position::byte_range_to_range(&doc.text, &cte_def.name_range, encoding);
+
                      self.client.log_message(MessageType::INFO, format!("Found CTE definition: {}", word)).await;
                      return Ok(Some(GotoDefinitionResponse::Scalar(Location {
                          uri: uri.clone(),
-                         range: Range {
-                             start: Position::new(start_line as u32, start_char as u32),
-                             end: Position::new(end_line as u32, end_char as u32),
-                         },
+                         range,
                      })));
                  }
              }
@@ -221,17 +464,53 @@ impl LanguageServer for Backend {
                  if byte_idx >= range.start && byte_idx < range.end {
                       self.client.log_message(MessageType::INFO, format!("Found matching ref: {:?}", dbt_ref)).await;
                       match dbt_ref {
-                          crate::jinja::DbtRef::Model(name) => {
-                               let manifest = self.state.manifest.read().await;
+                          crate::jinja::DbtRef::Model(name) | crate::jinja::DbtRef::VersionedModel(name, _) => {
+                               let manifest = self.state.manifest_for(&uri).await;
                                if let Some(manifest) = manifest.as_ref() {
-                                   if let Some(path) = manifest.models.get(name) {
-                                       let target_uri = Url::from_file_path(path.value()).unwrap();
+                                   // A versioned model's ref goes to the file defining the version, the latest by default
+                                   let version = match dbt_ref {
+                                       crate::jinja::DbtRef::VersionedModel(_, version) => Some(version.as_str()),
+                                       _ => None,
+                                   };
+                                   let name = &manifest.resolve_model_version(name, version).unwrap_or_else(|| name.clone());
+                                   let files = manifest.model_files(name);
+                                   if files.len() > 1 {
+                                       // A duplicated name: let the user pick rather than guess
+                                       let locations = files.iter()
+                                           .filter_map(|p| Url::from_file_path(p).ok())
+                                           .map(|uri| Location { uri, range: Range::default() })
+                                           .collect();
+                                       return Ok(Some(GotoDefinitionResponse::Array(locations)));
+                                   } else if let Some(path) = manifest.models.get(name) {
+                                       let Ok(target_uri) = Url::from_file_path(path.value()) else { return Ok(None) };
                                        return Ok(Some(GotoDefinitionResponse::Scalar(Location {
                                            uri: target_uri,
                                            range: Range::default(),
                                        })));
+                                   } else if let Some(entry) = manifest.seeds.get(name).and(manifest.seed_entries.get(name)) {
+                                       // The seed's yml entry says more than its CSV
+                                       let Ok(target_uri) = Url::from_file_path(&entry.path) else { return Ok(None) };
+                                       return Ok(Some(GotoDefinitionResponse::Scalar(Location {
+                                           uri: target_uri,
+                                           range: crate::position::file_span_to_range(&self.state.documents, &entry.path, entry.line, entry.column, name.len(), encoding),
+                                       })));
                                    } else if let Some(path) = manifest.seeds.get(name) {
-                                       let target_uri = Url::from_file_path(path.value()).unwrap();
+                                       let Ok(target_uri) = Url::from_file_path(path.value()) else { return Ok(None) };
+                                       return Ok(Some(GotoDefinitionResponse::Scalar(Location {
+                                           uri: target_uri,
+                                           range: Range::default(),
+                                       })));
+                                   } else if let Some(snapshot) = manifest.snapshots.get(name) {
+                                       let Ok(target_uri) = Url::from_file_path(&snapshot.path) else { return Ok(None) };
+                                       return Ok(Some(GotoDefinitionResponse::Scalar(Location {
+                                           uri: target_uri,
+                                           range: Range {
+                                               start: Position::new(snapshot.line as u32, 0),
+                                               end: Position::new(snapshot.line as u32, 0),
+                                           },
+                                       })));
+                                   } else if let Some(path) = manifest.defining_packages(name).first().and_then(|pkg| manifest.resolve_package_model(pkg, name)) {
+                                       let Ok(target_uri) = Url::from_file_path(path) else { return Ok(None) };
                                        return Ok(Some(GotoDefinitionResponse::Scalar(Location {
                                            uri: target_uri,
                                            range: Range::default(),
@@ -239,19 +518,33 @@ impl LanguageServer for Backend {
                                    } else {
                                        self.client.show_message(MessageType::WARNING, format!("Model/Seed '{}' not found in project manifest", name)).await;
                                    }
+                               } else if self.state.indexing.load(Ordering::SeqCst) > 0 {
+                                   self.client.log_message(MessageType::INFO, "Project is still being indexed").await;
                                } else {
                                    self.client.show_message(MessageType::ERROR, "Project manifest not loaded!").await;
                                }
                           },
+                          crate::jinja::DbtRef::PackageModel(pkg, name) => {
+                               let manifest = self.state.manifest_for(&uri).await;
+                               if let Some(path) = manifest.as_ref().and_then(|m| m.resolve_package_model(pkg, name)) {
+                                   let Ok(target_uri) = Url::from_file_path(path) else { return Ok(None) };
+                                   return Ok(Some(GotoDefinitionResponse::Scalar(Location {
+                                       uri: target_uri,
+                                       range: Range::default(),
+                                   })));
+                               } else if manifest.is_some() {
+                                   self.client.show_message(MessageType::WARNING, format!("Model '{}' not found in package '{}'", name, pkg)).await;
+                               }
+                          },
                           crate::jinja::DbtRef::Source(src, tbl) => {
-                               let manifest = self.state.manifest.read().await;
+                               let manifest = self.state.manifest_for(&uri).await;
                                if let Some(manifest) = manifest.as_ref() {
                                    let full_name = format!("{}.{}", src, tbl);
-                                   if let Some(path) = manifest.sources.get(&full_name) {
-                                       let target_uri = Url::from_file_path(path.value()).unwrap();
+                                   if let Some(src_def) = manifest.sources.get(&full_name) {
+                                       let Ok(target_uri) = Url::from_file_path(&src_def.path) else { return Ok(None) };
                                        return Ok(Some(GotoDefinitionResponse::Scalar(Location {
                                            uri: target_uri,
-                                           range: Range::default(),
+                                           range: crate::position::file_span_to_range(&self.state.documents, &src_def.path, src_def.line, src_def.column, tbl.len(), encoding),
                                        })));
                                    } else {
                                        self.client.show_message(MessageType::WARNING, format!("Source '{}.{}' not found in manifest", src, tbl)).await;
@@ -259,10 +552,10 @@ impl LanguageServer for Backend {
                                }
                           },
                           crate::jinja::DbtRef::Macro(name) => {
-                               let manifest = self.state.manifest.read().await;
+                               let manifest = self.state.manifest_for(&uri).await;
                                if let Some(manifest) = manifest.as_ref() {
-                                   if let Some(m_def) = manifest.macros.get(name) {
-                                       let target_uri = Url::from_file_path(&m_def.path).unwrap();
+                                   if let Some(m_def) = manifest.resolve_macro(name) {
+                                       let Ok(target_uri) = Url::from_file_path(&m_def.path) else { return Ok(None) };
                                        return Ok(Some(GotoDefinitionResponse::Scalar(Location {
                                            uri: target_uri,
                                            range: Range {
@@ -272,10 +565,71 @@ impl LanguageServer for Backend {
                                        })));
                                    }
                                }
-                          }
+                          },
+                          crate::jinja::DbtRef::Doc(name) => {
+                               let manifest = self.state.manifest_for(&uri).await;
+                               if let Some(block) = manifest.as_ref().and_then(|m| m.docs.get(name).map(|d| d.value().clone())) {
+                                   let Ok(target_uri) = Url::from_file_path(&block.path) else { return Ok(None) };
+                                   return Ok(Some(GotoDefinitionResponse::Scalar(Location {
+                                       uri: target_uri,
+                                       range: crate::position::file_span_to_range(&self.state.documents, &block.path, block.line, block.column, name.len(), encoding),
+                                   })));
+                               }
+                          },
+                          crate::jinja::DbtRef::Metric(name) => {
+                               let manifest = self.state.manifest_for(&uri).await;
+                               if let Some(metric) = manifest.as_ref().and_then(|m| m.metrics.get(name).map(|d| d.value().clone())) {
+                                   let Ok(target_uri) = Url::from_file_path(&metric.path) else { return Ok(None) };
+                                   return Ok(Some(GotoDefinitionResponse::Scalar(Location {
+                                       uri: target_uri,
+                                       range: crate::position::file_span_to_range(&self.state.documents, &metric.path, metric.line, metric.column, name.len(), encoding),
+                                   })));
+                               }
+                          },
+                          crate::jinja::DbtRef::Var(name, _) => {
+                               let manifest = self.state.manifest_for(&uri).await;
+                               let path = uri.to_file_path().unwrap_or_default();
+                               if let Some(var_def) = manifest.as_ref().and_then(|m| m.var_for_path(&path, name)) {
+                                   let Ok(target_uri) = Url::from_file_path(&var_def.path) else { return Ok(None) };
+                                   return Ok(Some(GotoDefinitionResponse::Scalar(Location {
+                                       uri: target_uri,
+                                       range: crate::position::file_span_to_range(&self.state.documents, &var_def.path, var_def.line, var_def.column, name.len(), encoding),
+                                   })));
+                               }
+                          },
+                          crate::jinja::DbtRef::This => {
+                               let manifest = self.state.manifest_for(&uri).await;
+                               let Some(manifest) = manifest.as_ref() else { return Ok(None) };
+                               let Some(name) = uri.to_file_path().ok().and_then(|p| manifest.model_name_for_path(&p)) else {
+                                   return Ok(None);
+                               };
+                               if let Some(entry) = manifest.model_entries.get(&name) {
+                                   let Ok(target_uri) = Url::from_file_path(&entry.path) else { return Ok(None) };
+                                   return Ok(Some(GotoDefinitionResponse::Scalar(Location {
+                                       uri: target_uri,
+                                       range: crate::position::file_span_to_range(&self.state.documents, &entry.path, entry.line, entry.column, name.len(), encoding),
+                                   })));
+                               }
+                               return Ok(Some(GotoDefinitionResponse::Scalar(Location {
+                                   uri: uri.clone(),
+                                   range: Range::default(),
+                               })));
+                          },
+                          // Defined by the environment, not by any file
+                          crate::jinja::DbtRef::EnvVar(..) => {}
                       }
                  }
              }
+
+             // 2. Columns produced by a CTE's select list
+             if let Some(tree) = doc.tree.as_ref() {
+                 if let Some(range) = crate::columns::resolve_column_definition(tree, &doc.text.to_string(), byte_idx) {
+                     return Ok(Some(GotoDefinitionResponse::Scalar(Location {
+                         uri: uri.clone(),
+                         range: crate::position::byte_range_to_range(&doc.text, &range, encoding),
+                     })));
+                 }
+             }
         }
         Ok(None)
     }
@@ -285,82 +639,122 @@ impl LanguageServer for Backend {
         let position = params.text_document_position_params.position;
         
         self.client.log_message(MessageType::LOG, format!("Hover request at Line: {}, Col: {}", position.line, position.character)).await;
+        let encoding = *self.state.position_encoding.read().await;
 
         if let Some(doc) = self.state.documents.get(&uri) {
-             let char_idx = doc.text.line_to_char(position.line as usize) + position.character as usize;
+             let char_idx = match crate::position::position_to_char(&doc.text, position, encoding) {
+                 Some(idx) => idx,
+                 None => return Ok(None),
+             };
              let byte_idx = doc.text.char_to_byte(char_idx);
              eprintln!("HOVER DEBUG: byte_idx={}, refs={}", byte_idx, doc.refs.len());
 
-             if let Some(word) = get_word_at_pos(&doc.text, char_idx) {
+             let max_chars = self.state.settings.read().await.hover_max_chars;
+             let markdown_hover = |value: String, range: Range| Hover {
+                 contents: HoverContents::Markup(MarkupContent {
+                     kind: MarkupKind::Markdown,
+                     value: crate::hover::truncate(value, max_chars),
+                 }),
+                 range: Some(range),
+             };
+
+             // The model's config() call: summarise it merged with dbt_project.yml
+             if let Some(config) = doc.config.as_ref().filter(|c| c.range.contains(&byte_idx)) {
+                 let manifest = self.state.manifest_for(&uri).await;
+                 let path = uri.to_file_path().ok();
+                 let folder_configs = match (manifest.as_ref(), path.as_ref()) {
+                     (Some(m), Some(path)) => crate::relation::model_folder_configs(m, path),
+                     _ => Default::default(),
+                 };
+                 let folder_config: Vec<(&str, serde_yaml::Value)> = folder_configs.iter()
+                     .map(|(key, value)| (key.as_str(), value.clone()))
+                     .collect();
+                 let range = crate::position::byte_range_to_range(&doc.text, &config.range, encoding);
+                 return Ok(Some(markdown_hover(crate::hover::config_markdown(config, &folder_config), range)));
+             }
+
+             // A generic test in yml: the `{% test %}` block it runs
+             if let Some(test) = is_yaml_uri(&uri).then(|| yaml_test_at(&doc.text, char_idx)).flatten() {
+                 let Some(manifest) = self.state.manifest_for(&uri).await else { return Ok(None) };
+                 let max_lines = self.state.settings.read().await.macro_hover_lines;
+                 let markdown = match manifest.resolve_generic_test(&test.name) {
+                     Some(crate::project::GenericTest::Macro(def)) => {
+                         let content = std::fs::read_to_string(&def.path).unwrap_or_default();
+                         crate::hover::generic_test_markdown(&test.name, Some((def.package.as_deref(), &content, def.line)), max_lines)
+                     }
+                     Some(crate::project::GenericTest::Builtin) => crate::hover::generic_test_markdown(&test.name, None, max_lines),
+                     None => return Ok(None),
+                 };
+                 let range = crate::position::line_span_to_range(&doc.text, test.line, test.column, test.name.len(), encoding);
+                 return Ok(Some(markdown_hover(markdown, range)));
+             }
+
+             if let Some(word_chars) = word_char_range(&doc.text, char_idx) {
+                 let word = doc.text.slice(word_chars.clone()).to_string();
+                 let word_range = Range {
+                     start: crate::position::char_to_position(&doc.text, word_chars.start, encoding),
+                     end: crate::position::char_to_position(&doc.text, word_chars.end, encoding),
+                 };
+                 let full_body = self.state.settings.read().await.cte_hover_full_body;
+                 let text = doc.text.to_string();
+                 let cte_columns = |name: &str| doc.tree.as_ref().and_then(|tree| crate::columns::cte_output_columns(tree, &text, name));
+
                  // 1. Check if word is a CTE name
                  if let Some(cte_def) = doc.ctes.get(&word) {
-                     let body_slice = doc.text.slice(cte_def.body_range.clone());
-                     return Ok(Some(Hover {
-                         contents: HoverContents::Markup(MarkupContent {
-                             kind: MarkupKind::Markdown,
-                             value: format!("```sql\n{}\n```", body_slice),
-                         }),
-                         range: None,
-                     }));
+                     let body = &text[cte_def.body_range.clone()];
+                     let columns = cte_columns(&word);
+                     let value = crate::hover::cte_markdown(&format!("**CTE** `{}`", word), columns.as_deref(), body, full_body);
+                     return Ok(Some(markdown_hover(value, word_range)));
                  }
                  
                  // 2. Check if word is an Alias
                  if let Some(alias_def) = doc.aliases.get(&word) {
                      // Resolve target
                      if let Some(cte_def) = doc.ctes.get(&alias_def.target_name) {
-                         // Alias points to a CTE -> show CTE body
-                         let body_slice = doc.text.slice(cte_def.body_range.clone());
-                         return Ok(Some(Hover {
-                             contents: HoverContents::Markup(MarkupContent {
-                                 kind: MarkupKind::Markdown,
-                                 value: format!("**Alias for CTE** `{}`\n```sql\n{}\n```", alias_def.target_name, body_slice),
-                             }),
-                             range: None,
-                         }));
+                         // Alias points to a CTE -> show the CTE's columns and body
+                         let body = &text[cte_def.body_range.clone()];
+                         let columns = cte_columns(&alias_def.target_name);
+                         let title = format!("**Alias for CTE** `{}`", alias_def.target_name);
+                         let value = crate::hover::cte_markdown(&title, columns.as_deref(), body, full_body);
+                         return Ok(Some(markdown_hover(value, word_range)));
                      } else {
                          // Alias points to something else (source/seed/model) -> show definition line
                          let source_slice = doc.text.slice(alias_def.reference_range.clone());
-                          return Ok(Some(Hover {
-                             contents: HoverContents::Markup(MarkupContent {
-                                 kind: MarkupKind::Markdown,
-                                 value: format!("**Alias Definition**:\n```sql\n{}\n```", source_slice),
-                             }),
-                             range: None,
-                         }));
+                         let value = format!("**Alias Definition**:\n```sql\n{}\n```", source_slice);
+                         return Ok(Some(markdown_hover(value, word_range)));
                      }
                  }
                  
                  // 3. Fallback: Check for alias.column pattern
-                 if char_idx > 0 {
-                      // find start of current word
-                      let mut s = char_idx;
-                      while s > 0 {
-                          let c = doc.text.char(s - 1);
-                          if !c.is_alphanumeric() && c != '_' { break; }
-                          s -= 1;
-                      }
-                      
-                      // Check for dot before word
-                      if s > 0 && doc.text.char(s - 1) == '.' {
+                 {
+                      let s = word_chars.start;
+
+                      // Check for dot before word (with room for an alias before it)
+                      if s > 1 && doc.text.char(s - 1) == '.' {
                            // Extract previous word (the alias)
                             if let Some(alias) = get_word_at_pos(&doc.text, s - 2) {
                                  if let Some(alias_def) = doc.aliases.get(&alias) {
                                      // Found alias! Resolve it.
                                      let target_desc = if let Some(cte_def) = doc.ctes.get(&alias_def.target_name) {
-                                          let body_slice = doc.text.slice(cte_def.body_range.clone());
-                                          format!("**Column of CTE** `{}` (alias `{}`)\n```sql\n{}\n```", alias_def.target_name, alias, body_slice)
+                                          // The CTE's columns, like hovering its name, rather than its whole body
+                                          let body = &text[cte_def.body_range.clone()];
+                                          let columns = cte_columns(&alias_def.target_name);
+                                          let title = format!("**Column of CTE** `{}` (alias `{}`)", alias_def.target_name, alias);
+                                          crate::hover::cte_markdown(&title, columns.as_deref(), body, full_body)
                                      } else {
                                           let source_slice = doc.text.slice(alias_def.reference_range.clone());
-                                          format!("**Column of Source** (alias `{}`)\n```sql\n{}\n```", alias, source_slice)
+                                          let source_desc = format!("**Column of Source** (alias `{}`)\n```sql\n{}\n```", alias, source_slice);
+                                          let manifest = self.state.manifest_for(&uri).await;
+                                          let column = manifest.as_ref()
+                                              .and_then(|m| m.relation_columns(&alias_def.target_name))
+                                              .and_then(|(columns, origin)| Some((columns.into_iter().find(|c| c.name.eq_ignore_ascii_case(&word))?, origin)));
+                                          match column {
+                                              Some((column, origin)) => format!("{}\n\n{}", crate::hover::column_markdown(&column, &alias_def.target_name, origin), source_desc),
+                                              None => source_desc,
+                                          }
                                      };
                                      
-                                     return Ok(Some(Hover {
-                                         contents: HoverContents::Markup(MarkupContent {
-                                             kind: MarkupKind::Markdown,
-                                             value: target_desc,
-                                         }),
-                                         range: None,
-                                     }));
+                                     return Ok(Some(markdown_hover(target_desc, word_range)));
                                  }
                             }
                       }
@@ -370,136 +764,3685 @@ impl LanguageServer for Backend {
              for (dbt_ref, range) in &doc.refs {
                  if byte_idx >= range.start && byte_idx < range.end {
                       let value = match dbt_ref {
-                          crate::jinja::DbtRef::Model(name) => {
-                               let manifest = self.state.manifest.read().await;
+                          crate::jinja::DbtRef::Model(name) | crate::jinja::DbtRef::VersionedModel(name, _) => {
+                               let manifest = self.state.manifest_for(&uri).await;
                                if let Some(m) = manifest.as_ref() {
-                                   if m.seeds.contains_key(name) {
-                                       format!("**Seed**: `{}`", name)
+                                   let target = self.state.settings.read().await.relation_target(m.target.as_ref());
+                                   let version = match dbt_ref {
+                                       crate::jinja::DbtRef::VersionedModel(_, version) => Some(version.as_str()),
+                                       _ => None,
+                                   };
+                                   if let Some(path) = m.seeds.get(name).map(|p| p.value().clone()) {
+                                       let mut msg = match self.state.seed_preview(&path) {
+                                           Some(preview) => crate::hover::seed_markdown(name, &preview, &m.seed_columns(name, &preview.header)),
+                                           None => format!("**Seed**: `{}`", name),
+                                       };
+                                       if let Some(relation) = crate::relation::seed_relation(m, name, &target) {
+                                           msg.push_str(&crate::hover::relation_markdown(&relation, m.target.as_ref()));
+                                       }
+                                       msg
+                                   } else if m.snapshots.contains_key(name) && !m.models.contains_key(name) {
+                                       format!("**Snapshot**: `{}`", name)
+                                   } else if let Some(pkg) = m.defining_packages(name).first().filter(|pkg| **pkg != m.config.name) {
+                                       format!("**Model**: `{}` (package `{}`)", name, pkg)
                                    } else {
-                                       format!("**Model**: `{}`", name)
+                                       let mut msg = crate::hover::model_markdown(m, name, version);
+                                       let file = m.resolve_model_version(name, version).unwrap_or_else(|| name.clone());
+                                       if let Some(relation) = crate::relation::model_relation(m, &file, &target) {
+                                           msg.push_str(&crate::hover::relation_markdown(&relation, m.target.as_ref()));
+                                       }
+                                       msg
                                    }
                                } else {
                                    format!("**Model**: `{}`", name)
                                }
                           },
+                          crate::jinja::DbtRef::PackageModel(pkg, name) => {
+                               format!("**Model**: `{}` (package `{}`)", name, pkg)
+                          },
                           crate::jinja::DbtRef::Source(src, tbl) => {
-                               format!("**Source**: `{}.{}`", src, tbl)
+                               let manifest = self.state.manifest_for(&uri).await;
+                               let src_def = manifest.as_ref().and_then(|m| m.sources.get(&format!("{}.{}", src, tbl)).map(|s| s.value().clone()));
+                               match (manifest.as_ref(), src_def) {
+                                   (Some(m), Some(mut src_def)) => {
+                                       src_def.description = src_def.description.map(|d| m.render_docs(&d));
+                                       src_def.columns = m.render_column_docs(&src_def.columns);
+                                       let target = self.state.settings.read().await.relation_target(m.target.as_ref());
+                                       crate::hover::source_markdown(src, tbl, &src_def, target.database.as_deref())
+                                   }
+                                   _ => format!("**Source**: `{}.{}`", src, tbl),
+                               }
                           },
                           crate::jinja::DbtRef::Macro(name) => {
-                               let manifest = self.state.manifest.read().await;
-                               let mut msg = format!("**Macro**: `{}`", name);
-                               if let Some(manifest) = manifest.as_ref() {
-                                   if let Some(m_def) = manifest.macros.get(name) {
-                                       if let Ok(content) = std::fs::read_to_string(&m_def.path) {
-                                           let macro_lines: Vec<&str> = content.lines().skip(m_def.line).take(15).collect();
-                                           msg.push_str("\n\n```jinja\n");
-                                           msg.push_str(&macro_lines.join("\n"));
-                                           msg.push_str("\n```");
-                                       }
-                                   }
+                               let manifest = self.state.manifest_for(&uri).await;
+                               let max_lines = self.state.settings.read().await.macro_hover_lines;
+                               let m_def = manifest.as_ref().and_then(|m| m.resolve_macro(name));
+                               match m_def.and_then(|d| std::fs::read_to_string(&d.path).ok().map(|c| (d, c))) {
+                                   Some((m_def, content)) => crate::hover::macro_markdown(name, m_def.package.as_deref(), &content, m_def.line, max_lines),
+                                   None => format!("**Macro**: `{}`", name),
+                               }
+                          },
+                          crate::jinja::DbtRef::Doc(name) => {
+                               let manifest = self.state.manifest_for(&uri).await;
+                               match manifest.as_ref().and_then(|m| m.docs.get(name).map(|d| d.body.clone())) {
+                                   Some(body) => format!("**Doc**: `{}`\n\n{}", name, body),
+                                   None => format!("**Doc**: `{}`", name),
+                               }
+                          },
+                          crate::jinja::DbtRef::Metric(name) => {
+                               let manifest = self.state.manifest_for(&uri).await;
+                               match manifest.as_ref().and_then(|m| m.metrics.get(name).map(|d| (d.value().clone(), m.metric_dimensions(&d)))) {
+                                   Some((metric, dimensions)) => crate::hover::metric_markdown(name, &metric, &dimensions),
+                                   None => format!("**Metric**: `{}`", name),
+                               }
+                          },
+                          crate::jinja::DbtRef::Var(name, default) => {
+                               let manifest = self.state.manifest_for(&uri).await;
+                               let path = uri.to_file_path().unwrap_or_default();
+                               let value = manifest.as_ref().and_then(|m| m.var_for_path(&path, name)).map(|v| v.value);
+                               crate::hover::var_markdown(name, value.as_ref(), default.as_deref())
+                          },
+                          crate::jinja::DbtRef::EnvVar(name, default) => {
+                               let value = std::env::var(name).ok();
+                               crate::hover::env_var_markdown(name, value.as_deref(), default.as_deref())
+                          },
+                          crate::jinja::DbtRef::This => {
+                               let manifest = self.state.manifest_for(&uri).await;
+                               let Some(manifest) = manifest.as_ref() else { return Ok(None) };
+                               let Some(name) = uri.to_file_path().ok().and_then(|p| manifest.model_name_for_path(&p)) else {
+                                   return Ok(None);
+                               };
+                               let alias = doc.config.as_ref().and_then(|c| c.get("alias")).map(str::to_string)
+                                   .or_else(|| manifest.model_entries.get(&name).and_then(|e| e.alias.clone()));
+                               match alias {
+                                   Some(alias) => format!("**This**: `{}` (model `{}`)", alias, name),
+                                   None => format!("**This**: `{}`", name),
                                }
-                               msg
                           }
                       };
                       
-                      return Ok(Some(Hover {
-                          contents: HoverContents::Markup(MarkupContent {
-                              kind: MarkupKind::Markdown,
-                              value,
-                          }),
-                          range: None,
-                      }));
+                      let ref_range = crate::position::byte_range_to_range(&doc.text, range, encoding);
+                      return Ok(Some(markdown_hover(value, ref_range)));
                  }
              }
         }
         Ok(None)
     }
 
-    async fn completion(&self, _params: CompletionParams) -> Result<Option<CompletionResponse>> {
-        let manifest = self.state.manifest.read().await;
-        let mut items = Vec::new();
+    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+        let encoding = *self.state.position_encoding.read().await;
 
-        // 1. Keyword Snippets
-        items.push(CompletionItem {
-            label: "ref".to_string(),
-            kind: Some(CompletionItemKind::SNIPPET),
-            insert_text: Some("{{ ref('$1') }}".to_string()),
-            insert_text_format: Some(InsertTextFormat::SNIPPET),
-            detail: Some("Expand to ref() tag".to_string()),
-            ..CompletionItem::default()
-        });
+        // CTEs are local to the document
+        if let Some(occurrences) = self.cte_occurrences_at(&uri, position, encoding) {
+            let Some(doc) = self.state.documents.get(&uri) else { return Ok(None) };
+            let declaration = params.context.include_declaration.then_some(&occurrences.definition);
+            let locations = declaration.into_iter().chain(&occurrences.usages)
+                .map(|range| Location { uri: uri.clone(), range: crate::position::byte_range_to_range(&doc.text, range, encoding) })
+                .collect();
+            return Ok(Some(locations));
+        }
+        let Some(manifest) = self.state.manifest_for(&uri).await else { return Ok(None) };
 
-        items.push(CompletionItem {
-            label: "source".to_string(),
-            kind: Some(CompletionItemKind::SNIPPET),
-            insert_text: Some("{{ source('$1', '$2') }}".to_string()),
-            insert_text_format: Some(InsertTextFormat::SNIPPET),
-            detail: Some("Expand to source() tag".to_string()),
-            ..CompletionItem::default()
+        // The ref or macro call under the cursor, a macro header on the cursor's line,
+        // else the model the document itself is. In yml, a source table entry.
+        let target = match self.state.documents.get(&uri) {
+            Some(doc) if is_yaml_uri(&uri) => {
+                let Some(char_idx) = crate::position::position_to_char(&doc.text, position, encoding) else { return Ok(None) };
+                yaml_source_table(&doc.text, char_idx).map(|(src, tbl)| crate::references::ReferenceTarget::Source(src, tbl))
+            }
+            Some(doc) => {
+                let Some(char_idx) = crate::position::position_to_char(&doc.text, position, encoding) else { return Ok(None) };
+                let byte_idx = doc.text.char_to_byte(char_idx);
+                doc.refs.iter()
+                    .find(|(_, range)| range.contains(&byte_idx))
+                    .and_then(|(dbt_ref, _)| crate::references::ReferenceTarget::from_ref(dbt_ref, &manifest.config.name))
+                    .or_else(|| {
+                        let line = doc.text.line(doc.text.char_to_line(char_idx)).to_string();
+                        crate::references::macro_definition_on_line(&line).map(crate::references::ReferenceTarget::Macro)
+                    })
+            }
+            None => None,
+        };
+        let target = target.or_else(|| {
+            let path = uri.to_file_path().ok()?;
+            manifest.model_name_for_path(&path).map(crate::references::ReferenceTarget::Model)
         });
+        let Some(target) = target else { return Ok(None) };
+
+        let index = manifest.clone();
+        let _ = tokio::task::spawn_blocking(move || index.ensure_reference_index()).await;
 
-        // 2. Model names from manifest
-        if let Some(manifest) = manifest.as_ref() {
-            for model_ref in manifest.models.iter() {
-                items.push(CompletionItem {
-                    label: model_ref.key().clone(),
-                    kind: Some(CompletionItemKind::FILE),
-                    detail: Some("dbt model".to_string()),
-                    ..CompletionItem::default()
-                });
+        let mut locations = crate::references::find_references(&manifest, &target, encoding);
+        if params.context.include_declaration {
+            if let Some(declaration) = crate::references::declaration(&manifest, &self.state.documents, &target, encoding) {
+                locations.insert(0, declaration);
             }
         }
-        
-        Ok(Some(CompletionResponse::Array(items)))
+        Ok(Some(locations))
     }
-}
 
-fn get_word_at_pos(rope: &ropey::Rope, char_idx: usize) -> Option<String> {
-    let len = rope.len_chars();
-    if char_idx >= len { return None; }
-    
-    // Scan backwards
-    let mut start = char_idx;
-    while start > 0 {
-        let c = rope.char(start - 1);
-        if !c.is_alphanumeric() && c != '_' {
-            break;
+    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+        let encoding = *self.state.position_encoding.read().await;
+
+        if let Some(occurrences) = self.cte_occurrences_at(&uri, position, encoding) {
+            let Some(doc) = self.state.documents.get(&uri) else { return Ok(None) };
+            let edits = crate::rename::cte_rename_edits(&occurrences, &doc.text, &params.new_name, encoding)
+                .map_err(tower_lsp::jsonrpc::Error::invalid_params)?;
+            return Ok(Some(WorkspaceEdit {
+                changes: Some(std::collections::HashMap::from([(uri.clone(), edits)])),
+                ..WorkspaceEdit::default()
+            }));
         }
-        start -= 1;
+
+        // A ref('model') or macro under the cursor, else the model the document itself is
+        let Some(manifest) = self.state.manifest_for(&uri).await else { return Ok(None) };
+        let at_cursor = self.state.documents.get(&uri).and_then(|doc| {
+            let byte_idx = doc.text.char_to_byte(crate::position::position_to_char(&doc.text, position, encoding)?);
+            crate::rename::rename_target_at(&manifest, &doc.text.to_string(), &doc.refs, byte_idx).map(|(target, _)| target)
+        });
+        let target = at_cursor.or_else(|| {
+            let name = manifest.model_name_for_path(&uri.to_file_path().ok()?)?;
+            Some(crate::rename::RenameTarget::Model(name))
+        });
+
+        let edit = match target {
+            Some(crate::rename::RenameTarget::Model(old_name)) => {
+                let rename_file = self.state.client_capabilities.read().await.workspace.as_ref()
+                    .and_then(|w| w.workspace_edit.as_ref())
+                    .and_then(|e| e.resource_operations.as_ref())
+                    .is_some_and(|ops| ops.contains(&ResourceOperationKind::Rename));
+                let edit = crate::rename::model_rename_edit(&manifest, &self.state.documents, &old_name, &params.new_name, rename_file, encoding);
+                if edit.is_ok() && !rename_file {
+                    self.client.show_message(
+                        MessageType::INFO,
+                        format!("Updated the refs to '{}'. This editor can't rename files: rename {}.sql to {}.sql to finish.", params.new_name, old_name, params.new_name),
+                    ).await;
+                }
+                edit
+            }
+            Some(crate::rename::RenameTarget::Macro(old_name)) => {
+                crate::rename::macro_rename_edits(&manifest, &self.state.documents, &old_name, &params.new_name, encoding)
+                    .map(|changes| WorkspaceEdit { changes: Some(changes), ..WorkspaceEdit::default() })
+            }
+            None => return Ok(None),
+        };
+        edit.map(Some).map_err(tower_lsp::jsonrpc::Error::invalid_params)
     }
-    
-    // Scan forwards
-    let mut end = char_idx;
-    while end < len {
-        let c = rope.char(end);
-        if !c.is_alphanumeric() && c != '_' {
-            break;
+
+    async fn prepare_rename(&self, params: TextDocumentPositionParams) -> Result<Option<PrepareRenameResponse>> {
+        let uri = params.text_document.uri;
+        let encoding = *self.state.position_encoding.read().await;
+        let cte = self.cte_occurrences_at(&uri, params.position, encoding);
+        let manifest = self.state.manifest_for(&uri).await;
+
+        let Some(doc) = self.state.documents.get(&uri) else { return Ok(None) };
+        let Some(char_idx) = crate::position::position_to_char(&doc.text, params.position, encoding) else { return Ok(None) };
+        let byte_idx = doc.text.char_to_byte(char_idx);
+        let renameable = match (cte, manifest) {
+            (Some(cte), _) => std::iter::once(&cte.definition).chain(&cte.usages)
+                .find(|r| r.start <= byte_idx && byte_idx <= r.end)
+                .map(|r| (cte.name.clone(), r.clone())),
+            (None, Some(manifest)) => {
+                let text = doc.text.to_string();
+                crate::rename::rename_target_at(&manifest, &text, &doc.refs, byte_idx).map(|(target, range)| match target {
+                    crate::rename::RenameTarget::Model(name) | crate::rename::RenameTarget::Macro(name) => (name, range),
+                })
+            }
+            (None, None) => None,
+        };
+        Ok(renameable.map(|(placeholder, range)| PrepareRenameResponse::RangeWithPlaceholder {
+            range: crate::position::byte_range_to_range(&doc.text, &range, encoding),
+            placeholder,
+        }))
+    }
+
+    async fn document_symbol(&self, params: DocumentSymbolParams) -> Result<Option<DocumentSymbolResponse>> {
+        let encoding = *self.state.position_encoding.read().await;
+        let Some(doc) = self.state.documents.get(&params.text_document.uri) else { return Ok(None) };
+        if is_yaml_uri(&params.text_document.uri) {
+            return Ok(None);
         }
-        end += 1;
+        Ok(Some(DocumentSymbolResponse::Nested(crate::symbols::document_symbols(&doc, encoding))))
     }
-    
-    if start == end { return None; }
-    
-    Some(rope.slice(start..end).to_string())
-}
 
-#[tokio::main]
-async fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.iter().any(|a| a == "--test") {
-        eprintln!("dbt-lsp binary check: OK");
-        return;
+    async fn symbol(&self, params: WorkspaceSymbolParams) -> Result<Option<Vec<SymbolInformation>>> {
+        let manifests: Vec<_> = self.state.manifests.read().await.values().cloned().collect();
+        let encoding = *self.state.position_encoding.read().await;
+        let mut symbols: Vec<(u8, SymbolInformation)> = manifests.iter()
+            .flat_map(|m| crate::symbols::workspace_symbols(m, &params.query, encoding))
+            .collect();
+        // Best matches first, shorter names before longer ones with the same rank
+        symbols.sort_by(|(rank_a, a), (rank_b, b)| (rank_a, a.name.len(), &a.name).cmp(&(rank_b, b.name.len(), &b.name)));
+        symbols.truncate(crate::symbols::WORKSPACE_SYMBOL_LIMIT);
+        Ok(Some(symbols.into_iter().map(|(_, symbol)| symbol).collect()))
     }
 
-    env_logger::init();
-    eprintln!("dbt-lsp starting...");
+    async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
+        if is_yaml_uri(&params.text_document.uri) {
+            return Ok(None);
+        }
+        let Some(doc) = self.state.documents.get(&params.text_document.uri) else { return Ok(None) };
+        Ok(Some(crate::symbols::folding_ranges(&doc)))
+    }
 
-    let stdin = tokio::io::stdin();
-    let stdout = tokio::io::stdout();
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        let uri = params.text_document.uri;
+        let Some(manifest) = self.state.manifest_for(&uri).await else { return Ok(None) };
+        let Ok(path) = uri.to_file_path() else { return Ok(None) };
 
-    let (service, socket) = LspService::new(|client| Backend { 
-        client,
-        state: GlobalState::default(),
-    });
-    Server::new(stdin, stdout, socket).serve(service).await;
+        let encoding = *self.state.position_encoding.read().await;
+        let mut actions = Vec::new();
+        let mut wrap = None;
+        let columns = {
+            let Some(doc) = self.state.documents.get(&uri) else { return Ok(None) };
+            for diagnostic in &params.context.diagnostics {
+                actions.extend(crate::actions::did_you_mean_actions(&uri, &doc.text, diagnostic, encoding));
+                actions.extend(crate::actions::remove_cte_action(&uri, &doc, diagnostic, encoding));
+                actions.extend(crate::actions::qualify_ref_actions(&uri, &doc.text, diagnostic, encoding));
+            }
+            if let Some(char_idx) = crate::position::position_to_char(&doc.text, params.range.start, encoding) {
+                let byte_idx = doc.text.char_to_byte(char_idx);
+                actions.extend(crate::actions::hardcoded_table_actions(&uri, &doc, &manifest, byte_idx, encoding));
+            }
+            actions.extend(crate::actions::extract_cte_action(&uri, &doc, params.range, encoding));
+            if manifest.model_name_for_path(&path).is_some() {
+                wrap = crate::actions::wrap_incremental_action(&uri, &doc, params.range, encoding);
+            }
+            let text = doc.text.to_string();
+            doc.tree.as_ref().and_then(|tree| crate::columns::final_output_columns(tree, &text)).unwrap_or_default()
+        };
+
+        // The document is released first: the yml fixes may read other open documents
+        let read = |path: &std::path::Path| self.file_text(path);
+        for diagnostic in &params.context.diagnostics {
+            actions.extend(crate::actions::create_model_action(&manifest, &path, diagnostic));
+            actions.extend(crate::actions::add_source_table_action(&manifest, diagnostic, &read, encoding));
+            actions.extend(crate::actions::create_source_action(&manifest, &path, diagnostic, &read, encoding));
+        }
+        if let Some(name) = manifest.model_name_for_path(&path) {
+            match manifest.model_entries.get(&name) {
+                Some(entry) => actions.extend(crate::actions::missing_columns_action(&name, &columns, &entry, &read, encoding)),
+                None => if let Some(yml) = crate::actions::schema_yml_path(&path) {
+                    actions.extend(crate::actions::schema_stub_action(&name, &columns, &yml, read(&yml).as_deref(), encoding));
+                },
+            }
+        }
+        // Outside incremental models the wrap is rarely wanted, so it goes last there
+        match wrap {
+            Some(action) if action.is_preferred == Some(true) => actions.insert(0, action),
+            other => actions.extend(other),
+        }
+        Ok(Some(actions.into_iter().map(CodeActionOrCommand::CodeAction).collect()))
+    }
+
+    async fn prepare_call_hierarchy(&self, params: CallHierarchyPrepareParams) -> Result<Option<Vec<CallHierarchyItem>>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+        if is_yaml_uri(&uri) {
+            return Ok(None);
+        }
+        let encoding = *self.state.position_encoding.read().await;
+        let Some(manifest) = self.state.manifest_for(&uri).await else { return Ok(None) };
+
+        // The ref or source under the cursor, else the model, snapshot or test the document is
+        let under_cursor = self.state.documents.get(&uri).and_then(|doc| {
+            let byte_idx = doc.text.char_to_byte(crate::position::position_to_char(&doc.text, position, encoding)?);
+            doc.refs.iter()
+                .find(|(_, range)| range.contains(&byte_idx))
+                .and_then(|(dbt_ref, _)| crate::hierarchy::DagNode::from_ref(&manifest, dbt_ref))
+        });
+        let node = under_cursor.or_else(|| crate::hierarchy::DagNode::for_file(&manifest, &uri.to_file_path().ok()?));
+        Ok(node.and_then(|n| crate::hierarchy::item(&manifest, &self.state.documents, &n, encoding)).map(|item| vec![item]))
+    }
+
+    async fn incoming_calls(&self, params: CallHierarchyIncomingCallsParams) -> Result<Option<Vec<CallHierarchyIncomingCall>>> {
+        let Some((manifest, node)) = self.hierarchy_node(&params.item).await else { return Ok(None) };
+        let encoding = *self.state.position_encoding.read().await;
+        Ok(Some(crate::hierarchy::incoming_calls(&manifest, &self.state.documents, &node, encoding)))
+    }
+
+    async fn outgoing_calls(&self, params: CallHierarchyOutgoingCallsParams) -> Result<Option<Vec<CallHierarchyOutgoingCall>>> {
+        let Some((manifest, node)) = self.hierarchy_node(&params.item).await else { return Ok(None) };
+        let encoding = *self.state.position_encoding.read().await;
+        Ok(Some(crate::hierarchy::outgoing_calls(&manifest, &self.state.documents, &node, encoding)))
+    }
+
+    async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        let uri = params.text_document.uri;
+        if is_yaml_uri(&uri) {
+            return Ok(None);
+        }
+        let encoding = *self.state.position_encoding.read().await;
+        let Some(doc) = self.state.documents.get(&uri) else { return Ok(None) };
+        let text = doc.text.to_string();
+        // Macro files are mostly jinja; leave their layout to the author
+        if crate::jinja::is_macro_file(&text) {
+            return Ok(None);
+        }
+        let Some(formatted) = crate::format::format_document(&text, &params.options) else {
+            self.client.log_message(MessageType::WARNING, format!("Not formatting {}: the SQL or jinja could not be tokenized safely", uri)).await;
+            return Ok(None);
+        };
+        let edit = crate::format::minimal_edit(&doc.text, 0..doc.text.len_bytes(), &formatted, encoding);
+        Ok(Some(edit.into_iter().collect()))
+    }
+
+    async fn range_formatting(&self, params: DocumentRangeFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        let uri = params.text_document.uri;
+        if is_yaml_uri(&uri) {
+            return Ok(None);
+        }
+        let encoding = *self.state.position_encoding.read().await;
+        let Some(doc) = self.state.documents.get(&uri) else { return Ok(None) };
+        if crate::jinja::is_macro_file(&doc.text.to_string()) {
+            return Ok(None);
+        }
+        let to_byte = |position| crate::position::position_to_char(&doc.text, position, encoding).map(|c| doc.text.char_to_byte(c));
+        let (Some(start), Some(end)) = (to_byte(params.range.start), to_byte(params.range.end)) else { return Ok(None) };
+        let Some((range, formatted)) = crate::format::format_range(&doc, start..end, &params.options) else {
+            return Ok(None);
+        };
+        let edit = crate::format::minimal_edit(&doc.text, range, &formatted, encoding);
+        Ok(Some(edit.into_iter().collect()))
+    }
+
+    async fn semantic_tokens_full(&self, params: SemanticTokensParams) -> Result<Option<SemanticTokensResult>> {
+        if is_yaml_uri(&params.text_document.uri) {
+            return Ok(None);
+        }
+        let encoding = *self.state.position_encoding.read().await;
+        let Some(doc) = self.state.documents.get(&params.text_document.uri) else { return Ok(None) };
+        let data = crate::semantic::semantic_tokens(&doc.text, None, encoding);
+        Ok(Some(SemanticTokensResult::Tokens(SemanticTokens { result_id: None, data })))
+    }
+
+    async fn semantic_tokens_range(&self, params: SemanticTokensRangeParams) -> Result<Option<SemanticTokensRangeResult>> {
+        if is_yaml_uri(&params.text_document.uri) {
+            return Ok(None);
+        }
+        let encoding = *self.state.position_encoding.read().await;
+        let Some(doc) = self.state.documents.get(&params.text_document.uri) else { return Ok(None) };
+        let to_byte = |position| crate::position::position_to_char(&doc.text, position, encoding).map(|c| doc.text.char_to_byte(c));
+        let start = to_byte(params.range.start).unwrap_or(0);
+        let end = to_byte(params.range.end).unwrap_or(doc.text.len_bytes());
+        let data = crate::semantic::semantic_tokens(&doc.text, Some(start..end), encoding);
+        Ok(Some(SemanticTokensRangeResult::Tokens(SemanticTokens { result_id: None, data })))
+    }
+
+    async fn inlay_hint(&self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>> {
+        let uri = params.text_document.uri;
+        if is_yaml_uri(&uri) {
+            return Ok(None);
+        }
+        let settings = self.state.settings.read().await.clone();
+        if !settings.inlay_hints {
+            return Ok(None);
+        }
+        let encoding = *self.state.position_encoding.read().await;
+        let Some(manifest) = self.state.manifest_for(&uri).await else { return Ok(None) };
+        let Some(doc) = self.state.documents.get(&uri) else { return Ok(None) };
+        let to_byte = |position| crate::position::position_to_char(&doc.text, position, encoding).map(|c| doc.text.char_to_byte(c));
+        let start = to_byte(params.range.start).unwrap_or(0);
+        let end = to_byte(params.range.end).unwrap_or(doc.text.len_bytes());
+        let target = settings.relation_target(manifest.target.as_ref());
+        Ok(Some(crate::hints::relation_hints(&doc, &manifest, &target, start..end, encoding)))
+    }
+
+    async fn code_lens(&self, params: CodeLensParams) -> Result<Option<Vec<CodeLens>>> {
+        let uri = params.text_document.uri;
+        let Some((upstream, downstream)) = self.dependency_locations(&uri).await else { return Ok(None) };
+        Ok(Some(crate::lenses::dependency_lenses(&uri, upstream.len(), downstream.len())))
+    }
+
+    async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<serde_json::Value>> {
+        match params.command.as_str() {
+            crate::lenses::SHOW_DEPENDENCIES => self.show_dependencies(&params.arguments).await,
+            crate::commands::SHOW_COMPILED_SQL => self.show_compiled_sql(&params.arguments).await,
+            crate::commands::RUN_MODEL => self.run_model(&params.arguments).await,
+            crate::commands::TEST_MODEL => self.test_model(&params.arguments).await,
+            crate::commands::CLEAR_TEST_RESULTS => self.clear_test_results(&params.arguments).await,
+            crate::commands::RESCAN_PROJECT => self.rescan_project(&params.arguments).await,
+            crate::commands::SHOW_LINEAGE => self.show_lineage(&params.arguments).await,
+            crate::commands::DOWNSTREAM => self.downstream(&params.arguments).await,
+            crate::commands::OPEN_TARGET_ARTIFACT => self.open_target_artifact(&params.arguments).await,
+            crate::commands::PREVIEW_MODEL => self.preview_model(&params.arguments).await,
+            crate::commands::EXPORT_GRAPH => self.export_graph(&params.arguments).await,
+            crate::commands::PROJECT_STATS => self.project_stats(&params.arguments).await,
+            _ => Err(tower_lsp::jsonrpc::Error::invalid_params(format!("Unknown command: {}", params.command))),
+        }
+    }
+
+    async fn document_link(&self, params: DocumentLinkParams) -> Result<Option<Vec<DocumentLink>>> {
+        let uri = params.text_document.uri;
+        if is_yaml_uri(&uri) {
+            return Ok(None);
+        }
+        let encoding = *self.state.position_encoding.read().await;
+        let Some(manifest) = self.state.manifest_for(&uri).await else { return Ok(None) };
+        let Some(doc) = self.state.documents.get(&uri) else { return Ok(None) };
+        Ok(Some(crate::links::document_links(&doc, &manifest, encoding)))
+    }
+
+    async fn document_link_resolve(&self, mut link: DocumentLink) -> Result<DocumentLink> {
+        let Some(data) = link.data.clone().and_then(|d| serde_json::from_value::<crate::links::LinkData>(d).ok()) else {
+            return Ok(link);
+        };
+        let manifest = self.state.manifests.read().await.get(&data.root).cloned();
+        if let Some(target) = manifest.and_then(|m| crate::links::resolve_target(&m, &data)) {
+            link.target = Some(target);
+        }
+        Ok(link)
+    }
+
+    async fn selection_range(&self, params: SelectionRangeParams) -> Result<Option<Vec<SelectionRange>>> {
+        let encoding = *self.state.position_encoding.read().await;
+        let Some(doc) = self.state.documents.get(&params.text_document.uri) else { return Ok(None) };
+        let ranges = params.positions.iter().map(|position| {
+            crate::position::position_to_char(&doc.text, *position, encoding)
+                .and_then(|char_idx| crate::selection::selection_range(&doc, doc.text.char_to_byte(char_idx), encoding))
+                .unwrap_or(SelectionRange { range: Range::new(*position, *position), parent: None })
+        }).collect();
+        Ok(Some(ranges))
+    }
+
+    async fn document_highlight(&self, params: DocumentHighlightParams) -> Result<Option<Vec<DocumentHighlight>>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+        let encoding = *self.state.position_encoding.read().await;
+
+        // A CTE name, else a table alias
+        let occurrences = match self.cte_occurrences_at(&uri, position, encoding) {
+            Some(cte) => Some((cte.definition, cte.usages)),
+            None => self.alias_occurrences_at(&uri, position, encoding).map(|alias| (alias.definition, alias.usages)),
+        };
+        let Some((definition, usages)) = occurrences else { return Ok(None) };
+        let Some(doc) = self.state.documents.get(&uri) else { return Ok(None) };
+        let highlight = |range: &std::ops::Range<usize>, kind| DocumentHighlight {
+            range: crate::position::byte_range_to_range(&doc.text, range, encoding),
+            kind: Some(kind),
+        };
+        let mut highlights = vec![highlight(&definition, DocumentHighlightKind::WRITE)];
+        highlights.extend(usages.iter().map(|r| highlight(r, DocumentHighlightKind::READ)));
+        Ok(Some(highlights))
+    }
+
+    async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+        let encoding = *self.state.position_encoding.read().await;
+        let manifest = self.state.manifest_for(&uri).await;
+        let exclude_packages = self.state.settings.read().await.completion_exclude_packages.clone();
+
+        // The line up to the cursor, plus where the identifier under the cursor ends
+        let (line_prefix, cursor, name_end) = match self.state.documents.get(&uri) {
+            Some(doc) => match crate::position::position_to_char(&doc.text, position, encoding) {
+                Some(char_idx) => {
+                    let line = doc.text.char_to_line(char_idx);
+                    let line_start = doc.text.line_to_char(line);
+                    let line_end = line_start + doc.text.line(line).len_chars();
+                    let rest = doc.text.slice(char_idx..line_end).chars()
+                        .take_while(|c| c.is_alphanumeric() || *c == '_')
+                        .count();
+                    (doc.text.slice(line_start..char_idx).to_string(), char_idx, char_idx + rest)
+                }
+                None => return Ok(None),
+            },
+            None => (String::new(), 0, 0),
+        };
+
+        // Inside a call's quotes only names make sense
+        let context = crate::completion::completion_context(&line_prefix);
+        let typed = context.typed_prefix().map(|p| p.chars().count()).unwrap_or(0);
+        let named = match (context, manifest.as_ref()) {
+            (crate::completion::CompletionContext::Other, _) => None,
+            (_, None) => Some(Vec::new()),
+            (crate::completion::CompletionContext::RefName { prefix }, Some(m)) => Some(crate::completion::ref_items(m, &prefix, &exclude_packages)),
+            (crate::completion::CompletionContext::PackageRefName { package, prefix }, Some(m)) => {
+                Some(crate::completion::package_ref_items(m, &package, &prefix))
+            }
+            (crate::completion::CompletionContext::SourceName { prefix }, Some(m)) => Some(crate::completion::source_name_items(m, &prefix)),
+            (crate::completion::CompletionContext::SourceTable { source, prefix }, Some(m)) => {
+                Some(crate::completion::source_table_items(m, &source, &prefix))
+            }
+            (crate::completion::CompletionContext::VarName { prefix }, Some(m)) => Some(crate::completion::var_items(m, &prefix)),
+            (crate::completion::CompletionContext::DocName { prefix }, Some(m)) => Some(crate::completion::doc_items(m, &prefix)),
+            (crate::completion::CompletionContext::AliasColumn { alias, prefix }, Some(m)) => self.state.documents.get(&uri)
+                .and_then(|doc| doc.aliases.get(&alias).map(|a| a.target_name.clone()))
+                .map(|target| {
+                    let seed_path = m.seeds.get(&target).map(|p| p.clone());
+                    let header = seed_path.and_then(|p| self.state.seed_preview(&p)).map(|p| p.header);
+                    crate::completion::column_items(m, &target, header.as_deref(), &prefix)
+                }),
+        };
+        if let Some(items) = named {
+            // Replace the whole partial name, including any part after the cursor
+            let items = match self.state.documents.get(&uri) {
+                Some(doc) => {
+                    let range = Range {
+                        start: crate::position::char_to_position(&doc.text, cursor - typed, encoding),
+                        end: crate::position::char_to_position(&doc.text, name_end, encoding),
+                    };
+                    crate::completion::with_text_edit(items, range)
+                }
+                None => items,
+            };
+            return Ok(Some(CompletionResponse::Array(items)));
+        }
+
+        // SQL snippets make no sense in yml
+        if is_yaml_uri(&uri) {
+            return Ok(None);
+        }
+        let path = uri.to_file_path().ok();
+        let under = |dirs: fn(&crate::project::DbtProjectConfig) -> &Vec<String>| {
+            matches!((manifest.as_ref(), path.as_ref()), (Some(m), Some(p)) if m.is_under(p, dirs(&m.config)))
+        };
+        let items = crate::completion::snippet_items(&line_prefix, under(|c| &c.macro_paths), under(|c| &c.snapshot_paths));
+
+        Ok(Some(CompletionResponse::Array(items)))
+    }
+
+    async fn completion_resolve(&self, mut item: CompletionItem) -> Result<CompletionItem> {
+        let Some(data) = item.data.clone().and_then(|d| serde_json::from_value::<crate::completion::ItemData>(d).ok()) else {
+            return Ok(item);
+        };
+        let manifest = self.state.manifests.read().await.get(&data.root).cloned();
+        if let Some(value) = manifest.and_then(|m| crate::completion::resolve_documentation(&m, &data.item)) {
+            item.documentation = Some(Documentation::MarkupContent(MarkupContent { kind: MarkupKind::Markdown, value }));
+        }
+        Ok(item)
+    }
+}
+
+impl Backend {
+    /// Builds the manifest for `root` off the async runtime, reporting each scan phase
+    /// through `$/progress` when the client supports it, and swaps it in once fully
+    /// scanned. Returns the new manifest, or None when loading failed.
+    async fn index_project(&self, root: std::path::PathBuf) -> Option<Arc<crate::project::ProjectManifest>> {
+        self.state.indexing.fetch_add(1, Ordering::SeqCst);
+        let progress = self.begin_progress("Indexing dbt project").await;
+
+        let settings = self.state.settings.read().await.clone();
+        let load = move || {
+            let mut manifest = crate::project::ProjectManifest::new(root)?;
+            let missing = settings.override_paths(&mut manifest.config, &manifest.root_dir);
+            if !settings.filesystem_only {
+                manifest.load_artifacts();
+            }
+            anyhow::Ok((manifest, missing))
+        };
+        let manifest = match tokio::task::spawn_blocking(load).await {
+            Ok(Ok((manifest, missing))) => {
+                if !missing.is_empty() {
+                    let msg = format!("dbt-lsp settings: {} not found in {}", missing.join(", "), manifest.root_dir.display());
+                    self.client.show_message(MessageType::WARNING, msg).await;
+                }
+                Arc::new(manifest)
+            }
+            Ok(Err(e)) => {
+                let msg = format!("Failed to load dbt project: {}", e);
+                self.client.log_message(MessageType::ERROR, msg.clone()).await;
+                self.client.show_message(MessageType::ERROR, msg).await;
+                self.end_progress(progress, "Failed").await;
+                self.state.indexing.fetch_sub(1, Ordering::SeqCst);
+                return None;
+            }
+            Err(e) => {
+                self.client.log_message(MessageType::ERROR, format!("Indexing task failed: {}", e)).await;
+                self.end_progress(progress, "Failed").await;
+                self.state.indexing.fetch_sub(1, Ordering::SeqCst);
+                return None;
+            }
+        };
+
+        type Phase = (&'static str, fn(&crate::project::ProjectManifest), fn(&crate::project::ProjectManifest) -> usize);
+        let phases: [Phase; 9] = [
+            ("models", |m| m.scan_models(), |m| m.models.len()),
+            ("seeds", |m| m.scan_seeds(), |m| m.seeds.len()),
+            ("snapshots", |m| m.scan_snapshots(), |m| m.snapshots.len()),
+            ("analyses", |m| m.scan_analyses(), |m| m.analyses.len()),
+            ("singular tests", |m| m.scan_singular_tests(), |m| m.singular_tests.len()),
+            ("macros and docs blocks", |m| m.scan_macros_and_docs(), |m| m.macros.len() + m.docs.len()),
+            ("sources", |m| m.scan_sources(), |m| m.sources.len()),
+            ("packages", |m| m.scan_packages(), |m| m.packages.len()),
+            ("vars", |m| m.scan_vars(), |m| m.vars.len()),
+        ];
+        for (i, (label, scan, count)) in phases.into_iter().enumerate() {
+            let m = manifest.clone();
+            let started = std::time::Instant::now();
+            let _ = tokio::task::spawn_blocking(move || scan(&m)).await;
+            let percentage = ((i + 1) * 100 / phases.len()) as u32;
+            let message = format!("{} {} in {} ms", count(&manifest), label, started.elapsed().as_millis());
+            self.report_progress(&progress, message, percentage).await;
+        }
+
+        let msg = format!(
+            "Loaded dbt project: {} with {} models, {} sources, {} seeds and {} macros",
+            manifest.config.name, manifest.models.len(), manifest.sources.len(), manifest.seeds.len(), manifest.macros.len()
+        );
+        self.client.log_message(MessageType::INFO, msg.clone()).await;
+        self.client.show_message(MessageType::INFO, msg).await;
+        self.state.manifests.write().await.insert(manifest.root_dir.clone(), manifest.clone());
+        self.state.indexing.fetch_sub(1, Ordering::SeqCst);
+        self.end_progress(progress, "Done").await;
+
+        if manifest.root_dir.join("packages.yml").exists() {
+            self.publish_package_diagnostics(&manifest).await;
+        }
+        self.publish_project_file_diagnostics(&manifest).await;
+
+        // Documents opened while indexing were validated without a manifest
+        self.revalidate_open_documents().await;
+        self.refresh_code_lenses().await;
+        Some(manifest)
+    }
+
+    /// Creates a work-done progress token and sends the `begin` notification.
+    /// Returns None when the client can't display progress.
+    async fn begin_progress(&self, title: &str) -> Option<NumberOrString> {
+        let supported = self.state.client_capabilities.read().await.window.as_ref()
+            .and_then(|w| w.work_done_progress)
+            .unwrap_or(false);
+        if !supported {
+            return None;
+        }
+
+        let token = NumberOrString::String(format!("dbt-lsp/{}", title.to_lowercase().replace(' ', "-")));
+        let created = self.client
+            .send_request::<request::WorkDoneProgressCreate>(WorkDoneProgressCreateParams { token: token.clone() })
+            .await;
+        if created.is_err() {
+            return None;
+        }
+
+        self.client.send_notification::<notification::Progress>(ProgressParams {
+            token: token.clone(),
+            value: ProgressParamsValue::WorkDone(WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: title.to_string(),
+                percentage: Some(0),
+                ..WorkDoneProgressBegin::default()
+            })),
+        }).await;
+        Some(token)
+    }
+
+    async fn report_progress(&self, token: &Option<NumberOrString>, message: String, percentage: u32) {
+        self.client.log_message(MessageType::LOG, format!("Indexed {}", message)).await;
+        if let Some(token) = token {
+            self.client.send_notification::<notification::Progress>(ProgressParams {
+                token: token.clone(),
+                value: ProgressParamsValue::WorkDone(WorkDoneProgress::Report(WorkDoneProgressReport {
+                    message: Some(message),
+                    percentage: Some(percentage),
+                    ..WorkDoneProgressReport::default()
+                })),
+            }).await;
+        }
+    }
+
+    async fn end_progress(&self, token: Option<NumberOrString>, message: &str) {
+        if let Some(token) = token {
+            self.client.send_notification::<notification::Progress>(ProgressParams {
+                token,
+                value: ProgressParamsValue::WorkDone(WorkDoneProgress::End(WorkDoneProgressEnd {
+                    message: Some(message.to_string()),
+                })),
+            }).await;
+        }
+    }
+
+    /// Asks the client to notify us about project files changed outside the editor.
+    async fn register_file_watchers(&self) {
+        let supported = self.state.client_capabilities.read().await.workspace.as_ref()
+            .and_then(|w| w.did_change_watched_files.as_ref())
+            .and_then(|c| c.dynamic_registration)
+            .unwrap_or(false);
+        if !supported {
+            return;
+        }
+
+        let watchers = ["**/*.sql", "**/*.yml", "**/*.csv", "**/*.md", "**/dbt_project.yml"]
+            .iter()
+            .map(|glob| FileSystemWatcher {
+                glob_pattern: GlobPattern::String(glob.to_string()),
+                kind: None,
+            })
+            .collect();
+        let registration = Registration {
+            id: "dbt-lsp/watched-files".to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: serde_json::to_value(DidChangeWatchedFilesRegistrationOptions { watchers }).ok(),
+        };
+        if let Err(e) = self.client.register_capability(vec![registration]).await {
+            self.client.log_message(MessageType::WARNING, format!("Failed to register file watchers: {}", e)).await;
+        }
+    }
+
+    /// Merges a settings payload into the current settings, logging unknown keys and
+    /// rejecting payloads that don't deserialize.
+    async fn update_settings(&self, value: &serde_json::Value) {
+        let result = self.state.settings.write().await.apply(value);
+        match result {
+            Ok(unknown) => {
+                for key in unknown {
+                    self.client.log_message(MessageType::INFO, format!("Ignoring unknown setting '{}'", key)).await;
+                }
+            }
+            Err(e) => {
+                self.client.show_message(MessageType::WARNING, format!("Invalid dbt-lsp settings, left unchanged: {}", e)).await;
+            }
+        }
+    }
+
+    /// Updates the manifests for files created, changed or deleted (`true`) outside the
+    /// editor's buffers, then revalidates the open documents.
+    async fn apply_file_changes(&self, changes: Vec<(std::path::PathBuf, bool)>) {
+        let mut reload_roots = Vec::new();
+        let mut package_changes: Vec<Arc<crate::project::ProjectManifest>> = Vec::new();
+        let mut changed_projects: Vec<Arc<crate::project::ProjectManifest>> = Vec::new();
+        for (path, deleted) in changes {
+            let Some(manifest) = self.state.manifest_for_path(&path).await else { continue };
+            if path == manifest.root_dir.join("dbt_project.yml") {
+                if !reload_roots.contains(&manifest.root_dir) {
+                    reload_roots.push(manifest.root_dir.clone());
+                }
+                continue;
+            }
+            // `dbt deps` writes many files at once; rescan the packages once per project
+            if manifest.is_package_file(&path) {
+                if !package_changes.iter().any(|m| m.root_dir == manifest.root_dir) {
+                    package_changes.push(manifest);
+                }
+                continue;
+            }
+
+            if deleted {
+                manifest.remove_file(&path);
+            } else {
+                manifest.refresh_file(&path);
+            }
+            if !changed_projects.iter().any(|m| m.root_dir == manifest.root_dir) {
+                changed_projects.push(manifest);
+            }
+        }
+
+        for manifest in package_changes.into_iter().filter(|m| !reload_roots.contains(&m.root_dir)) {
+            let m = manifest.clone();
+            let _ = tokio::task::spawn_blocking(move || {
+                m.scan_packages();
+                m.scan_vars();
+            }).await;
+            self.publish_package_diagnostics(&manifest).await;
+        }
+
+        for manifest in changed_projects.into_iter().filter(|m| !reload_roots.contains(&m.root_dir)) {
+            self.publish_project_file_diagnostics(&manifest).await;
+        }
+        // Paths and project name may have changed, so rebuild those projects from scratch
+        for root in &reload_roots {
+            self.index_project(root.clone()).await;
+        }
+        if reload_roots.is_empty() {
+            self.revalidate_open_documents().await;
+            self.refresh_code_lenses().await;
+        }
+    }
+
+    /// The dbt project root of each workspace folder, following `projectDir`. Folders
+    /// where it leads nowhere are reported to the user and skipped.
+    async fn project_roots(&self) -> Vec<std::path::PathBuf> {
+        let workspace_roots = self.state.workspace_roots.read().await.clone();
+        let settings = self.state.settings.read().await.clone();
+        let mut roots = Vec::new();
+        for root in workspace_roots {
+            match settings.project_root(&root) {
+                Ok(project_root) => roots.push(project_root),
+                Err(e) => self.client.show_message(MessageType::ERROR, format!("dbt-lsp: {}", e)).await,
+            }
+        }
+        roots
+    }
+
+    /// Re-runs analysis for every open document, e.g. after the manifest changed.
+    /// Documents whose edits went out of sync wait for their full text.
+    async fn revalidate_open_documents(&self) {
+        let open: Vec<Url> = self.state.documents.iter().map(|entry| entry.key().clone()).collect();
+        for uri in open {
+            // Read each text only now: it may have been edited while the previous one was analysed
+            let Some(rope) = self.state.documents.get(&uri).filter(|doc| !doc.out_of_sync).map(|doc| doc.text.clone()) else { continue };
+            self.analyze_document(uri, rope).await;
+        }
+    }
+
+    /// The upstream and downstream locations of the model open at `uri`. None for
+    /// documents that aren't models.
+    async fn dependency_locations(&self, uri: &Url) -> Option<(Vec<Location>, Vec<Location>)> {
+        let manifest = self.state.manifest_for(uri).await?;
+        let model = manifest.model_name_for_path(&uri.to_file_path().ok()?)?;
+        let encoding = *self.state.position_encoding.read().await;
+
+        let index = manifest.clone();
+        let _ = tokio::task::spawn_blocking(move || index.ensure_reference_index()).await;
+
+        // Copied out: the sources' yml files may be open documents themselves
+        let refs = self.state.documents.get(uri)?.refs.clone();
+        let upstream = crate::lenses::upstream(&manifest, &self.state.documents, &refs, encoding);
+        Some((upstream, crate::lenses::downstream(&manifest, &model, encoding)))
+    }
+
+    /// The DAG node behind a call hierarchy item, with its manifest's reference index built.
+    async fn hierarchy_node(&self, item: &CallHierarchyItem) -> Option<(Arc<crate::project::ProjectManifest>, crate::hierarchy::DagNode)> {
+        let data = serde_json::from_value::<crate::hierarchy::NodeData>(item.data.clone()?).ok()?;
+        let manifest = self.state.manifests.read().await.get(&data.root).cloned()?;
+        let index = manifest.clone();
+        let _ = tokio::task::spawn_blocking(move || index.ensure_reference_index()).await;
+        Some((manifest, data.node))
+    }
+
+    /// `dbt.showDependencies`: the model's upstream or downstream locations, opening the
+    /// target straight away when there is only one.
+    async fn show_dependencies(&self, arguments: &[serde_json::Value]) -> Result<Option<serde_json::Value>> {
+        let uri = arguments.first().and_then(|a| serde_json::from_value::<Url>(a.clone()).ok());
+        let direction = arguments.get(1).and_then(|a| a.as_str());
+        let (Some(uri), Some(direction)) = (uri, direction) else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params("Expected a document URI and a direction"));
+        };
+        let Some((upstream, downstream)) = self.dependency_locations(&uri).await else { return Ok(None) };
+        let locations = if direction == "upstream" { upstream } else { downstream };
+        if let [location] = locations.as_slice() {
+            let _ = self.client.show_document(ShowDocumentParams {
+                uri: location.uri.clone(),
+                external: None,
+                take_focus: Some(true),
+                selection: Some(location.range),
+            }).await;
+        }
+        Ok(serde_json::to_value(locations).ok())
+    }
+
+    /// The manifest and model name for a command's URI argument. Errors when the project
+    /// isn't loaded or the file isn't one of its models.
+    async fn command_model(&self, arguments: &[serde_json::Value]) -> Result<(Arc<crate::project::ProjectManifest>, std::path::PathBuf, String)> {
+        let Some(uri) = arguments.first().and_then(|a| serde_json::from_value::<Url>(a.clone()).ok()) else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params("Expected a document URI"));
+        };
+        let Some(manifest) = self.state.manifest_for(&uri).await else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params("No dbt project is loaded for this file"));
+        };
+        let path = uri.to_file_path().map_err(|_| tower_lsp::jsonrpc::Error::invalid_params("Not a file URI"))?;
+        let Some(name) = manifest.model_name_for_path(&path) else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(format!("{} is not a model", path.display())));
+        };
+        Ok((manifest, path, name))
+    }
+
+    /// `dbt-lsp.showCompiledSql`: compiles the model unless its compiled SQL is already
+    /// newer than the source, opens the compiled file and returns its text.
+    async fn show_compiled_sql(&self, arguments: &[serde_json::Value]) -> Result<Option<serde_json::Value>> {
+        let (manifest, path, name) = self.command_model(arguments).await?;
+        let force = arguments.get(1).is_some_and(|a| a == "force" || a == true);
+        let Some(compiled) = crate::commands::artifact_path(&manifest, &path, "compiled") else { return Ok(None) };
+
+        if force || !crate::commands::is_fresh(&compiled, &path) {
+            let executable = self.state.settings.read().await.dbt_executable.clone();
+            let failure = match crate::commands::run_dbt(&executable, &manifest.root_dir, &["compile", "--select", &name]).await {
+                Ok(output) if output.status.success() => None,
+                Ok(output) => Some(crate::commands::output_tail(&output)),
+                Err(e) => Some(format!("Could not run {}: {}", executable, e)),
+            };
+            if let Some(detail) = failure {
+                self.client.show_message(MessageType::ERROR, format!("dbt compile failed for {}:\n{}", name, detail)).await;
+                return Ok(None);
+            }
+        }
+
+        let Ok(sql) = std::fs::read_to_string(&compiled) else {
+            self.client.show_message(MessageType::ERROR, format!("dbt didn't write {}", compiled.display())).await;
+            return Ok(None);
+        };
+        let Ok(compiled_uri) = Url::from_file_path(&compiled) else { return Ok(None) };
+        let _ = self.client.show_document(ShowDocumentParams {
+            uri: compiled_uri.clone(),
+            external: None,
+            take_focus: Some(true),
+            selection: None,
+        }).await;
+        Ok(Some(serde_json::json!({ "uri": compiled_uri, "sql": sql })))
+    }
+
+    /// `dbt-lsp.openTargetArtifact`: opens the model's compiled or run SQL from `target/`
+    /// when it exists, and reports whether it is missing or older than the model.
+    async fn open_target_artifact(&self, arguments: &[serde_json::Value]) -> Result<Option<serde_json::Value>> {
+        let (manifest, path, _) = self.command_model(arguments).await?;
+        let kind = match arguments.get(1).and_then(|a| a.as_str()) {
+            Some(kind @ ("compiled" | "run")) => kind,
+            None => "compiled",
+            Some(_) => return Err(tower_lsp::jsonrpc::Error::invalid_params("Kind must be compiled or run")),
+        };
+        let Some(artifact) = crate::commands::artifact_path(&manifest, &path, kind) else { return Ok(None) };
+        let Ok(artifact_uri) = Url::from_file_path(&artifact) else { return Ok(None) };
+
+        let exists = artifact.is_file();
+        if exists {
+            let _ = self.client.show_document(ShowDocumentParams {
+                uri: artifact_uri.clone(),
+                external: None,
+                take_focus: Some(true),
+                selection: None,
+            }).await;
+        }
+        Ok(Some(serde_json::json!({
+            "uri": artifact_uri,
+            "exists": exists,
+            "stale": exists && !crate::commands::is_fresh(&artifact, &path),
+        })))
+    }
+
+    /// `dbt-lsp.previewModel`: runs the model's current text through the preview command
+    /// and returns the query with the rows it printed. Refused unless previews are enabled.
+    async fn preview_model(&self, arguments: &[serde_json::Value]) -> Result<Option<serde_json::Value>> {
+        let settings = self.state.settings.read().await.clone();
+        if !settings.enable_preview {
+            return Err(tower_lsp::jsonrpc::Error {
+                code: tower_lsp::jsonrpc::ErrorCode::InvalidRequest,
+                message: "Previews are disabled; set enablePreview to run queries against the warehouse".into(),
+                data: None,
+            });
+        }
+        let (manifest, path, name) = self.command_model(arguments).await?;
+        let limit = arguments.get(1).and_then(|a| a.as_u64()).map_or(settings.preview_limit, |l| l as usize);
+        let text = self.file_text(&path).unwrap_or_default();
+        let target = settings.relation_target(manifest.target.as_ref());
+        let quote = if settings.dialect_name(manifest.target.as_ref()) == "bigquery" { "`" } else { "" };
+        let sql = crate::commands::preview_sql(&manifest, &name, &text, &target, quote, limit)
+            .map_err(|e| crate::commands::preview_error("render", e, None))?;
+
+        let output = crate::commands::run_shell(&settings.preview_command, &manifest.root_dir, &sql).await
+            .map_err(|e| crate::commands::preview_error("execute", format!("Could not run {}: {}", settings.preview_command, e), None))?;
+        if !output.status.success() {
+            let message = format!("Preview query failed for {}", name);
+            return Err(crate::commands::preview_error("execute", message, Some(crate::commands::output_tail(&output))));
+        }
+        let rows = serde_json::from_slice::<serde_json::Value>(&output.stdout).ok().filter(|rows| rows.is_array());
+        let Some(rows) = rows else {
+            let message = "The preview command didn't print a JSON array of rows".to_string();
+            return Err(crate::commands::preview_error("parse", message, Some(crate::commands::output_tail(&output))));
+        };
+        Ok(Some(serde_json::json!({ "sql": sql, "rows": rows })))
+    }
+
+    /// `dbt-lsp.runModel`: runs the model, logging dbt's output as it arrives, and reports
+    /// the outcome and duration. Only one run per model at a time.
+    async fn run_model(&self, arguments: &[serde_json::Value]) -> Result<Option<serde_json::Value>> {
+        let (manifest, path, name) = self.command_model(arguments).await?;
+        let full_refresh = arguments.get(1).is_some_and(|a| a == "--full-refresh");
+        let Some(running) = self.state.start_run(&path) else {
+            return Err(tower_lsp::jsonrpc::Error {
+                code: tower_lsp::jsonrpc::ErrorCode::InvalidRequest,
+                message: format!("dbt run is already running for {}", name).into(),
+                data: None,
+            });
+        };
+
+        let executable = self.state.settings.read().await.dbt_executable.clone();
+        let mut args = vec!["run", "--select", name.as_str()];
+        if full_refresh {
+            args.push("--full-refresh");
+        }
+        let started = std::time::Instant::now();
+        let outcome = self.stream_dbt(&executable, &manifest.root_dir, &args).await;
+        drop(running);
+
+        let elapsed = started.elapsed().as_secs_f64();
+        let (success, message) = match outcome {
+            Ok((true, _)) => (true, format!("dbt run passed for {} in {:.1}s", name, elapsed)),
+            Ok((false, tail)) => (false, format!("dbt run failed for {} after {:.1}s:\n{}", name, elapsed, tail)),
+            Err(e) => (false, format!("Could not run {}: {}", executable, e)),
+        };
+        let level = if success { MessageType::INFO } else { MessageType::ERROR };
+        self.client.show_message(level, message).await;
+        Ok(Some(serde_json::json!({ "success": success, "elapsed": elapsed })))
+    }
+
+    /// `dbt-lsp.testModel`: runs the model's tests and reports the failing ones from
+    /// `run_results.json` as diagnostics, replacing those of the model's previous run.
+    async fn test_model(&self, arguments: &[serde_json::Value]) -> Result<Option<serde_json::Value>> {
+        let (manifest, path, name) = self.command_model(arguments).await?;
+        let executable = self.state.settings.read().await.dbt_executable.clone();
+        // Failing tests make dbt exit non-zero; run_results.json tells them apart from errors
+        let started = std::time::SystemTime::now();
+        let outcome = self.stream_dbt(&executable, &manifest.root_dir, &["test", "--select", &name]).await;
+        let results = match outcome {
+            Ok((_, tail)) => match crate::commands::read_run_results(&manifest, started) {
+                Some(results) => results,
+                None => {
+                    self.client.show_message(MessageType::ERROR, format!("dbt test failed for {}:\n{}", name, tail)).await;
+                    return Ok(None);
+                }
+            },
+            Err(e) => {
+                self.client.show_message(MessageType::ERROR, format!("Could not run {}: {}", executable, e)).await;
+                return Ok(None);
+            }
+        };
+
+        let tests = results.iter().filter(|r| r.unique_id.starts_with("test.")).count();
+        let encoding = *self.state.position_encoding.read().await;
+        let failures: Vec<(Url, Diagnostic)> = crate::commands::test_failure_diagnostics(&manifest, &name, &path, &results, encoding)
+            .into_iter()
+            .filter_map(|(file, diagnostic)| Some((Url::from_file_path(file).ok()?, diagnostic)))
+            .collect();
+        let failed = failures.len();
+        let previous = self.state.test_failures.insert(path, failures.clone()).unwrap_or_default();
+        self.republish_diagnostics(previous.into_iter().chain(failures).map(|(uri, _)| uri).collect()).await;
+
+        let (level, message) = match failed {
+            0 => (MessageType::INFO, format!("{} test{} passed for {}", tests, if tests == 1 { "" } else { "s" }, name)),
+            _ => (MessageType::WARNING, format!("{} of {} tests failed for {}", failed, tests, name)),
+        };
+        self.client.show_message(level, message).await;
+        Ok(Some(serde_json::json!({ "tests": tests, "failed": failed })))
+    }
+
+    /// `dbt-lsp.clearTestResults`: drops the diagnostics of `dbt-lsp.testModel`, for one
+    /// model when given its URI, otherwise for all.
+    async fn clear_test_results(&self, arguments: &[serde_json::Value]) -> Result<Option<serde_json::Value>> {
+        let cleared: Vec<(Url, Diagnostic)> = if arguments.is_empty() {
+            let all: Vec<_> = self.state.test_failures.iter().flat_map(|e| e.value().clone()).collect();
+            self.state.test_failures.clear();
+            all
+        } else {
+            let (_, path, _) = self.command_model(arguments).await?;
+            self.state.test_failures.remove(&path).map(|(_, failures)| failures).unwrap_or_default()
+        };
+        self.republish_diagnostics(cleared.into_iter().map(|(uri, _)| uri).collect()).await;
+        Ok(None)
+    }
+
+    /// `dbt-lsp.rescanProject`: reloads the project at the given root, or every workspace
+    /// root, and returns what each now contains.
+    async fn rescan_project(&self, arguments: &[serde_json::Value]) -> Result<Option<serde_json::Value>> {
+        let roots = match arguments.first().and_then(|a| a.as_str()) {
+            Some(arg) => {
+                let path = Url::parse(arg).ok()
+                    .and_then(|uri| uri.to_file_path().ok())
+                    .unwrap_or_else(|| std::path::PathBuf::from(arg));
+                if self.state.manifests.read().await.contains_key(&path) {
+                    vec![path]
+                } else if self.state.workspace_roots.read().await.contains(&path) {
+                    let project_root = self.state.settings.read().await.project_root(&path);
+                    vec![project_root.map_err(tower_lsp::jsonrpc::Error::invalid_params)?]
+                } else {
+                    return Err(tower_lsp::jsonrpc::Error::invalid_params(format!("{} is not a dbt project root", path.display())));
+                }
+            }
+            None => self.project_roots().await,
+        };
+
+        let mut projects = Vec::new();
+        for root in roots {
+            let Some(manifest) = self.index_project(root).await else { continue };
+            projects.push(serde_json::json!({
+                "root": manifest.root_dir,
+                "name": manifest.config.name,
+                "models": manifest.models.len(),
+                "sources": manifest.sources.len(),
+                "seeds": manifest.seeds.len(),
+                "macros": manifest.macros.len(),
+            }));
+        }
+        Ok(Some(serde_json::Value::Array(projects)))
+    }
+
+    /// `dbt-lsp.showLineage`: the model's upstream and/or downstream tree as markdown.
+    async fn show_lineage(&self, arguments: &[serde_json::Value]) -> Result<Option<serde_json::Value>> {
+        let (manifest, _, name) = self.command_model(arguments).await?;
+        let direction = match arguments.get(1).and_then(|a| a.as_str()) {
+            Some(direction) => crate::lineage::Direction::parse(direction)
+                .ok_or_else(|| tower_lsp::jsonrpc::Error::invalid_params("Direction must be upstream, downstream or both"))?,
+            None => crate::lineage::Direction::Both,
+        };
+        let max_depth = arguments.get(2).and_then(|a| a.as_u64()).map_or(crate::lineage::DEFAULT_DEPTH, |d| d as usize);
+
+        let index = manifest.clone();
+        let _ = tokio::task::spawn_blocking(move || index.ensure_reference_index()).await;
+        let root = crate::hierarchy::DagNode::Model { name };
+        Ok(Some(serde_json::Value::String(crate::lineage::lineage_markdown(&manifest, &root, direction, max_depth))))
+    }
+
+    /// `dbt-lsp.downstream`: everything that transitively refs a model or source, as
+    /// `{model, type, path, depth}` objects ordered by depth. Singular tests and exposures
+    /// are included.
+    async fn downstream(&self, arguments: &[serde_json::Value]) -> Result<Option<serde_json::Value>> {
+        let Some(target) = arguments.first().and_then(|a| a.as_str()) else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params("Expected a model name, URI or source:<source>.<table>"));
+        };
+        let max_depth = arguments.get(1).and_then(|a| a.as_u64()).map(|d| d as usize);
+        let Some((manifest, root)) = self.graph_node(target).await else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(format!("Unknown model or source: {}", target)));
+        };
+
+        let index = manifest.clone();
+        let _ = tokio::task::spawn_blocking(move || index.ensure_reference_index()).await;
+        let nodes: Vec<serde_json::Value> = crate::lineage::downstream_closure(&manifest, &root, max_depth)
+            .into_iter()
+            .filter_map(|(node, depth)| {
+                let (path, _, _) = node.location(&manifest)?;
+                let (kind, name) = match node {
+                    crate::hierarchy::DagNode::Model { name } => ("model", name),
+                    crate::hierarchy::DagNode::Snapshot { name } => ("snapshot", name),
+                    crate::hierarchy::DagNode::Test { name } => ("test", name),
+                    crate::hierarchy::DagNode::Exposure { name } => ("exposure", name),
+                    _ => return None,
+                };
+                Some(serde_json::json!({ "model": name, "type": kind, "path": path, "depth": depth }))
+            })
+            .collect();
+        Ok(Some(serde_json::Value::Array(nodes)))
+    }
+
+    /// `dbt-lsp.exportGraph`: the project's DAG as DOT and JSON, or written to a file.
+    async fn export_graph(&self, arguments: &[serde_json::Value]) -> Result<Option<serde_json::Value>> {
+        let output = arguments.first().and_then(|a| a.as_str()).filter(|p| !p.is_empty()).map(std::path::PathBuf::from);
+        let manifest = self.command_project(arguments.get(1)).await?;
+        let graph = crate::lineage::project_graph(&manifest);
+        let Some(output) = output else {
+            return Ok(Some(serde_json::json!({ "dot": graph.to_dot(), "json": graph })));
+        };
+
+        let is_dot = output.extension().is_some_and(|e| e == "dot" || e == "gv");
+        let text = if is_dot { graph.to_dot() } else { serde_json::to_string_pretty(&graph).unwrap_or_default() };
+        if let Err(e) = std::fs::write(&output, text) {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(format!("Could not write {}: {}", output.display(), e)));
+        }
+        Ok(Some(serde_json::json!({ "path": output, "nodes": graph.nodes.len(), "edges": graph.edges.len() })))
+    }
+
+    /// `dbt-lsp.projectStats`: counts and hygiene lists for the project, as markdown and JSON.
+    async fn project_stats(&self, arguments: &[serde_json::Value]) -> Result<Option<serde_json::Value>> {
+        let manifest = self.command_project(arguments.first()).await?;
+        let stats = crate::stats::project_stats(&manifest);
+        Ok(Some(serde_json::json!({ "markdown": stats.to_markdown(&manifest.config.name), "stats": stats })))
+    }
+
+    /// The project named by a command's root argument, or the only one loaded, with its
+    /// reference index built.
+    async fn command_project(&self, root: Option<&serde_json::Value>) -> Result<Arc<crate::project::ProjectManifest>> {
+        let root = root.and_then(|a| a.as_str()).map(std::path::PathBuf::from);
+        let manifest = {
+            let manifests = self.state.manifests.read().await;
+            match root {
+                Some(root) => manifests.get(&root).cloned(),
+                None if manifests.len() == 1 => manifests.values().next().cloned(),
+                None => None,
+            }
+        };
+        let Some(manifest) = manifest else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params("Expected the root of a loaded dbt project"));
+        };
+        let index = manifest.clone();
+        let _ = tokio::task::spawn_blocking(move || index.ensure_reference_index()).await;
+        Ok(manifest)
+    }
+
+    /// The DAG node named by a command argument: a model file URI, `source:<source>.<table>`,
+    /// or the name of a model, seed or snapshot in any loaded project.
+    async fn graph_node(&self, target: &str) -> Option<(Arc<crate::project::ProjectManifest>, crate::hierarchy::DagNode)> {
+        if let Some(path) = Url::parse(target).ok().and_then(|uri| uri.to_file_path().ok()) {
+            let manifest = self.state.manifest_for_path(&path).await?;
+            let name = manifest.model_name_for_path(&path)?;
+            return Some((manifest, crate::hierarchy::DagNode::Model { name }));
+        }
+        let dbt_ref = match target.strip_prefix("source:") {
+            Some(source) => {
+                let (source, table) = source.split_once('.')?;
+                crate::jinja::DbtRef::Source(source.to_string(), table.to_string())
+            }
+            None => crate::jinja::DbtRef::Model(target.to_string()),
+        };
+        let manifests: Vec<_> = self.state.manifests.read().await.values().cloned().collect();
+        manifests.into_iter().find_map(|manifest| {
+            let node = crate::hierarchy::DagNode::from_ref(&manifest, &dbt_ref)?;
+            Some((manifest, node))
+        })
+    }
+
+    /// Republishes the stored diagnostics of `uris`, merged with their current
+    /// project-level ones: test failures, duplicates and yml ref errors.
+    async fn republish_diagnostics(&self, uris: std::collections::HashSet<Url>) {
+        for uri in uris {
+            let diagnostics = self.state.documents.get(&uri).map(|doc| doc.diagnostics.clone()).unwrap_or_default();
+            self.publish_diagnostics(uri, diagnostics).await;
+        }
+    }
+
+    /// Publishes the missing-package warnings on the project's packages.yml.
+    async fn publish_package_diagnostics(&self, manifest: &crate::project::ProjectManifest) {
+        let Ok(uri) = Url::from_file_path(manifest.root_dir.join("packages.yml")) else { return };
+        let enabled = self.state.settings.read().await.diagnostics;
+        let encoding = *self.state.position_encoding.read().await;
+        let diagnostics = if enabled { crate::diagnostics::package_diagnostics(manifest, encoding) } else { Vec::new() };
+        self.publish_diagnostics(uri, diagnostics).await;
+    }
+
+    /// Republishes the files whose project-level errors may have changed: those with
+    /// duplicates or exposures now and those that had errors before.
+    async fn publish_project_file_diagnostics(&self, manifest: &crate::project::ProjectManifest) {
+        let mut uris: std::collections::HashSet<Url> = self.state.project_diagnostic_files.iter()
+            .filter(|uri| uri.to_file_path().is_ok_and(|p| p.starts_with(&manifest.root_dir)))
+            .map(|uri| uri.clone())
+            .collect();
+        for entry in manifest.duplicate_models.iter() {
+            uris.extend(entry.value().iter().filter_map(|p| Url::from_file_path(p).ok()));
+        }
+        for entry in manifest.duplicate_docs.iter() {
+            uris.extend(entry.value().iter().filter_map(|b| Url::from_file_path(&b.path).ok()));
+        }
+        uris.extend(manifest.exposures.iter().filter_map(|e| Url::from_file_path(&e.path).ok()));
+        uris.extend(manifest.semantic_models.iter().filter_map(|m| Url::from_file_path(&m.path).ok()));
+        uris.extend(manifest.seed_entries.iter().filter_map(|e| Url::from_file_path(&e.path).ok()));
+        uris.extend(manifest.generic_tests.iter().filter_map(|t| Url::from_file_path(t.key()).ok()));
+        uris.extend(Url::from_file_path(manifest.root_dir.join("dbt_project.yml")).ok());
+        self.republish_diagnostics(uris).await;
+    }
+
+    /// Publishes a file's diagnostics together with the failing tests reported on it, the
+    /// errors on model names or docs blocks it duplicates and those on the refs in its yml.
+    async fn publish_diagnostics(&self, uri: Url, mut diagnostics: Vec<Diagnostic>) {
+        for entry in self.state.test_failures.iter() {
+            diagnostics.extend(entry.value().iter().filter(|(file, _)| *file == uri).map(|(_, d)| d.clone()));
+        }
+        let project = match (uri.to_file_path(), self.state.manifest_for(&uri).await) {
+            (Ok(path), Some(manifest)) if self.state.settings.read().await.diagnostics => {
+                let encoding = *self.state.position_encoding.read().await;
+                let mut project = crate::diagnostics::duplicate_model_diagnostics(&manifest, &path);
+                project.extend(crate::diagnostics::duplicate_docs_diagnostics(&manifest, &path, encoding));
+                project.extend(crate::diagnostics::yml_ref_diagnostics(&manifest, &path, encoding));
+                project.extend(crate::diagnostics::seed_column_type_diagnostics(&manifest, &path, encoding));
+                project.extend(crate::diagnostics::generic_test_diagnostics(&manifest, &path, encoding));
+                project
+            }
+            _ => Vec::new(),
+        };
+        if project.is_empty() {
+            self.state.project_diagnostic_files.remove(&uri);
+        } else {
+            self.state.project_diagnostic_files.insert(uri.clone());
+        }
+        diagnostics.extend(project);
+        self.client.publish_diagnostics(uri, diagnostics, None).await;
+    }
+
+    /// Runs dbt, sending each line it prints to stdout to the client's log as it arrives.
+    /// Returns whether it succeeded and the tail of its output.
+    async fn stream_dbt(&self, executable: &str, root: &std::path::Path, args: &[&str]) -> std::io::Result<(bool, String)> {
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt};
+        let mut child = crate::commands::spawn_dbt(executable, root, args)?;
+        let mut stderr = child.stderr.take();
+        let errors = tokio::spawn(async move {
+            let mut text = String::new();
+            if let Some(stderr) = stderr.as_mut() {
+                let _ = stderr.read_to_string(&mut text).await;
+            }
+            text
+        });
+
+        let mut printed = Vec::new();
+        if let Some(stdout) = child.stdout.take() {
+            let mut lines = tokio::io::BufReader::new(stdout).lines();
+            while let Some(line) = lines.next_line().await? {
+                self.client.log_message(MessageType::LOG, &line).await;
+                printed.push(line);
+            }
+        }
+        let status = child.wait().await?;
+        let output = std::process::Output {
+            status,
+            stdout: printed.join("\n").into_bytes(),
+            stderr: errors.await.unwrap_or_default().into_bytes(),
+        };
+        Ok((status.success(), crate::commands::output_tail(&output)))
+    }
+
+    /// Asks the client to re-request code lenses, whose counts depend on the manifest.
+    async fn refresh_code_lenses(&self) {
+        let supported = self.state.client_capabilities.read().await.workspace.as_ref()
+            .and_then(|w| w.code_lens.as_ref())
+            .and_then(|c| c.refresh_support)
+            .unwrap_or(false);
+        if supported {
+            let _ = self.client.code_lens_refresh().await;
+        }
+    }
+
+    /// The text of `path`: the open document's when the client has it open, else the file's.
+    fn file_text(&self, path: &std::path::Path) -> Option<String> {
+        let open = Url::from_file_path(path).ok().and_then(|uri| self.state.documents.get(&uri).map(|doc| doc.text.to_string()));
+        open.or_else(|| std::fs::read_to_string(path).ok())
+    }
+
+    /// The CTE named at `position` in an open SQL document, with all its occurrences.
+    fn cte_occurrences_at(&self, uri: &Url, position: Position, encoding: crate::position::PositionEncoding) -> Option<crate::columns::CteOccurrences> {
+        let doc = self.state.documents.get(uri)?;
+        let tree = doc.tree.as_ref()?;
+        let char_idx = crate::position::position_to_char(&doc.text, position, encoding)?;
+        let text = crate::jinja::preprocess_for_parsing(&doc.text.to_string());
+        crate::columns::cte_occurrences(tree, &text, doc.text.char_to_byte(char_idx))
+    }
+
+    /// The table alias at `position` in an open SQL document, with its uses in the
+    /// statement that defines it. Only aliases the document's alias map knows about count.
+    fn alias_occurrences_at(&self, uri: &Url, position: Position, encoding: crate::position::PositionEncoding) -> Option<crate::columns::AliasOccurrences> {
+        let doc = self.state.documents.get(uri)?;
+        let tree = doc.tree.as_ref()?;
+        let char_idx = crate::position::position_to_char(&doc.text, position, encoding)?;
+        let text = crate::jinja::preprocess_for_parsing(&doc.text.to_string());
+        crate::columns::alias_occurrences(tree, &text, doc.text.char_to_byte(char_idx))
+            .filter(|alias| doc.aliases.keys().any(|known| known.eq_ignore_ascii_case(&alias.name)))
+    }
+
+    /// Goto definition from a yml file: model and seed names jump to their files, source
+    /// table names to their entry in the manifest.
+    async fn yaml_definition(&self, uri: &Url, rope: &ropey::Rope, char_idx: usize) -> Option<GotoDefinitionResponse> {
+        let word = get_word_at_pos(rope, char_idx)?;
+        let manifest = self.state.manifest_for(uri).await?;
+        let encoding = *self.state.position_encoding.read().await;
+
+        // A generic test goes to its `{% test %}` block
+        if let Some(test) = yaml_test_at(rope, char_idx) {
+            let Some(crate::project::GenericTest::Macro(def)) = manifest.resolve_generic_test(&test.name) else { return None };
+            return Some(GotoDefinitionResponse::Scalar(Location {
+                uri: Url::from_file_path(&def.path).ok()?,
+                range: Range::new(Position::new(def.line as u32, 0), Position::new(def.line as u32, 0)),
+            }));
+        }
+
+        let source_table = yaml_source_table(rope, char_idx).map(|(src, tbl)| format!("{}.{}", src, tbl));
+        if let Some(src_def) = source_table.and_then(|name| manifest.sources.get(&name).map(|s| s.value().clone())) {
+            return Some(GotoDefinitionResponse::Scalar(Location {
+                uri: Url::from_file_path(&src_def.path).ok()?,
+                range: crate::position::file_span_to_range(&self.state.documents, &src_def.path, src_def.line, src_def.column, word.len(), encoding),
+            }));
+        }
+
+        if let Some(path) = manifest.models.get(&word).or_else(|| manifest.seeds.get(&word)).map(|p| p.value().clone()) {
+            return Some(GotoDefinitionResponse::Scalar(Location {
+                uri: Url::from_file_path(path).ok()?,
+                range: Range::default(),
+            }));
+        }
+
+        // Metric names in a derived metric's inputs, semantic model names in saved queries,
+        // a model's `group:`
+        let (path, line, column) = manifest.metrics.get(&word).map(|m| (m.path.clone(), m.line, m.column))
+            .or_else(|| manifest.semantic_models.get(&word).map(|m| (m.path.clone(), m.line, m.column)))
+            .or_else(|| manifest.groups.get(&word).map(|g| (g.path.clone(), g.line, g.column)))?;
+        Some(GotoDefinitionResponse::Scalar(Location {
+            uri: Url::from_file_path(&path).ok()?,
+            range: crate::position::file_span_to_range(&self.state.documents, &path, line, column, word.len(), encoding),
+        }))
+    }
+
+    /// Parses and validates `rope`, replaces the stored DocumentState for `uri`
+    /// and publishes the resulting diagnostics.
+    async fn analyze_document(&self, uri: Url, rope: ropey::Rope) {
+        let settings = self.state.settings.read().await.clone();
+        let text = rope.to_string();
+
+        // yml files are only kept for navigation; there is no SQL to parse
+        if is_yaml_uri(&uri) {
+            let _ = self.store_document(uri, crate::state::DocumentState::text_only(rope));
+            return;
+        }
+
+        // Oversized files are tracked for edits only; parsing them would stall the server
+        if text.len() > settings.max_file_size {
+            if self.store_document(uri.clone(), crate::state::DocumentState::text_only(rope)) {
+                self.publish_diagnostics(uri, Vec::new()).await;
+            }
+            return;
+        }
+
+        // 1. Preprocess for parsing (preserves length)
+        let preprocessed = crate::jinja::preprocess_for_parsing(&text);
+
+        // 2. Parse (using preprocessed text)
+        let tree = if let Ok(mut parser) = crate::parser::DbtParser::new() {
+             parser.parse(&preprocessed, None)
+        } else {
+             None
+        };
+
+        // 3. Extract Refs (using original text for semantics)
+        let refs = crate::jinja::extract_refs(&text);
+
+        // 4. Generate Diagnostics
+        let (diagnostics, ctes, aliases) = {
+            let encoding = *self.state.position_encoding.read().await;
+            let manifest = self.state.manifest_for(&uri).await;
+            let (mut diagnostics, ctes, aliases) = crate::diagnostics::validate_refs(&refs, manifest.as_deref(), &rope, tree.as_ref(), encoding, &settings);
+            // Which models may be ref'd depends on the group of the model being edited
+            if let (Some(manifest), Ok(path)) = (manifest.as_deref(), uri.to_file_path()) {
+                diagnostics.extend(crate::diagnostics::access_diagnostics(&refs, manifest, &path, &rope, encoding));
+            }
+            (diagnostics, ctes, aliases)
+        };
+        let diagnostics = if settings.diagnostics { diagnostics } else { Vec::new() };
+
+        // 5. Update State
+        let stored = self.store_document(uri.clone(), crate::state::DocumentState {
+            text: rope,
+            tree,
+            refs,
+            ctes,
+            aliases,
+            diagnostics: diagnostics.clone(),
+            config: crate::jinja::parse_config(&text),
+            out_of_sync: false,
+        });
+
+        if stored {
+            self.publish_diagnostics(uri, diagnostics).await;
+        }
+    }
+
+    /// Replaces the stored state for `uri`, unless its text was edited while `doc` was
+    /// being analysed; the analysis of that edit stores it instead. Whether the text went
+    /// out of sync is kept: only the handlers that receive the full text clear that.
+    fn store_document(&self, uri: Url, mut doc: crate::state::DocumentState) -> bool {
+        match self.state.documents.entry(uri) {
+            dashmap::mapref::entry::Entry::Occupied(mut old) => {
+                if old.get().text != doc.text {
+                    return false;
+                }
+                doc.out_of_sync = old.get().out_of_sync;
+                old.insert(doc);
+            }
+            dashmap::mapref::entry::Entry::Vacant(slot) => { slot.insert(doc); }
+        }
+        true
+    }
+}
+
+fn sql_file_operation_filter() -> FileOperationRegistrationOptions {
+    FileOperationRegistrationOptions {
+        filters: vec![FileOperationFilter {
+            scheme: Some("file".to_string()),
+            pattern: FileOperationPattern {
+                glob: "**/*.sql".to_string(),
+                matches: Some(FileOperationPatternKind::File),
+                options: None,
+            },
+        }],
+    }
+}
+
+/// Models, seeds, snapshots, the yml files documenting them and markdown docs blocks.
+fn project_file_operation_filter() -> FileOperationRegistrationOptions {
+    FileOperationRegistrationOptions {
+        filters: ["**/*.sql", "**/*.csv", "**/*.yml", "**/*.md"].into_iter()
+            .map(|glob| FileOperationFilter {
+                scheme: Some("file".to_string()),
+                pattern: FileOperationPattern {
+                    glob: glob.to_string(),
+                    matches: Some(FileOperationPatternKind::File),
+                    options: None,
+                },
+            })
+            .collect(),
+    }
+}
+
+/// The source and table named by a `- name: <table>` entry under `sources:` at the cursor.
+fn yaml_source_table(rope: &ropey::Rope, char_idx: usize) -> Option<(String, String)> {
+    let word = get_word_at_pos(rope, char_idx)?;
+    let line = rope.char_to_line(char_idx);
+    let keys = crate::yaml::scan_keys(&rope.to_string());
+    keys.iter().find(|k| k.line == line && k.key == "name" && k.value.as_deref() == Some(word.as_str()))
+        .and_then(|k| match k.path.as_slice() {
+            [sources, src, tables] if sources == "sources" && tables == "tables" => Some((src.clone(), word.clone())),
+            _ => None,
+        })
+}
+
+/// The generic test in a yml `data_tests:` list whose name is at `char_idx`.
+fn yaml_test_at(rope: &ropey::Rope, char_idx: usize) -> Option<crate::yaml::YamlTest> {
+    let line = rope.char_to_line(char_idx);
+    let column = rope.char_to_byte(char_idx) - rope.line_to_byte(line);
+    crate::yaml::test_entries(&rope.to_string()).into_iter()
+        .find(|t| t.line == line && (t.column..=t.column + t.name.len()).contains(&column))
+}
+
+fn is_yaml_uri(uri: &Url) -> bool {
+    let path = uri.path();
+    path.ends_with(".yml") || path.ends_with(".yaml")
+}
+
+/// The char range of the identifier-like word around `char_idx`.
+fn word_char_range(rope: &ropey::Rope, char_idx: usize) -> Option<std::ops::Range<usize>> {
+    let len = rope.len_chars();
+    if char_idx >= len { return None; }
+    
+    // Scan backwards
+    let mut start = char_idx;
+    while start > 0 {
+        let c = rope.char(start - 1);
+        if !c.is_alphanumeric() && c != '_' {
+            break;
+        }
+        start -= 1;
+    }
+    
+    // Scan forwards
+    let mut end = char_idx;
+    while end < len {
+        let c = rope.char(end);
+        if !c.is_alphanumeric() && c != '_' {
+            break;
+        }
+        end += 1;
+    }
+    
+    if start == end { return None; }
+    Some(start..end)
+}
+
+fn get_word_at_pos(rope: &ropey::Rope, char_idx: usize) -> Option<String> {
+    word_char_range(rope, char_idx).map(|range| rope.slice(range).to_string())
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.iter().any(|a| a == "--test") {
+        eprintln!("dbt-lsp binary check: OK");
+        return;
+    }
+
+    env_logger::init();
+    eprintln!("dbt-lsp starting...");
+
+    let stdin = tokio::io::stdin();
+    let stdout = tokio::io::stdout();
+
+    let (service, socket) = LspService::new(|client| Backend { 
+        client,
+        state: GlobalState::default(),
+    });
+    Server::new(stdin, stdout, socket).serve(service).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::tests::temp_project;
+
+    fn test_service() -> LspService<Backend> {
+        let (service, _socket) = LspService::new(|client| Backend {
+            client,
+            state: GlobalState::default(),
+        });
+        service
+    }
+
+    /// Loads the project at `root` and registers it with `backend`, as `initialized` would.
+    async fn load_project(backend: &Backend, root: &std::path::Path) {
+        let manifest = crate::project::ProjectManifest::load(root.to_path_buf()).unwrap();
+        backend.state.manifests.write().await.insert(root.to_path_buf(), Arc::new(manifest));
+    }
+
+    fn position_params(uri: &Url, position: Position) -> TextDocumentPositionParams {
+        TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            position,
+        }
+    }
+
+    async fn open(backend: &Backend, uri: &Url, text: &str) {
+        backend.did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: uri.clone(),
+                language_id: "sql".to_string(),
+                version: 0,
+                text: text.to_string(),
+            },
+        }).await;
+    }
+
+    async fn completion_items(backend: &Backend, uri: &Url, position: Position) -> Vec<CompletionItem> {
+        let response = backend.completion(CompletionParams {
+            text_document_position: position_params(uri, position),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+            context: None,
+        }).await.unwrap();
+        match response {
+            Some(CompletionResponse::Array(items)) => items,
+            other => panic!("unexpected completion: {:?}", other),
+        }
+    }
+
+    async fn completion_labels(backend: &Backend, uri: &Url, position: Position) -> Vec<String> {
+        completion_items(backend, uri, position).await.into_iter().map(|i| i.label).collect()
+    }
+
+    #[tokio::test]
+    async fn test_hover_alias_column_of_cte_lists_columns() {
+        let service = test_service();
+        let backend = service.inner();
+        let uri = Url::parse("file:///tmp/cte_alias.sql").unwrap();
+        // Longer than the preview, so the whole body would reach `where_clause_end`
+        let body = (0..20).map(|i| format!("    -- note {}\n", i)).collect::<String>();
+        let text = format!("with orders as (\n    select id, amount from raw_orders\n{}    where_clause_end\n)\nselect o.amount from orders o", body);
+        open(backend, &uri, &text).await;
+
+        let hover = backend.hover(HoverParams {
+            text_document_position_params: position_params(&uri, Position::new(24, 10)),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        }).await.unwrap();
+        match hover.map(|h| h.contents) {
+            Some(HoverContents::Markup(markup)) => {
+                assert!(markup.value.starts_with("**Column of CTE** `orders` (alias `o`)"));
+                assert!(markup.value.contains("`id`, `amount`"));
+                assert!(!markup.value.contains("where_clause_end"));
+            }
+            other => panic!("unexpected hover: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hover_and_goto_after_multibyte_text() {
+        let root = temp_project("utf16");
+        std::fs::write(root.join("models").join("x.sql"), "select 1 as id").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+
+        let uri = Url::from_file_path(root.join("models").join("y.sql")).unwrap();
+        // "😀" takes two UTF-16 code units, so the closing "}" is at UTF-16 column 31 but char 30
+        open(backend, &uri, "select 'é😀' from {{ ref('x') }}").await;
+        let on_ref = Position::new(0, 31);
+
+        let hover = backend.hover(HoverParams {
+            text_document_position_params: position_params(&uri, on_ref),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        }).await.unwrap();
+        match hover.map(|h| h.contents) {
+            Some(HoverContents::Markup(markup)) => assert!(markup.value.contains("`x`")),
+            other => panic!("unexpected hover: {:?}", other),
+        }
+
+        let definition = backend.goto_definition(GotoDefinitionParams {
+            text_document_position_params: position_params(&uri, on_ref),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }).await.unwrap();
+        match definition {
+            Some(GotoDefinitionResponse::Scalar(location)) => assert!(location.uri.path().ends_with("models/x.sql")),
+            other => panic!("unexpected definition: {:?}", other),
+        }
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_this_resolves_to_current_model() {
+        let root = temp_project("this");
+        std::fs::create_dir_all(root.join("analyses")).unwrap();
+        let text = "select * from {{ this }}";
+        std::fs::write(root.join("models").join("orders.sql"), text).unwrap();
+        std::fs::write(root.join("models").join("schema.yml"), "models:\n  - name: orders\n    config:\n      alias: fct_orders\n").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+
+        let model_uri = Url::from_file_path(root.join("models").join("orders.sql")).unwrap();
+        let analysis_uri = Url::from_file_path(root.join("analyses").join("orders.sql")).unwrap();
+        open(backend, &model_uri, text).await;
+        open(backend, &analysis_uri, text).await;
+        let on_this = Position::new(0, 18);
+        let goto = |uri: &Url| GotoDefinitionParams {
+            text_document_position_params: position_params(uri, on_this),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+
+        match backend.goto_definition(goto(&model_uri)).await.unwrap() {
+            Some(GotoDefinitionResponse::Scalar(location)) => {
+                assert!(location.uri.path().ends_with("models/schema.yml"));
+                assert_eq!(location.range.start, Position::new(1, 10));
+            }
+            other => panic!("unexpected definition: {:?}", other),
+        }
+        assert!(backend.goto_definition(goto(&analysis_uri)).await.unwrap().is_none());
+
+        let hover = backend.hover(HoverParams {
+            text_document_position_params: position_params(&model_uri, on_this),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        }).await.unwrap();
+        match hover.map(|h| h.contents) {
+            Some(HoverContents::Markup(markup)) => assert!(markup.value.contains("`fct_orders` (model `orders`)")),
+            other => panic!("unexpected hover: {:?}", other),
+        }
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_goto_alias_and_cte() {
+        let service = test_service();
+        let backend = service.inner();
+        let uri = Url::parse("file:///tmp/dbt-lsp-alias/model.sql").unwrap();
+        open(backend, &uri, "with orders as (select 1 as amount)\nselect o.amount from orders as o").await;
+
+        let goto = |position: Position| GotoDefinitionParams {
+            text_document_position_params: position_params(&uri, position),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+        let target = |response: Option<GotoDefinitionResponse>| match response {
+            Some(GotoDefinitionResponse::Scalar(location)) => location.range,
+            other => panic!("unexpected definition: {:?}", other),
+        };
+
+        // `o` in `o.amount` jumps to the from clause that introduces it
+        let on_alias = target(backend.goto_definition(goto(Position::new(1, 7))).await.unwrap());
+        assert_eq!(on_alias, Range::new(Position::new(1, 21), Position::new(1, 27)));
+
+        // `orders` in that from clause is still the CTE
+        let on_cte = target(backend.goto_definition(goto(Position::new(1, 23))).await.unwrap());
+        assert_eq!(on_cte, Range::new(Position::new(0, 5), Position::new(0, 11)));
+    }
+
+    #[tokio::test]
+    async fn test_goto_from_schema_yml() {
+        let root = temp_project("yml");
+        std::fs::write(root.join("models").join("orders.sql"), "select 1 as id").unwrap();
+        let yml = "models:\n  - name: orders\n";
+        std::fs::write(root.join("models").join("schema.yml"), yml).unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+
+        let uri = Url::from_file_path(root.join("models").join("schema.yml")).unwrap();
+        open(backend, &uri, yml).await;
+        assert!(backend.state.documents.get(&uri).unwrap().refs.is_empty());
+
+        let definition = backend.goto_definition(GotoDefinitionParams {
+            text_document_position_params: position_params(&uri, Position::new(1, 12)),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }).await.unwrap();
+        match definition {
+            Some(GotoDefinitionResponse::Scalar(location)) => assert!(location.uri.path().ends_with("models/orders.sql")),
+            other => panic!("unexpected definition: {:?}", other),
+        }
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_hover_range_covers_ref() {
+        let service = test_service();
+        let backend = service.inner();
+        let uri = Url::parse("file:///tmp/dbt-lsp-hover-range/model.sql").unwrap();
+        open(backend, &uri, "select *\nfrom {{ ref('orders') }} o").await;
+
+        let hover = backend.hover(HoverParams {
+            text_document_position_params: position_params(&uri, Position::new(1, 12)),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        }).await.unwrap().unwrap();
+        assert_eq!(hover.range, Some(Range::new(Position::new(1, 5), Position::new(1, 24))));
+    }
+
+    #[tokio::test]
+    async fn test_ref_completion_only_inside_quotes() {
+        let root = temp_project("complete");
+        for model in ["stg_orders", "stg_users", "fct_orders"] {
+            std::fs::write(root.join("models").join(format!("{}.sql", model)), "select 1 as id").unwrap();
+        }
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+
+        let uri = Url::from_file_path(root.join("models").join("new.sql")).unwrap();
+        open(backend, &uri, "select stg from {{ ref('stg_").await;
+        assert_eq!(completion_labels(backend, &uri, Position::new(0, 28)).await, vec!["stg_orders", "stg_users"]);
+        // Outside the quotes only snippets are offered, not model names
+        let labels = completion_labels(backend, &uri, Position::new(0, 10)).await;
+        assert!(labels.starts_with(&["ref".to_string(), "source".to_string()]) && !labels.iter().any(|l| l.starts_with("stg_")));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    /// Start and end character of the first completion item's text edit.
+    async fn edit_range(backend: &Backend, uri: &Url, position: Position) -> (u32, u32) {
+        match completion_items(backend, uri, position).await[0].text_edit.clone() {
+            Some(CompletionTextEdit::Edit(edit)) => (edit.range.start.character, edit.range.end.character),
+            other => panic!("unexpected edit: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_completion_replaces_partial_name() {
+        let root = temp_project("edits");
+        std::fs::write(root.join("models").join("stg_orders.sql"), "select 1 as id").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+        let uri = Url::from_file_path(root.join("models").join("new.sql")).unwrap();
+
+        open(backend, &uri, "from {{ ref('stg_ord') }}").await;
+        assert_eq!(edit_range(backend, &uri, Position::new(0, 20)).await, (13, 20));
+        // Cursor in the middle of the name: the rest of it is replaced too
+        assert_eq!(edit_range(backend, &uri, Position::new(0, 17)).await, (13, 20));
+
+        // Empty prefix, no closing quote yet
+        open(backend, &uri, "from {{ ref('").await;
+        assert_eq!(edit_range(backend, &uri, Position::new(0, 13)).await, (13, 13));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_alias_column_completion() {
+        let root = temp_project("columns");
+        std::fs::write(root.join("models").join("dim_customers.sql"), "select 1 as customer_id").unwrap();
+        std::fs::write(root.join("models").join("stg_events.sql"), "select 1 as id").unwrap();
+        std::fs::write(root.join("models").join("schema.yml"), "\
+version: 2
+models:
+  - name: dim_customers
+    columns:
+      - name: customer_id
+        description: Primary key
+      - name: country
+").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+
+        let uri = Url::from_file_path(root.join("models").join("new.sql")).unwrap();
+        open(backend, &uri, "select c.cu, e.\nfrom {{ ref('dim_customers') }} as c\njoin {{ ref('stg_events') }} e on true").await;
+        assert_eq!(completion_labels(backend, &uri, Position::new(0, 9)).await, vec!["customer_id", "country"]);
+        assert_eq!(completion_labels(backend, &uri, Position::new(0, 11)).await, vec!["customer_id"]);
+        // Undocumented model: the columns its select list names
+        assert_eq!(completion_labels(backend, &uri, Position::new(0, 15)).await, vec!["id"]);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_seed_column_completion() {
+        let root = temp_project("seed-columns");
+        std::fs::create_dir_all(root.join("seeds")).unwrap();
+        std::fs::write(root.join("dbt_project.yml"), "\
+name: test_project
+seeds:
+  test_project:
+    +column_types:
+      name: varchar(64)
+").unwrap();
+        std::fs::write(root.join("seeds").join("country_codes.csv"), "\u{feff}\"code\";\"name\";region\nNL;Netherlands;EU\n").unwrap();
+        std::fs::write(root.join("seeds").join("schema.yml"), "\
+seeds:
+  - name: country_codes
+    config:
+      column_types:
+        code: char(2)
+").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+
+        let uri = Url::from_file_path(root.join("models").join("new.sql")).unwrap();
+        open(backend, &uri, "select cc.\nfrom {{ ref('country_codes') }} cc").await;
+        let items: Vec<(String, Option<String>)> = completion_items(backend, &uri, Position::new(0, 10)).await
+            .into_iter()
+            .map(|i| (i.label, i.detail))
+            .collect();
+        assert_eq!(items, vec![
+            ("code".to_string(), Some("char(2)".to_string())),
+            ("name".to_string(), Some("varchar(64)".to_string())),
+            ("region".to_string(), None),
+        ]);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_seed_column_types() {
+        let root = temp_project("seed-types");
+        std::fs::create_dir_all(root.join("seeds").join("geo")).unwrap();
+        std::fs::write(root.join("dbt_project.yml"), "\
+name: test_project
+seeds:
+  test_project:
+    geo:
+      +column_types:
+        regoin: varchar(8)
+    currencies:
+      +column_types:
+        symbol: varchar(4)
+").unwrap();
+        std::fs::write(root.join("seeds").join("geo").join("countries.csv"), "code,name,region\nNL,Netherlands,EU\n").unwrap();
+        std::fs::write(root.join("seeds").join("currencies.csv"), "code,name\nEUR,Euro\n").unwrap();
+        std::fs::write(root.join("seeds").join("schema.yml"), "\
+seeds:
+  - name: countries
+    config:
+      column_types:
+        code: char(2)
+        cdoe: char(2)
+    columns:
+      - name: name
+        description: Country name
+").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        let manifest = crate::project::ProjectManifest::load(root.clone()).unwrap();
+        let messages = |file: &std::path::Path| -> Vec<(u32, String)> {
+            crate::diagnostics::seed_column_type_diagnostics(&manifest, file, Default::default()).into_iter().map(|d| (d.range.start.line, d.message)).collect()
+        };
+        assert_eq!(messages(&root.join("seeds").join("schema.yml")), [(5, "Seed 'countries' has no column 'cdoe' (its columns: code, name, region).".to_string())]);
+        assert_eq!(messages(&root.join("dbt_project.yml")), [
+            (5, "Seed 'countries' has no column 'regoin' (its columns: code, name, region).".to_string()),
+            (8, "Seed 'currencies' has no column 'symbol' (its columns: code, name).".to_string()),
+        ]);
+        backend.state.manifests.write().await.insert(root.clone(), Arc::new(manifest));
+
+        let uri = Url::from_file_path(root.join("models").join("stg_countries.sql")).unwrap();
+        open(backend, &uri, "select * from {{ ref('countries') }} join {{ ref('currencies') }}").await;
+        let hover = backend.hover(HoverParams {
+            text_document_position_params: position_params(&uri, Position::new(0, 20)),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        }).await.unwrap();
+        match hover.map(|h| h.contents) {
+            Some(HoverContents::Markup(markup)) => assert!(markup.value.contains("| `code` | char(2) |  |\n| `name` |  | Country name |\n| `region` |  |  |")),
+            other => panic!("unexpected hover: {:?}", other),
+        }
+
+        // The yml entry when the seed has one, else the CSV
+        let goto = |position: Position| backend.goto_definition(GotoDefinitionParams {
+            text_document_position_params: position_params(&uri, position),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        });
+        match goto(Position::new(0, 20)).await.unwrap() {
+            Some(GotoDefinitionResponse::Scalar(location)) => {
+                assert!(location.uri.path().ends_with("seeds/schema.yml"));
+                assert_eq!(location.range.start, Position::new(1, 10));
+            }
+            other => panic!("unexpected definition: {:?}", other),
+        }
+        match goto(Position::new(0, 50)).await.unwrap() {
+            Some(GotoDefinitionResponse::Scalar(location)) => assert!(location.uri.path().ends_with("seeds/currencies.csv")),
+            other => panic!("unexpected definition: {:?}", other),
+        }
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_generic_test_goto_and_hover() {
+        let root = temp_project("generic-tests");
+        std::fs::create_dir_all(root.join("tests").join("generic")).unwrap();
+        std::fs::write(root.join("tests").join("generic").join("is_positive.sql"), "\n{% test is_positive(model, column_name) %}\nselect 1\n{% endtest %}").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+
+        let uri = Url::from_file_path(root.join("models").join("schema.yml")).unwrap();
+        open(backend, &uri, "models:\n  - name: orders\n    data_tests: [is_positive, unique]\n").await;
+        let goto = |position: Position| backend.goto_definition(GotoDefinitionParams {
+            text_document_position_params: position_params(&uri, position),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        });
+        match goto(Position::new(2, 20)).await.unwrap() {
+            Some(GotoDefinitionResponse::Scalar(location)) => {
+                assert!(location.uri.path().ends_with("tests/generic/is_positive.sql"));
+                assert_eq!(location.range.start, Position::new(1, 0));
+            }
+            other => panic!("unexpected definition: {:?}", other),
+        }
+        // Builtins have no source to go to
+        assert!(goto(Position::new(2, 31)).await.unwrap().is_none());
+
+        let hover = |position: Position| backend.hover(HoverParams {
+            text_document_position_params: position_params(&uri, position),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        });
+        match hover(Position::new(2, 20)).await.unwrap().map(|h| h.contents) {
+            Some(HoverContents::Markup(markup)) => {
+                assert!(markup.value.starts_with("**Generic test**"));
+                assert!(markup.value.contains("{% test is_positive(model, column_name) %}"));
+            }
+            other => panic!("unexpected hover: {:?}", other),
+        }
+        match hover(Position::new(2, 31)).await.unwrap().map(|h| h.contents) {
+            Some(HoverContents::Markup(markup)) => assert_eq!(markup.value, "**Generic test**: `unique` (built into dbt)"),
+            other => panic!("unexpected hover: {:?}", other),
+        }
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_doc_completion_in_yml() {
+        let root = temp_project("doc-complete");
+        std::fs::write(root.join("models").join("docs.md"), "{% docs orders_status %}\n\nOne of placed, shipped.\n{% enddocs %}").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+
+        let uri = Url::from_file_path(root.join("models").join("schema.yml")).unwrap();
+        open(backend, &uri, "models:\n  - name: orders\n    description: '{{ doc(\"ord").await;
+        let items = completion_items(backend, &uri, Position::new(2, 29)).await;
+        assert_eq!(items.len(), 1);
+        assert_eq!((items[0].label.as_str(), items[0].detail.as_deref()), ("orders_status", Some("One of placed, shipped.")));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_completion_resolve_adds_documentation() {
+        let root = temp_project("resolve");
+        std::fs::write(root.join("models").join("dim_customers.sql"), "select 1 as customer_id").unwrap();
+        std::fs::write(root.join("models").join("schema.yml"), "models:\n  - name: dim_customers\n    description: One row per customer\n").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+
+        let uri = Url::from_file_path(root.join("models").join("new.sql")).unwrap();
+        open(backend, &uri, "from {{ ref('dim").await;
+        let item = completion_items(backend, &uri, Position::new(0, 16)).await.remove(0);
+        assert!(item.documentation.is_none());
+
+        let resolved = backend.completion_resolve(item).await.unwrap();
+        match resolved.documentation {
+            Some(Documentation::MarkupContent(markup)) => {
+                assert!(markup.value.starts_with("One row per customer"));
+                assert!(markup.value.contains("`models/dim_customers.sql`"));
+                assert!(markup.value.contains("select 1 as customer_id"));
+            }
+            other => panic!("unexpected documentation: {:?}", other),
+        }
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_model_references() {
+        let root = temp_project("references");
+        std::fs::create_dir_all(root.join("analyses")).unwrap();
+        std::fs::write(root.join("models").join("stg_payments.sql"), "select 1 as id").unwrap();
+        std::fs::write(root.join("models").join("fct_orders.sql"), "select *\nfrom {{ ref('stg_payments') }}").unwrap();
+        std::fs::write(root.join("analyses").join("check.sql"), "select * from {{ ref('test_project', 'stg_payments') }}").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+
+        let references = |uri: &Url, position: Position, include_declaration: bool| ReferenceParams {
+            text_document_position: position_params(uri, position),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+            context: ReferenceContext { include_declaration },
+        };
+        let summary = |locations: Option<Vec<Location>>| -> Vec<(String, u32, u32, u32)> {
+            locations.unwrap_or_default().into_iter().map(|l| {
+                let file = l.uri.path().rsplit('/').next().unwrap_or_default().to_string();
+                (file, l.range.start.line, l.range.start.character, l.range.end.character)
+            }).collect()
+        };
+
+        // From a ref in an open document
+        let uri = Url::from_file_path(root.join("models").join("fct_orders.sql")).unwrap();
+        open(backend, &uri, "select *\nfrom {{ ref('stg_payments') }}").await;
+        let found = backend.references(references(&uri, Position::new(1, 15), false)).await.unwrap();
+        assert_eq!(summary(found), vec![
+            ("check.sql".to_string(), 0, 14, 55),
+            ("fct_orders.sql".to_string(), 1, 5, 30),
+        ]);
+
+        // From anywhere in the model's own file, with the declaration
+        let model_uri = Url::from_file_path(root.join("models").join("stg_payments.sql")).unwrap();
+        open(backend, &model_uri, "select 1 as id").await;
+        let found = summary(backend.references(references(&model_uri, Position::new(0, 3), true)).await.unwrap());
+        assert_eq!(found.len(), 3);
+        assert_eq!(found[0], ("stg_payments.sql".to_string(), 0, 0, 0));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_macro_references() {
+        let root = temp_project("macro-references");
+        std::fs::create_dir_all(root.join("macros")).unwrap();
+        std::fs::create_dir_all(root.join("tests")).unwrap();
+        let macro_text = "{% macro cents_to_dollars(col) %}\n({{ col }} / 100)\n{% endmacro %}";
+        std::fs::write(root.join("macros").join("cents.sql"), macro_text).unwrap();
+        std::fs::write(root.join("models").join("orders.sql"), "select {{ cents_to_dollars('amount') }} as amount from t").unwrap();
+        std::fs::write(root.join("tests").join("positive.sql"), "{% set x = test_project.cents_to_dollars('amount') %}select 1").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+
+        let uri = Url::from_file_path(root.join("macros").join("cents.sql")).unwrap();
+        open(backend, &uri, macro_text).await;
+        let references = |include_declaration: bool| ReferenceParams {
+            text_document_position: position_params(&uri, Position::new(0, 3)),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+            context: ReferenceContext { include_declaration },
+        };
+        let files = |locations: Option<Vec<Location>>| -> Vec<String> {
+            locations.unwrap_or_default().into_iter()
+                .map(|l| l.uri.path().rsplit('/').next().unwrap_or_default().to_string())
+                .collect()
+        };
+
+        assert_eq!(files(backend.references(references(false)).await.unwrap()), vec!["orders.sql", "positive.sql"]);
+        assert_eq!(files(backend.references(references(true)).await.unwrap()), vec!["cents.sql", "orders.sql", "positive.sql"]);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_source_references_from_yml() {
+        let root = temp_project("source-references");
+        let yml = "sources:\n  - name: raw\n    tables:\n      - name: users\n";
+        std::fs::write(root.join("models").join("sources.yml"), yml).unwrap();
+        std::fs::write(root.join("models").join("a.sql"), "select * from {{source( \"raw\",'users' )}}").unwrap();
+        std::fs::write(root.join("models").join("b.sql"), "{# select * from {{ source('raw', 'users') }} #}\nselect 1").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+
+        let uri = Url::from_file_path(root.join("models").join("sources.yml")).unwrap();
+        open(backend, &uri, yml).await;
+        let found = backend.references(ReferenceParams {
+            text_document_position: position_params(&uri, Position::new(3, 16)),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+            context: ReferenceContext { include_declaration: false },
+        }).await.unwrap().unwrap_or_default();
+        let files: Vec<&str> = found.iter().map(|l| l.uri.path().rsplit('/').next().unwrap_or_default()).collect();
+        assert_eq!(files, vec!["a.sql"]);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_cte_highlight_and_references() {
+        let service = test_service();
+        let backend = service.inner();
+        let uri = Url::parse("file:///tmp/dbt-lsp-cte-highlight/model.sql").unwrap();
+        open(backend, &uri, "with orders as (select 1 as id)\nselect * from orders").await;
+
+        let highlights = backend.document_highlight(DocumentHighlightParams {
+            text_document_position_params: position_params(&uri, Position::new(1, 16)),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }).await.unwrap().unwrap();
+        let summary: Vec<(u32, u32, Option<DocumentHighlightKind>)> = highlights.iter()
+            .map(|h| (h.range.start.line, h.range.start.character, h.kind))
+            .collect();
+        assert_eq!(summary, vec![(0, 5, Some(DocumentHighlightKind::WRITE)), (1, 14, Some(DocumentHighlightKind::READ))]);
+
+        let references = backend.references(ReferenceParams {
+            text_document_position: position_params(&uri, Position::new(0, 6)),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+            context: ReferenceContext { include_declaration: false },
+        }).await.unwrap().unwrap();
+        assert_eq!(references.iter().map(|l| l.range.start).collect::<Vec<_>>(), vec![Position::new(1, 14)]);
+    }
+
+    #[tokio::test]
+    async fn test_cte_rename() {
+        let service = test_service();
+        let backend = service.inner();
+        let uri = Url::parse("file:///tmp/dbt-lsp-cte-rename/model.sql").unwrap();
+        open(backend, &uri, "with orders as (select 1 as id),\npaid as (select * from orders)\nselect 'orders' from orders").await;
+
+        let rename = |new_name: &str| RenameParams {
+            text_document_position: position_params(&uri, Position::new(2, 24)),
+            new_name: new_name.to_string(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        };
+        let edit = backend.rename(rename("base")).await.unwrap().unwrap();
+        let edits = edit.changes.unwrap().remove(&uri).unwrap();
+        let starts: Vec<Position> = edits.iter().map(|e| e.range.start).collect();
+        assert_eq!(starts, vec![Position::new(0, 5), Position::new(1, 23), Position::new(2, 21)]);
+        assert!(edits.iter().all(|e| e.new_text == "base"));
+
+        let err = backend.rename(rename("paid")).await.unwrap_err();
+        assert!(err.message.contains("already exists"));
+    }
+
+    #[tokio::test]
+    async fn test_model_rename() {
+        let root = temp_project("model-rename");
+        std::fs::create_dir_all(root.join("analyses")).unwrap();
+        std::fs::write(root.join("models").join("stg_pay.sql"), "select 1 as id").unwrap();
+        std::fs::write(root.join("models").join("fct.sql"), "select * from {{ ref('stg_pay') }}").unwrap();
+        std::fs::write(root.join("analyses").join("a.sql"), "select * from {{ ref(\"stg_pay\") }}").unwrap();
+        std::fs::write(root.join("models").join("schema.yml"), "models:\n  - name: stg_pay\n").unwrap();
+        std::fs::write(root.join("models").join("exposures.yml"), "\
+models:
+  - name: fct
+    columns:
+      - name: id
+        tests:
+          - relationships:
+              to: ref('stg_pay')
+              field: id
+exposures:
+  - name: weekly
+    depends_on:
+      - ref('stg_pay')
+").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+        backend.state.client_capabilities.write().await.workspace = Some(WorkspaceClientCapabilities {
+            workspace_edit: Some(WorkspaceEditClientCapabilities {
+                resource_operations: Some(vec![ResourceOperationKind::Rename]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        let uri = Url::from_file_path(root.join("models").join("fct.sql")).unwrap();
+        open(backend, &uri, "select * from {{ ref('stg_pay') }}").await;
+        let rename = |new_name: &str| RenameParams {
+            text_document_position: position_params(&uri, Position::new(0, 24)),
+            new_name: new_name.to_string(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        };
+
+        let edit = backend.rename(rename("stg_payments")).await.unwrap().unwrap();
+        let Some(DocumentChanges::Operations(operations)) = edit.document_changes else { panic!("expected operations") };
+        let mut edited: Vec<String> = operations.iter().filter_map(|op| match op {
+            DocumentChangeOperation::Edit(e) => Some(e.text_document.uri.path().rsplit('/').next().unwrap_or_default().to_string()),
+            _ => None,
+        }).collect();
+        edited.sort();
+        assert_eq!(edited, vec!["a.sql", "exposures.yml", "fct.sql", "schema.yml"]);
+        let yaml_edits = operations.iter().find_map(|op| match op {
+            DocumentChangeOperation::Edit(e) if e.text_document.uri.path().ends_with("exposures.yml") => Some(e.edits.len()),
+            _ => None,
+        });
+        assert_eq!(yaml_edits, Some(2));
+        match operations.last() {
+            Some(DocumentChangeOperation::Op(ResourceOp::Rename(r))) => assert!(r.new_uri.path().ends_with("models/stg_payments.sql")),
+            other => panic!("unexpected last operation: {:?}", other),
+        }
+
+        // A client that can't rename files only gets the text edits
+        backend.state.client_capabilities.write().await.workspace = None;
+        let edit = backend.rename(rename("stg_payments")).await.unwrap().unwrap();
+        assert!(edit.document_changes.is_none());
+        assert_eq!(edit.changes.map(|c| c.len()), Some(4));
+
+        let err = backend.rename(rename("fct")).await.unwrap_err();
+        assert!(err.message.contains("already exists"));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_prepare_rename_and_macro_rename() {
+        let root = temp_project("prepare-rename");
+        std::fs::create_dir_all(root.join("macros")).unwrap();
+        std::fs::write(root.join("macros").join("cents.sql"), "{% macro cents(col) %}({{ col }} / 100){% endmacro %}").unwrap();
+        std::fs::write(root.join("models").join("stg.sql"), "select 1 as id").unwrap();
+        let text = "with t as (select {{ test_project.cents('x') }} as c from {{ ref('stg') }})\nselect * from t where source = '{{ source('raw', 'a') }}'";
+        std::fs::write(root.join("models").join("fct.sql"), text).unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+        let uri = Url::from_file_path(root.join("models").join("fct.sql")).unwrap();
+        open(backend, &uri, text).await;
+
+        let prepare = |line: u32, character: u32| backend.prepare_rename(position_params(&uri, Position::new(line, character)));
+        let placeholder = |response: Option<PrepareRenameResponse>| match response {
+            Some(PrepareRenameResponse::RangeWithPlaceholder { range, placeholder }) => Some((range.start.character, placeholder)),
+            _ => None,
+        };
+        assert_eq!(placeholder(prepare(0, 5).await.unwrap()), Some((5, "t".to_string())));
+        assert_eq!(placeholder(prepare(0, 37).await.unwrap()), Some((34, "cents".to_string())));
+        assert_eq!(placeholder(prepare(0, 68).await.unwrap()), Some((66, "stg".to_string())));
+        assert_eq!(placeholder(prepare(1, 44).await.unwrap()), None);
+        assert_eq!(placeholder(prepare(1, 1).await.unwrap()), None);
+
+        let edit = backend.rename(RenameParams {
+            text_document_position: position_params(&uri, Position::new(0, 37)),
+            new_name: "to_dollars".to_string(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        }).await.unwrap().unwrap();
+        let changes = edit.changes.unwrap();
+        assert_eq!(changes[&uri][0].range.start, Position::new(0, 34));
+        let macro_uri = Url::from_file_path(root.join("macros").join("cents.sql")).unwrap();
+        assert_eq!(changes[&macro_uri][0].range.start, Position::new(0, 9));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_document_symbols() {
+        let service = test_service();
+        let backend = service.inner();
+        let uri = Url::parse("file:///tmp/dbt-lsp-symbols/model.sql").unwrap();
+        open(backend, &uri, "{{ config(materialized='table') }}\nwith orders as (\n  select 1 as id\n)\nselect * from orders\nwhere true {% if is_incremental() %} and id > 0 {% endif %}").await;
+
+        let response = backend.document_symbol(DocumentSymbolParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }).await.unwrap();
+        let Some(DocumentSymbolResponse::Nested(symbols)) = response else { panic!("expected nested symbols") };
+        let names: Vec<&str> = symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["config", "orders", "final select"]);
+        assert_eq!(symbols[1].range.end, Position::new(3, 1));
+        let nested: Vec<&str> = symbols[2].children.iter().flatten().map(|s| s.name.as_str()).collect();
+        assert_eq!(nested, vec!["if is_incremental()"]);
+
+        let macro_uri = Url::parse("file:///tmp/dbt-lsp-symbols/macros.sql").unwrap();
+        open(backend, &macro_uri, "{% macro a() %}1{% endmacro %}\n{% macro b(x) %}{{ x }}{% endmacro %}").await;
+        let response = backend.document_symbol(DocumentSymbolParams {
+            text_document: TextDocumentIdentifier { uri: macro_uri },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }).await.unwrap();
+        let Some(DocumentSymbolResponse::Nested(symbols)) = response else { panic!("expected nested symbols") };
+        assert_eq!(symbols.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(), vec!["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn test_workspace_symbols() {
+        let root = temp_project("workspace-symbols");
+        std::fs::create_dir_all(root.join("macros")).unwrap();
+        for model in ["stg_orders", "fct_orders", "dim_users"] {
+            std::fs::write(root.join("models").join(format!("{}.sql", model)), "select 1 as id").unwrap();
+        }
+        std::fs::write(root.join("models").join("sources.yml"), "sources:\n  - name: raw\n    tables:\n      - name: orders\n").unwrap();
+        std::fs::write(root.join("macros").join("m.sql"), "{% macro order_status() %}1{% endmacro %}").unwrap();
+        std::fs::create_dir_all(root.join("analyses")).unwrap();
+        std::fs::write(root.join("analyses").join("orders_report.sql"), "select * from {{ ref('fct_orders') }}").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+
+        let symbols = backend.symbol(WorkspaceSymbolParams {
+            query: "ord".to_string(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }).await.unwrap().unwrap();
+        let found: Vec<(&str, SymbolKind)> = symbols.iter().map(|s| (s.name.as_str(), s.kind)).collect();
+        assert_eq!(found, vec![
+            ("order_status", SymbolKind::FUNCTION),
+            ("orders_report", SymbolKind::FILE),
+            ("fct_orders", SymbolKind::FILE),
+            ("raw.orders", SymbolKind::STRUCT),
+            ("stg_orders", SymbolKind::FILE),
+        ]);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_alias_highlight() {
+        let service = test_service();
+        let backend = service.inner();
+        let uri = Url::parse("file:///tmp/dbt-lsp-alias-highlight/model.sql").unwrap();
+        open(backend, &uri, "select c.id, c.name\nfrom {{ ref('dim_customers') }} as c").await;
+
+        let highlights = backend.document_highlight(DocumentHighlightParams {
+            text_document_position_params: position_params(&uri, Position::new(0, 7)),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }).await.unwrap().unwrap();
+        let summary: Vec<(Position, Option<DocumentHighlightKind>)> = highlights.iter().map(|h| (h.range.start, h.kind)).collect();
+        assert_eq!(summary, vec![
+            (Position::new(1, 35), Some(DocumentHighlightKind::WRITE)),
+            (Position::new(0, 7), Some(DocumentHighlightKind::READ)),
+            (Position::new(0, 13), Some(DocumentHighlightKind::READ)),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_selection_range_expands_to_statement() {
+        let service = test_service();
+        let backend = service.inner();
+        let uri = Url::parse("file:///tmp/dbt-lsp-selection/model.sql").unwrap();
+        open(backend, &uri, "with a as (\n  select id from {{ ref('orders') }}\n)\nselect * from a").await;
+
+        let ranges = backend.selection_range(SelectionRangeParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            positions: vec![Position::new(1, 27)],
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }).await.unwrap().unwrap();
+        let mut chain = vec![];
+        let mut current = ranges.first();
+        while let Some(selection) = current {
+            chain.push(selection.range);
+            current = selection.parent.as_deref();
+        }
+        assert_eq!(chain[0], Range::new(Position::new(1, 25), Position::new(1, 31)));
+        assert_eq!(chain[1], Range::new(Position::new(1, 24), Position::new(1, 32)));
+        assert_eq!(chain[2], Range::new(Position::new(1, 20), Position::new(1, 33)));
+        assert_eq!(chain[3], Range::new(Position::new(1, 17), Position::new(1, 36)));
+        assert!(chain.contains(&Range::new(Position::new(0, 11), Position::new(2, 0))));
+        assert_eq!(chain.last(), Some(&Range::new(Position::new(0, 0), Position::new(3, 15))));
+    }
+
+    #[tokio::test]
+    async fn test_document_links() {
+        let root = temp_project("document-links");
+        std::fs::create_dir_all(root.join("macros")).unwrap();
+        std::fs::write(root.join("models").join("sources.yml"), "sources:\n  - name: raw\n    tables:\n      - name: users\n").unwrap();
+        std::fs::write(root.join("models").join("stg_users.sql"), "select 1").unwrap();
+        std::fs::write(root.join("macros").join("cents.sql"), "\n{% macro cents(col) %}{{ col }}{% endmacro %}").unwrap();
+        let text = "select {{ cents('x') }}\nfrom {{ ref('stg_users') }}\njoin {{ source('raw', 'users') }}\njoin {{ ref('missing') }}";
+        std::fs::write(root.join("models").join("users.sql"), text).unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+        let uri = Url::from_file_path(root.join("models").join("users.sql")).unwrap();
+        open(backend, &uri, text).await;
+
+        let mut links = backend.document_link(DocumentLinkParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }).await.unwrap().unwrap();
+        links.sort_by_key(|l| l.range.start);
+        let lines: Vec<u32> = links.iter().map(|l| l.range.start.line).collect();
+        assert_eq!(lines, vec![0, 1, 2]);
+
+        let target = |link: &DocumentLink| link.target.as_ref().map(|t| (t.path().rsplit('/').next().unwrap().to_string(), t.fragment().map(str::to_string)));
+        assert_eq!(target(&links[0]), Some(("cents.sql".to_string(), Some("L2".to_string()))));
+        assert_eq!(target(&links[1]), Some(("stg_users.sql".to_string(), None)));
+        assert_eq!(target(&links[2]), None);
+        let resolved = backend.document_link_resolve(links[2].clone()).await.unwrap();
+        assert_eq!(target(&resolved), Some(("sources.yml".to_string(), Some("L4".to_string()))));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_dependency_lenses() {
+        let root = temp_project("dependency-lenses");
+        std::fs::create_dir_all(root.join("macros")).unwrap();
+        std::fs::write(root.join("models").join("sources.yml"), "sources:\n  - name: raw\n    tables:\n      - name: users\n").unwrap();
+        let text = "select * from {{ source('raw', 'users') }} join {{ ref('stg_orders') }} join {{ ref('stg_orders') }}";
+        std::fs::write(root.join("models").join("users.sql"), text).unwrap();
+        std::fs::write(root.join("models").join("stg_orders.sql"), "select 1").unwrap();
+        std::fs::write(root.join("models").join("a.sql"), "select * from {{ ref('users') }} join {{ ref('users') }}").unwrap();
+        std::fs::write(root.join("models").join("b.sql"), "select * from {{ ref('users') }}").unwrap();
+        std::fs::write(root.join("macros").join("m.sql"), "{% macro m() %}{{ ref('users') }}{% endmacro %}").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+        let uri = Url::from_file_path(root.join("models").join("users.sql")).unwrap();
+        open(backend, &uri, text).await;
+
+        let lenses = backend.code_lens(CodeLensParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }).await.unwrap().unwrap();
+        let titles: Vec<String> = lenses.iter().filter_map(|l| l.command.as_ref()).map(|c| c.title.clone()).collect();
+        assert_eq!(titles, vec!["⬆ 2 upstream", "⬇ 3 downstream"]);
+
+        let command = lenses[1].command.clone().unwrap();
+        let result = backend.execute_command(ExecuteCommandParams {
+            command: command.command,
+            arguments: command.arguments.unwrap(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        }).await.unwrap().unwrap();
+        let locations: Vec<Location> = serde_json::from_value(result).unwrap();
+        let files: Vec<String> = locations.iter().map(|l| l.uri.path().rsplit('/').next().unwrap().to_string()).collect();
+        assert_eq!(files, vec!["m.sql", "a.sql", "b.sql"]);
+
+        let macro_uri = Url::from_file_path(root.join("macros").join("m.sql")).unwrap();
+        open(backend, &macro_uri, "{% macro m() %}{{ ref('users') }}{% endmacro %}").await;
+        let lenses = backend.code_lens(CodeLensParams {
+            text_document: TextDocumentIdentifier { uri: macro_uri },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }).await.unwrap();
+        assert!(lenses.is_none());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    /// Writes an executable shell script standing in for dbt.
+    #[cfg(unix)]
+    fn fake_dbt(path: &std::path::Path, script: &str) {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::write(path, format!("#!/bin/sh\n{}", script)).unwrap();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_show_compiled_sql() {
+        let root = temp_project("compiled-sql");
+        std::fs::write(root.join("models").join("orders.sql"), "select * from {{ ref('x') }}").unwrap();
+        let dbt = root.join("fake-dbt");
+        fake_dbt(&dbt, "echo \"$@\" >> calls.log\nmkdir -p target/compiled/test_project/models\necho 'select * from x' > target/compiled/test_project/models/orders.sql\n");
+
+        let service = test_service();
+        let backend = service.inner();
+        backend.state.settings.write().await.dbt_executable = dbt.to_string_lossy().to_string();
+        load_project(backend, &root).await;
+        let uri = Url::from_file_path(root.join("models").join("orders.sql")).unwrap();
+        let run = |arguments: Vec<serde_json::Value>| backend.execute_command(ExecuteCommandParams {
+            command: crate::commands::SHOW_COMPILED_SQL.to_string(),
+            arguments,
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        });
+        let calls = || std::fs::read_to_string(root.join("calls.log")).unwrap_or_default().lines().count();
+
+        let result = run(vec![serde_json::json!(uri)]).await.unwrap().unwrap();
+        assert_eq!(result["sql"], "select * from x\n");
+        assert_eq!(std::fs::read_to_string(root.join("calls.log")).unwrap(), "compile --select orders\n");
+        // Up to date: no second compile unless forced
+        run(vec![serde_json::json!(uri)]).await.unwrap().unwrap();
+        assert_eq!(calls(), 1);
+        run(vec![serde_json::json!(uri), serde_json::json!("force")]).await.unwrap().unwrap();
+        assert_eq!(calls(), 2);
+
+        fake_dbt(&dbt, "echo 'Compilation Error in model orders' >&2\nexit 2\n");
+        assert!(run(vec![serde_json::json!(uri), serde_json::json!("force")]).await.unwrap().is_none());
+        let not_a_model = Url::from_file_path(root.join("dbt_project.yml")).unwrap();
+        assert!(run(vec![serde_json::json!(not_a_model)]).await.is_err());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_open_target_artifact() {
+        let root = temp_project("target-artifact");
+        std::fs::create_dir_all(root.join("models").join("staging")).unwrap();
+        let model = root.join("models").join("staging").join("orders.sql");
+        std::fs::write(&model, "select 1").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+        let uri = Url::from_file_path(&model).unwrap();
+        let open_artifact = |kind: &str| backend.execute_command(ExecuteCommandParams {
+            command: crate::commands::OPEN_TARGET_ARTIFACT.to_string(),
+            arguments: vec![serde_json::json!(uri), serde_json::json!(kind)],
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        });
+
+        let run = root.join("target").join("run").join("test_project").join("models").join("staging").join("orders.sql");
+        let result = open_artifact("run").await.unwrap().unwrap();
+        assert_eq!(result["uri"], serde_json::json!(Url::from_file_path(&run).unwrap()));
+        assert_eq!(result["exists"], false);
+
+        std::fs::create_dir_all(run.parent().unwrap()).unwrap();
+        std::fs::write(&run, "create table orders as select 1").unwrap();
+        let result = open_artifact("run").await.unwrap().unwrap();
+        assert_eq!((result["exists"].clone(), result["stale"].clone()), (serde_json::json!(true), serde_json::json!(false)));
+        assert!(open_artifact("docs").await.is_err());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_preview_model() {
+        let root = temp_project("preview-model");
+        std::fs::write(root.join("models").join("orders.sql"), "select 1 as id").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+        let uri = Url::from_file_path(root.join("models").join("orders.sql")).unwrap();
+        let preview = || backend.execute_command(ExecuteCommandParams {
+            command: crate::commands::PREVIEW_MODEL.to_string(),
+            arguments: vec![serde_json::json!(uri), serde_json::json!(5)],
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        });
+
+        // Nothing runs until previews are enabled
+        backend.state.settings.write().await.preview_command = "touch ran".to_string();
+        assert!(preview().await.is_err());
+        assert!(!root.join("ran").exists());
+
+        // The open buffer is previewed, not the file on disk
+        open(backend, &uri, "select 2 as id;").await;
+        {
+            let mut settings = backend.state.settings.write().await;
+            settings.enable_preview = true;
+            settings.preview_command = "cat > query.sql; echo '[{\"id\": 2}]'".to_string();
+        }
+        let result = preview().await.unwrap().unwrap();
+        assert_eq!(result["rows"], serde_json::json!([{ "id": 2 }]));
+        assert_eq!(std::fs::read_to_string(root.join("query.sql")).unwrap(), "select * from (\nselect 2 as id\n) limit 5");
+
+        backend.state.settings.write().await.preview_command = "echo 'Syntax error: Unexpected keyword' >&2; exit 1".to_string();
+        let error = preview().await.unwrap_err();
+        assert_eq!(error.code, tower_lsp::jsonrpc::ErrorCode::ServerError(crate::commands::PREVIEW_FAILED));
+        assert_eq!(error.data.unwrap()["output"], "Syntax error: Unexpected keyword");
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_model() {
+        let root = temp_project("run-model");
+        std::fs::write(root.join("models").join("orders.sql"), "select 1").unwrap();
+        let dbt = root.join("fake-dbt");
+        fake_dbt(&dbt, "echo \"$@\" >> calls.log\necho 'Running with dbt'\nsleep 0.3\necho 'Completed successfully'\n");
+
+        let service = test_service();
+        let backend = service.inner();
+        backend.state.settings.write().await.dbt_executable = dbt.to_string_lossy().to_string();
+        load_project(backend, &root).await;
+        let uri = Url::from_file_path(root.join("models").join("orders.sql")).unwrap();
+        let run = |arguments: Vec<serde_json::Value>| backend.execute_command(ExecuteCommandParams {
+            command: crate::commands::RUN_MODEL.to_string(),
+            arguments,
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        });
+
+        // A second run of the same model is refused while the first is in flight
+        let (first, second) = tokio::join!(
+            run(vec![serde_json::json!(uri), serde_json::json!("--full-refresh")]),
+            run(vec![serde_json::json!(uri)]),
+        );
+        assert_eq!(first.unwrap().unwrap()["success"], true);
+        assert!(second.is_err());
+        assert_eq!(std::fs::read_to_string(root.join("calls.log")).unwrap(), "run --select orders --full-refresh\n");
+
+        fake_dbt(&dbt, "echo 'Database Error in model orders'\nexit 1\n");
+        assert_eq!(run(vec![serde_json::json!(uri)]).await.unwrap().unwrap()["success"], false);
+        let not_a_model = Url::from_file_path(root.join("dbt_project.yml")).unwrap();
+        assert!(run(vec![serde_json::json!(not_a_model)]).await.is_err());
+
+        // A cancelled run doesn't leave the model marked as running
+        fake_dbt(&dbt, "sleep 5\n");
+        assert!(tokio::time::timeout(std::time::Duration::from_millis(200), run(vec![serde_json::json!(uri)])).await.is_err());
+        assert!(backend.state.running_models.is_empty());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_rescan_project() {
+        let root = temp_project("rescan");
+        std::fs::write(root.join("models").join("orders.sql"), "select * from {{ ref('customers') }}").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        backend.state.workspace_roots.write().await.push(root.clone());
+        load_project(backend, &root).await;
+        let uri = Url::from_file_path(root.join("models").join("orders.sql")).unwrap();
+        open(backend, &uri, "select * from {{ ref('customers') }}").await;
+        assert!(!backend.state.documents.get(&uri).unwrap().diagnostics.is_empty());
+
+        // A model created behind the server's back is picked up and the open document revalidated
+        std::fs::write(root.join("models").join("customers.sql"), "select 1").unwrap();
+        let rescan = |arguments: Vec<serde_json::Value>| backend.execute_command(ExecuteCommandParams {
+            command: crate::commands::RESCAN_PROJECT.to_string(),
+            arguments,
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        });
+        let result = rescan(vec![serde_json::json!(root.to_string_lossy())]).await.unwrap().unwrap();
+        assert_eq!(result[0]["models"], 2);
+        assert!(backend.state.documents.get(&uri).unwrap().diagnostics.is_empty());
+        assert!(backend.state.manifest_for(&uri).await.unwrap().models.contains_key("customers"));
+
+        assert_eq!(rescan(vec![]).await.unwrap().unwrap().as_array().unwrap().len(), 1);
+        assert!(rescan(vec![serde_json::json!(root.join("models").to_string_lossy())]).await.is_err());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_project_dir_setting() {
+        let workspace = std::env::temp_dir().join(format!("dbt-lsp-project-dir-ws-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&workspace);
+        let root = workspace.join("transform");
+        std::fs::create_dir_all(root.join("sql")).unwrap();
+        std::fs::write(root.join("dbt_project.yml"), "name: test_project\n").unwrap();
+        std::fs::write(root.join("sql").join("orders.sql"), "select 1").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        backend.state.workspace_roots.write().await.push(workspace.clone());
+        backend.update_settings(&serde_json::json!({ "projectDir": "transform", "modelPaths": ["sql"] })).await;
+        backend.initialized(InitializedParams {}).await;
+
+        let uri = Url::from_file_path(root.join("sql").join("orders.sql")).unwrap();
+        let manifest = backend.state.manifest_for(&uri).await.unwrap();
+        assert_eq!(manifest.root_dir, root);
+        assert!(manifest.models.contains_key("orders"));
+
+        // Pointing it elsewhere drops the old project rather than keeping it around
+        backend.did_change_configuration(DidChangeConfigurationParams { settings: serde_json::json!({ "projectDir": "missing" }) }).await;
+        assert!(backend.state.manifest_for(&uri).await.is_none());
+
+        let _ = std::fs::remove_dir_all(workspace);
+    }
+
+    #[tokio::test]
+    async fn test_downstream_command() {
+        let root = temp_project("downstream");
+        std::fs::write(root.join("models").join("sources.yml"), "sources:\n  - name: raw\n    tables:\n      - name: users\n").unwrap();
+        std::fs::write(root.join("models").join("stg_users.sql"), "select * from {{ source('raw', 'users') }}").unwrap();
+        std::fs::write(root.join("models").join("dim_users.sql"), "select * from {{ ref('stg_users') }}").unwrap();
+        std::fs::create_dir_all(root.join("tests")).unwrap();
+        std::fs::write(root.join("tests").join("assert_users_unique.sql"), "select id from {{ ref('dim_users') }} group by id having count(*) > 1").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+        let downstream = |arguments: Vec<serde_json::Value>| backend.execute_command(ExecuteCommandParams {
+            command: crate::commands::DOWNSTREAM.to_string(),
+            arguments,
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        });
+
+        let result = downstream(vec![serde_json::json!("source:raw.users")]).await.unwrap().unwrap();
+        assert_eq!(result, serde_json::json!([
+            { "model": "stg_users", "type": "model", "path": root.join("models").join("stg_users.sql"), "depth": 1 },
+            { "model": "dim_users", "type": "model", "path": root.join("models").join("dim_users.sql"), "depth": 2 },
+            { "model": "assert_users_unique", "type": "test", "path": root.join("tests").join("assert_users_unique.sql"), "depth": 3 },
+        ]));
+        let uri = Url::from_file_path(root.join("models").join("stg_users.sql")).unwrap();
+        assert_eq!(downstream(vec![serde_json::json!(uri)]).await.unwrap().unwrap().as_array().unwrap().len(), 2);
+        assert_eq!(downstream(vec![serde_json::json!("source:raw.users"), serde_json::json!(1)]).await.unwrap().unwrap().as_array().unwrap().len(), 1);
+        assert!(downstream(vec![serde_json::json!("missing")]).await.is_err());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_export_graph_and_stats() {
+        let root = temp_project("export-graph");
+        std::fs::write(root.join("models").join("stg_users.sql"), "select 1").unwrap();
+        std::fs::write(root.join("models").join("dim_users.sql"), "select * from {{ ref('stg_users') }}").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+        let export = |arguments: Vec<serde_json::Value>| backend.execute_command(ExecuteCommandParams {
+            command: crate::commands::EXPORT_GRAPH.to_string(),
+            arguments,
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        });
+
+        let result = export(vec![]).await.unwrap().unwrap();
+        assert_eq!(result["json"]["edges"], serde_json::json!([{ "from": "model.test_project.stg_users", "to": "model.test_project.dim_users" }]));
+        assert_eq!(result["json"]["nodes"][0]["type"], "model");
+        assert!(result["dot"].as_str().unwrap().contains("->"));
+
+        let dot = root.join("graph.dot");
+        let result = export(vec![serde_json::json!(dot.to_string_lossy()), serde_json::json!(root.to_string_lossy())]).await.unwrap().unwrap();
+        assert_eq!(result["nodes"], 2);
+        assert!(std::fs::read_to_string(&dot).unwrap().starts_with("digraph dbt {"));
+        assert!(export(vec![serde_json::json!(""), serde_json::json!("/not/a/project")]).await.is_err());
+
+        let stats = backend.execute_command(ExecuteCommandParams {
+            command: crate::commands::PROJECT_STATS.to_string(),
+            arguments: vec![],
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        }).await.unwrap().unwrap();
+        assert_eq!(stats["stats"]["orphans"], serde_json::json!(["dim_users"]));
+        assert!(stats["markdown"].as_str().unwrap().starts_with("# test_project\n"));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_test_model() {
+        let root = temp_project("test-model");
+        std::fs::write(root.join("models").join("orders.sql"), "select 1 as order_id").unwrap();
+        std::fs::write(root.join("models").join("schema.yml"), "models:\n  - name: orders\n    columns:\n      - name: order_id\n        tests:\n          - not_null\n          - unique\n").unwrap();
+        let results = |entries: &str| format!("mkdir -p target\ncat > target/run_results.json <<'EOF'\n{{\"results\": [{}]}}\nEOF\nexit 1\n", entries);
+        let failing = r#"{"unique_id": "test.test_project.not_null_orders_order_id.5fb22c2710", "status": "fail", "failures": 3, "message": "Got 3 results"},
+            {"unique_id": "test.test_project.unique_orders_order_id.a1b2c3", "status": "pass", "failures": 0, "message": null},
+            {"unique_id": "test.test_project.assert_orders_positive", "status": "fail", "failures": 1, "message": "Got 1 result"}"#;
+        let dbt = root.join("fake-dbt");
+        fake_dbt(&dbt, &results(failing));
+
+        let service = test_service();
+        let backend = service.inner();
+        backend.state.settings.write().await.dbt_executable = dbt.to_string_lossy().to_string();
+        load_project(backend, &root).await;
+        let model = root.join("models").join("orders.sql");
+        let uri = Url::from_file_path(&model).unwrap();
+        let run = |command: &str, arguments: Vec<serde_json::Value>| backend.execute_command(ExecuteCommandParams {
+            command: command.to_string(),
+            arguments,
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        });
+        let reported = || backend.state.test_failures.get(&model).map(|f| f.clone()).unwrap_or_default();
+
+        let result = run(crate::commands::TEST_MODEL, vec![serde_json::json!(uri)]).await.unwrap().unwrap();
+        assert_eq!(result["tests"], 3);
+        assert_eq!(result["failed"], 2);
+        let failures = reported();
+        let schema = Url::from_file_path(root.join("models").join("schema.yml")).unwrap();
+        // The generic test lands on its column, the singular one on the model's entry
+        assert_eq!(failures[0].0, schema);
+        assert_eq!(failures[0].1.range, Range::new(Position::new(3, 14), Position::new(3, 22)));
+        assert!(failures[0].1.message.contains("3 failing rows"));
+        assert_eq!(failures[0].1.severity, Some(DiagnosticSeverity::WARNING));
+        assert_eq!(failures[1].1.range, Range::new(Position::new(1, 10), Position::new(1, 16)));
+
+        // A later run replaces the earlier results rather than adding to them
+        fake_dbt(&dbt, &results(r#"{"unique_id": "test.test_project.assert_orders_positive", "status": "fail", "failures": 1, "message": null}"#));
+        run(crate::commands::TEST_MODEL, vec![serde_json::json!(uri)]).await.unwrap().unwrap();
+        assert_eq!(reported().len(), 1);
+
+        // dbt failing before it writes run_results.json doesn't report the previous run's results
+        let stale = std::time::SystemTime::now() - std::time::Duration::from_secs(60);
+        std::fs::File::options().write(true).open(root.join("target").join("run_results.json")).unwrap().set_modified(stale).unwrap();
+        fake_dbt(&dbt, "echo 'Compilation Error'\nexit 2\n");
+        assert!(run(crate::commands::TEST_MODEL, vec![serde_json::json!(uri)]).await.unwrap().is_none());
+        assert_eq!(reported().len(), 1);
+
+        run(crate::commands::CLEAR_TEST_RESULTS, vec![]).await.unwrap();
+        assert!(reported().is_empty());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_relation_inlay_hints() {
+        let root = temp_project("inlay-hints");
+        std::fs::write(root.join("models").join("sources.yml"), "sources:\n  - name: raw\n    database: rawdb\n    tables:\n      - name: users\n").unwrap();
+        std::fs::write(root.join("models").join("stg_orders.sql"), "{{ config(alias='orders') }}\nselect 1").unwrap();
+        let text = "select * from {{ ref('stg_orders') }}\njoin {{ source('raw', 'users') }}\njoin {{ ref('missing') }}";
+        std::fs::write(root.join("models").join("users.sql"), text).unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+        {
+            let mut settings = backend.state.settings.write().await;
+            settings.target_database = Some("analytics".to_string());
+            settings.target_schema = Some("stg".to_string());
+        }
+        let uri = Url::from_file_path(root.join("models").join("users.sql")).unwrap();
+        open(backend, &uri, text).await;
+
+        let hints = |range: Range| backend.inlay_hint(InlayHintParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            range,
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        });
+        let summary = |hints: Option<Vec<InlayHint>>| -> Vec<(Position, String)> {
+            hints.unwrap_or_default().into_iter()
+                .map(|h| (h.position, match h.label { InlayHintLabel::String(s) => s, _ => String::new() }))
+                .collect()
+        };
+        let everything = Range::new(Position::new(0, 0), Position::new(3, 0));
+        assert_eq!(summary(hints(everything).await.unwrap()), vec![
+            (Position::new(0, 37), "→ analytics.stg.orders".to_string()),
+            (Position::new(1, 33), "→ rawdb.raw.users".to_string()),
+        ]);
+        let second_line = Range::new(Position::new(1, 0), Position::new(1, 40));
+        assert_eq!(summary(hints(second_line).await.unwrap()).len(), 1);
+
+        backend.state.settings.write().await.inlay_hints = false;
+        assert!(hints(everything).await.unwrap().is_none());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_formatting_skips_macro_files() {
+        let service = test_service();
+        let backend = service.inner();
+        let format = |uri: Url| backend.formatting(DocumentFormattingParams {
+            text_document: TextDocumentIdentifier { uri },
+            options: FormattingOptions { tab_size: 2, insert_spaces: true, ..Default::default() },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        });
+
+        let model = Url::parse("file:///tmp/dbt-lsp-formatting/model.sql").unwrap();
+        open(backend, &model, "SELECT id, name FROM {{ ref('users') }}\n").await;
+        let edits = format(model.clone()).await.unwrap().unwrap();
+        let mut rope = ropey::Rope::from_str("SELECT id, name FROM {{ ref('users') }}\n");
+        for edit in edits.iter().rev() {
+            let start = crate::position::position_to_char(&rope, edit.range.start, crate::position::PositionEncoding::Utf16).unwrap();
+            let end = crate::position::position_to_char(&rope, edit.range.end, crate::position::PositionEncoding::Utf16).unwrap();
+            rope.remove(start..end);
+            rope.insert(start, &edit.new_text);
+        }
+        assert_eq!(rope.to_string(), "select\n  id,\n  name\nfrom {{ ref('users') }}\n");
+
+        let macro_uri = Url::parse("file:///tmp/dbt-lsp-formatting/macros.sql").unwrap();
+        open(backend, &macro_uri, "{% macro m() %}SELECT 1{% endmacro %}").await;
+        assert_eq!(format(macro_uri).await.unwrap(), None);
+    }
+
+    async fn range_formatted(backend: &Backend, uri: &Url, text: &str, needle: &str) -> Option<String> {
+        let start = text.find(needle).unwrap();
+        let position = |idx: usize| {
+            let before = &text[..idx];
+            Position::new(before.matches('\n').count() as u32, (idx - before.rfind('\n').map_or(0, |i| i + 1)) as u32)
+        };
+        let edits = backend.range_formatting(DocumentRangeFormattingParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            range: Range::new(position(start), position(start + needle.len())),
+            options: FormattingOptions { tab_size: 4, insert_spaces: true, ..Default::default() },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        }).await.unwrap()?;
+        let mut rope = ropey::Rope::from_str(text);
+        for edit in edits.iter().rev() {
+            let start = crate::position::position_to_char(&rope, edit.range.start, crate::position::PositionEncoding::Utf16).unwrap();
+            let end = crate::position::position_to_char(&rope, edit.range.end, crate::position::PositionEncoding::Utf16).unwrap();
+            rope.remove(start..end);
+            rope.insert(start, &edit.new_text);
+        }
+        Some(rope.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_range_formatting() {
+        let service = test_service();
+        let backend = service.inner();
+
+        let uri = Url::parse("file:///tmp/dbt-lsp-range-formatting/ctes.sql").unwrap();
+        let text = "with a as (\n  SELECT   x,   y FROM t\n),\nb as (\n    select    CASE WHEN 1 THEN 2 END\n)\nselect * from a";
+        open(backend, &uri, text).await;
+        assert_eq!(
+            range_formatted(backend, &uri, text, "FROM").await.unwrap(),
+            "with a as (\n    select\n        x,\n        y\n    from t\n),\nb as (\n    select    CASE WHEN 1 THEN 2 END\n)\nselect * from a",
+        );
+
+        let uri = Url::parse("file:///tmp/dbt-lsp-range-formatting/blocks.sql").unwrap();
+        let text = "select a from t\n{% if x %}\nwhere   b = 1\n{% endif %}";
+        open(backend, &uri, text).await;
+        assert_eq!(
+            range_formatted(backend, &uri, text, "b = 1").await.unwrap(),
+            "select\n    a\nfrom t\n{% if x %}\nwhere b = 1\n{% endif %}",
+        );
+
+        let uri = Url::parse("file:///tmp/dbt-lsp-range-formatting/unclosed.sql").unwrap();
+        let text = "select a from t\n{% if x %}\nwhere   b = 1";
+        open(backend, &uri, text).await;
+        assert_eq!(range_formatted(backend, &uri, text, "b = 1").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_call_hierarchy() {
+        let root = temp_project("call-hierarchy");
+        std::fs::create_dir_all(root.join("seeds")).unwrap();
+        std::fs::write(root.join("models").join("sources.yml"), "sources:\n  - name: raw\n    tables:\n      - name: users\n").unwrap();
+        std::fs::write(root.join("seeds").join("countries.csv"), "code,name\n").unwrap();
+        let text = "select * from {{ source('raw', 'users') }}\njoin {{ ref('countries') }}\njoin {{ ref('countries') }}";
+        std::fs::write(root.join("models").join("stg_users.sql"), text).unwrap();
+        std::fs::write(root.join("models").join("users.sql"), "select * from {{ ref('stg_users') }}").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+        let uri = Url::from_file_path(root.join("models").join("stg_users.sql")).unwrap();
+        open(backend, &uri, text).await;
+
+        let prepare = |position: Position| backend.prepare_call_hierarchy(CallHierarchyPrepareParams {
+            text_document_position_params: position_params(&uri, position),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        });
+        let item = prepare(Position::new(1, 0)).await.unwrap().unwrap().remove(0);
+        assert_eq!(item.name, "stg_users");
+        let source = prepare(Position::new(0, 20)).await.unwrap().unwrap().remove(0);
+        assert_eq!((source.name.as_str(), source.range.start.line), ("raw.users", 3));
+
+        let outgoing = backend.outgoing_calls(CallHierarchyOutgoingCallsParams {
+            item: item.clone(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }).await.unwrap().unwrap();
+        let summary: Vec<(String, usize)> = outgoing.iter().map(|c| (c.to.name.clone(), c.from_ranges.len())).collect();
+        assert_eq!(summary, vec![("raw.users".to_string(), 1), ("countries".to_string(), 2)]);
+
+        let incoming = backend.incoming_calls(CallHierarchyIncomingCallsParams {
+            item,
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }).await.unwrap().unwrap();
+        let callers: Vec<&str> = incoming.iter().map(|c| c.from.name.as_str()).collect();
+        assert_eq!(callers, vec!["users"]);
+        assert_eq!(incoming[0].from_ranges[0].start, Position::new(0, 14));
+
+        let leaf = backend.outgoing_calls(CallHierarchyOutgoingCallsParams {
+            item: source,
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }).await.unwrap().unwrap();
+        assert!(leaf.is_empty());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_create_missing_model_action() {
+        let root = temp_project("create-model");
+        std::fs::create_dir_all(root.join("models").join("marts")).unwrap();
+        let text = "select * from {{ ref('stg_foo') }}";
+        std::fs::write(root.join("models").join("marts").join("orders.sql"), text).unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+        let uri = Url::from_file_path(root.join("models").join("marts").join("orders.sql")).unwrap();
+        open(backend, &uri, text).await;
+        let diagnostics = backend.state.documents.get(&uri).unwrap().diagnostics.clone();
+
+        let actions = backend.code_action(CodeActionParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            range: diagnostics[0].range,
+            context: CodeActionContext { diagnostics: diagnostics.clone(), only: None, trigger_kind: None },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }).await.unwrap().unwrap();
+        let CodeActionOrCommand::CodeAction(action) = &actions[0] else { panic!("expected a code action") };
+        assert_eq!(action.title, "Create model stg_foo.sql");
+        let Some(DocumentChanges::Operations(operations)) = action.edit.as_ref().and_then(|e| e.document_changes.clone()) else {
+            panic!("expected document operations");
+        };
+        let new_path = root.join("models").join("marts").join("stg_foo.sql");
+        let DocumentChangeOperation::Op(ResourceOp::Create(create)) = &operations[0] else { panic!("expected a create") };
+        assert_eq!(create.uri, Url::from_file_path(&new_path).unwrap());
+
+        // The client creates the file, then tells the server
+        std::fs::write(&new_path, "select 1 as placeholder\n").unwrap();
+        backend.did_create_files(CreateFilesParams {
+            files: vec![FileCreate { uri: create.uri.to_string() }],
+        }).await;
+        assert!(backend.state.documents.get(&uri).unwrap().diagnostics.is_empty());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_did_create_and_delete_files() {
+        let root = temp_project("file-ops");
+        std::fs::create_dir_all(root.join("seeds")).unwrap();
+        let tables: String = (1..=5).map(|i| format!("      - name: t{}\n", i)).collect();
+        std::fs::write(root.join("models").join("raw.yml"), format!("sources:\n  - name: raw\n    tables:\n{}", tables)).unwrap();
+        std::fs::write(root.join("models").join("other.yml"), "sources:\n  - name: other\n    tables:\n      - name: t1\n").unwrap();
+        std::fs::write(root.join("models").join("customers.sql"), "select 1").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+        let text = "select * from {{ ref('customers') }}\njoin {{ ref('countries') }} using (id)\njoin {{ source('raw', 't1') }} using (id)";
+        let uri = Url::from_file_path(root.join("models").join("orders.sql")).unwrap();
+        open(backend, &uri, text).await;
+        let messages = |backend: &Backend| backend.state.documents.get(&uri).unwrap().diagnostics.iter().map(|d| d.message.clone()).collect::<Vec<_>>();
+        assert_eq!(messages(backend), ["Model/Seed 'countries' not found in project."]);
+
+        let file = |name: &str| Url::from_file_path(root.join(name)).unwrap().to_string();
+        std::fs::write(root.join("seeds").join("countries.csv"), "id,name\n").unwrap();
+        backend.did_create_files(CreateFilesParams { files: vec![FileCreate { uri: file("seeds/countries.csv") }] }).await;
+        assert!(messages(backend).is_empty());
+
+        std::fs::remove_file(root.join("models").join("raw.yml")).unwrap();
+        std::fs::remove_file(root.join("models").join("customers.sql")).unwrap();
+        backend.did_delete_files(DeleteFilesParams {
+            files: vec![FileDelete { uri: file("models/raw.yml") }, FileDelete { uri: file("models/customers.sql") }],
+        }).await;
+        let manifest = backend.state.manifest_for(&uri).await.unwrap();
+        assert_eq!(manifest.sources.iter().map(|s| s.key().clone()).collect::<Vec<_>>(), ["other.t1"]);
+        assert_eq!(messages(backend), ["Model/Seed 'customers' not found in project.", "Source 'raw.t1' not found."]);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_model_names() {
+        let root = temp_project("duplicates");
+        std::fs::create_dir_all(root.join("models").join("legacy")).unwrap();
+        std::fs::write(root.join("models").join("customers.sql"), "select 1").unwrap();
+        std::fs::write(root.join("models").join("legacy").join("customers.sql"), "select 2").unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+        let customers = Url::from_file_path(root.join("models").join("customers.sql")).unwrap();
+        open(backend, &customers, "select 1").await;
+        let uri = Url::from_file_path(root.join("models").join("orders.sql")).unwrap();
+        let text = "select * from {{ ref('customers') }}";
+        open(backend, &uri, text).await;
+
+        let manifest = backend.state.manifest_for(&uri).await.unwrap();
+        let messages: Vec<String> = crate::diagnostics::duplicate_model_diagnostics(&manifest, &root.join("models").join("customers.sql"))
+            .into_iter().map(|d| d.message).collect();
+        assert_eq!(messages, ["Duplicate model name 'customers': also defined at models/legacy/customers.sql. dbt requires model names to be unique."]);
+        backend.publish_project_file_diagnostics(&manifest).await;
+        assert_eq!(backend.state.project_diagnostic_files.len(), 2);
+
+        let definition = backend.goto_definition(GotoDefinitionParams {
+            text_document_position_params: position_params(&uri, Position::new(0, 24)),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }).await.unwrap();
+        match definition {
+            Some(GotoDefinitionResponse::Array(locations)) => {
+                let paths: Vec<String> = locations.iter().map(|l| l.uri.path().to_string()).collect();
+                assert!(paths[0].ends_with("models/customers.sql") && paths[1].ends_with("models/legacy/customers.sql"), "{:?}", paths);
+            }
+            other => panic!("unexpected definition: {:?}", other),
+        }
+
+        // Deleting one copy clears the error on the other and resolves the name to it
+        std::fs::remove_file(root.join("models").join("customers.sql")).unwrap();
+        backend.did_delete_files(DeleteFilesParams {
+            files: vec![FileDelete { uri: customers.to_string() }],
+        }).await;
+        assert!(backend.state.project_diagnostic_files.is_empty());
+        let definition = backend.goto_definition(GotoDefinitionParams {
+            text_document_position_params: position_params(&uri, Position::new(0, 24)),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }).await.unwrap();
+        match definition {
+            Some(GotoDefinitionResponse::Scalar(location)) => assert!(location.uri.path().ends_with("models/legacy/customers.sql")),
+            other => panic!("unexpected definition: {:?}", other),
+        }
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_did_you_mean_actions() {
+        let root = temp_project("did-you-mean");
+        std::fs::write(root.join("models").join("sources.yml"), "sources:\n  - name: raw\n    tables:\n      - name: users\n").unwrap();
+        std::fs::write(root.join("models").join("stg_payments.sql"), "select 1").unwrap();
+        let text = "select * from {{ ref('stg_payment') }}\njoin {{ source('raw', 'user') }}";
+        std::fs::write(root.join("models").join("orders.sql"), text).unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+        let uri = Url::from_file_path(root.join("models").join("orders.sql")).unwrap();
+        open(backend, &uri, text).await;
+        let diagnostics = backend.state.documents.get(&uri).unwrap().diagnostics.clone();
+
+        let actions = backend.code_action(CodeActionParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            range: Range::new(Position::new(0, 0), Position::new(2, 0)),
+            context: CodeActionContext { diagnostics, only: None, trigger_kind: None },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }).await.unwrap().unwrap();
+        let fixes: Vec<(String, Range, String)> = actions.iter().filter_map(|a| match a {
+            CodeActionOrCommand::CodeAction(action) if action.title.starts_with("Change") => {
+                let edit = action.edit.as_ref()?.changes.as_ref()?.get(&uri)?[0].clone();
+                Some((action.title.clone(), edit.range, edit.new_text))
+            }
+            _ => None,
+        }).collect();
+        assert_eq!(fixes, vec![
+            ("Change to 'stg_payments'".to_string(), Range::new(Position::new(0, 22), Position::new(0, 33)), "stg_payments".to_string()),
+            ("Change to 'users'".to_string(), Range::new(Position::new(1, 23), Position::new(1, 27)), "users".to_string()),
+        ]);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_source_scaffold_actions() {
+        let root = temp_project("source-scaffold");
+        std::fs::create_dir_all(root.join("models").join("staging")).unwrap();
+        let sources = "version: 2\n\nsources:\n    - name: raw\n      tables:\n          - name: users\n";
+        std::fs::write(root.join("models").join("sources.yml"), sources).unwrap();
+        let text = "select * from {{ source('raw', 'new_table') }}\njoin {{ source('stripe', 'charges') }}";
+        std::fs::write(root.join("models").join("staging").join("orders.sql"), text).unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+        let uri = Url::from_file_path(root.join("models").join("staging").join("orders.sql")).unwrap();
+        open(backend, &uri, text).await;
+        let diagnostics = backend.state.documents.get(&uri).unwrap().diagnostics.clone();
+
+        let actions = backend.code_action(CodeActionParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            range: Range::new(Position::new(0, 0), Position::new(2, 0)),
+            context: CodeActionContext { diagnostics, only: None, trigger_kind: None },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }).await.unwrap().unwrap();
+        let yml = Url::from_file_path(root.join("models").join("sources.yml")).unwrap();
+        let fixes: Vec<(String, String)> = actions.iter().filter_map(|a| match a {
+            CodeActionOrCommand::CodeAction(action) if action.title.starts_with("Add") || action.title.starts_with("Create source") => {
+                Some((action.title.clone(), apply_changes(sources, &yml, action.edit.as_ref()?)))
+            }
+            _ => None,
+        }).collect();
+        assert_eq!(fixes, vec![
+            ("Add table 'new_table' to source 'raw'".to_string(), format!("{}          - name: new_table\n", sources)),
+            ("Create source 'stripe' with table 'charges'".to_string(), format!("{}    - name: stripe\n      tables:\n          - name: charges\n", sources)),
+        ]);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_qualify_ambiguous_ref() {
+        let root = temp_project("ambiguous-ref");
+        std::fs::create_dir_all(root.join("dbt_packages").join("dbt_date").join("models")).unwrap();
+        std::fs::write(root.join("dbt_project.yml"), "name: my_project\n").unwrap();
+        std::fs::write(root.join("dbt_packages").join("dbt_date").join("dbt_project.yml"), "name: dbt_date\n").unwrap();
+        std::fs::write(root.join("dbt_packages").join("dbt_date").join("models").join("dim_dates.sql"), "select 1").unwrap();
+        std::fs::write(root.join("models").join("dim_dates.sql"), "select 1").unwrap();
+        let text = "select * from {{ ref(\"dim_dates\") }}";
+        std::fs::write(root.join("models").join("orders.sql"), text).unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+        let uri = Url::from_file_path(root.join("models").join("orders.sql")).unwrap();
+        open(backend, &uri, text).await;
+        let diagnostics = backend.state.documents.get(&uri).unwrap().diagnostics.clone();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::INFORMATION));
+
+        let actions = backend.code_action(CodeActionParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            range: diagnostics[0].range,
+            context: CodeActionContext { diagnostics, only: None, trigger_kind: None },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }).await.unwrap().unwrap();
+        let fixes: Vec<(String, String)> = actions.iter().filter_map(|a| match a {
+            CodeActionOrCommand::CodeAction(action) if action.title.starts_with("Qualify") => {
+                Some((action.title.clone(), apply_changes(text, &uri, action.edit.as_ref()?)))
+            }
+            _ => None,
+        }).collect();
+        assert_eq!(fixes, vec![
+            ("Qualify as ref('my_project', 'dim_dates')".to_string(), "select * from {{ ref(\"my_project\", \"dim_dates\") }}".to_string()),
+            ("Qualify as ref('dbt_date', 'dim_dates')".to_string(), "select * from {{ ref(\"dbt_date\", \"dim_dates\") }}".to_string()),
+        ]);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    async fn code_actions(backend: &Backend, uri: &Url, position: Position) -> Vec<CodeActionOrCommand> {
+        backend.code_action(CodeActionParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            range: Range::new(position, position),
+            context: CodeActionContext { diagnostics: Vec::new(), only: None, trigger_kind: None },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }).await.unwrap().unwrap_or_default()
+    }
+
+    async fn code_action_titles(backend: &Backend, uri: &Url, position: Position) -> Vec<(String, Option<TextEdit>)> {
+        code_actions(backend, uri, position).await.into_iter().filter_map(|a| match a {
+            CodeActionOrCommand::CodeAction(action) => {
+                let edit = action.edit.as_ref().and_then(|e| e.changes.as_ref()).and_then(|c| c.get(uri)).map(|edits| edits[0].clone());
+                Some((action.title, edit))
+            }
+            CodeActionOrCommand::Command(_) => None,
+        }).collect()
+    }
+
+    /// Applies a single-file workspace edit to `text`.
+    fn apply_changes(text: &str, uri: &Url, edit: &WorkspaceEdit) -> String {
+        let mut rope = ropey::Rope::from_str(text);
+        let mut edits = edit.changes.as_ref().unwrap()[uri].clone();
+        edits.sort_by_key(|e| std::cmp::Reverse(e.range.start));
+        for e in edits {
+            let start = crate::position::position_to_char(&rope, e.range.start, crate::position::PositionEncoding::Utf16).unwrap();
+            let end = crate::position::position_to_char(&rope, e.range.end, crate::position::PositionEncoding::Utf16).unwrap();
+            rope.remove(start..end);
+            rope.insert(start, &e.new_text);
+        }
+        rope.to_string()
+    }
+
+    async fn extracted(text: &str, range: Range) -> Option<String> {
+        let service = test_service();
+        let backend = service.inner();
+        let uri = Url::parse("file:///tmp/dbt-lsp-extract-cte/models/orders.sql").unwrap();
+        open(backend, &uri, text).await;
+        let doc = backend.state.documents.get(&uri).unwrap();
+        let action = crate::actions::extract_cte_action(&uri, &doc, range, crate::position::PositionEncoding::Utf16)?;
+        Some(apply_changes(text, &uri, action.edit.as_ref().unwrap()))
+    }
+
+    #[tokio::test]
+    async fn test_remove_unused_cte() {
+        let service = test_service();
+        let backend = service.inner();
+        let uri = &Url::parse("file:///tmp/dbt-lsp-remove-cte/models/orders.sql").unwrap();
+        let removed = |text: &'static str| async move {
+            open(backend, uri, text).await;
+            let doc = backend.state.documents.get(uri).unwrap();
+            let unused: Vec<&Diagnostic> = doc.diagnostics.iter()
+                .filter(|d| d.code == Some(NumberOrString::String(crate::diagnostics::UNUSED_CTE.to_string())))
+                .collect();
+            assert_eq!(unused.len(), 1, "{:?}", doc.diagnostics);
+            let action = crate::actions::remove_cte_action(uri, &doc, unused[0], crate::position::PositionEncoding::Utf16).unwrap();
+            let result = apply_changes(text, uri, action.edit.as_ref().unwrap());
+            let preprocessed = crate::jinja::preprocess_for_parsing(&result);
+            let tree = crate::parser::DbtParser::new().unwrap().parse(&preprocessed, None).unwrap();
+            assert!(!tree.root_node().has_error(), "{}", result);
+            (action.title, result)
+        };
+
+        let (title, first) = removed("with unused as (select 1 as id),\nb as (select 2 as id)\nselect * from b").await;
+        assert_eq!(title, "Remove CTE 'unused'");
+        assert_eq!(first, "with b as (select 2 as id)\nselect * from b");
+        let (_, middle) = removed("with a as (select 1 as id),\nunused as (select 2 as id),\nc as (select * from a)\nselect * from c").await;
+        assert_eq!(middle, "with a as (select 1 as id),\nc as (select * from a)\nselect * from c");
+        let (_, last) = removed("with a as (select 1 as id),\nunused as (\n    select (1 + 2) as id\n)\nselect * from a").await;
+        assert_eq!(last, "with a as (select 1 as id)\nselect * from a");
+        let (_, only) = removed("{{ config(materialized='table') }}\nwith unused as (select 1 as id)\nselect 2 as id").await;
+        assert_eq!(only, "{{ config(materialized='table') }}\nselect 2 as id");
+    }
+
+    #[tokio::test]
+    async fn test_wrap_in_is_incremental() {
+        let service = test_service();
+        let backend = service.inner();
+        let uri = &Url::parse("file:///tmp/dbt-lsp-wrap-incremental/models/orders.sql").unwrap();
+        let wrapped = |text: &'static str, range: Range| async move {
+            open(backend, uri, text).await;
+            let doc = backend.state.documents.get(uri).unwrap();
+            let action = crate::actions::wrap_incremental_action(uri, &doc, range, crate::position::PositionEncoding::Utf16)?;
+            Some((apply_changes(text, uri, action.edit.as_ref().unwrap()), action.is_preferred))
+        };
+
+        let text = "{{ config(materialized='incremental') }}\nselect * from {{ ref('events') }}\n  where updated_at > (select max(updated_at) from {{ this }})\n";
+        assert_eq!(
+            wrapped(text, Range::new(Position::new(2, 4), Position::new(2, 20))).await,
+            Some(("{{ config(materialized='incremental') }}\nselect * from {{ ref('events') }}\n  {% if is_incremental() %}\n  where updated_at > (select max(updated_at) from {{ this }})\n  {% endif %}\n".to_string(), Some(true)))
+        );
+        // Half of {{ this }}
+        assert!(wrapped(text, Range::new(Position::new(2, 50), Position::new(2, 58))).await.is_none());
+        assert!(wrapped(text, Range::new(Position::new(2, 4), Position::new(2, 4))).await.is_none());
+
+        // Whole lines up to the end of the file, in a table model
+        let text = "select *\nfrom events\nwhere true";
+        assert_eq!(
+            wrapped(text, Range::new(Position::new(2, 0), Position::new(2, 10))).await,
+            Some(("select *\nfrom events\n{% if is_incremental() %}\nwhere true\n{% endif %}".to_string(), None))
+        );
+        // Ending at the start of a line leaves that line out
+        assert_eq!(
+            wrapped(text, Range::new(Position::new(1, 0), Position::new(2, 0))).await.unwrap().0,
+            "select *\n{% if is_incremental() %}\nfrom events\n{% endif %}\nwhere true"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_extract_subquery_to_cte() {
+        let text = "select *\nfrom (select id\n      from x) as s";
+        let subquery = Range::new(Position::new(1, 5), Position::new(2, 13));
+        assert_eq!(extracted(text, subquery).await.unwrap(), "with subquery as (\n    select id\n    from x\n)\nselect *\nfrom subquery as s");
+
+        // Without the parentheses, and placed after the CTE it uses
+        let text = "with a as (select 1 as id),\nsubquery as (select 2 as id)\nselect * from (select id from a) s join subquery using (id)";
+        let inner = Range::new(Position::new(2, 15), Position::new(2, 31));
+        assert_eq!(
+            extracted(text, inner).await.unwrap(),
+            "with a as (select 1 as id),\nsubquery_2 as (\n    select id from a\n),\nsubquery as (select 2 as id)\nselect * from subquery_2 s join subquery using (id)"
+        );
+
+        // Uses no CTE: goes first
+        let text = "with a as (select 1 as id)\nselect * from a join (select 2 as id) b using (id)";
+        let subquery = Range::new(Position::new(1, 21), Position::new(1, 37));
+        assert_eq!(extracted(text, subquery).await.unwrap(), "with subquery as (\n    select 2 as id\n),\na as (select 1 as id)\nselect * from a join subquery b using (id)");
+
+        // Not exactly a subquery
+        assert!(extracted(text, Range::new(Position::new(1, 21), Position::new(1, 30))).await.is_none());
+        assert!(extracted(text, Range::new(Position::new(1, 0), Position::new(1, 8))).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_generate_schema_entry() {
+        let root = temp_project("schema-stub");
+        std::fs::write(root.join("models").join("customers.sql"), "select 1 as id").unwrap();
+        let text = "select id, amount from {{ ref('customers') }}";
+        std::fs::write(root.join("models").join("orders.sql"), text).unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+        let uri = Url::from_file_path(root.join("models").join("orders.sql")).unwrap();
+        open(backend, &uri, text).await;
+
+        // No yml yet: the entry goes into a new schema.yml
+        let actions = code_actions(backend, &uri, Position::new(0, 0)).await;
+        let Some(CodeActionOrCommand::CodeAction(action)) = actions.last() else { panic!("no action") };
+        assert_eq!(action.title, "Generate schema.yml entry");
+        let Some(DocumentChanges::Operations(ops)) = action.edit.as_ref().unwrap().document_changes.as_ref() else { panic!("no operations") };
+        let DocumentChangeOperation::Edit(edit) = &ops[1] else { panic!("no edit") };
+        let OneOf::Left(insert) = &edit.edits[0] else { panic!("annotated edit") };
+        assert_eq!(insert.new_text, "version: 2\n\nmodels:\n  - name: orders\n    columns:\n      - name: id\n      - name: amount\n");
+
+        // An existing schema.yml is appended to
+        std::fs::write(root.join("models").join("schema.yml"), "models:\n  - name: customers\n").unwrap();
+        load_project(backend, &root).await;
+        let schema = Url::from_file_path(root.join("models").join("schema.yml")).unwrap();
+        let actions = code_actions(backend, &uri, Position::new(0, 0)).await;
+        let Some(CodeActionOrCommand::CodeAction(action)) = actions.last() else { panic!("no action") };
+        let edit = &action.edit.as_ref().unwrap().changes.as_ref().unwrap()[&schema][0];
+        assert_eq!(edit.range.start, Position::new(2, 0));
+
+        // Documented models don't get it
+        std::fs::write(root.join("models").join("schema.yml"), "models:\n  - name: orders\n").unwrap();
+        load_project(backend, &root).await;
+        assert!(code_action_titles(backend, &uri, Position::new(0, 0)).await.iter().all(|(t, _)| t != "Generate schema.yml entry"));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_add_missing_columns() {
+        let root = temp_project("missing-columns");
+        let schema = "version: 2\nmodels:\n  - name: orders\n    description: All orders\n    columns:\n      - name: id\n      - name: legacy\n  - name: other\n";
+        std::fs::write(root.join("models").join("schema.yml"), schema).unwrap();
+        let text = "select o.id, o.amount, upper(o.status) as status, o.* from t as o";
+        std::fs::write(root.join("models").join("orders.sql"), text).unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+        let uri = Url::from_file_path(root.join("models").join("orders.sql")).unwrap();
+        open(backend, &uri, text).await;
+
+        let yml = Url::from_file_path(root.join("models").join("schema.yml")).unwrap();
+        let actions = code_actions(backend, &uri, Position::new(0, 0)).await;
+        let added: Vec<String> = actions.iter().filter_map(|a| match a {
+            CodeActionOrCommand::CodeAction(action) if action.title == "Add missing columns to schema.yml" => {
+                Some(apply_changes(schema, &yml, action.edit.as_ref()?))
+            }
+            _ => None,
+        }).collect();
+        assert_eq!(added, vec![schema.replace("- name: legacy\n", "- name: legacy\n      - name: amount\n      - name: status\n")]);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_replace_hardcoded_table() {
+        let root = temp_project("hardcoded-table");
+        std::fs::write(root.join("models").join("sources.yml"), "sources:\n  - name: raw\n    schema: analytics\n    tables:\n      - name: orders\n        identifier: stg_orders\n").unwrap();
+        std::fs::write(root.join("models").join("stg_orders.sql"), "select 1").unwrap();
+        let text = "select * from analytics.stg_orders as o\njoin other.customers c on o.id = c.id";
+        std::fs::write(root.join("models").join("orders.sql"), text).unwrap();
+
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+        let uri = Url::from_file_path(root.join("models").join("orders.sql")).unwrap();
+        open(backend, &uri, text).await;
+
+        let uri = &uri;
+        let replacements = |position| async move {
+            code_action_titles(backend, uri, position).await.into_iter().filter(|(title, _)| title.starts_with("Replace")).collect::<Vec<_>>()
+        };
+        let table = Range::new(Position::new(0, 14), Position::new(0, 34));
+        assert_eq!(replacements(Position::new(0, 20)).await, vec![
+            ("Replace with {{ ref('stg_orders') }}".to_string(), Some(TextEdit { range: table, new_text: "{{ ref('stg_orders') }}".to_string() })),
+            ("Replace with {{ source('raw', 'orders') }}".to_string(), Some(TextEdit { range: table, new_text: "{{ source('raw', 'orders') }}".to_string() })),
+        ]);
+        assert!(replacements(Position::new(1, 10)).await.is_empty());
+        assert!(replacements(Position::new(0, 3)).await.is_empty());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_folding_ranges() {
+        let service = test_service();
+        let backend = service.inner();
+        let uri = Url::parse("file:///tmp/dbt-lsp-folding/model.sql").unwrap();
+        let text = "{{ config(\n  materialized='table'\n) }}\nwith a as (\n  select 1 as id\n  from t\n)\nselect * from a\nwhere true\n{% if is_incremental() %}\n  and {% if x %}\n  id > 0\n  {% endif %}\n{% endif %}";
+        open(backend, &uri, text).await;
+
+        let folds = backend.folding_range(FoldingRangeParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        }).await.unwrap().unwrap();
+        let lines: Vec<(u32, u32)> = folds.iter().map(|f| (f.start_line, f.end_line)).collect();
+        assert_eq!(lines, vec![(0, 1), (3, 5), (9, 12), (10, 11)]);
+    }
+
+    #[tokio::test]
+    async fn test_out_of_bounds_edit_waits_for_full_sync() {
+        let service = test_service();
+        let backend = service.inner();
+        let uri = Url::parse("file:///tmp/dbt-lsp-sync/model.sql").unwrap();
+        open(backend, &uri, "select 1").await;
+
+        let change = |range: Option<Range>, text: &str| DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier { uri: uri.clone(), version: 1 },
+            content_changes: vec![TextDocumentContentChangeEvent { range, range_length: None, text: text.to_string() }],
+        };
+
+        let beyond = Range::new(Position::new(5, 0), Position::new(5, 1));
+        backend.did_change(change(Some(beyond), "x")).await;
+        assert!(backend.state.documents.get(&uri).unwrap().out_of_sync);
+
+        // Later incremental edits are ignored until the full text arrives
+        backend.did_change(change(Some(Range::new(Position::new(0, 0), Position::new(0, 0))), "--")).await;
+        assert_eq!(backend.state.documents.get(&uri).unwrap().text.to_string(), "select 1");
+        // Re-validating doesn't bring the document back in sync
+        backend.revalidate_open_documents().await;
+        assert!(backend.state.documents.get(&uri).unwrap().out_of_sync);
+
+        backend.did_change(change(None, "select 2")).await;
+        let doc = backend.state.documents.get(&uri).unwrap();
+        assert!(!doc.out_of_sync);
+        assert_eq!(doc.text.to_string(), "select 2");
+    }
+
+    #[tokio::test]
+    async fn test_did_save_refreshes_manifest_and_revalidates() {
+        let root = temp_project("did-save");
+        std::fs::write(root.join("models").join("customers.sql"), "select 1").unwrap();
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+
+        // A model created in the editor after the scan, with a typo in its ref
+        let path = root.join("models").join("stg_orders.sql");
+        let uri = Url::from_file_path(&path).unwrap();
+        open(backend, &uri, "select * from {{ ref('customer') }}").await;
+        let messages = |backend: &Backend| backend.state.documents.get(&uri).unwrap().diagnostics.iter().map(|d| d.message.clone()).collect::<Vec<_>>();
+        assert_eq!(messages(backend).len(), 1);
+
+        let fixed = "select * from {{ ref('customers') }}";
+        std::fs::write(&path, fixed).unwrap();
+        backend.did_save(DidSaveTextDocumentParams { text_document: TextDocumentIdentifier { uri: uri.clone() }, text: Some(fixed.to_string()) }).await;
+        assert!(messages(backend).is_empty());
+        let manifest = backend.state.manifest_for(&uri).await.unwrap();
+        assert_eq!(manifest.models.get("stg_orders").map(|p| p.value().clone()), Some(path));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_stale_analysis_keeps_newer_edit() {
+        let service = test_service();
+        let backend = service.inner();
+        let uri = Url::parse("file:///tmp/dbt-lsp-stale/model.sql").unwrap();
+        open(backend, &uri, "select 1").await;
+        let before = backend.state.documents.get(&uri).unwrap().text.clone();
+
+        // An edit lands while a re-validation of the old text is still running
+        backend.state.documents.get_mut(&uri).unwrap().text = ropey::Rope::from_str("select 2");
+        backend.analyze_document(uri.clone(), before).await;
+        assert_eq!(backend.state.documents.get(&uri).unwrap().text.to_string(), "select 2");
+
+        backend.revalidate_open_documents().await;
+        assert_eq!(backend.state.documents.get(&uri).unwrap().text.to_string(), "select 2");
+    }
+
+    #[tokio::test]
+    async fn test_save_without_text_rereads_out_of_sync_document() {
+        let root = temp_project("save-resync");
+        let path = root.join("models").join("orders.sql");
+        std::fs::write(&path, "select 1").unwrap();
+        let service = test_service();
+        let backend = service.inner();
+        load_project(backend, &root).await;
+        let uri = Url::from_file_path(&path).unwrap();
+        open(backend, &uri, "select 1").await;
+
+        let beyond = Range::new(Position::new(5, 0), Position::new(5, 1));
+        backend.did_change(DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier { uri: uri.clone(), version: 1 },
+            content_changes: vec![TextDocumentContentChangeEvent { range: Some(beyond), range_length: None, text: "x".to_string() }],
+        }).await;
+        assert!(backend.state.documents.get(&uri).unwrap().out_of_sync);
+
+        // The client saved its own text, which it didn't send along
+        std::fs::write(&path, "select * from {{ ref('customers') }}").unwrap();
+        backend.did_save(DidSaveTextDocumentParams { text_document: TextDocumentIdentifier { uri: uri.clone() }, text: None }).await;
+        let doc = backend.state.documents.get(&uri).unwrap();
+        assert!(!doc.out_of_sync);
+        assert_eq!(doc.text.to_string(), "select * from {{ ref('customers') }}");
+        assert_eq!(doc.refs.len(), 1);
+        drop(doc);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
 }
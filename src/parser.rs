@@ -1,5 +1,4 @@
 use tree_sitter::{Parser, Tree};
-use tree_sitter_sql_bigquery;
 
 pub struct DbtParser {
     parser: Parser,
@@ -0,0 +1,172 @@
+use crate::state::DocumentState;
+use dashmap::DashMap;
+use ropey::Rope;
+use std::path::Path;
+use tower_lsp::lsp_types::{Position, PositionEncodingKind, Range, Url};
+
+/// The unit in which `Position.character` is counted, as agreed with the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PositionEncoding {
+    Utf8,
+    #[default]
+    Utf16,
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Picks the best encoding offered by the client. UTF-8 maps directly onto the byte
+    /// offsets we get from regex and tree-sitter, UTF-32 onto rope char indices; UTF-16
+    /// is the mandatory fallback when the client doesn't advertise anything.
+    pub fn negotiate(offered: Option<&[PositionEncodingKind]>) -> Self {
+        let offered = offered.unwrap_or_default();
+        if offered.contains(&PositionEncodingKind::UTF8) {
+            PositionEncoding::Utf8
+        } else if offered.contains(&PositionEncodingKind::UTF32) {
+            PositionEncoding::Utf32
+        } else {
+            PositionEncoding::Utf16
+        }
+    }
+
+    pub fn kind(self) -> PositionEncodingKind {
+        match self {
+            PositionEncoding::Utf8 => PositionEncodingKind::UTF8,
+            PositionEncoding::Utf16 => PositionEncodingKind::UTF16,
+            PositionEncoding::Utf32 => PositionEncodingKind::UTF32,
+        }
+    }
+}
+
+/// Converts an LSP position into a char index in the rope.
+/// Returns None when the line is past the end of the document; columns past the end of
+/// the line are clamped to the line end.
+pub fn position_to_char(rope: &Rope, position: Position, encoding: PositionEncoding) -> Option<usize> {
+    let line_idx = position.line as usize;
+    if line_idx >= rope.len_lines() {
+        return None;
+    }
+
+    let line = rope.line(line_idx);
+    let col = position.character as usize;
+    let char_col = match encoding {
+        PositionEncoding::Utf8 => line.byte_to_char(col.min(line.len_bytes())),
+        PositionEncoding::Utf16 => line.utf16_cu_to_char(col.min(line.len_utf16_cu())),
+        PositionEncoding::Utf32 => col.min(line.len_chars()),
+    };
+    Some(rope.line_to_char(line_idx) + char_col)
+}
+
+/// Converts a char index in the rope into an LSP position.
+pub fn char_to_position(rope: &Rope, char_idx: usize, encoding: PositionEncoding) -> Position {
+    let char_idx = char_idx.min(rope.len_chars());
+    let line_idx = rope.char_to_line(char_idx);
+    let char_col = char_idx - rope.line_to_char(line_idx);
+    let line = rope.line(line_idx);
+    let col = match encoding {
+        PositionEncoding::Utf8 => line.char_to_byte(char_col),
+        PositionEncoding::Utf16 => line.char_to_utf16_cu(char_col),
+        PositionEncoding::Utf32 => char_col,
+    };
+    Position::new(line_idx as u32, col as u32)
+}
+
+/// Converts a byte offset in the rope into an LSP position.
+pub fn byte_to_position(rope: &Rope, byte_idx: usize, encoding: PositionEncoding) -> Position {
+    let byte_idx = byte_idx.min(rope.len_bytes());
+    char_to_position(rope, rope.byte_to_char(byte_idx), encoding)
+}
+
+/// Converts a byte range (as produced by the regex and tree-sitter passes) into an LSP range.
+pub fn byte_range_to_range(rope: &Rope, range: &std::ops::Range<usize>, encoding: PositionEncoding) -> Range {
+    Range {
+        start: byte_to_position(rope, range.start, encoding),
+        end: byte_to_position(rope, range.end, encoding),
+    }
+}
+
+/// Converts a location as the yml and markdown scans record it, `len` bytes from byte
+/// `column` of `line`, into an LSP range.
+pub fn line_span_to_range(rope: &Rope, line: usize, column: usize, len: usize, encoding: PositionEncoding) -> Range {
+    let start = rope.try_line_to_byte(line).map_or(rope.len_bytes(), |b| b + column);
+    byte_range_to_range(rope, &(start..start + len), encoding)
+}
+
+/// [`line_span_to_range`] in the file at `path`, read from the open document when there is
+/// one so unsaved edits are respected, otherwise from disk. Columns are left as recorded
+/// when the file can't be read.
+pub fn file_span_to_range(
+    documents: &DashMap<Url, DocumentState>,
+    path: &Path,
+    line: usize,
+    column: usize,
+    len: usize,
+    encoding: PositionEncoding,
+) -> Range {
+    let open = Url::from_file_path(path).ok().and_then(|uri| documents.get(&uri).map(|doc| doc.text.clone()));
+    match open.or_else(|| std::fs::read_to_string(path).ok().map(|text| Rope::from_str(&text))) {
+        Some(rope) => line_span_to_range(&rope, line, column, len, encoding),
+        None => Range::new(Position::new(line as u32, column as u32), Position::new(line as u32, (column + len) as u32)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utf16_round_trip() {
+        // "é" is 2 bytes / 1 UTF-16 unit, "😀" is 4 bytes / 2 UTF-16 units
+        let rope = Rope::from_str("-- café 😀\nselect x");
+        let byte_idx = "-- café 😀".len();
+        let pos = byte_to_position(&rope, byte_idx, PositionEncoding::Utf16);
+        assert_eq!(pos, Position::new(0, 10));
+        let char_idx = position_to_char(&rope, pos, PositionEncoding::Utf16);
+        assert_eq!(char_idx.map(|c| rope.char_to_byte(c)), Some(byte_idx));
+
+        assert_eq!(position_to_char(&rope, Position::new(1, 7), PositionEncoding::Utf16), Some(10 + 7));
+        assert_eq!(position_to_char(&rope, Position::new(5, 0), PositionEncoding::Utf16), None);
+    }
+
+    #[test]
+    fn test_line_span_to_range() {
+        // The value after "café: " starts at byte 9 but UTF-16 column 8
+        let rope = Rope::from_str("x: 1
+- café: users
+");
+        let range = line_span_to_range(&rope, 1, 9, 5, PositionEncoding::Utf16);
+        assert_eq!((range.start, range.end), (Position::new(1, 8), Position::new(1, 13)));
+        assert_eq!(line_span_to_range(&rope, 1, 9, 5, PositionEncoding::Utf8).start, Position::new(1, 9));
+    }
+
+    #[test]
+    fn test_file_span_prefers_open_document() {
+        let path = std::env::temp_dir().join(format!("dbt-lsp-file-span-{}.yml", std::process::id()));
+        std::fs::write(&path, "x: 1\n- cafe: users\n").unwrap();
+        let documents = DashMap::new();
+        assert_eq!(file_span_to_range(&documents, &path, 1, 8, 5, PositionEncoding::Utf16).start, Position::new(1, 8));
+
+        // The unsaved buffer has the accent the file on disk doesn't
+        let uri = Url::from_file_path(&path).unwrap();
+        documents.insert(uri, DocumentState::text_only(Rope::from_str("x: 1\n- café: users\n")));
+        assert_eq!(file_span_to_range(&documents, &path, 1, 9, 5, PositionEncoding::Utf16).start, Position::new(1, 8));
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_utf8_and_utf32_columns() {
+        let rope = Rope::from_str("-- café 😀\nselect x");
+        let byte_idx = "-- café 😀".len();
+        assert_eq!(byte_to_position(&rope, byte_idx, PositionEncoding::Utf8), Position::new(0, byte_idx as u32));
+        assert_eq!(byte_to_position(&rope, byte_idx, PositionEncoding::Utf32), Position::new(0, 9));
+        assert_eq!(position_to_char(&rope, Position::new(0, 9), PositionEncoding::Utf32), Some(9));
+        assert_eq!(position_to_char(&rope, Position::new(0, byte_idx as u32), PositionEncoding::Utf8), Some(9));
+    }
+
+    #[test]
+    fn test_negotiate_prefers_utf8() {
+        let offered = vec![PositionEncodingKind::UTF16, PositionEncodingKind::UTF8];
+        assert_eq!(PositionEncoding::negotiate(Some(&offered)), PositionEncoding::Utf8);
+        assert_eq!(PositionEncoding::negotiate(None), PositionEncoding::Utf16);
+    }
+}
@@ -0,0 +1,132 @@
+use std::path::{Path, PathBuf};
+
+/// The default target of the project's profile in profiles.yml.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Target {
+    /// The adapter, e.g. `bigquery` or `snowflake`.
+    pub adapter_type: Option<String>,
+    pub database: Option<String>,
+    pub schema: Option<String>,
+    /// Fields whose value is jinja (usually `env_var()`). They are kept as written.
+    pub templated: Vec<String>,
+}
+
+/// Where dbt looks for profiles.yml, in order.
+fn profiles_paths(root_dir: &Path) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let Ok(dir) = std::env::var("DBT_PROFILES_DIR") {
+        paths.push(PathBuf::from(dir).join("profiles.yml"));
+    }
+    paths.push(root_dir.join("profiles.yml"));
+    if let Ok(home) = std::env::var("HOME") {
+        paths.push(PathBuf::from(home).join(".dbt").join("profiles.yml"));
+    }
+    paths
+}
+
+fn is_templated(value: &str) -> bool {
+    value.contains("{{") || value.contains("{%")
+}
+
+/// Reads the default target of `profile` from the first profiles.yml found. Fields the
+/// target lacks are left empty; the error says why nothing could be read at all.
+pub fn load_target(root_dir: &Path, profile: Option<&str>) -> Result<Target, String> {
+    load_target_from(&profiles_paths(root_dir), profile)
+}
+
+/// [`load_target`] from the first of `paths` that can be read.
+fn load_target_from(paths: &[PathBuf], profile: Option<&str>) -> Result<Target, String> {
+    let profile_name = profile.ok_or("dbt_project.yml names no profile")?;
+    let (path, content) = paths.iter()
+        .find_map(|p| std::fs::read_to_string(p).ok().map(|c| (p, c)))
+        .ok_or("no profiles.yml found")?;
+    let profiles: serde_yaml::Value = serde_yaml::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let profile = profiles.get(profile_name).ok_or_else(|| format!("{}: no profile '{}'", path.display(), profile_name))?;
+
+    let mut templated = Vec::new();
+    // A templated target name can't be rendered here; dbt's own default is the best guess
+    let target_name = match profile.get("target").and_then(|t| t.as_str()) {
+        Some(name) if is_templated(name) => {
+            templated.push("target".to_string());
+            "dev"
+        }
+        Some(name) => name,
+        None => "dev",
+    };
+    let output = profile.get("outputs").and_then(|o| o.get(target_name))
+        .ok_or_else(|| format!("{}: profile '{}' has no output '{}'", path.display(), profile_name, target_name))?;
+
+    // Adapters name these differently (BigQuery: project/dataset, Postgres: dbname)
+    let mut field = |keys: &[&str]| {
+        let (key, value) = keys.iter().find_map(|k| output.get(*k).and_then(|v| v.as_str()).map(|v| (*k, v.to_string())))?;
+        if is_templated(&value) {
+            templated.push(key.to_string());
+        }
+        Some(value)
+    };
+    Ok(Target {
+        adapter_type: field(&["type"]),
+        database: field(&["database", "project", "dbname"]),
+        schema: field(&["schema", "dataset"]),
+        templated,
+    })
+}
+
+/// The sqlparser dialect closest to a dbt adapter.
+pub fn adapter_dialect(adapter_type: &str) -> &'static str {
+    match adapter_type {
+        "bigquery" => "bigquery",
+        "snowflake" => "snowflake",
+        "postgres" => "postgres",
+        "redshift" => "redshift",
+        "duckdb" => "duckdb",
+        "mysql" => "mysql",
+        "sqlite" => "sqlite",
+        "clickhouse" => "clickhouse",
+        "spark" | "databricks" => "hive",
+        "sqlserver" | "synapse" | "fabric" => "mssql",
+        _ => "generic",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_target() {
+        let root = std::env::temp_dir().join(format!("dbt-lsp-profiles-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("profiles.yml"), "\
+shop:
+  target: \"{{ env_var('DBT_TARGET', 'dev') }}\"
+  outputs:
+    dev:
+      type: snowflake
+      database: \"{{ env_var('SNOWFLAKE_DATABASE') }}\"
+      schema: dbt_dev
+broken:
+  target: prod
+  outputs:
+    dev:
+      type: postgres
+").unwrap();
+
+        // Only this file, whatever DBT_PROFILES_DIR says
+        let paths = [root.join("profiles.yml")];
+        let target = load_target_from(&paths, Some("shop")).unwrap();
+        assert_eq!(target, Target {
+            adapter_type: Some("snowflake".to_string()),
+            database: Some("{{ env_var('SNOWFLAKE_DATABASE') }}".to_string()),
+            schema: Some("dbt_dev".to_string()),
+            templated: vec!["target".to_string(), "database".to_string()],
+        });
+        assert!(load_target_from(&paths, Some("broken")).unwrap_err().ends_with("profile 'broken' has no output 'prod'"));
+        assert!(load_target_from(&paths, Some("missing")).unwrap_err().ends_with("no profile 'missing'"));
+        assert!(load_target_from(&paths, None).is_err());
+        assert_eq!(adapter_dialect("databricks"), "hive");
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -1,7 +1,11 @@
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
 use dashmap::DashMap;
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+use crate::artifacts::{CatalogFile, ManifestArtifact};
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct DbtProjectConfig {
@@ -12,6 +16,40 @@ pub struct DbtProjectConfig {
     pub seed_paths: Vec<String>,
     #[serde(rename = "macro-paths", default = "default_macro_paths")]
     pub macro_paths: Vec<String>,
+    #[serde(rename = "snapshot-paths", default = "default_snapshot_paths")]
+    pub snapshot_paths: Vec<String>,
+    #[serde(rename = "analysis-paths", default = "default_analysis_paths")]
+    pub analysis_paths: Vec<String>,
+    #[serde(rename = "test-paths", default = "default_test_paths")]
+    pub test_paths: Vec<String>,
+    /// Extra directories searched for docs blocks, besides the model paths.
+    #[serde(rename = "docs-paths", default)]
+    pub docs_paths: Vec<String>,
+    /// Where dbt writes compiled SQL, run artifacts and run_results.json.
+    #[serde(rename = "target-path", default = "default_target_path")]
+    pub target_path: String,
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Folder-level `models:` configs, kept raw for relation resolution.
+    #[serde(default)]
+    pub models: serde_yaml::Value,
+    #[serde(default)]
+    pub seeds: serde_yaml::Value,
+    /// `vars:`, kept raw; `scan_vars` indexes it with locations.
+    #[serde(default)]
+    pub vars: serde_yaml::Value,
+}
+
+impl DbtProjectConfig {
+    /// `generic/` under each test path, where `{% test %}` blocks live besides the macro paths.
+    pub fn generic_test_dirs(&self) -> Vec<String> {
+        self.test_paths.iter().map(|p| format!("{}/generic", p)).collect()
+    }
+
+    /// The directories macros are read from: the macro paths and the generic test dirs.
+    pub fn macro_dirs(&self) -> Vec<String> {
+        self.macro_paths.iter().cloned().chain(self.generic_test_dirs()).collect()
+    }
 }
 
 fn default_model_paths() -> Vec<String> {
@@ -23,139 +61,2492 @@ fn default_seed_paths() -> Vec<String> {
 fn default_macro_paths() -> Vec<String> {
     vec!["macros".to_string()]
 }
+fn default_snapshot_paths() -> Vec<String> {
+    vec!["snapshots".to_string()]
+}
+fn default_analysis_paths() -> Vec<String> {
+    vec!["analyses".to_string()]
+}
+fn default_test_paths() -> Vec<String> {
+    vec!["tests".to_string()]
+}
+fn default_target_path() -> String {
+    "target".to_string()
+}
+
+/// dbt's own generic tests, which need no `{% test %}` block in the project.
+pub const BUILTIN_TESTS: &[&str] = &["unique", "not_null", "accepted_values", "relationships"];
+
+/// What a generic test named in yml runs.
+#[derive(Debug, Clone)]
+pub enum GenericTest {
+    Builtin,
+    /// The `test_<name>` macro a `{% test <name> %}` block defines.
+    Macro(MacroDef),
+}
 
 #[derive(Debug, Clone)]
 pub struct MacroDef {
     pub path: PathBuf,
     pub line: usize,
+    /// The installed package defining the macro; None for the project's own macros.
+    pub package: Option<String>,
+}
+
+/// A `{% snapshot name %}` block.
+#[derive(Debug, Clone)]
+pub struct SnapshotDef {
+    pub path: PathBuf,
+    pub line: usize,
+}
+
+/// A `{% docs name %}...{% enddocs %}` block.
+#[derive(Debug, Clone)]
+pub struct DocsBlock {
+    pub path: PathBuf,
+    /// Line and byte column of the block's name in `{% docs name %}`.
+    pub line: usize,
+    pub column: usize,
+    pub body: String,
+}
+
+/// The jinja references found in one of the project's SQL files. The text is kept so
+/// reference locations can be reported without reading the file again.
+#[derive(Debug, Clone)]
+pub struct IndexedFile {
+    pub text: ropey::Rope,
+    pub refs: Vec<(crate::jinja::DbtRef, std::ops::Range<usize>)>,
+}
+
+/// A key under `vars:` in dbt_project.yml.
+#[derive(Debug, Clone)]
+pub struct VarDef {
+    pub path: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    /// The parsed value; mappings and lists are kept whole.
+    pub value: serde_yaml::Value,
+}
+
+/// An entry of packages.yml.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeclaredPackage {
+    /// The project name it installs as: from package-lock.yml when that records one,
+    /// else guessed from the spec.
+    pub name: String,
+    /// The hub package, git URL or local path as written.
+    pub spec: String,
+    /// `version:` (or `revision:` for git packages) as written.
+    pub version: Option<String>,
+    pub line: usize,
+    /// Byte column of the spec, inside its quotes if it has them.
+    pub column: usize,
+}
+
+/// A column documented in yml (`columns:` under a model or source table).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ColumnDoc {
+    pub name: String,
+    pub description: Option<String>,
+    pub data_type: Option<String>,
+    /// Names of the column's data tests, e.g. `unique` or `dbt_utils.at_least_one`.
+    pub tests: Vec<String>,
+    /// Line of the column's `name:` in the yml file; None when not documented there.
+    pub line: Option<usize>,
+}
+
+/// Where the columns of a relation were found, from most to least complete.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnOrigin {
+    /// target/catalog.json: every column as it is in the warehouse.
+    Catalog,
+    /// The columns documented in yml, which may be only some of them.
+    Documented,
+    /// The model's final select list.
+    SelectList,
+}
+
+/// A model's entry under `models:` (or a seed's under `seeds:`) in a yml file.
+#[derive(Debug, Clone)]
+pub struct ModelEntry {
+    pub path: PathBuf,
+    /// Line and column of the model's `name:` value.
+    pub line: usize,
+    pub column: usize,
+    /// `config.alias` (or the legacy top-level `alias`) from the entry.
+    pub alias: Option<String>,
+    pub description: Option<String>,
+    pub columns: Vec<ColumnDoc>,
+    /// Names of the model-level data tests.
+    pub tests: Vec<String>,
+    /// The entry's `versions:`, empty for an unversioned model.
+    pub versions: Vec<ModelVersion>,
+    /// `latest_version`, else the greatest of `versions`.
+    pub latest_version: Option<String>,
+    /// `group` and `access`, set on the entry or under its `config:`.
+    pub group: Option<String>,
+    pub access: Option<String>,
+}
+
+/// A version under a model's `versions:` in yml.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelVersion {
+    pub version: String,
+    /// The model defining this version: `defined_in`, else `<model>_v<version>`.
+    pub defined_in: String,
+    /// Line of the version's `v:` key.
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SourceDef {
+    pub source_name: String,
+    pub table_name: String,
+    pub path: PathBuf,
+    /// Line and column of the table's `name:` value.
+    pub line: usize,
+    pub column: usize,
+    /// The table's description, or the source's when the table has none.
+    pub description: Option<String>,
+    pub loader: Option<String>,
+    pub database: Option<String>,
+    /// Defaults to the source name.
+    pub schema: String,
+    /// Physical table name; defaults to the table name.
+    pub identifier: String,
+    pub loaded_at_field: Option<String>,
+    /// Summary of the effective `freshness` config, e.g. "warn after 12 hour".
+    pub freshness: Option<String>,
+    pub columns: Vec<ColumnDoc>,
+    /// Names of the table-level data tests.
+    pub tests: Vec<String>,
+}
+
+/// An entry under `groups:` in a yml file.
+#[derive(Debug, Clone)]
+pub struct GroupDef {
+    pub path: PathBuf,
+    /// Line and column of the group's `name:` value.
+    pub line: usize,
+    pub column: usize,
+    /// The owner's name, else their email.
+    pub owner: Option<String>,
+}
+
+/// An entry under `exposures:` in a yml file: a dashboard, notebook or application that
+/// reads from the project.
+#[derive(Debug, Clone)]
+pub struct ExposureDef {
+    pub path: PathBuf,
+    /// Line and column of the exposure's `name:` value.
+    pub line: usize,
+    pub column: usize,
+    /// `type`: dashboard, notebook, analysis, ml or application.
+    pub exposure_type: Option<String>,
+    /// `owner.name`, else `owner.email`.
+    pub owner: Option<String>,
+    /// The `ref()` and `source()` entries of `depends_on`, with their byte ranges in the file.
+    pub depends_on: Vec<(crate::jinja::DbtRef, std::ops::Range<usize>)>,
+}
+
+/// An entry under `metrics:` in a yml file.
+#[derive(Debug, Clone, Default)]
+pub struct MetricDef {
+    pub path: PathBuf,
+    /// Line and column of the metric's `name:` value.
+    pub line: usize,
+    pub column: usize,
+    pub label: Option<String>,
+    pub description: Option<String>,
+    /// `type` (simple, ratio, cumulative, derived, conversion), or the legacy
+    /// `calculation_method`.
+    pub metric_type: Option<String>,
+    /// What the metric computes: the measure, the expression, or numerator / denominator.
+    pub expression: Option<String>,
+    /// The measure of a simple or cumulative metric, for finding its semantic model.
+    pub measure: Option<String>,
+    /// Dimensions listed on a legacy metric.
+    pub dimensions: Vec<String>,
+}
+
+/// An entry under `semantic_models:` in a yml file.
+#[derive(Debug, Clone)]
+pub struct SemanticModelDef {
+    pub path: PathBuf,
+    /// Line and column of the semantic model's `name:` value.
+    pub line: usize,
+    pub column: usize,
+    /// The `model: ref('...')` it is built on, with its byte range in the file.
+    pub model: Option<(crate::jinja::DbtRef, std::ops::Range<usize>)>,
+    pub measures: Vec<String>,
+    pub dimensions: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
 pub struct ProjectManifest {
     pub root_dir: PathBuf,
     pub config: DbtProjectConfig,
+    /// Active target from profiles.yml, when one could be read.
+    pub target: Option<crate::profiles::Target>,
+    /// target/manifest.json from the last dbt run, unless it is missing or stale.
+    pub artifact: Option<Arc<ManifestArtifact>>,
+    /// target/catalog.json, unless only the project files are to be used.
+    pub catalog: Option<Arc<CatalogFile>>,
     pub models: DashMap<String, PathBuf>,
-    pub sources: DashMap<String, PathBuf>, // source.table -> yml path
+    /// Model names defined by more than one file, with every such file. `models` maps
+    /// the name to the first of them.
+    pub duplicate_models: DashMap<String, Vec<PathBuf>>,
+    pub sources: DashMap<String, SourceDef>, // source.table -> yml location
+    pub model_entries: DashMap<String, ModelEntry>, // model name -> documenting yml entry
+    pub seed_entries: DashMap<String, ModelEntry>, // seed name -> documenting yml entry
     pub seeds: DashMap<String, PathBuf>,
+    pub snapshots: DashMap<String, SnapshotDef>,
+    /// Analyses by file stem. dbt compiles them but they can't be ref'd.
+    pub analyses: DashMap<String, PathBuf>,
+    /// Singular tests (SQL files under test-paths) by file stem.
+    pub singular_tests: DashMap<String, PathBuf>,
     pub macros: DashMap<String, MacroDef>,
+    pub docs: DashMap<String, DocsBlock>,
+    /// Docs block names defined more than once, with every definition in path and line
+    /// order. `docs` maps the name to the first of them.
+    pub duplicate_docs: DashMap<String, Vec<DocsBlock>>,
+    pub packages: DashMap<String, PathBuf>, // package name -> installed package root
+    pub package_models: DashMap<(String, String), PathBuf>, // (package, model) -> path
+    pub package_macros: DashMap<(String, String), MacroDef>, // (package, macro) -> definition
+    /// packages.yml entries by the name they install as.
+    pub declared_packages: DashMap<String, DeclaredPackage>,
+    pub vars: DashMap<String, VarDef>, // vars visible to this project's own models
+    /// Every var in dbt_project.yml by dotted key: `name` when global, `scope.name` when
+    /// scoped to the project or a package.
+    pub all_vars: DashMap<String, VarDef>,
+    /// Exposures by name.
+    pub exposures: DashMap<String, ExposureDef>,
+    pub metrics: DashMap<String, MetricDef>,
+    pub groups: DashMap<String, GroupDef>,
+    /// The generic tests each properties yml file lists.
+    pub generic_tests: DashMap<PathBuf, Vec<crate::yaml::YamlTest>>,
+    pub semantic_models: DashMap<String, SemanticModelDef>,
+    /// Per-file references for find-references, built on first use.
+    pub references: DashMap<PathBuf, IndexedFile>,
+    references_built: OnceLock<()>,
+}
+
+/// A non-empty string field of a yml mapping, trimmed.
+fn yaml_str(value: &serde_yaml::Value, key: &str) -> Option<String> {
+    value.get(key).and_then(|s| s.as_str()).map(|s| s.trim().to_string()).filter(|s| !s.is_empty())
+}
+
+/// The names of a `data_tests:` (or the older `tests:`) list: `- unique` or
+/// `- accepted_values: {...}`.
+fn yaml_tests(value: &serde_yaml::Value) -> Vec<String> {
+    let Some(tests) = value.get("data_tests").or_else(|| value.get("tests")).and_then(|t| t.as_sequence()) else { return Vec::new() };
+    tests.iter()
+        .filter_map(|test| match test {
+            serde_yaml::Value::String(name) => Some(name.clone()),
+            serde_yaml::Value::Mapping(map) => map.keys().next()?.as_str().map(|k| k.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// A config of a yml entry, set under its `config:` or on the entry itself.
+fn entry_config(entry: Option<&serde_yaml::Value>, key: &str) -> Option<String> {
+    let entry = entry?;
+    entry.get("config").and_then(|c| yaml_str(c, key)).or_else(|| yaml_str(entry, key))
+}
+
+/// A yml scalar as written, for values like versions that may be numbers or strings.
+fn yaml_scalar(value: &serde_yaml::Value) -> Option<String> {
+    match value {
+        serde_yaml::Value::String(s) => Some(s.trim().to_string()),
+        serde_yaml::Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// Orders model versions numerically when both are numbers, so `10` comes after `9`.
+pub fn compare_versions(a: &str, b: &str) -> std::cmp::Ordering {
+    match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.total_cmp(&b),
+        _ => a.cmp(b),
+    }
+}
+
+/// The `versions:` of the model entry `value` named `name`, located under `path`, and its
+/// latest version.
+fn yaml_versions(value: &serde_yaml::Value, keys: &[crate::yaml::YamlKey], path: &[&str], name: &str) -> (Vec<ModelVersion>, Option<String>) {
+    let Some(items) = value.get("versions").and_then(|v| v.as_sequence()) else { return (Vec::new(), None) };
+    let mut lines = keys.iter()
+        .filter(|k| k.key == "v" && k.path.len() == path.len() + 2 && k.path[..path.len()] == *path && k.path[path.len()] == "versions")
+        .map(|k| k.line);
+    let versions: Vec<ModelVersion> = items.iter()
+        .filter_map(|item| {
+            let version = yaml_scalar(item.get("v")?)?;
+            Some(ModelVersion {
+                defined_in: yaml_str(item, "defined_in").unwrap_or_else(|| format!("{}_v{}", name, version)),
+                line: lines.next().unwrap_or(0),
+                version,
+            })
+        })
+        .collect();
+    let greatest = versions.iter().map(|v| &v.version).max_by(|a, b| compare_versions(a, b));
+    let latest = value.get("latest_version").and_then(yaml_scalar).or_else(|| greatest.cloned());
+    (versions, latest)
+}
+
+/// The `columns:` list of a model or source table, located under `path` (the entry's
+/// path as for [`crate::yaml::find_named_item`]).
+fn yaml_columns(value: &serde_yaml::Value, keys: &[crate::yaml::YamlKey], path: &[&str]) -> Vec<ColumnDoc> {
+    let Some(cols) = value.get("columns").and_then(|c| c.as_sequence()) else { return Vec::new() };
+    let mut columns: Vec<ColumnDoc> = cols.iter()
+        .filter_map(|col| Some(ColumnDoc {
+            name: yaml_str(col, "name")?,
+            description: yaml_str(col, "description"),
+            data_type: yaml_str(col, "data_type"),
+            tests: yaml_tests(col),
+            line: None,
+        }))
+        .collect();
+    locate_columns(&mut columns, keys, path);
+    columns
+}
+
+/// Fills in the yml line of each column documented under the entry at `path`.
+fn locate_columns(columns: &mut [ColumnDoc], keys: &[crate::yaml::YamlKey], path: &[&str]) {
+    let columns_path: Vec<&str> = path.iter().copied().chain(["columns"]).collect();
+    for col in columns {
+        col.line = crate::yaml::find_named_item(keys, &columns_path, &col.name).map(|k| k.line);
+    }
+}
+
+/// Summarises `freshness: {warn_after: {count: 12, period: hour}, ...}`.
+pub(crate) fn freshness_summary(freshness: &serde_yaml::Value) -> Option<String> {
+    let parts: Vec<String> = ["warn_after", "error_after"]
+        .iter()
+        .filter_map(|key| {
+            let rule = freshness.get(*key)?;
+            let count = rule.get("count").and_then(|c| c.as_u64())?;
+            let period = rule.get("period").and_then(|p| p.as_str())?;
+            Some(format!("{} {} {}", key.replace('_', " "), count, period))
+        })
+        .collect();
+    (!parts.is_empty()).then(|| parts.join(", "))
+}
+
+/// The `{% macro name(...) %}` definitions in a file, with their line numbers.
+fn macro_defs(path: &Path, content: &str, package: Option<&str>) -> Vec<(String, MacroDef)> {
+    static RE: OnceLock<regex::Regex> = OnceLock::new();
+    // `{% test name(...) %}` defines the macro `test_name`
+    let macro_regex = RE.get_or_init(|| regex::Regex::new(r#"(?s)\{%-?\s*(macro|test)\s+([a-zA-Z0-9_]+)\s*\("#).unwrap());
+
+    // Lines are counted from the previous macro on, so each file is walked once
+    let (mut line, mut counted) = (0, 0);
+    macro_regex.captures_iter(content)
+        .filter_map(|cap| Some((cap.get(1)?.as_str() == "test", cap.get(2)?)))
+        .map(|(is_test, m)| {
+            line += content[counted..m.start()].matches('\n').count();
+            counted = m.start();
+            let def = MacroDef { path: path.to_path_buf(), line, package: package.map(str::to_string) };
+            let name = if is_test { format!("test_{}", m.as_str()) } else { m.as_str().to_string() };
+            (name, def)
+        })
+        .collect()
+}
+
+/// The `package:`, `git:` or `local:` entries of a packages.yml or package-lock.yml,
+/// with the item's `version:`/`revision:` and `name:` keys.
+fn package_entries(content: &str) -> Vec<(&'static str, crate::yaml::YamlKey, Option<String>, Option<String>)> {
+    let mut entries: Vec<(&'static str, crate::yaml::YamlKey, Option<String>, Option<String>)> = Vec::new();
+    for k in crate::yaml::scan_keys(content) {
+        // `name:` labels its item, so it sits one level up from the item's other keys
+        let depth = if k.key == "name" { 1 } else { 2 };
+        if k.path.len() != depth || k.path[0] != "packages" {
+            continue;
+        }
+        match (k.key.as_str(), entries.last_mut()) {
+            ("package", _) => entries.push(("package", k, None, None)),
+            ("git", _) => entries.push(("git", k, None, None)),
+            ("local", _) => entries.push(("local", k, None, None)),
+            ("version" | "revision", Some(entry)) => entry.2 = k.value,
+            ("name", Some(entry)) => entry.3 = k.value,
+            _ => {}
+        }
+    }
+    entries
+}
+
+/// The files under `dirs` (relative to `root_dir`) with one of the extensions `exts`,
+/// compared case-insensitively. Each file is listed once, even when directories overlap.
+fn files_in<'a>(root_dir: &Path, dirs: impl IntoIterator<Item = &'a String>, exts: &[&str]) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = dirs.into_iter()
+        .flat_map(|dir| WalkDir::new(root_dir.join(dir)).into_iter().filter_map(|e| e.ok()))
+        .filter(|entry| !entry.file_type().is_dir())
+        .filter(|entry| entry.path().extension().is_some_and(|ext| exts.iter().any(|e| ext.eq_ignore_ascii_case(e))))
+        .map(|entry| entry.into_path())
+        .collect();
+    files.sort();
+    files.dedup();
+    files
+}
+
+/// Inserts `value` unless `key` is already defined in the same file or one earlier in
+/// path order, so the first definition wins a duplicated name whichever file is indexed first.
+fn insert_first<K: Eq + std::hash::Hash, V>(map: &DashMap<K, V>, key: K, value: V, path: impl Fn(&V) -> &Path) {
+    match map.entry(key) {
+        dashmap::mapref::entry::Entry::Occupied(mut first) => {
+            if path(&value) < path(first.get()) {
+                first.insert(value);
+            }
+        }
+        dashmap::mapref::entry::Entry::Vacant(slot) => { slot.insert(value); }
+    }
+}
+
+/// Reads every file in `files` once and hands it to `index`, spread over the available
+/// cores. `index` writes into the manifest's DashMaps, which do their own locking; files
+/// finish in no set order, so duplicate names go through `insert_first`.
+fn index_files_parallel(files: &[PathBuf], index: impl Fn(&Path, &str) + Sync) {
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get()).min(files.len());
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                while let Some(path) = files.get(next.fetch_add(1, std::sync::atomic::Ordering::Relaxed)) {
+                    if let Ok(content) = std::fs::read_to_string(path) {
+                        index(path, &content);
+                    }
+                }
+            });
+        }
+    });
 }
 
 impl ProjectManifest {
-    pub fn load(root_dir: PathBuf) -> anyhow::Result<Self> {
+    /// Reads dbt_project.yml without scanning any files; call the `scan_*` methods
+    /// (or use `load`) to populate the indexes.
+    pub fn new(root_dir: PathBuf) -> anyhow::Result<Self> {
         let config_path = root_dir.join("dbt_project.yml");
         let content = std::fs::read_to_string(&config_path)?;
         let config: DbtProjectConfig = serde_yaml::from_str(&content)?;
+        let target = match crate::profiles::load_target(&root_dir, config.profile.as_deref()) {
+            Ok(target) => {
+                if !target.templated.is_empty() {
+                    eprintln!("profiles.yml: left unrendered: {}", target.templated.join(", "));
+                }
+                Some(target)
+            }
+            Err(e) => {
+                eprintln!("Could not read the target from profiles.yml: {}", e);
+                None
+            }
+        };
 
-        let manifest = Self {
-            root_dir: root_dir.clone(),
-            config: config.clone(),
+        Ok(Self {
+            root_dir,
+            config,
+            target,
+            artifact: None,
+            catalog: None,
             models: DashMap::new(),
+            duplicate_models: DashMap::new(),
             sources: DashMap::new(),
+            model_entries: DashMap::new(),
+            seed_entries: DashMap::new(),
             seeds: DashMap::new(),
+            snapshots: DashMap::new(),
+            analyses: DashMap::new(),
+            singular_tests: DashMap::new(),
             macros: DashMap::new(),
-        };
+            docs: DashMap::new(),
+            duplicate_docs: DashMap::new(),
+            packages: DashMap::new(),
+            package_models: DashMap::new(),
+            package_macros: DashMap::new(),
+            declared_packages: DashMap::new(),
+            vars: DashMap::new(),
+            all_vars: DashMap::new(),
+            exposures: DashMap::new(),
+            metrics: DashMap::new(),
+            groups: DashMap::new(),
+            generic_tests: DashMap::new(),
+            semantic_models: DashMap::new(),
+            references: DashMap::new(),
+            references_built: OnceLock::new(),
+        })
+    }
+
+    /// Reads target/manifest.json, whose descriptions, columns and configs are then used
+    /// for the yml entries it covers, and starts using target/catalog.json for columns.
+    /// The yml files are still read for locations and tests. Call before the scans.
+    pub fn load_artifacts(&mut self) {
+        self.catalog = Some(Arc::new(CatalogFile::new(&self.root_dir, &self.config.target_path, &self.config.name)));
+        self.artifact = ManifestArtifact::read(&self.root_dir, &self.config.target_path, &self.config.name).map(Arc::new);
+        if let Some(artifact) = &self.artifact {
+            eprintln!("Loaded manifest.json with {} nodes and {} sources", artifact.nodes.len(), artifact.sources.len());
+        }
+    }
 
+    /// Reads the config and target/manifest.json, and runs every scan synchronously.
+    #[cfg(test)]
+    pub fn load(root_dir: PathBuf) -> anyhow::Result<Self> {
+        let mut manifest = Self::new(root_dir)?;
+        manifest.load_artifacts();
         manifest.scan_models();
         manifest.scan_seeds();
-        manifest.scan_macros();
+        manifest.scan_snapshots();
+        manifest.scan_analyses();
+        manifest.scan_singular_tests();
+        manifest.scan_macros_and_docs();
         manifest.scan_sources();
+        manifest.scan_packages();
+        manifest.scan_vars();
         Ok(manifest)
     }
 
+    /// Lists the files of one kind by name, e.g. models by their file stem. Nothing is read.
+    fn list_by_stem(&self, map: &DashMap<String, PathBuf>, dirs: &[String], exts: &[&str]) {
+        map.clear();
+        for path in files_in(&self.root_dir, dirs, exts) {
+            if let Some(stem) = path.file_stem() {
+                map.insert(stem.to_string_lossy().to_string(), path);
+            }
+        }
+    }
+
     pub fn scan_models(&self) {
+        let started = Instant::now();
         self.models.clear();
-        for path in &self.config.model_paths {
-            let full_path = self.root_dir.join(path);
-            eprintln!("Scanning models in: {:?}", full_path);
-            for entry in WalkDir::new(full_path).into_iter().filter_map(|e| e.ok()) {
-                if entry.path().extension().map_or(false, |ext| ext == "sql") {
-                    if let Some(stem) = entry.path().file_stem() {
-                        let model_name = stem.to_string_lossy().to_string();
-                        self.models.insert(model_name, entry.path().to_path_buf());
-                    }
+        self.duplicate_models.clear();
+        for path in files_in(&self.root_dir, &self.config.model_paths, &["sql"]) {
+            self.add_model_file(path);
+        }
+        eprintln!("Found {} models in {:?}", self.models.len(), started.elapsed());
+        for entry in self.duplicate_models.iter() {
+            eprintln!("Model name '{}' is defined {} times", entry.key(), entry.value().len());
+        }
+    }
+
+    /// Records the model file at `path`. When another file already defines the name,
+    /// both are remembered as duplicates and the name keeps pointing at the first.
+    fn add_model_file(&self, path: PathBuf) {
+        let Some(name) = path.file_stem().map(|s| s.to_string_lossy().to_string()) else { return };
+        let existing = self.models.get(&name).map(|p| p.value().clone());
+        match existing {
+            Some(existing) if existing != path => {
+                let mut paths = self.duplicate_models.entry(name).or_insert_with(|| vec![existing]);
+                if !paths.contains(&path) {
+                    paths.push(path);
                 }
             }
+            _ => {
+                self.models.insert(name, path);
+            }
+        }
+    }
+
+    /// The files defining the model `name`: one, or several when the name is duplicated.
+    pub fn model_files(&self, name: &str) -> Vec<PathBuf> {
+        match self.duplicate_models.get(name) {
+            Some(paths) => paths.clone(),
+            None => self.models.get(name).map(|p| p.value().clone()).into_iter().collect(),
         }
-        eprintln!("Found {} models", self.models.len());
     }
 
     pub fn scan_seeds(&self) {
-        self.seeds.clear();
-        for path in &self.config.seed_paths {
-            let full_path = self.root_dir.join(path);
-            eprintln!("Scanning seeds in: {:?}", full_path);
-            for entry in WalkDir::new(full_path).into_iter().filter_map(|e| e.ok()) {
-                let matches_csv = entry.path().extension().map_or(false, |ext| {
-                    let ext_str = ext.to_string_lossy().to_lowercase();
-                    ext_str == "csv"
-                });
-                if matches_csv {
-                    if let Some(stem) = entry.path().file_stem() {
-                        let seed_name = stem.to_string_lossy().to_string();
-                        self.seeds.insert(seed_name, entry.path().to_path_buf());
-                    }
-                }
+        let started = Instant::now();
+        self.list_by_stem(&self.seeds, &self.config.seed_paths, &["csv"]);
+        eprintln!("Found {} seeds in {:?}", self.seeds.len(), started.elapsed());
+    }
+
+    pub fn scan_snapshots(&self) {
+        let started = Instant::now();
+        self.snapshots.clear();
+        let files = files_in(&self.root_dir, &self.config.snapshot_paths, &["sql"]);
+        index_files_parallel(&files, |path, content| self.index_snapshots_in_file(path, content));
+        eprintln!("Found {} snapshots in {:?}", self.snapshots.len(), started.elapsed());
+    }
+
+    pub fn scan_analyses(&self) {
+        let started = Instant::now();
+        self.list_by_stem(&self.analyses, &self.config.analysis_paths, &["sql"]);
+        eprintln!("Found {} analyses in {:?}", self.analyses.len(), started.elapsed());
+    }
+
+    pub fn scan_singular_tests(&self) {
+        let started = Instant::now();
+        self.list_by_stem(&self.singular_tests, &self.config.test_paths, &["sql"]);
+        self.singular_tests.retain(|_, path| !self.is_under(path, &self.config.generic_test_dirs()));
+        eprintln!("Found {} singular tests in {:?}", self.singular_tests.len(), started.elapsed());
+    }
+
+    fn index_snapshots_in_file(&self, path: &Path, content: &str) {
+        static RE: OnceLock<regex::Regex> = OnceLock::new();
+        let snapshot_regex = RE.get_or_init(|| regex::Regex::new(r"\{%-?\s*snapshot\s+([a-zA-Z0-9_]+)\s*-?%\}").unwrap());
+
+        for cap in snapshot_regex.captures_iter(content) {
+            if let Some(name) = cap.get(1) {
+                let line = content[..name.start()].matches('\n').count();
+                insert_first(&self.snapshots, name.as_str().to_string(), SnapshotDef {
+                    path: path.to_path_buf(),
+                    line,
+                }, |d| &d.path);
             }
         }
-        eprintln!("Found {} seeds", self.seeds.len());
     }
 
-    pub fn scan_macros(&self) {
+    /// Whether a plain `ref('name')` resolves, either in the project or in an installed
+    /// package. A versioned model resolves through its latest version.
+    pub fn has_ref_target(&self, name: &str) -> bool {
+        let resolved = self.resolve_model_version(name, None).unwrap_or_else(|| name.to_string());
+        let name = resolved.as_str();
+        self.models.contains_key(name) || self.seeds.contains_key(name) || self.snapshots.contains_key(name)
+            || self.package_models.iter().any(|m| m.key().1 == name)
+    }
+
+    /// The model `ref(name, v=version)` resolves to: the one defining that version, or the
+    /// latest version when none is given. An unversioned model resolves to itself without a
+    /// version; None for a version the model doesn't declare.
+    pub fn resolve_model_version(&self, name: &str, version: Option<&str>) -> Option<String> {
+        let Some(entry) = self.model_entries.get(name).filter(|e| !e.versions.is_empty()) else {
+            return version.is_none().then(|| name.to_string());
+        };
+        let version = version.or(entry.latest_version.as_deref())?;
+        entry.versions.iter().find(|v| v.version == version).map(|v| v.defined_in.clone())
+    }
+
+    /// The name a plain or versioned `ref()` points at in the project: the model defining the
+    /// version for a versioned model, else the ref's own name.
+    pub fn ref_target_name(&self, dbt_ref: &crate::jinja::DbtRef) -> Option<String> {
+        match dbt_ref {
+            crate::jinja::DbtRef::Model(name) => Some(self.resolve_model_version(name, None).unwrap_or_else(|| name.clone())),
+            crate::jinja::DbtRef::VersionedModel(name, version) => self.resolve_model_version(name, Some(version)),
+            _ => None,
+        }
+    }
+
+    /// Indexes the macros under the macro directories and the docs blocks in the .md and
+    /// .sql files under the docs directories. A file under both is read once.
+    pub fn scan_macros_and_docs(&self) {
+        let started = Instant::now();
         self.macros.clear();
-        let macro_regex = regex::Regex::new(r#"(?s)\{%\s*macro\s+([a-zA-Z0-9_]+)\s*\("#).unwrap();
-
-        for path in &self.config.macro_paths {
-            let full_path = self.root_dir.join(path);
-            eprintln!("Scanning macros in: {:?}", full_path);
-            for entry in WalkDir::new(full_path).into_iter().filter_map(|e| e.ok()) {
-                if entry.path().extension().map_or(false, |ext| ext == "sql" || ext == "jinja") {
-                    if let Ok(content) = std::fs::read_to_string(entry.path()) {
-                        for cap in macro_regex.captures_iter(&content) {
-                            if let Some(m) = cap.get(1) {
-                                let name = m.as_str().to_string();
-                                // Calculate line number (naive but works)
-                                let line = content[..m.start()].lines().count().saturating_sub(1);
-                                self.macros.insert(name, MacroDef {
-                                    path: entry.path().to_path_buf(),
-                                    line,
-                                });
-                            }
-                        }
-                    }
-                }
+        self.docs.clear();
+        self.duplicate_docs.clear();
+        let macro_files = files_in(&self.root_dir, &self.config.macro_dirs(), &["sql", "jinja"]);
+        let docs_files = files_in(&self.root_dir, &self.docs_dirs(), &["md", "sql"]);
+        let mut files: Vec<PathBuf> = macro_files.iter().chain(&docs_files).cloned().collect();
+        files.sort();
+        files.dedup();
+        index_files_parallel(&files, |path, content| {
+            if macro_files.binary_search_by(|p| p.as_path().cmp(path)).is_ok() {
+                self.index_macros_in_file(path, content);
             }
+            if docs_files.binary_search_by(|p| p.as_path().cmp(path)).is_ok() {
+                self.index_docs_in_file(path, content);
+            }
+        });
+        eprintln!("Found {} macros and {} docs blocks in {:?}", self.macros.len(), self.docs.len(), started.elapsed());
+    }
+
+    fn index_macros_in_file(&self, path: &Path, content: &str) {
+        for (name, def) in macro_defs(path, content, None) {
+            insert_first(&self.macros, name, def, |d| &d.path);
         }
-        eprintln!("Found {} macros", self.macros.len());
     }
 
+    /// Drops the macros defined in `path` and returns their names.
+    fn remove_macros_in(&self, path: &Path) -> Vec<String> {
+        let names = self.macros.iter().filter(|m| m.path == path).map(|m| m.key().clone()).collect();
+        self.macros.retain(|_, m| m.path != path);
+        names
+    }
+
+    /// Gives each of `names` left without a definition the first one another file still
+    /// has. Only the files defining a duplicated name hold it, so they are read again.
+    fn restore_macros(&self, names: Vec<String>) {
+        let lost: Vec<String> = names.into_iter().filter(|name| !self.macros.contains_key(name)).collect();
+        if lost.is_empty() {
+            return;
+        }
+        let files = files_in(&self.root_dir, &self.config.macro_dirs(), &["sql", "jinja"]);
+        index_files_parallel(&files, |path, content| {
+            for (name, def) in macro_defs(path, content, None).into_iter().filter(|(name, _)| lost.contains(name)) {
+                insert_first(&self.macros, name, def, |d| &d.path);
+            }
+        });
+    }
+
+    /// The yml files under the model and seed paths, which declare sources, entries,
+    /// tests, exposures and the semantic layer.
+    pub fn yaml_files(&self) -> Vec<PathBuf> {
+        files_in(&self.root_dir, self.config.model_paths.iter().chain(&self.config.seed_paths), &["yml", "yaml"])
+    }
+
+    /// Indexes sources and documented model and seed entries from the yml files under
+    /// the model and seed paths.
     pub fn scan_sources(&self) {
         self.sources.clear();
-        for path in &self.config.model_paths {
-            let full_path = self.root_dir.join(path);
-            eprintln!("Scanning sources (YML) in: {:?}", full_path);
-            for entry in WalkDir::new(full_path).into_iter().filter_map(|e| e.ok()) {
-                if entry.path().extension().map_or(false, |ext| ext == "yml" || ext == "yaml") {
-                    if let Ok(content) = std::fs::read_to_string(entry.path()) {
-                        if let Ok(val) = serde_yaml::from_str::<serde_yaml::Value>(&content) {
-                            if let Some(sources) = val.get("sources").and_then(|s| s.as_sequence()) {
-                                for src in sources {
-                                    if let Some(src_name) = src.get("name").and_then(|n| n.as_str()) {
-                                        if let Some(tables) = src.get("tables").and_then(|t| t.as_sequence()) {
-                                            for tbl in tables {
-                                                if let Some(tbl_name) = tbl.get("name").and_then(|n| n.as_str()) {
-                                                    let full_src_name = format!("{}.{}", src_name, tbl_name);
-                                                    self.sources.insert(full_src_name, entry.path().to_path_buf());
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
+        self.model_entries.clear();
+        self.seed_entries.clear();
+        self.exposures.clear();
+        self.metrics.clear();
+        self.groups.clear();
+        self.generic_tests.clear();
+        self.semantic_models.clear();
+        let started = Instant::now();
+        let files = self.yaml_files();
+        index_files_parallel(&files, |path, content| {
+            match &self.artifact {
+                Some(artifact) if artifact.covers(path) => self.index_artifact_entries(artifact, path, content),
+                _ => {
+                    self.index_sources_in_file(path, content);
+                    self.index_model_entries_in_file(path, content);
+                }
+            }
+            self.index_exposures_in_file(path, content);
+            self.index_semantic_layer_in_file(path, content);
+            self.index_groups_in_file(path, content);
+            self.index_generic_tests_in_file(path, content);
+        });
+        eprintln!("Found {} sources in {} yml files in {:?}", self.sources.len(), files.len(), started.elapsed());
+    }
+
+    fn index_sources_in_file(&self, path: &Path, content: &str) {
+        let Ok(val) = serde_yaml::from_str::<serde_yaml::Value>(content) else { return };
+        let Some(sources) = val.get("sources").and_then(|s| s.as_sequence()) else { return };
+        let keys = crate::yaml::scan_keys(content);
+
+        for src in sources {
+            let Some(src_name) = src.get("name").and_then(|n| n.as_str()) else { continue };
+            let source_line = crate::yaml::find_named_item(&keys, &["sources"], src_name).map_or(0, |k| k.line);
+            let Some(tables) = src.get("tables").and_then(|t| t.as_sequence()) else { continue };
+            for tbl in tables {
+                let Some(tbl_name) = tbl.get("name").and_then(|n| n.as_str()) else { continue };
+                let full_src_name = format!("{}.{}", src_name, tbl_name);
+                let (line, column) = crate::yaml::find_named_item(&keys, &["sources", src_name, "tables"], tbl_name)
+                    .map_or((source_line, 0), |k| (k.line, k.value_column));
+                // Table-level settings override the source's
+                let inherited = |key: &str| yaml_str(tbl, key).or_else(|| yaml_str(src, key));
+                let freshness = match tbl.get("freshness") {
+                    Some(serde_yaml::Value::Null) => None,
+                    Some(f) => freshness_summary(f),
+                    None => src.get("freshness").and_then(freshness_summary),
+                };
+
+                insert_first(&self.sources, full_src_name, SourceDef {
+                    source_name: src_name.to_string(),
+                    table_name: tbl_name.to_string(),
+                    path: path.to_path_buf(),
+                    line,
+                    column,
+                    description: inherited("description"),
+                    loader: yaml_str(src, "loader"),
+                    database: yaml_str(src, "database"),
+                    schema: yaml_str(src, "schema").unwrap_or_else(|| src_name.to_string()),
+                    identifier: yaml_str(tbl, "identifier").unwrap_or_else(|| tbl_name.to_string()),
+                    loaded_at_field: inherited("loaded_at_field"),
+                    freshness,
+                    columns: yaml_columns(tbl, &keys, &["sources", src_name, "tables", tbl_name]),
+                    tests: yaml_tests(tbl),
+                }, |d| &d.path);
+            }
+        }
+    }
+
+    /// Indexes the sources and model and seed entries manifest.json has for the yml file
+    /// at `path`. Only their locations and data tests are read from the file, as
+    /// manifest.json keeps tests as separate nodes.
+    fn index_artifact_entries(&self, artifact: &ManifestArtifact, path: &Path, content: &str) {
+        let keys = crate::yaml::scan_keys(content);
+        let val = serde_yaml::from_str::<serde_yaml::Value>(content).unwrap_or_default();
+        let named = |items: Option<&serde_yaml::Value>, name: &str| -> Option<serde_yaml::Value> {
+            items?.as_sequence()?.iter().find(|i| i.get("name").and_then(|n| n.as_str()) == Some(name)).cloned()
+        };
+        // Data tests of an entry and of each of its columns, as written in the file
+        let tests_of = |entry: Option<serde_yaml::Value>, columns: &mut [ColumnDoc]| -> Vec<String> {
+            let Some(entry) = entry else { return Vec::new() };
+            for col in columns.iter_mut() {
+                col.tests = named(entry.get("columns"), &col.name).map(|c| yaml_tests(&c)).unwrap_or_default();
+            }
+            yaml_tests(&entry)
+        };
+        for src in artifact.sources.iter().filter(|s| s.path == path) {
+            let (line, column) = crate::yaml::find_named_item(&keys, &["sources", &src.source_name, "tables"], &src.table_name)
+                .or_else(|| crate::yaml::find_named_item(&keys, &["sources"], &src.source_name))
+                .map_or((0, 0), |k| (k.line, k.value_column));
+            let mut columns = src.columns.clone();
+            locate_columns(&mut columns, &keys, &["sources", &src.source_name, "tables", &src.table_name]);
+            let table = named(val.get("sources"), &src.source_name).and_then(|s| named(s.get("tables"), &src.table_name));
+            let tests = tests_of(table, &mut columns);
+            insert_first(&self.sources, format!("{}.{}", src.source_name, src.table_name), SourceDef {
+                source_name: src.source_name.clone(),
+                table_name: src.table_name.clone(),
+                path: path.to_path_buf(),
+                line,
+                column,
+                description: src.description.clone(),
+                loader: src.loader.clone(),
+                database: src.database.clone(),
+                schema: src.schema.clone().unwrap_or_else(|| src.source_name.clone()),
+                identifier: src.identifier.clone().unwrap_or_else(|| src.table_name.clone()),
+                loaded_at_field: src.loaded_at_field.clone(),
+                freshness: src.freshness.clone(),
+                columns,
+                tests,
+            }, |d| &d.path);
+        }
+
+        for node in artifact.nodes.iter().filter(|n| n.patch_path.as_deref() == Some(path)) {
+            let (section, entries) = match node.resource_type.as_str() {
+                "model" => ("models", &self.model_entries),
+                "seed" => ("seeds", &self.seed_entries),
+                _ => continue,
+            };
+            let (line, column) = crate::yaml::find_named_item(&keys, &[section], &node.name)
+                .map_or((0, 0), |k| (k.line, k.value_column));
+            let mut columns = node.columns.clone();
+            for (col_name, col_type) in &node.column_types {
+                match columns.iter_mut().find(|c| c.name == *col_name) {
+                    Some(col) => { col.data_type.get_or_insert_with(|| col_type.clone()); }
+                    None => columns.push(ColumnDoc { name: col_name.clone(), data_type: Some(col_type.clone()), ..ColumnDoc::default() }),
+                }
+            }
+            locate_columns(&mut columns, &keys, &[section, &node.name]);
+            let entry = named(val.get(section), &node.name);
+            let (versions, latest_version) = entry.as_ref()
+                .map(|e| yaml_versions(e, &keys, &[section, &node.name], &node.name))
+                .unwrap_or_default();
+            let (group, access) = (entry_config(entry.as_ref(), "group"), entry_config(entry.as_ref(), "access"));
+            let tests = tests_of(entry, &mut columns);
+            insert_first(entries, node.name.clone(), ModelEntry {
+                path: path.to_path_buf(),
+                line,
+                column,
+                alias: node.alias.clone(),
+                description: node.description.clone(),
+                columns,
+                tests,
+                versions,
+                latest_version,
+                group,
+                access,
+            }, |d| &d.path);
+        }
+    }
+
+    fn index_generic_tests_in_file(&self, path: &Path, content: &str) {
+        if !content.contains("tests") {
+            return;
+        }
+        let tests = crate::yaml::test_entries(content);
+        if !tests.is_empty() {
+            self.generic_tests.insert(path.to_path_buf(), tests);
+        }
+    }
+
+    /// The macro a generic test in yml runs: `pkg.name` is the package's `test_name`; a
+    /// plain name is the project's, else dbt's own, else any installed package's.
+    pub fn resolve_generic_test(&self, name: &str) -> Option<GenericTest> {
+        match name.split_once('.') {
+            Some(("dbt", test)) if BUILTIN_TESTS.contains(&test) => Some(GenericTest::Builtin),
+            Some((namespace, test)) => self.resolve_macro(&format!("{}.test_{}", namespace, test)).map(GenericTest::Macro),
+            None => {
+                let macro_name = format!("test_{}", name);
+                self.macros.get(&macro_name).map(|m| GenericTest::Macro(m.value().clone()))
+                    .or_else(|| BUILTIN_TESTS.contains(&name).then_some(GenericTest::Builtin))
+                    .or_else(|| self.package_macros.iter()
+                        .filter(|m| m.key().1 == macro_name)
+                        .min_by(|a, b| a.key().0.cmp(&b.key().0))
+                        .map(|m| GenericTest::Macro(m.value().clone())))
+            }
+        }
+    }
+
+    fn index_groups_in_file(&self, path: &Path, content: &str) {
+        if !content.contains("groups") {
+            return;
+        }
+        let Ok(val) = serde_yaml::from_str::<serde_yaml::Value>(content) else { return };
+        let Some(groups) = val.get("groups").and_then(|g| g.as_sequence()) else { return };
+        let keys = crate::yaml::scan_keys(content);
+        for group in groups {
+            let Some(name) = group.get("name").and_then(|n| n.as_str()) else { continue };
+            let (line, column) = crate::yaml::find_named_item(&keys, &["groups"], name)
+                .map_or((0, 0), |k| (k.line, k.value_column));
+            let owner = group.get("owner");
+            insert_first(&self.groups, name.to_string(), GroupDef {
+                path: path.to_path_buf(),
+                line,
+                column,
+                owner: owner.and_then(|o| yaml_str(o, "name").or_else(|| yaml_str(o, "email"))),
+            }, |d| &d.path);
+        }
+    }
+
+    fn index_exposures_in_file(&self, path: &Path, content: &str) {
+        if !content.contains("exposures") {
+            return;
+        }
+        let Ok(val) = serde_yaml::from_str::<serde_yaml::Value>(content) else { return };
+        let Some(exposures) = val.get("exposures").and_then(|e| e.as_sequence()) else { return };
+        let keys = crate::yaml::scan_keys(content);
+        let line_starts: Vec<usize> = std::iter::once(0).chain(content.match_indices('\n').map(|(i, _)| i + 1)).collect();
+
+        for exposure in exposures {
+            let Some(name) = exposure.get("name").and_then(|n| n.as_str()) else { continue };
+            let (line, column) = crate::yaml::find_named_item(&keys, &["exposures"], name)
+                .map_or((0, 0), |k| (k.line, k.value_column));
+            // depends_on entries are bare `ref('x')` / `source('s', 't')` calls, found in
+            // the text in order from the `depends_on:` key
+            let mut cursor = keys.iter()
+                .find(|k| k.key == "depends_on" && k.path.len() == 2 && k.path[0] == "exposures" && k.path[1] == name)
+                .and_then(|k| line_starts.get(k.line).copied())
+                .unwrap_or(content.len());
+            let mut depends_on = Vec::new();
+            for entry in exposure.get("depends_on").and_then(|d| d.as_sequence()).into_iter().flatten().filter_map(|d| d.as_str()) {
+                let range = match content[cursor..].find(entry) {
+                    Some(offset) => cursor + offset..cursor + offset + entry.len(),
+                    None => cursor..cursor,
+                };
+                cursor = range.end;
+                let refs = crate::jinja::extract_refs(&format!("{{{{ {} }}}}", entry));
+                let dbt_ref = refs.into_iter().map(|(dbt_ref, _)| dbt_ref)
+                    .find(|r| matches!(r, crate::jinja::DbtRef::Model(_) | crate::jinja::DbtRef::VersionedModel(..) | crate::jinja::DbtRef::PackageModel(..) | crate::jinja::DbtRef::Source(..)));
+                if let Some(dbt_ref) = dbt_ref {
+                    depends_on.push((dbt_ref, range));
+                }
+            }
+            let owner = exposure.get("owner");
+            insert_first(&self.exposures, name.to_string(), ExposureDef {
+                path: path.to_path_buf(),
+                line,
+                column,
+                exposure_type: yaml_str(exposure, "type"),
+                owner: owner.and_then(|o| yaml_str(o, "name").or_else(|| yaml_str(o, "email"))),
+                depends_on,
+            }, |d| &d.path);
+        }
+    }
+
+    /// Indexes `metrics:` and `semantic_models:`, the semantic layer's yml.
+    fn index_semantic_layer_in_file(&self, path: &Path, content: &str) {
+        if !content.contains("metrics") && !content.contains("semantic_models") {
+            return;
+        }
+        let Ok(val) = serde_yaml::from_str::<serde_yaml::Value>(content) else { return };
+        let keys = crate::yaml::scan_keys(content);
+        let names = |item: &serde_yaml::Value, key: &str| -> Vec<String> {
+            item.get(key).and_then(|l| l.as_sequence()).into_iter().flatten()
+                .filter_map(|d| d.as_str().map(str::to_string).or_else(|| yaml_str(d, "name")))
+                .collect()
+        };
+
+        for metric in val.get("metrics").and_then(|m| m.as_sequence()).into_iter().flatten() {
+            let Some(name) = metric.get("name").and_then(|n| n.as_str()) else { continue };
+            let (line, column) = crate::yaml::find_named_item(&keys, &["metrics"], name)
+                .map_or((0, 0), |k| (k.line, k.value_column));
+            let params = metric.get("type_params");
+            // `measure: revenue` or `measure: {name: revenue, filter: ...}`
+            let measure = params.and_then(|p| p.get("measure"))
+                .and_then(|m| m.as_str().map(str::to_string).or_else(|| yaml_str(m, "name")));
+            let ratio = params.and_then(|p| {
+                let part = |key: &str| p.get(key).and_then(|m| m.as_str().map(str::to_string).or_else(|| yaml_str(m, "name")));
+                Some(format!("{} / {}", part("numerator")?, part("denominator")?))
+            });
+            let expression = params.and_then(|p| yaml_str(p, "expr"))
+                .or_else(|| measure.clone())
+                .or(ratio)
+                .or_else(|| yaml_str(metric, "expression"))
+                .or_else(|| yaml_str(metric, "sql"));
+            insert_first(&self.metrics, name.to_string(), MetricDef {
+                path: path.to_path_buf(),
+                line,
+                column,
+                label: yaml_str(metric, "label"),
+                description: yaml_str(metric, "description"),
+                metric_type: yaml_str(metric, "type").or_else(|| yaml_str(metric, "calculation_method")),
+                expression,
+                measure,
+                dimensions: names(metric, "dimensions"),
+            }, |d| &d.path);
+        }
+
+        let line_starts: Vec<usize> = std::iter::once(0).chain(content.match_indices('\n').map(|(i, _)| i + 1)).collect();
+        for semantic_model in val.get("semantic_models").and_then(|m| m.as_sequence()).into_iter().flatten() {
+            let Some(name) = semantic_model.get("name").and_then(|n| n.as_str()) else { continue };
+            let (line, column) = crate::yaml::find_named_item(&keys, &["semantic_models"], name)
+                .map_or((0, 0), |k| (k.line, k.value_column));
+            // Located in the text from its `model:` key, like an exposure's depends_on
+            let model = yaml_str(semantic_model, "model").and_then(|call| {
+                let key = keys.iter().find(|k| k.key == "model" && k.path.len() == 2 && k.path[0] == "semantic_models" && k.path[1] == name)?;
+                let start = line_starts.get(key.line)?;
+                let offset = start + content[*start..].find(&call)?;
+                let refs = crate::jinja::extract_refs(&format!("{{{{ {} }}}}", call));
+                let dbt_ref = refs.into_iter().map(|(dbt_ref, _)| dbt_ref)
+                    .find(|r| matches!(r, crate::jinja::DbtRef::Model(_) | crate::jinja::DbtRef::VersionedModel(..) | crate::jinja::DbtRef::PackageModel(..)))?;
+                Some((dbt_ref, offset..offset + call.len()))
+            });
+            insert_first(&self.semantic_models, name.to_string(), SemanticModelDef {
+                path: path.to_path_buf(),
+                line,
+                column,
+                model,
+                measures: names(semantic_model, "measures"),
+                dimensions: names(semantic_model, "dimensions"),
+            }, |d| &d.path);
+        }
+    }
+
+    /// The dimensions a metric can be grouped by: those listed on a legacy metric, else
+    /// those of the semantic models defining its measure.
+    pub fn metric_dimensions(&self, metric: &MetricDef) -> Vec<String> {
+        if !metric.dimensions.is_empty() {
+            return metric.dimensions.clone();
+        }
+        let Some(measure) = &metric.measure else { return Vec::new() };
+        let mut dimensions: Vec<String> = self.semantic_models.iter()
+            .filter(|m| m.measures.contains(measure))
+            .flat_map(|m| m.dimensions.clone())
+            .collect();
+        dimensions.sort();
+        dimensions.dedup();
+        dimensions
+    }
+
+    /// The exposure's yml file with its `depends_on` entries, for reporting their locations.
+    pub fn exposure_file(&self, name: &str) -> Option<IndexedFile> {
+        let exposure = self.exposures.get(name)?;
+        let content = std::fs::read_to_string(&exposure.path).ok()?;
+        Some(IndexedFile { text: ropey::Rope::from_str(&content), refs: exposure.depends_on.clone() })
+    }
+
+    fn index_model_entries_in_file(&self, path: &Path, content: &str) {
+        let Ok(val) = serde_yaml::from_str::<serde_yaml::Value>(content) else { return };
+        let keys = crate::yaml::scan_keys(content);
+
+        for (section, entries) in [("models", &self.model_entries), ("seeds", &self.seed_entries)] {
+            let Some(items) = val.get(section).and_then(|m| m.as_sequence()) else { continue };
+            for item in items {
+                let Some(name) = item.get("name").and_then(|n| n.as_str()) else { continue };
+                let (line, column) = crate::yaml::find_named_item(&keys, &[section], name)
+                    .map_or((0, 0), |k| (k.line, k.value_column));
+                let config = item.get("config");
+                let alias = config.and_then(|c| yaml_str(c, "alias")).or_else(|| yaml_str(item, "alias"));
+
+                // Seeds can set types through `config.column_types` instead of per column
+                let mut columns = yaml_columns(item, &keys, &[section, name]);
+                if let Some(types) = config.and_then(|c| c.get("column_types")).and_then(|t| t.as_mapping()) {
+                    for (col_name, col_type) in types {
+                        let (Some(col_name), Some(col_type)) = (col_name.as_str(), col_type.as_str()) else { continue };
+                        match columns.iter_mut().find(|c| c.name == col_name) {
+                            Some(col) => { col.data_type.get_or_insert_with(|| col_type.to_string()); }
+                            None => columns.push(ColumnDoc { name: col_name.to_string(), data_type: Some(col_type.to_string()), ..ColumnDoc::default() }),
                         }
                     }
                 }
+
+                let (versions, latest_version) = yaml_versions(item, &keys, &[section, name], name);
+                insert_first(entries, name.to_string(), ModelEntry {
+                    path: path.to_path_buf(),
+                    line,
+                    column,
+                    alias,
+                    description: yaml_str(item, "description"),
+                    columns,
+                    tests: yaml_tests(item),
+                    versions,
+                    latest_version,
+                    group: entry_config(Some(item), "group"),
+                    access: entry_config(Some(item), "access"),
+                }, |d| &d.path);
+            }
+        }
+    }
+
+    /// The directories docs blocks are read from: the model paths and any `docs-paths`.
+    pub fn docs_dirs(&self) -> Vec<String> {
+        self.config.model_paths.iter().chain(&self.config.docs_paths).cloned().collect()
+    }
+
+    fn index_docs_in_file(&self, path: &Path, content: &str) {
+        static RE: OnceLock<regex::Regex> = OnceLock::new();
+        let docs_regex = RE.get_or_init(|| regex::Regex::new(r"(?s)\{%-?\s*docs\s+([a-zA-Z0-9_]+)\s*-?%\}(.*?)\{%-?\s*enddocs\s*-?%\}").unwrap());
+        if !content.contains("enddocs") {
+            return;
+        }
+
+        for cap in docs_regex.captures_iter(content) {
+            if let (Some(name), Some(body)) = (cap.get(1), cap.get(2)) {
+                let line_start = content[..name.start()].rfind('\n').map_or(0, |i| i + 1);
+                self.add_docs_block(name.as_str().to_string(), DocsBlock {
+                    path: path.to_path_buf(),
+                    line: content[..name.start()].matches('\n').count(),
+                    column: name.start() - line_start,
+                    body: body.as_str().trim().to_string(),
+                });
+            }
+        }
+    }
+
+    /// Records a docs block. When the name is already taken, the block that comes first by
+    /// path and line keeps it and every definition is listed in `duplicate_docs`.
+    fn add_docs_block(&self, name: String, block: DocsBlock) {
+        let mut first = self.docs.entry(name.clone()).or_insert_with(|| block.clone());
+        if first.path == block.path && first.line == block.line {
+            return;
+        }
+        let mut blocks = self.duplicate_docs.entry(name).or_insert_with(|| vec![first.clone()]);
+        if !blocks.iter().any(|b| b.path == block.path && b.line == block.line) {
+            blocks.push(block);
+            blocks.sort_by(|a, b| (&a.path, a.line).cmp(&(&b.path, b.line)));
+        }
+        *first = blocks[0].clone();
+    }
+
+    /// Drops the docs blocks defined in `path`; a duplicated name goes to the next definition.
+    fn remove_docs_in(&self, path: &Path) {
+        self.docs.retain(|_, d| d.path != path);
+        let mut promoted = Vec::new();
+        self.duplicate_docs.retain(|name, blocks| {
+            blocks.retain(|b| b.path != path);
+            if let Some(first) = blocks.first() {
+                promoted.push((name.clone(), first.clone()));
+            }
+            blocks.len() > 1
+        });
+        for (name, first) in promoted {
+            self.docs.insert(name, first);
+        }
+    }
+
+    /// `description` with each `{{ doc('name') }}` replaced by the block's body, as dbt
+    /// renders it. Calls to unknown blocks are left as written.
+    pub fn render_docs(&self, description: &str) -> String {
+        static RE: OnceLock<regex::Regex> = OnceLock::new();
+        let doc_call = RE.get_or_init(|| regex::Regex::new(r#"\{\{-?\s*doc\(\s*['"]([a-zA-Z0-9_]+)['"]\s*\)\s*-?\}\}"#).unwrap());
+        if !description.contains("doc(") {
+            return description.to_string();
+        }
+        doc_call.replace_all(description, |cap: &regex::Captures| {
+            self.docs.get(&cap[1]).map_or_else(|| cap[0].to_string(), |d| d.body.clone())
+        }).into_owned()
+    }
+
+    /// `columns` with their descriptions rendered by [`Self::render_docs`].
+    pub fn render_column_docs(&self, columns: &[ColumnDoc]) -> Vec<ColumnDoc> {
+        columns.iter()
+            .map(|c| ColumnDoc { description: c.description.as_deref().map(|d| self.render_docs(d)), ..c.clone() })
+            .collect()
+    }
+
+    /// Indexes installed packages (`dbt_packages/`, or the legacy `dbt_modules/`) and the
+    /// models and macros they ship, keyed by the package's project name.
+    pub fn scan_packages(&self) {
+        let started = Instant::now();
+        self.packages.clear();
+        self.package_models.clear();
+        self.package_macros.clear();
+        for dir in ["dbt_packages", "dbt_modules"] {
+            let Ok(entries) = std::fs::read_dir(self.root_dir.join(dir)) else { continue };
+            for entry in entries.filter_map(|e| e.ok()) {
+                let pkg_root = entry.path();
+                let Ok(content) = std::fs::read_to_string(pkg_root.join("dbt_project.yml")) else { continue };
+                let Ok(config) = serde_yaml::from_str::<DbtProjectConfig>(&content) else { continue };
+                if self.packages.contains_key(&config.name) {
+                    continue;
+                }
+
+                for path in files_in(&pkg_root, &config.model_paths, &["sql"]) {
+                    if let Some(stem) = path.file_stem() {
+                        self.package_models.insert((config.name.clone(), stem.to_string_lossy().to_string()), path.clone());
+                    }
+                }
+                let macro_files = files_in(&pkg_root, &config.macro_dirs(), &["sql", "jinja"]);
+                index_files_parallel(&macro_files, |path, content| {
+                    for (name, def) in macro_defs(path, content, Some(&config.name)) {
+                        insert_first(&self.package_macros, (config.name.clone(), name), def, |d| &d.path);
+                    }
+                });
+                self.packages.insert(config.name, pkg_root);
+            }
+        }
+        eprintln!(
+            "Found {} packages with {} models and {} macros in {:?}",
+            self.packages.len(), self.package_models.len(), self.package_macros.len(), started.elapsed()
+        );
+        self.scan_declared_packages();
+    }
+
+    /// Reads packages.yml, naming each entry after its package-lock.yml counterpart
+    /// when the lock file records names (dbt 1.7+).
+    fn scan_declared_packages(&self) {
+        self.declared_packages.clear();
+        let Ok(content) = std::fs::read_to_string(self.root_dir.join("packages.yml")) else { return };
+        let lock = std::fs::read_to_string(self.root_dir.join("package-lock.yml")).unwrap_or_default();
+        let locked_names: HashMap<String, String> = package_entries(&lock).into_iter()
+            .filter_map(|(_, k, _, name)| Some((k.value?, name?)))
+            .collect();
+
+        for (kind, k, version, _) in package_entries(&content) {
+            let Some(spec) = k.value else { continue };
+            // A local package's name is in its own dbt_project.yml
+            let local_name = (kind == "local")
+                .then(|| std::fs::read_to_string(self.root_dir.join(&spec).join("dbt_project.yml")).ok())
+                .flatten()
+                .and_then(|c| serde_yaml::from_str::<DbtProjectConfig>(&c).ok())
+                .map(|c| c.name);
+            // dbt-labs/dbt_utils, https://github.com/dbt-labs/dbt-utils.git, ../shared
+            let last_segment = spec.trim_end_matches('/').rsplit(['/', '\\']).next().unwrap_or(&spec);
+            let name = locked_names.get(&spec).cloned()
+                .or(local_name)
+                .unwrap_or_else(|| last_segment.trim_end_matches(".git").replace('-', "_"));
+            let quoted = content.lines().nth(k.line)
+                .and_then(|l| l.get(k.value_column..))
+                .is_some_and(|v| v.starts_with(['"', '\'']));
+            self.declared_packages.insert(name.clone(), DeclaredPackage {
+                name,
+                spec,
+                version,
+                line: k.line,
+                column: k.value_column + usize::from(quoted),
+            });
+        }
+    }
+
+    /// Whether `path` is one whose change can alter the installed or declared packages.
+    pub fn is_package_file(&self, path: &Path) -> bool {
+        ["packages.yml", "package-lock.yml"].iter().any(|f| path == self.root_dir.join(f))
+            || ["dbt_packages", "dbt_modules"].iter().any(|d| path.starts_with(self.root_dir.join(d)))
+    }
+
+    /// packages.yml entries that aren't installed, in file order.
+    pub fn missing_packages(&self) -> Vec<DeclaredPackage> {
+        let mut missing: Vec<DeclaredPackage> = self.declared_packages.iter()
+            .filter(|p| !self.packages.contains_key(p.key()))
+            .map(|p| p.value().clone())
+            .collect();
+        missing.sort_by_key(|p| p.line);
+        missing
+    }
+
+    /// Indexes `vars:` from dbt_project.yml. Vars scoped under the project's own name
+    /// override global ones; vars scoped under a package (installed or declared) are
+    /// only visible to that package. Run after `scan_packages` so scopes can be told
+    /// apart from vars with mapping values. Values are kept as written, jinja included.
+    pub fn scan_vars(&self) {
+        self.vars.clear();
+        self.all_vars.clear();
+        let config_path = self.root_dir.join("dbt_project.yml");
+        let Ok(content) = std::fs::read_to_string(&config_path) else { return };
+        let is_scope = |key: &str| {
+            key == self.config.name || self.packages.contains_key(key) || self.declared_packages.contains_key(key)
+        };
+
+        let var_def = |k: &crate::yaml::YamlKey, scope: &serde_yaml::Value| VarDef {
+            path: config_path.clone(),
+            line: k.line,
+            column: k.key_column,
+            value: scope.get(k.key.as_str()).cloned().unwrap_or_default(),
+        };
+        for k in crate::yaml::scan_keys(&content) {
+            match k.path.as_slice() {
+                [vars] if vars == "vars" => {
+                    if k.value.is_none() && is_scope(&k.key) && self.config.vars.get(k.key.as_str()).is_some_and(|v| v.is_mapping()) {
+                        continue;
+                    }
+                    self.all_vars.entry(k.key.clone()).or_insert_with(|| var_def(&k, &self.config.vars));
+                }
+                [vars, scope] if vars == "vars" && is_scope(scope) => {
+                    let scoped = self.config.vars.get(scope.as_str()).cloned().unwrap_or_default();
+                    self.all_vars.insert(format!("{}.{}", scope, k.key), var_def(&k, &scoped));
+                }
+                _ => {}
+            }
+        }
+
+        for var in self.all_vars.iter() {
+            match var.key().split_once('.') {
+                Some((scope, name)) if scope == self.config.name => { self.vars.insert(name.to_string(), var.value().clone()); }
+                Some(_) => {}
+                None => { self.vars.entry(var.key().clone()).or_insert_with(|| var.value().clone()); }
+            }
+        }
+        eprintln!("Found {} vars", self.vars.len());
+    }
+
+    /// The var a model of this project sees as `var('name')`.
+    pub fn var(&self, name: &str) -> Option<VarDef> {
+        self.vars.get(name).map(|v| v.value().clone())
+    }
+
+    /// The var a model of `package` sees as `var('name')`: the package-scoped value,
+    /// else the global one.
+    pub fn package_var(&self, package: &str, name: &str) -> Option<VarDef> {
+        self.all_vars.get(&format!("{}.{}", package, name))
+            .or_else(|| self.all_vars.get(name))
+            .map(|v| v.value().clone())
+    }
+
+    /// The var `var('name')` resolves to in the file at `path`, which may belong to an
+    /// installed package.
+    pub fn var_for_path(&self, path: &Path, name: &str) -> Option<VarDef> {
+        match self.packages.iter().find(|p| path.starts_with(p.value())) {
+            Some(package) => self.package_var(package.key(), name),
+            None => self.var(name),
+        }
+    }
+
+    /// Directories whose SQL files are indexed for find-references.
+    fn reference_paths(&self) -> impl Iterator<Item = &String> {
+        self.config.model_paths.iter()
+            .chain(&self.config.snapshot_paths)
+            .chain(&self.config.analysis_paths)
+            .chain(&self.config.macro_paths)
+            .chain(&self.config.test_paths)
+    }
+
+    fn index_references_in_file(&self, path: &Path, content: &str) {
+        // Commented-out jinja isn't a real use
+        let comments = crate::jinja::comment_spans(content);
+        let mut refs = crate::jinja::extract_refs(content);
+        refs.retain(|(_, range)| !comments.iter().any(|c| c.contains(&range.start)));
+        self.references.insert(path.to_path_buf(), IndexedFile {
+            text: ropey::Rope::from_str(content),
+            refs,
+        });
+    }
+
+    /// Reads every SQL file under the reference paths, once per manifest. Later edits
+    /// are picked up by `refresh_file`.
+    pub fn ensure_reference_index(&self) {
+        self.references_built.get_or_init(|| {
+            let started = Instant::now();
+            let files = files_in(&self.root_dir, self.reference_paths(), &["sql"]);
+            index_files_parallel(&files, |path, content| self.index_references_in_file(path, content));
+            eprintln!("Indexed references in {} files in {:?}", self.references.len(), started.elapsed());
+        });
+    }
+
+    /// Distinct source names, sorted.
+    pub fn source_names(&self) -> Vec<String> {
+        let names: std::collections::BTreeSet<String> = self.sources.iter().map(|s| s.source_name.clone()).collect();
+        names.into_iter().collect()
+    }
+
+    /// The tables declared under `source`, sorted.
+    pub fn source_tables(&self, source: &str) -> Vec<String> {
+        let mut tables: Vec<String> = self.sources.iter()
+            .filter(|s| s.source_name == source)
+            .map(|s| s.table_name.clone())
+            .collect();
+        tables.sort();
+        tables
+    }
+
+    pub fn has_package(&self, package: &str) -> bool {
+        package == self.config.name || self.packages.contains_key(package)
+    }
+
+    /// The packages defining a model called `name`: the project itself first, then
+    /// installed packages by name. More than one makes a plain `ref('name')` ambiguous.
+    pub fn defining_packages(&self, name: &str) -> Vec<String> {
+        let mut packages: Vec<String> = self.package_models.iter()
+            .filter(|m| m.key().1 == name)
+            .map(|m| m.key().0.clone())
+            .collect();
+        packages.sort();
+        if self.models.contains_key(name) {
+            packages.insert(0, self.config.name.clone());
+        }
+        packages
+    }
+
+    /// Resolves `ref('package', 'model')`; the project's own name refers to local models.
+    pub fn resolve_package_model(&self, package: &str, name: &str) -> Option<PathBuf> {
+        if package == self.config.name {
+            return self.models.get(name).map(|p| p.value().clone());
+        }
+        self.package_models.get(&(package.to_string(), name.to_string())).map(|p| p.value().clone())
+    }
+
+    /// Looks up a macro by the name used at the call site. Calls qualified with the
+    /// project's own namespace (`my_project.name`) resolve to the unqualified macro,
+    /// calls qualified with an installed package's name to that package's macro.
+    pub fn resolve_macro(&self, name: &str) -> Option<MacroDef> {
+        let local_name = match name.split_once('.') {
+            Some((namespace, rest)) if namespace == self.config.name => rest,
+            Some((namespace, rest)) => {
+                return self.package_macros.get(&(namespace.to_string(), rest.to_string())).map(|m| m.value().clone());
+            }
+            None => name,
+        };
+        self.macros.get(local_name).map(|m| m.value().clone())
+    }
+
+    /// Whether a macro call can be checked against the index at all: unqualified calls
+    /// and calls into the project or an installed package. Other namespaces (`dbt.`,
+    /// `adapter.`, packages that aren't installed) can't be validated here.
+    pub fn is_indexed_macro_name(&self, name: &str) -> bool {
+        match name.split_once('.') {
+            Some((namespace, _)) => self.has_package(namespace),
+            None => true,
+        }
+    }
+
+    /// The model a file under the model paths defines, or None for anything else
+    /// (analyses, macros, files outside the project).
+    pub fn model_name_for_path(&self, path: &Path) -> Option<String> {
+        if !self.is_under(path, &self.config.model_paths) {
+            return None;
+        }
+        let stem = path.file_stem()?.to_string_lossy().to_string();
+        self.model_files(&stem).iter().any(|p| p == path).then_some(stem)
+    }
+
+    /// The warehouse columns catalog.json has for `target` (a ref name or `source.table`).
+    /// None when there is no catalog, or the model, seed or snapshot changed since it was
+    /// generated.
+    pub fn catalog_columns(&self, target: &str) -> Option<Vec<ColumnDoc>> {
+        let catalog = self.catalog.as_ref()?.current()?;
+        let columns = catalog.columns.get(target)?;
+        let path = self.models.get(target).map(|p| p.value().clone())
+            .or_else(|| self.seeds.get(target).map(|p| p.value().clone()))
+            .or_else(|| self.snapshots.get(target).map(|s| s.path.clone()));
+        let changed = path.and_then(|p| std::fs::metadata(p).and_then(|m| m.modified()).ok())
+            .is_some_and(|modified| modified > catalog.written);
+        (!changed).then(|| columns.clone())
+    }
+
+    /// The columns of the model, seed, snapshot or source `target`: from catalog.json,
+    /// else as documented in yml, else from a model's select list. Catalog columns keep
+    /// their yml description when they have no comment, and their yml tests and location.
+    /// `doc()` calls in descriptions are rendered.
+    pub fn relation_columns(&self, target: &str) -> Option<(Vec<ColumnDoc>, ColumnOrigin)> {
+        let documented = self.model_entries.get(target).map(|e| e.columns.clone())
+            .or_else(|| self.sources.get(target).map(|s| s.columns.clone()))
+            .or_else(|| self.seed_entries.get(target).map(|e| e.columns.clone()))
+            .filter(|columns| !columns.is_empty());
+        if let Some(mut columns) = self.catalog_columns(target) {
+            for col in &mut columns {
+                let doc = documented.iter().flatten().find(|d| d.name.eq_ignore_ascii_case(&col.name));
+                let Some(doc) = doc else { continue };
+                if col.description.is_none() {
+                    col.description = doc.description.clone();
+                }
+                col.tests = doc.tests.clone();
+                col.line = doc.line;
             }
+            return Some((self.render_column_docs(&columns), ColumnOrigin::Catalog));
         }
-        eprintln!("Found {} sources", self.sources.len());
+        if let Some(columns) = documented {
+            return Some((self.render_column_docs(&columns), ColumnOrigin::Documented));
+        }
+        let text = std::fs::read_to_string(self.models.get(target)?.value()).ok()?;
+        let columns = crate::columns::model_output_columns(&text)?.into_iter()
+            .map(|name| ColumnDoc { name, ..ColumnDoc::default() })
+            .collect();
+        Some((columns, ColumnOrigin::SelectList))
+    }
+
+    /// A seed's columns as named by its CSV header, typed and described from its yml entry,
+    /// else typed from `+column_types` in dbt_project.yml.
+    pub fn seed_columns(&self, seed: &str, header: &[String]) -> Vec<ColumnDoc> {
+        let documented = self.seed_entries.get(seed).map(|e| self.render_column_docs(&e.columns)).unwrap_or_default();
+        let project_types = self.seeds.get(seed)
+            .and_then(|path| crate::relation::seed_folder_config(self, &path, "column_types"))
+            .unwrap_or_default();
+        header.iter()
+            .map(|name| {
+                let doc = documented.iter().find(|c| &c.name == name);
+                ColumnDoc {
+                    name: name.clone(),
+                    description: doc.and_then(|c| c.description.clone()),
+                    data_type: doc.and_then(|c| c.data_type.clone())
+                        .or_else(|| project_types.get(name.as_str()).and_then(|t| t.as_str()).map(str::to_string)),
+                    tests: doc.map(|c| c.tests.clone()).unwrap_or_default(),
+                    line: doc.and_then(|c| c.line),
+                }
+            })
+            .collect()
+    }
+
+    pub fn is_under(&self, path: &Path, dirs: &[String]) -> bool {
+        dirs.iter().any(|dir| path.starts_with(self.root_dir.join(dir)))
+    }
+
+    /// Re-indexes a single file after it was written, without rescanning the project.
+    /// Entries previously contributed by this file are dropped first, so renamed
+    /// macros or removed source tables don't linger.
+    pub fn refresh_file(&self, path: &Path) {
+        let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let stem = path.file_stem().map(|s| s.to_string_lossy().to_string());
+        // Read at most once, however many indexes the file feeds
+        let text = std::cell::OnceCell::new();
+        let read = || text.get_or_init(|| std::fs::read_to_string(path).ok()).as_deref();
+
+        if self.is_under(path, &self.config.model_paths) && ext == "sql" {
+            self.add_model_file(path.to_path_buf());
+        }
+
+        if (ext == "md" || ext == "sql") && self.is_under(path, &self.docs_dirs()) {
+            self.remove_docs_in(path);
+            if let Some(content) = read() {
+                self.index_docs_in_file(path, content);
+            }
+        }
+
+        let property_paths = [&self.config.model_paths, &self.config.seed_paths];
+        if (ext == "yml" || ext == "yaml") && property_paths.iter().any(|dirs| self.is_under(path, dirs)) {
+            self.sources.retain(|_, s| s.path != path);
+            self.model_entries.retain(|_, e| e.path != path);
+            self.seed_entries.retain(|_, e| e.path != path);
+            self.exposures.retain(|_, e| e.path != path);
+            self.metrics.retain(|_, m| m.path != path);
+            self.groups.retain(|_, g| g.path != path);
+            self.semantic_models.retain(|_, m| m.path != path);
+            self.generic_tests.remove(path);
+            if let Some(content) = read() {
+                self.index_sources_in_file(path, content);
+                self.index_model_entries_in_file(path, content);
+                self.index_exposures_in_file(path, content);
+                self.index_semantic_layer_in_file(path, content);
+                self.index_groups_in_file(path, content);
+                self.index_generic_tests_in_file(path, content);
+            }
+        }
+
+        if self.is_under(path, &self.config.seed_paths) && ext == "csv" {
+            if let Some(stem) = stem.clone() {
+                self.seeds.insert(stem, path.to_path_buf());
+            }
+        }
+
+        if self.is_under(path, &self.config.analysis_paths) && ext == "sql" {
+            if let Some(stem) = stem.clone() {
+                self.analyses.insert(stem, path.to_path_buf());
+            }
+        }
+
+        if self.is_under(path, &self.config.test_paths) && !self.is_under(path, &self.config.generic_test_dirs()) && ext == "sql" {
+            if let Some(stem) = stem.clone() {
+                self.singular_tests.insert(stem, path.to_path_buf());
+            }
+        }
+
+        if self.is_under(path, &self.config.snapshot_paths) && ext == "sql" {
+            self.snapshots.retain(|_, s| s.path != path);
+            if let Some(content) = read() {
+                self.index_snapshots_in_file(path, content);
+            }
+        }
+
+        if ext == "sql" && self.reference_paths().any(|dir| path.starts_with(self.root_dir.join(dir))) {
+            if let Some(content) = read() {
+                self.index_references_in_file(path, content);
+            }
+        }
+
+        if self.is_under(path, &self.config.macro_dirs()) && (ext == "sql" || ext == "jinja") {
+            let dropped = self.remove_macros_in(path);
+            if let Some(content) = read() {
+                self.index_macros_in_file(path, content);
+            }
+            self.restore_macros(dropped);
+        }
+    }
+
+    /// Drops every entry that was indexed from `path`, e.g. after the file was deleted.
+    pub fn remove_file(&self, path: &Path) {
+        self.models.retain(|_, p| p != path);
+        // The file left over takes the name back
+        let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        let remaining = self.duplicate_models.get_mut(&stem).map(|mut paths| {
+            paths.retain(|p| p != path);
+            paths.clone()
+        });
+        if let Some(remaining) = remaining {
+            if let Some(first) = remaining.first() {
+                self.models.insert(stem.clone(), first.clone());
+            }
+            if remaining.len() < 2 {
+                self.duplicate_models.remove(&stem);
+            }
+        }
+        self.seeds.retain(|_, p| p != path);
+        self.snapshots.retain(|_, s| s.path != path);
+        self.analyses.retain(|_, p| p != path);
+        self.singular_tests.retain(|_, p| p != path);
+        self.sources.retain(|_, s| s.path != path);
+        self.model_entries.retain(|_, e| e.path != path);
+        self.seed_entries.retain(|_, e| e.path != path);
+        let dropped = self.remove_macros_in(path);
+        self.restore_macros(dropped);
+        self.remove_docs_in(path);
+        self.exposures.retain(|_, e| e.path != path);
+        self.metrics.retain(|_, m| m.path != path);
+        self.groups.retain(|_, g| g.path != path);
+        self.generic_tests.remove(path);
+        self.semantic_models.retain(|_, m| m.path != path);
+        self.references.remove(path);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A fresh `test_project` with an empty models directory, shared with the server's tests.
+    pub(crate) fn temp_project(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dbt-lsp-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("models")).unwrap();
+        std::fs::write(dir.join("dbt_project.yml"), "name: test_project\n").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_refresh_file_indexes_new_model() {
+        let root = temp_project("refresh");
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+
+        let text = "select * from {{ ref('new_model') }}";
+        let refs = crate::jinja::extract_refs(text);
+        let rope = ropey::Rope::from_str(text);
+        let (diags, _, _) = crate::diagnostics::validate_refs(&refs, Some(&manifest), &rope, None, Default::default(), &Default::default());
+        assert!(diags.iter().any(|d| d.message.contains("new_model")));
+
+        let new_model = root.join("models").join("new_model.sql");
+        std::fs::write(&new_model, "select 1 as id").unwrap();
+        manifest.refresh_file(&new_model);
+
+        let (diags, _, _) = crate::diagnostics::validate_refs(&refs, Some(&manifest), &rope, None, Default::default(), &Default::default());
+        assert!(!diags.iter().any(|d| d.message.contains("new_model")));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_snapshot_refs() {
+        let root = temp_project("snapshots");
+        std::fs::create_dir_all(root.join("snapshots")).unwrap();
+        std::fs::write(root.join("snapshots").join("orders.sql"), "\n{% snapshot orders_snapshot %}\nselect 1\n{% endsnapshot %}").unwrap();
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+
+        assert_eq!(manifest.snapshots.get("orders_snapshot").map(|s| s.line), Some(1));
+        let text = "select * from {{ ref('orders_snapshot') }}";
+        let refs = crate::jinja::extract_refs(text);
+        let rope = ropey::Rope::from_str(text);
+        let (diags, _, _) = crate::diagnostics::validate_refs(&refs, Some(&manifest), &rope, None, Default::default(), &Default::default());
+        assert!(diags.is_empty());
+
+        // Every block of a file is indexed, and a refresh drops blocks that were removed
+        let path = root.join("snapshots").join("orders.sql");
+        std::fs::write(&path, "{% snapshot orders_daily %}\nselect 1\n{% endsnapshot %}\n\n{%- snapshot orders_hourly -%}\nselect 2\n{% endsnapshot %}").unwrap();
+        manifest.refresh_file(&path);
+        let mut names: Vec<(String, usize)> = manifest.snapshots.iter().map(|s| (s.key().clone(), s.line)).collect();
+        names.sort();
+        assert_eq!(names, vec![("orders_daily".to_string(), 0), ("orders_hourly".to_string(), 4)]);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_analyses() {
+        let root = temp_project("analyses");
+        std::fs::create_dir_all(root.join("analyses")).unwrap();
+        std::fs::write(root.join("analyses").join("revenue_report.sql"), "select * from {{ ref('orders') }}").unwrap();
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+        assert!(manifest.analyses.contains_key("revenue_report"));
+        assert!(!manifest.has_ref_target("revenue_report"));
+
+        // Analyses are indexed for references, but a ref to one is still an error
+        manifest.ensure_reference_index();
+        assert!(manifest.references.contains_key(&root.join("analyses").join("revenue_report.sql")));
+        let text = "select * from {{ ref('revenue_report') }}";
+        let refs = crate::jinja::extract_refs(text);
+        let rope = ropey::Rope::from_str(text);
+        let (diags, _, _) = crate::diagnostics::validate_refs(&refs, Some(&manifest), &rope, None, Default::default(), &Default::default());
+        assert_eq!(diags[0].message, "'revenue_report' is an analysis; analyses can't be ref'd.");
+
+        let added = root.join("analyses").join("churn.sql");
+        std::fs::write(&added, "select 1").unwrap();
+        manifest.refresh_file(&added);
+        assert!(manifest.analyses.contains_key("churn"));
+        manifest.remove_file(&added);
+        assert!(!manifest.analyses.contains_key("churn"));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_singular_tests() {
+        let root = temp_project("singular-tests");
+        std::fs::create_dir_all(root.join("tests").join("finance")).unwrap();
+        std::fs::write(root.join("models").join("orders.sql"), "select 1 as id").unwrap();
+        let test = root.join("tests").join("finance").join("assert_positive_total.sql");
+        std::fs::write(&test, "select * from {{ ref('orders') }} where total < 0").unwrap();
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+        assert_eq!(manifest.singular_tests.get("assert_positive_total").map(|p| p.clone()), Some(test.clone()));
+
+        // A model's references include the tests that select from it
+        manifest.ensure_reference_index();
+        let references = crate::references::find_references(&manifest, &crate::references::ReferenceTarget::Model("orders".to_string()), Default::default());
+        assert_eq!(references.len(), 1);
+        assert!(references[0].uri.path().ends_with("assert_positive_total.sql"));
+
+        manifest.remove_file(&test);
+        assert!(manifest.singular_tests.is_empty());
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_generic_tests() {
+        let root = temp_project("generic-tests");
+        std::fs::create_dir_all(root.join("tests").join("generic")).unwrap();
+        let generic = root.join("tests").join("generic").join("is_positive.sql");
+        std::fs::write(&generic, "\n{% test is_positive(model, column_name) %}\nselect * from {{ model }} where {{ column_name }} < 0\n{% endtest %}").unwrap();
+        let pkg = root.join("dbt_packages").join("dbt_utils");
+        std::fs::create_dir_all(pkg.join("macros")).unwrap();
+        std::fs::write(pkg.join("dbt_project.yml"), "name: dbt_utils\n").unwrap();
+        std::fs::write(pkg.join("macros").join("tests.sql"), "{% test at_least_one(model, column_name) %}select 1{% endtest %}").unwrap();
+        let yml = root.join("models").join("schema.yml");
+        std::fs::write(&yml, "\
+models:
+  - name: orders
+    data_tests: [is_positive, dbt_utils.at_least_one]
+    columns:
+      - name: id
+        data_tests:
+          - unique
+          - not_null
+          - dbt_utils.nope
+          - accepted_values:
+              values: [1, 2]
+      - name: total
+        tests:
+          - is_postive
+          - audit.balanced
+").unwrap();
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+
+        // Generic tests are macros, not singular tests
+        assert!(manifest.singular_tests.is_empty());
+        let names: Vec<String> = manifest.generic_tests.get(&yml).unwrap().iter().map(|t| t.name.clone()).collect();
+        assert_eq!(names, ["is_positive", "dbt_utils.at_least_one", "unique", "not_null", "dbt_utils.nope", "accepted_values", "is_postive", "audit.balanced"]);
+        match manifest.resolve_generic_test("is_positive") {
+            Some(GenericTest::Macro(def)) => assert_eq!((def.path, def.line), (generic, 1)),
+            other => panic!("unexpected test: {:?}", other),
+        }
+        assert!(matches!(manifest.resolve_generic_test("dbt_utils.at_least_one"), Some(GenericTest::Macro(def)) if def.package.as_deref() == Some("dbt_utils")));
+        assert!(matches!(manifest.resolve_generic_test("at_least_one"), Some(GenericTest::Macro(_))));
+        assert!(matches!(manifest.resolve_generic_test("dbt.unique"), Some(GenericTest::Builtin)));
+
+        let diags = crate::diagnostics::generic_test_diagnostics(&manifest, &yml, Default::default());
+        let messages: Vec<(u32, u32, &str)> = diags.iter().map(|d| (d.range.start.line, d.range.start.character, d.message.as_str())).collect();
+        assert_eq!(messages, vec![
+            (8, 12, "Package 'dbt_utils' has no generic test 'nope'."),
+            (13, 12, "Unknown generic test 'is_postive': not a dbt builtin and no `{% test is_postive %}` block in the project or its packages."),
+            (14, 12, "Test 'balanced' is from package 'audit', which is not installed."),
+        ]);
+
+        // The generic test's macro counts as used
+        manifest.ensure_reference_index();
+        assert!(!crate::stats::project_stats(&manifest).unused_macros.contains(&"test_is_positive".to_string()));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_package_refs() {
+        let root = temp_project("packages");
+        let pkg = root.join("dbt_packages").join("dbt_date");
+        std::fs::create_dir_all(pkg.join("models")).unwrap();
+        std::fs::write(pkg.join("dbt_project.yml"), "name: dbt_date\n").unwrap();
+        std::fs::write(pkg.join("models").join("dim_dates.sql"), "select 1 as d").unwrap();
+        std::fs::create_dir_all(pkg.join("macros")).unwrap();
+        std::fs::write(pkg.join("macros").join("dates.sql"), "\n{% macro today() %}current_date{% endmacro %}").unwrap();
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+
+        let today = manifest.resolve_macro("dbt_date.today").unwrap();
+        assert_eq!((today.line, today.package.as_deref()), (1, Some("dbt_date")));
+        // Package macros aren't callable unqualified, nor part of the project's own macros
+        assert!(manifest.resolve_macro("today").is_none());
+        assert!(manifest.macros.is_empty());
+
+        let text = "select * from {{ ref('dbt_date', 'dim_dates') }}, {{ ref('dbt_date', 'nope') }}, {{ ref('missing', 'x') }}, {{ ref('dim_dates') }} \
+            where d = '{{ dbt_date.today() }}' or d = '{{ dbt_date.tomorrow() }}' or d = '{{ other_pkg.anything() }}'";
+        let refs = crate::jinja::extract_refs(text);
+        let rope = ropey::Rope::from_str(text);
+        let (diags, _, _) = crate::diagnostics::validate_refs(&refs, Some(&manifest), &rope, None, Default::default(), &Default::default());
+        let messages: Vec<&str> = diags.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, vec![
+            "Model 'nope' not found in package 'dbt_date'.",
+            "Package 'missing' is not installed (not found in dbt_packages/).",
+            "Macro 'tomorrow' not found in package 'dbt_date'.",
+        ]);
+
+        let labels = |exclude: &[String]| -> Vec<(String, Option<String>)> {
+            crate::completion::ref_items(&manifest, "dim", exclude).into_iter().map(|i| (i.label, i.detail)).collect()
+        };
+        assert_eq!(labels(&[]), vec![("dim_dates".to_string(), Some("model in package 'dbt_date'".to_string()))]);
+        assert!(labels(&["dbt_date".to_string()]).is_empty());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_declared_packages() {
+        let root = temp_project("declared-packages");
+        std::fs::write(root.join("packages.yml"), "\
+packages:
+  - package: dbt-labs/dbt_utils
+    version: 1.1.1
+  - git: \"https://github.com/acme/dbt-audit.git\"
+    revision: v2
+  - local: shared
+").unwrap();
+        std::fs::write(root.join("package-lock.yml"), "\
+packages:
+  - package: dbt-labs/dbt_utils
+    version: 1.1.1
+    name: dbt_utils
+  - git: https://github.com/acme/dbt-audit.git
+    revision: abc123
+    name: audit
+").unwrap();
+        std::fs::create_dir_all(root.join("shared")).unwrap();
+        std::fs::write(root.join("shared").join("dbt_project.yml"), "name: shared_macros\n").unwrap();
+        std::fs::create_dir_all(root.join("dbt_packages").join("dbt_utils")).unwrap();
+        std::fs::write(root.join("dbt_packages").join("dbt_utils").join("dbt_project.yml"), "name: dbt_utils\n").unwrap();
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+
+        let utils = manifest.declared_packages.get("dbt_utils").unwrap();
+        assert_eq!((utils.spec.as_str(), utils.version.as_deref(), utils.line), ("dbt-labs/dbt_utils", Some("1.1.1"), 1));
+        let missing: Vec<(String, Option<String>)> = manifest.missing_packages().into_iter().map(|p| (p.name, p.version)).collect();
+        assert_eq!(missing, vec![("audit".to_string(), Some("v2".to_string())), ("shared_macros".to_string(), None)]);
+        assert!(manifest.is_package_file(&root.join("package-lock.yml")));
+        assert!(manifest.is_package_file(&root.join("dbt_packages").join("dbt_utils").join("dbt_project.yml")));
+
+        let diags = crate::diagnostics::package_diagnostics(&manifest, Default::default());
+        assert_eq!(diags[0].message, "Package 'audit' is declared but not installed — run `dbt deps`.");
+        assert_eq!((diags[0].range.start.line, diags[0].range.start.character, diags[0].range.end.character), (3, 10, 47));
+
+        let text = "select * from {{ ref('audit', 'log') }} where a = '{{ audit.check() }}' \
+            and b = '{{ dbt_expectations.expect() }}' and c = '{{ adapter.quote(\"x\") }}'";
+        let refs = crate::jinja::extract_refs(text);
+        let rope = ropey::Rope::from_str(text);
+        let (diags, _, _) = crate::diagnostics::validate_refs(&refs, Some(&manifest), &rope, None, Default::default(), &Default::default());
+        let messages: Vec<&str> = diags.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, vec![
+            "Package 'audit' is declared but not installed — run `dbt deps`.",
+            "Macro 'check' is from package 'audit', which is declared but not installed — run `dbt deps`.",
+            "Macro 'expect' is from package 'dbt_expectations', which is not installed — add it to packages.yml and run `dbt deps`.",
+        ]);
+
+        let packages: Vec<(String, Option<String>)> = crate::completion::ref_items(&manifest, "", &["shared_macros".to_string()])
+            .into_iter()
+            .map(|i| (i.label, i.detail))
+            .collect();
+        assert_eq!(packages, vec![
+            ("audit".to_string(), Some("dbt package (not installed)".to_string())),
+            ("dbt_utils".to_string(), Some("dbt package".to_string())),
+        ]);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_load_from_manifest_artifact() {
+        let root = temp_project("artifact");
+        std::fs::create_dir_all(root.join("target")).unwrap();
+        std::fs::write(root.join("models").join("orders.sql"), "select * from {{ source('raw', 'orders') }}").unwrap();
+        std::fs::write(root.join("models").join("schema.yml"), "\
+models:
+  - name: orders
+sources:
+  - name: raw
+    tables:
+      - name: orders
+        data_tests:
+          - dbt_utils.recency:
+              datepart: day
+").unwrap();
+        std::fs::write(root.join("target").join("manifest.json"), r#"{
+  "nodes": {
+    "model.test_project.orders": {
+      "unique_id": "model.test_project.orders", "resource_type": "model", "name": "orders", "package_name": "test_project",
+      "original_file_path": "models/orders.sql", "patch_path": "test_project://models/schema.yml",
+      "description": "One row per order", "config": {"materialized": "incremental"},
+      "depends_on": {"nodes": ["source.test_project.raw.orders"]}
+    }
+  },
+  "sources": {
+    "source.test_project.raw.orders": {
+      "package_name": "test_project", "source_name": "raw", "name": "orders",
+      "original_file_path": "models/schema.yml", "description": "Raw orders"
+    }
+  }
+}"#).unwrap();
+
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+        let entry = manifest.model_entries.get("orders").unwrap();
+        assert_eq!((entry.description.as_deref(), entry.line), (Some("One row per order"), 1));
+        let source = manifest.sources.get("raw.orders").unwrap();
+        assert_eq!((source.description.as_deref(), source.line, source.schema.as_str()), (Some("Raw orders"), 5, "raw"));
+        assert_eq!(source.tests, ["dbt_utils.recency"]);
+        assert_eq!(crate::relation::model_materialization(&manifest, "orders"), Some(("incremental".to_string(), "manifest.json")));
+        let orders = crate::hierarchy::DagNode::Model { name: "orders".to_string() };
+        manifest.references.clear();
+        assert_eq!(crate::hierarchy::upstream(&manifest, &orders), vec![crate::hierarchy::DagNode::Source { source: "raw".to_string(), table: "orders".to_string() }]);
+
+        // A yml file edited after dbt ran is parsed again
+        std::fs::write(root.join("models").join("schema.yml"), "models:\n  - name: orders\n    description: Edited\n").unwrap();
+        let later = manifest.artifact.as_ref().unwrap().written + std::time::Duration::from_secs(5);
+        std::fs::File::options().write(true).open(root.join("models").join("schema.yml")).unwrap().set_modified(later).unwrap();
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+        assert_eq!(manifest.model_entries.get("orders").unwrap().description.as_deref(), Some("Edited"));
+        assert!(manifest.sources.get("raw.orders").is_none());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_relation_columns_and_unknown_columns() {
+        let root = temp_project("catalog");
+        std::fs::create_dir_all(root.join("target")).unwrap();
+        std::fs::write(root.join("models").join("orders.sql"), "select id, amount, status from raw_orders").unwrap();
+        std::fs::write(root.join("models").join("customers.sql"), "select * from raw_customers").unwrap();
+        std::fs::write(root.join("models").join("payments.sql"), "select id, total from raw_payments").unwrap();
+        std::fs::write(root.join("models").join("schema.yml"), "\
+models:
+  - name: orders
+    columns:
+      - name: amount
+        description: Order total
+  - name: customers
+    columns:
+      - name: id
+").unwrap();
+        std::fs::write(root.join("target").join("catalog.json"), r#"{"nodes": {"model.test_project.orders": {"columns": {
+  "ID": {"type": "NUMBER", "index": 1, "name": "ID"},
+  "AMOUNT": {"type": "NUMBER", "index": 2, "name": "AMOUNT"}
+}}}}"#).unwrap();
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+
+        let (orders, origin) = manifest.relation_columns("orders").unwrap();
+        assert_eq!(origin, ColumnOrigin::Catalog);
+        assert_eq!(orders[1], ColumnDoc { name: "AMOUNT".to_string(), description: Some("Order total".to_string()), data_type: Some("NUMBER".to_string()), tests: Vec::new(), line: Some(3) });
+        assert_eq!(manifest.relation_columns("customers").unwrap().1, ColumnOrigin::Documented);
+        let (payments, origin) = manifest.relation_columns("payments").unwrap();
+        assert_eq!((payments.len(), origin), (2, ColumnOrigin::SelectList));
+
+        let text = "select o.id, o.amont, c.anything, p.totl\nfrom {{ ref('orders') }} o\njoin {{ ref('customers') }} c on o.id = c.id\njoin {{ ref('payments') }} p on p.id = o.id";
+        let refs = crate::jinja::extract_refs(text);
+        let rope = ropey::Rope::from_str(text);
+        let preprocessed = crate::jinja::preprocess_for_parsing(text);
+        let tree = crate::parser::DbtParser::new().unwrap().parse(&preprocessed, None).unwrap();
+        let (diags, _, _) = crate::diagnostics::validate_refs(&refs, Some(&manifest), &rope, Some(&tree), Default::default(), &Default::default());
+        let messages: Vec<&str> = diags.iter()
+            .filter(|d| d.code == Some(tower_lsp::lsp_types::NumberOrString::String(crate::diagnostics::UNKNOWN_COLUMN.to_string())))
+            .map(|d| d.message.as_str())
+            .collect();
+        assert_eq!(messages, [
+            "Column 'amont' not found in 'orders' (according to catalog.json).",
+            "Column 'totl' not found in 'payments' (according to its select list).",
+        ]);
+
+        // A model changed since `dbt docs generate` ran falls back to its yml columns
+        let later = manifest.catalog.as_ref().unwrap().current().unwrap().written + std::time::Duration::from_secs(5);
+        std::fs::File::options().write(true).open(root.join("models").join("orders.sql")).unwrap().set_modified(later).unwrap();
+        let (orders, origin) = manifest.relation_columns("orders").unwrap();
+        assert_eq!((orders.len(), origin), (1, ColumnOrigin::Documented));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_parallel_scan_of_many_files() {
+        let root = temp_project("many-files");
+        std::fs::create_dir_all(root.join("macros")).unwrap();
+        for i in 0..600 {
+            let dir = root.join("models").join(format!("area_{}", i % 12));
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join(format!("model_{}.sql", i)), format!("select * from {{{{ ref('model_{}') }}}}", i / 2)).unwrap();
+        }
+        for i in 0..40 {
+            let tables: String = (0..5).map(|t| format!("      - name: t{}\n", t)).collect();
+            std::fs::write(root.join("models").join(format!("src_{}.yml", i)), format!("sources:\n  - name: src_{}\n    tables:\n{}  - name: shared\n    tables:\n      - name: t\n", i, tables)).unwrap();
+            std::fs::write(root.join("models").join(format!("docs_{}.md", i)), format!("{{% docs doc_{} %}}\nText\n{{% enddocs %}}\n", i)).unwrap();
+            let macros: String = (0..3).map(|m| format!("{{% macro m_{}_{}() %}}\nselect 1\n{{% endmacro %}}\n\n", i, m)).collect::<String>()
+                + "{% macro shared() %}{% endmacro %}\n";
+            std::fs::write(root.join("macros").join(format!("macros_{}.sql", i)), macros).unwrap();
+        }
+
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+        manifest.ensure_reference_index();
+
+        assert_eq!(manifest.models.len(), 600);
+        assert_eq!(manifest.sources.len(), 201);
+        assert_eq!(manifest.docs.len(), 40);
+        assert_eq!(manifest.macros.len(), 121);
+        assert_eq!(manifest.references.len(), 600 + 40);
+        // Lines are still counted per macro, not per file
+        assert_eq!(manifest.macros.get("m_7_2").map(|m| m.line), Some(8));
+        assert_eq!(manifest.models.get("model_599").unwrap().value(), &root.join("models").join("area_11").join("model_599.sql"));
+        // A name defined in every file goes to the first file in path order, not the first indexed
+        assert_eq!(manifest.macros.get("shared").unwrap().path, root.join("macros").join("macros_0.sql"));
+        assert_eq!(manifest.sources.get("shared.t").unwrap().path, root.join("models").join("src_0.yml"));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_macros_and_docs_from_one_file() {
+        let root = temp_project("macros-and-docs");
+        std::fs::create_dir_all(root.join("macros")).unwrap();
+        std::fs::write(root.join("dbt_project.yml"), "name: test_project\ndocs-paths: [macros]\n").unwrap();
+        std::fs::write(root.join("macros").join("cents.sql"), "\
+{% docs cents %}
+Amounts in cents.
+{% enddocs %}
+{% macro cents(col) %}{{ col }} * 100{% endmacro %}
+{% macro cents(col) %}{{ col }}{% endmacro %}
+").unwrap();
+
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+        assert_eq!(manifest.docs.get("cents").map(|d| d.body.clone()).as_deref(), Some("Amounts in cents."));
+        // The first of two definitions in the same file wins
+        assert_eq!(manifest.macros.get("cents").map(|m| m.line), Some(3));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_refresh_file_restores_duplicate_macro() {
+        let root = temp_project("refresh-macros");
+        std::fs::create_dir_all(root.join("macros")).unwrap();
+        let first = root.join("macros").join("a.sql");
+        std::fs::write(&first, "{% macro cents(col) %}{{ col }} * 100{% endmacro %}").unwrap();
+        std::fs::write(root.join("macros").join("b.sql"), "\n{% macro cents(col) %}{{ col }}{% endmacro %}").unwrap();
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+        assert_eq!(manifest.macros.get("cents").map(|m| m.path.clone()), Some(first.clone()));
+
+        // The other file's definition takes over once the first drops it...
+        std::fs::write(&first, "{% macro dollars(col) %}{{ col }}{% endmacro %}").unwrap();
+        manifest.refresh_file(&first);
+        assert_eq!(manifest.macros.get("cents").map(|m| m.line), Some(1));
+        assert!(manifest.macros.contains_key("dollars"));
+
+        // ...and gives way again when the first file defines it anew
+        std::fs::write(&first, "{% macro cents(col) %}{{ col }} * 100{% endmacro %}").unwrap();
+        manifest.refresh_file(&first);
+        assert_eq!(manifest.macros.get("cents").map(|m| m.path.clone()), Some(first.clone()));
+
+        std::fs::remove_file(&first).unwrap();
+        manifest.remove_file(&first);
+        assert_eq!(manifest.macros.get("cents").map(|m| m.line), Some(1));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_duplicate_models() {
+        let root = temp_project("duplicates");
+        std::fs::create_dir_all(root.join("models").join("b")).unwrap();
+        std::fs::create_dir_all(root.join("models").join("a")).unwrap();
+        std::fs::write(root.join("models").join("a").join("orders.sql"), "select 1").unwrap();
+        std::fs::write(root.join("models").join("b").join("orders.sql"), "select 2").unwrap();
+        std::fs::write(root.join("models").join("customers.sql"), "select 3").unwrap();
+
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+        let a = root.join("models").join("a").join("orders.sql");
+        let b = root.join("models").join("b").join("orders.sql");
+        assert_eq!(manifest.model_files("orders"), vec![a.clone(), b.clone()]);
+        assert_eq!(manifest.model_files("customers").len(), 1);
+        assert_eq!(manifest.model_name_for_path(&b).as_deref(), Some("orders"));
+
+        std::fs::remove_file(&a).unwrap();
+        manifest.remove_file(&a);
+        assert!(manifest.duplicate_models.is_empty());
+        assert_eq!(manifest.models.get("orders").unwrap().value(), &b);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_scoped_vars() {
+        let root = temp_project("vars");
+        std::fs::write(root.join("dbt_project.yml"), "\
+name: test_project
+vars:
+  start_date: '2020-01-01'
+  test_project:
+    start_date: '2021-01-01'
+    countries:
+      - nl
+  dbt_date:
+    time_zone: UTC
+  run_date: \"{{ run_started_at.strftime('%Y-%m-%d') }}\"
+").unwrap();
+        std::fs::create_dir_all(root.join("dbt_packages").join("dbt_date")).unwrap();
+        std::fs::write(root.join("dbt_packages").join("dbt_date").join("dbt_project.yml"), "name: dbt_date\n").unwrap();
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+
+        let mut keys: Vec<String> = manifest.all_vars.iter().map(|v| v.key().clone()).collect();
+        keys.sort();
+        assert_eq!(keys, vec!["dbt_date.time_zone", "run_date", "start_date", "test_project.countries", "test_project.start_date"]);
+        assert_eq!(manifest.config.vars.get("start_date").and_then(|v| v.as_str()), Some("2020-01-01"));
+        // Jinja is stored as written, not rendered
+        assert_eq!(manifest.var("run_date").unwrap().value.as_str(), Some("{{ run_started_at.strftime('%Y-%m-%d') }}"));
+        // A package sees its own scope and the global vars, not the project's scope
+        let tz = manifest.package_var("dbt_date", "time_zone").unwrap();
+        assert_eq!((tz.line, tz.value.as_str()), (8, Some("UTC")));
+        assert_eq!(manifest.package_var("dbt_date", "start_date").unwrap().value.as_str(), Some("2020-01-01"));
+        let package_model = root.join("dbt_packages").join("dbt_date").join("models").join("dates.sql");
+        assert!(manifest.var_for_path(&package_model, "time_zone").is_some());
+        assert!(manifest.var_for_path(&root.join("models").join("a.sql"), "time_zone").is_none());
+
+        let start = manifest.vars.get("start_date").unwrap();
+        assert_eq!((start.line, start.column, start.value.as_str()), (4, 4, Some("2021-01-01")));
+        assert!(manifest.vars.get("countries").unwrap().value.is_sequence());
+        assert!(!manifest.vars.contains_key("time_zone"));
+        assert!(!manifest.vars.contains_key("test_project"));
+
+        let details: Vec<(String, Option<String>)> = crate::completion::var_items(&manifest, "").into_iter().map(|i| (i.label, i.detail)).collect();
+        assert_eq!(details, vec![
+            ("countries".to_string(), Some("[\"nl\"]".to_string())),
+            ("run_date".to_string(), Some("'{{ run_started_at.strftime(''%Y-%m-%d'') }}'".to_string())),
+            ("start_date".to_string(), Some("2021-01-01".to_string())),
+        ]);
+
+        let text = "select * from t where d >= '{{ var('start_date') }}' and tz = '{{ var('time_zone') }}' and n < '{{ var('limit', 10) }}'";
+        let refs = crate::jinja::extract_refs(text);
+        let rope = ropey::Rope::from_str(text);
+        let (diags, _, _) = crate::diagnostics::validate_refs(&refs, Some(&manifest), &rope, None, Default::default(), &Default::default());
+        let messages: Vec<&str> = diags.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, vec!["Var 'time_zone' is not defined in dbt_project.yml and has no default."]);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_yml_descriptions_tests_and_columns() {
+        let root = temp_project("yml_docs");
+        std::fs::write(root.join("models").join("schema.yml"), "\
+models:
+  - name: orders
+    description: One row per order
+    data_tests:
+      - dbt_utils.expression_is_true:
+          expression: amount >= 0
+    columns:
+      - name: id
+        data_type: int
+        tests: [unique, not_null]
+      - name: customer_id
+        data_tests:
+          - relationships:
+              to: ref('customers')
+              field: id
+sources:
+  - name: raw
+    description: Loaded by Fivetran
+    tables:
+      - name: events
+        columns:
+          - name: event_id
+            description: Event key
+").unwrap();
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+
+        let orders = manifest.model_entries.get("orders").unwrap();
+        assert_eq!(orders.description.as_deref(), Some("One row per order"));
+        assert_eq!(orders.tests, ["dbt_utils.expression_is_true"]);
+        assert_eq!(orders.columns, vec![
+            ColumnDoc { name: "id".to_string(), description: None, data_type: Some("int".to_string()), tests: vec!["unique".to_string(), "not_null".to_string()], line: Some(7) },
+            ColumnDoc { name: "customer_id".to_string(), description: None, data_type: None, tests: vec!["relationships".to_string()], line: Some(10) },
+        ]);
+        let events = manifest.sources.get("raw.events").unwrap();
+        assert_eq!(events.description.as_deref(), Some("Loaded by Fivetran"));
+        assert_eq!((events.columns[0].description.as_deref(), events.columns[0].line), (Some("Event key"), Some(21)));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_docs_blocks() {
+        let root = temp_project("docs_blocks");
+        std::fs::write(root.join("dbt_project.yml"), "name: test_project\ndocs-paths: [docs]\n").unwrap();
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::write(root.join("docs").join("shared.md"), "{% docs order_status %}\nOne of placed, shipped.\n{% enddocs %}\n").unwrap();
+        std::fs::write(root.join("models").join("orders.sql"), "{% docs orders %}\nOne row per order.\n{% enddocs %}\nselect 1 as id").unwrap();
+        let later = root.join("models").join("overview.md");
+        // "€" is 3 bytes but one UTF-16 unit, so the name starts at byte 12 and UTF-16 column 10
+        std::fs::write(&later, "# Overview\n\n€ {% docs order_status %}\nOther text\n{% enddocs %}\n").unwrap();
+        std::fs::write(root.join("models").join("schema.yml"), "\
+models:
+  - name: orders
+    description: '{{ doc(\"orders\") }}'
+    columns:
+      - name: status
+        description: \"Status: {{ doc('order_status') }} See {{ doc('missing') }}\"
+").unwrap();
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+
+        assert!(manifest.models.contains_key("orders"));
+        let status = manifest.docs.get("order_status").unwrap().clone();
+        assert_eq!((status.path, status.line, status.column), (root.join("docs").join("shared.md"), 0, 8));
+        let diagnostics = crate::diagnostics::duplicate_docs_diagnostics(&manifest, &later, Default::default());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "Docs block 'order_status' is already defined at docs/shared.md:1.");
+        assert_eq!(diagnostics[0].range, tower_lsp::lsp_types::Range::new(tower_lsp::lsp_types::Position::new(2, 10), tower_lsp::lsp_types::Position::new(2, 22)));
+        assert!(crate::diagnostics::duplicate_docs_diagnostics(&manifest, &root.join("docs").join("shared.md"), Default::default()).is_empty());
+
+        assert!(crate::hover::model_markdown(&manifest, "orders", None).contains("One row per order.\n\n| Column | Description |\n|---|---|\n| `status` | Status: One of placed, shipped. See {{ doc('missing') }} |"));
+
+        // Removing the first definition hands the name to the other one
+        manifest.remove_file(&root.join("docs").join("shared.md"));
+        assert!(manifest.duplicate_docs.is_empty());
+        assert_eq!(manifest.render_docs("{{ doc('order_status') }}"), "Other text");
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_exposures() {
+        let root = temp_project("exposures");
+        std::fs::write(root.join("models").join("fct_orders.sql"), "select 1 as id").unwrap();
+        std::fs::write(root.join("models").join("sources.yml"), "sources:\n  - name: raw\n    tables:\n      - name: events\n").unwrap();
+        let yml = "\
+exposures:
+  - name: weekly_metrics
+    type: dashboard
+    owner:
+      email: data@example.com
+    depends_on:
+      - ref('fct_orders')
+      - source('raw', 'events')
+      - \"ref('fct_order')\"
+  - name: churn_notebook
+    type: notebook
+    owner: {name: Ana}
+    depends_on: [ref('fct_orders')]
+";
+        std::fs::write(root.join("models").join("exposures.yml"), yml).unwrap();
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+        manifest.ensure_reference_index();
+
+        let weekly = manifest.exposures.get("weekly_metrics").unwrap().clone();
+        assert_eq!((weekly.line, weekly.exposure_type.as_deref(), weekly.owner.as_deref()), (1, Some("dashboard"), Some("data@example.com")));
+        let spans: Vec<&str> = weekly.depends_on.iter().map(|(_, range)| &yml[range.clone()]).collect();
+        assert_eq!(spans, ["ref('fct_orders')", "source('raw', 'events')", "ref('fct_order')"]);
+        assert_eq!(manifest.exposures.get("churn_notebook").unwrap().owner.as_deref(), Some("Ana"));
+
+        let path = root.join("models").join("exposures.yml");
+        let diagnostics = crate::diagnostics::yml_ref_diagnostics(&manifest, &path, Default::default());
+        let messages: Vec<(u32, String)> = diagnostics.into_iter().map(|d| (d.range.start.line, d.message)).collect();
+        assert_eq!(messages, [(8, "Model/Seed 'fct_order' not found in project.".to_string())]);
+
+        // Exposures are downstream of what they depend on, and are references to it
+        let fct_orders = crate::hierarchy::DagNode::Model { name: "fct_orders".to_string() };
+        let exposure = |name: &str| crate::hierarchy::DagNode::Exposure { name: name.to_string() };
+        assert_eq!(crate::hierarchy::downstream(&manifest, &fct_orders), vec![exposure("weekly_metrics"), exposure("churn_notebook")]);
+        assert_eq!(crate::hierarchy::upstream(&manifest, &exposure("weekly_metrics")), vec![
+            fct_orders.clone(),
+            crate::hierarchy::DagNode::Source { source: "raw".to_string(), table: "events".to_string() },
+        ]);
+        let target = crate::references::ReferenceTarget::Model("fct_orders".to_string());
+        let lines: Vec<u32> = crate::references::find_references(&manifest, &target, Default::default()).iter().map(|l| l.range.start.line).collect();
+        assert_eq!(lines, [6, 12]);
+        assert_eq!(crate::hierarchy::item(&manifest, &Default::default(), &exposure("weekly_metrics"), Default::default()).unwrap().detail.as_deref(), Some("exposure · dashboard · data@example.com"));
+
+        manifest.remove_file(&path);
+        assert!(manifest.exposures.is_empty());
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_metrics_and_semantic_models() {
+        let root = temp_project("metrics");
+        std::fs::write(root.join("models").join("fct_orders.sql"), "select 1 as id").unwrap();
+        let yml = "\
+semantic_models:
+  - name: orders
+    model: ref('fct_orders')
+    measures:
+      - name: order_total
+        agg: sum
+    dimensions:
+      - name: order_date
+        type: time
+      - name: status
+        type: categorical
+  - name: refunds
+    model: ref('fct_refund')
+metrics:
+  - name: revenue
+    label: Revenue
+    type: simple
+    type_params:
+      measure: order_total
+  - name: average_order
+    type: ratio
+    type_params:
+      numerator: {name: revenue}
+      denominator: order_count
+";
+        let path = root.join("models").join("semantic.yml");
+        std::fs::write(&path, yml).unwrap();
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+
+        let revenue = manifest.metrics.get("revenue").unwrap().clone();
+        assert_eq!((revenue.line, revenue.column), (14, 10));
+        assert_eq!((revenue.metric_type.as_deref(), revenue.expression.as_deref()), (Some("simple"), Some("order_total")));
+        assert_eq!(manifest.metric_dimensions(&revenue), ["order_date", "status"]);
+        assert_eq!(manifest.metrics.get("average_order").unwrap().expression.as_deref(), Some("revenue / order_count"));
+
+        let orders = manifest.semantic_models.get("orders").unwrap().clone();
+        let (dbt_ref, range) = orders.model.unwrap();
+        assert_eq!((dbt_ref, &yml[range]), (crate::jinja::DbtRef::Model("fct_orders".to_string()), "ref('fct_orders')"));
+        assert_eq!(orders.measures, ["order_total"]);
+
+        let diagnostics = crate::diagnostics::yml_ref_diagnostics(&manifest, &path, Default::default());
+        let messages: Vec<(u32, String)> = diagnostics.into_iter().map(|d| (d.range.start.line, d.message)).collect();
+        assert_eq!(messages, [(12, "Model/Seed 'fct_refund' not found in project.".to_string())]);
+
+        let refs = vec![
+            (crate::jinja::DbtRef::Metric("revenue".to_string()), 0..1),
+            (crate::jinja::DbtRef::Metric("profit".to_string()), 2..3),
+        ];
+        let diagnostics = crate::diagnostics::ref_diagnostics(&refs, &manifest, &ropey::Rope::from_str("abcd"), Default::default());
+        let messages: Vec<String> = diagnostics.into_iter().map(|d| d.message).collect();
+        assert_eq!(messages, ["Metric 'profit' not found in project."]);
+
+        manifest.remove_file(&path);
+        assert!(manifest.metrics.is_empty() && manifest.semantic_models.is_empty());
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_model_versions() {
+        use tower_lsp::lsp_types::DiagnosticSeverity;
+        let root = temp_project("versions");
+        for file in ["dim_customers_v1.sql", "dim_customers_v2.sql", "dim_customers_next.sql", "orders.sql"] {
+            std::fs::write(root.join("models").join(file), "select 1 as id").unwrap();
+        }
+        std::fs::write(root.join("models").join("schema.yml"), "\
+models:
+  - name: dim_customers
+    latest_version: 2
+    versions:
+      - v: 1
+      - v: 2
+      - v: 3
+        defined_in: dim_customers_next
+      - v: 4
+").unwrap();
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+
+        let entry = manifest.model_entries.get("dim_customers").unwrap().clone();
+        let versions: Vec<(&str, &str, usize)> = entry.versions.iter().map(|v| (v.version.as_str(), v.defined_in.as_str(), v.line)).collect();
+        assert_eq!(versions, [("1", "dim_customers_v1", 4), ("2", "dim_customers_v2", 5), ("3", "dim_customers_next", 6), ("4", "dim_customers_v4", 8)]);
+        assert_eq!(entry.latest_version.as_deref(), Some("2"));
+        assert_eq!(manifest.resolve_model_version("dim_customers", None).as_deref(), Some("dim_customers_v2"));
+        assert_eq!(manifest.resolve_model_version("dim_customers", Some("3")).as_deref(), Some("dim_customers_next"));
+        assert_eq!(manifest.resolve_model_version("dim_customers", Some("9")), None);
+        assert_eq!(manifest.resolve_model_version("orders", None).as_deref(), Some("orders"));
+        assert!(manifest.has_ref_target("dim_customers"));
+
+        // v=3 is a prerelease newer than the latest, which isn't worth a warning
+        let text = "{{ ref('dim_customers') }}\n{{ ref('dim_customers', v=1) }}\n{{ ref('dim_customers', v=9) }}\n{{ ref('dim_customers', v=4) }}\n{{ ref('orders', v=1) }}\n{{ ref('dim_customers', v=3) }}\n";
+        let diagnostics = crate::diagnostics::ref_diagnostics(&crate::jinja::extract_refs(text), &manifest, &ropey::Rope::from_str(text), Default::default());
+        let messages: Vec<(u32, DiagnosticSeverity, String)> = diagnostics.into_iter().map(|d| (d.range.start.line, d.severity.unwrap(), d.message)).collect();
+        assert_eq!(messages, [
+            (1, DiagnosticSeverity::WARNING, "Ref pins version 1 of model 'dim_customers', but the latest version is 2.".to_string()),
+            (2, DiagnosticSeverity::ERROR, "Model 'dim_customers' has no version 9 (versions: 1, 2, 3, 4).".to_string()),
+            (3, DiagnosticSeverity::ERROR, "Version 4 of model 'dim_customers' is defined in 'dim_customers_v4', which doesn't exist.".to_string()),
+            (4, DiagnosticSeverity::ERROR, "Model 'orders' is not versioned.".to_string()),
+        ]);
+        assert!(crate::hover::model_markdown(&manifest, "dim_customers", Some("1")).starts_with("**Model**: `dim_customers` — version 1 (latest: 2)"));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_groups_and_access() {
+        let root = temp_project("access");
+        std::fs::create_dir_all(root.join("models").join("marketing")).unwrap();
+        for file in ["finance_base.sql", "revenue.sql", "open_orders.sql", "marketing/campaigns.sql", "marketing/spend.sql"] {
+            std::fs::write(root.join("models").join(file), "select 1 as id").unwrap();
+        }
+        std::fs::write(root.join("models").join("ungrouped.sql"), "{{ config(access='private') }}\nselect 1 as id").unwrap();
+        std::fs::write(root.join("dbt_project.yml"), "name: test_project\nmodels:\n  test_project:\n    marketing:\n      +group: marketing\n").unwrap();
+        std::fs::write(root.join("models").join("schema.yml"), "\
+groups:
+  - name: finance
+    owner:
+      name: Finance team
+  - name: marketing
+    owner: {email: marketing@example.com}
+models:
+  - name: finance_base
+    config:
+      group: finance
+      access: private
+  - name: revenue
+    group: finance
+  - name: open_orders
+    access: public
+  - name: spend
+    access: private
+").unwrap();
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+
+        assert_eq!(manifest.groups.get("finance").unwrap().owner.as_deref(), Some("Finance team"));
+        assert_eq!(crate::relation::model_access(&manifest, "finance_base"), (Some("finance".to_string()), "private".to_string()));
+        assert_eq!(crate::relation::model_access(&manifest, "campaigns"), (Some("marketing".to_string()), "protected".to_string()));
+        assert_eq!(crate::relation::model_access(&manifest, "open_orders"), (None, "public".to_string()));
+
+        let text = "{{ ref('finance_base') }}\n{{ ref('open_orders') }}\n{{ ref('spend') }}\n{{ ref('ungrouped') }}\n";
+        let refs = crate::jinja::extract_refs(text);
+        let rope = ropey::Rope::from_str(text);
+        let messages = |file: &str| -> Vec<(u32, String)> {
+            crate::diagnostics::access_diagnostics(&refs, &manifest, &root.join("models").join(file), &rope, Default::default())
+                .into_iter().map(|d| (d.range.start.line, d.message)).collect()
+        };
+        assert_eq!(messages("marketing/campaigns.sql"), [
+            (0, "Node model.test_project.campaigns attempted to reference node model.test_project.finance_base, which is not allowed because the referenced node is private to the 'finance' group.".to_string()),
+            (3, "Model 'ungrouped' is private but in no group, so no other model can ref it.".to_string()),
+        ]);
+        assert_eq!(messages("revenue.sql"), [
+            (2, "Node model.test_project.revenue attempted to reference node model.test_project.spend, which is not allowed because the referenced node is private to the 'marketing' group.".to_string()),
+            (3, "Model 'ungrouped' is private but in no group, so no other model can ref it.".to_string()),
+        ]);
+        assert!(crate::hover::model_markdown(&manifest, "finance_base", None).contains("group: `finance` (Finance team) · access: `private`"));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_sources_grouped_by_name() {
+        let root = temp_project("source_groups");
+        std::fs::write(root.join("models").join("sources.yml"), "\
+version: 2
+sources:
+  - name: raw
+    tables:
+      - name: payments
+      - name: orders
+  - name: archive
+    tables:
+      - name: orders
+").unwrap();
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+
+        assert_eq!(manifest.source_names(), vec!["archive", "raw"]);
+        assert_eq!(manifest.source_tables("raw"), vec!["orders", "payments"]);
+        assert_eq!(manifest.source_tables("archive"), vec!["orders"]);
+        assert!(manifest.source_tables("missing").is_empty());
+
+        let _ = std::fs::remove_dir_all(root);
     }
 }
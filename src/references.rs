@@ -0,0 +1,131 @@
+use crate::jinja::DbtRef;
+use crate::position::PositionEncoding;
+use crate::project::ProjectManifest;
+use crate::state::DocumentState;
+use dashmap::DashMap;
+use regex::Regex;
+use std::sync::OnceLock;
+use tower_lsp::lsp_types::{Location, Position, Range, Url};
+
+/// What find-references was asked about.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReferenceTarget {
+    Model(String),
+    /// A project macro, by its unqualified name.
+    Macro(String),
+    /// A source table: source name and table name.
+    Source(String, String),
+}
+
+impl ReferenceTarget {
+    /// The target a reference under the cursor points at. `project` is the current
+    /// project's name, so `ref('my_project', 'x')` counts as a use of model `x`.
+    pub fn from_ref(dbt_ref: &DbtRef, project: &str) -> Option<Self> {
+        match dbt_ref {
+            DbtRef::Model(name) => Some(ReferenceTarget::Model(name.clone())),
+            DbtRef::PackageModel(pkg, name) if pkg == project => Some(ReferenceTarget::Model(name.clone())),
+            DbtRef::Source(src, tbl) => Some(ReferenceTarget::Source(src.clone(), tbl.clone())),
+            // Calls through the project namespace (`my_project.name(...)`) are the same macro
+            DbtRef::Macro(name) => match name.split_once('.') {
+                Some((namespace, unqualified)) if namespace == project => Some(ReferenceTarget::Macro(unqualified.to_string())),
+                Some(_) => None,
+                None => Some(ReferenceTarget::Macro(name.clone())),
+            },
+            _ => None,
+        }
+    }
+
+    fn matches(&self, dbt_ref: &DbtRef, project: &str) -> bool {
+        ReferenceTarget::from_ref(dbt_ref, project).as_ref() == Some(self)
+    }
+}
+
+/// The `{% macro name(...) %}` headers in `text`: each macro's name and the byte range of it.
+pub fn macro_definitions(text: &str) -> Vec<(String, std::ops::Range<usize>)> {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| Regex::new(r#"\{%-?\s*macro\s+([a-zA-Z0-9_]+)\s*\("#).unwrap());
+    re.captures_iter(text)
+        .filter_map(|cap| cap.get(1))
+        .map(|m| (m.as_str().to_string(), m.range()))
+        .collect()
+}
+
+/// The macro defined by a `{% macro name(...) %}` header on `line`, if any.
+pub fn macro_definition_on_line(line: &str) -> Option<String> {
+    macro_definitions(line).into_iter().next().map(|(name, _)| name)
+}
+
+/// Every use of `target` in the manifest's reference index and in exposures' `depends_on`,
+/// ordered by file and offset.
+pub fn find_references(manifest: &ProjectManifest, target: &ReferenceTarget, encoding: PositionEncoding) -> Vec<Location> {
+    let mut found: Vec<(std::path::PathBuf, usize, Location)> = Vec::new();
+    let mut collect = |path: &std::path::Path, file: &crate::project::IndexedFile| {
+        let Ok(uri) = Url::from_file_path(path) else { return };
+        for (dbt_ref, range) in &file.refs {
+            if target.matches(dbt_ref, &manifest.config.name) {
+                let location = Location {
+                    uri: uri.clone(),
+                    range: crate::position::byte_range_to_range(&file.text, range, encoding),
+                };
+                found.push((path.to_path_buf(), range.start, location));
+            }
+        }
+    };
+    for file in manifest.references.iter() {
+        collect(file.key(), file.value());
+    }
+    let exposures: Vec<(String, std::path::PathBuf)> = manifest.exposures.iter()
+        .filter(|e| e.depends_on.iter().any(|(dbt_ref, _)| target.matches(dbt_ref, &manifest.config.name)))
+        .map(|e| (e.key().clone(), e.path.clone()))
+        .collect();
+    for (name, path) in exposures {
+        if let Some(file) = manifest.exposure_file(&name) {
+            collect(&path, &file);
+        }
+    }
+    found.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+    found.into_iter().map(|(_, _, location)| location).collect()
+}
+
+/// Where `target` itself is defined, for `includeDeclaration`.
+pub fn declaration(manifest: &ProjectManifest, documents: &DashMap<Url, DocumentState>, target: &ReferenceTarget, encoding: PositionEncoding) -> Option<Location> {
+    match target {
+        ReferenceTarget::Model(name) => {
+            let path = manifest.models.get(name)?.clone();
+            Some(Location {
+                uri: Url::from_file_path(path).ok()?,
+                range: Range::new(Position::new(0, 0), Position::new(0, 0)),
+            })
+        }
+        ReferenceTarget::Source(src, tbl) => {
+            let def = manifest.sources.get(&format!("{}.{}", src, tbl))?.clone();
+            Some(Location {
+                uri: Url::from_file_path(&def.path).ok()?,
+                range: crate::position::file_span_to_range(documents, &def.path, def.line, def.column, tbl.len(), encoding),
+            })
+        }
+        ReferenceTarget::Macro(name) => {
+            let def = manifest.macros.get(name)?.clone();
+            let line = def.line as u32;
+            Some(Location {
+                uri: Url::from_file_path(&def.path).ok()?,
+                range: Range::new(Position::new(line, 0), Position::new(line, 0)),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_macro_targets() {
+        let target = |name: &str| ReferenceTarget::from_ref(&DbtRef::Macro(name.to_string()), "my_project");
+        assert_eq!(target("cents_to_dollars"), Some(ReferenceTarget::Macro("cents_to_dollars".to_string())));
+        assert_eq!(target("my_project.cents_to_dollars"), Some(ReferenceTarget::Macro("cents_to_dollars".to_string())));
+        assert_eq!(target("dbt_utils.star"), None);
+        assert_eq!(macro_definition_on_line("{%- macro cents_to_dollars(col) -%}"), Some("cents_to_dollars".to_string()));
+        assert_eq!(macro_definition_on_line("select {{ cents_to_dollars('x') }}"), None);
+    }
+}
@@ -0,0 +1,291 @@
+use crate::jinja::DbtRef;
+use crate::profiles::Target;
+use crate::project::ProjectManifest;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Every config a dbt_project.yml section such as `models:` applies to a file under
+/// `folders`, descending through the project name and then the folders, with the `+`
+/// prefix stripped. Deeper settings win, except tags, which add up as in dbt. Without the
+/// `+` prefix a mapping is taken to be a folder, not a config value.
+pub fn folder_configs(section: &serde_yaml::Value, project: &str, folders: &[String]) -> BTreeMap<String, serde_yaml::Value> {
+    let mut configs = BTreeMap::new();
+    let Some(mut node) = section.get(project) else { return configs };
+    apply_folder_level(&mut configs, node);
+    for folder in folders {
+        let Some(child) = node.get(folder.as_str()) else { break };
+        node = child;
+        apply_folder_level(&mut configs, node);
+    }
+    configs
+}
+
+fn apply_folder_level(configs: &mut BTreeMap<String, serde_yaml::Value>, node: &serde_yaml::Value) {
+    let Some(mapping) = node.as_mapping() else { return };
+    let mut entries: Vec<(&str, &serde_yaml::Value)> = mapping.iter().filter_map(|(k, v)| Some((k.as_str()?, v))).collect();
+    // Legacy unprefixed keys first, so `+key` wins on the same level
+    entries.sort_by_key(|(key, _)| key.starts_with('+'));
+    for (key, value) in entries {
+        let key = match key.strip_prefix('+') {
+            Some(key) => key,
+            None if value.is_mapping() => continue,
+            None => key,
+        };
+        match configs.get_mut(key) {
+            Some(existing) if key == "tags" => {
+                let as_list = |v: &serde_yaml::Value| match v {
+                    serde_yaml::Value::Sequence(items) => items.clone(),
+                    other => vec![other.clone()],
+                };
+                let mut tags = as_list(existing);
+                tags.extend(as_list(value).into_iter().filter(|t| !tags.contains(t)).collect::<Vec<_>>());
+                *existing = serde_yaml::Value::Sequence(tags);
+            }
+            _ => {
+                configs.insert(key.to_string(), value.clone());
+            }
+        }
+    }
+}
+
+/// Looks up `key` (or `+key`) in a dbt_project.yml config section, like [`folder_configs`].
+pub fn folder_config_value(section: &serde_yaml::Value, project: &str, folders: &[String], key: &str) -> Option<serde_yaml::Value> {
+    folder_configs(section, project, folders).remove(key)
+}
+
+/// Like `folder_config_value`, for string-valued configs.
+pub fn folder_config(section: &serde_yaml::Value, project: &str, folders: &[String], key: &str) -> Option<String> {
+    folder_config_value(section, project, folders, key).and_then(|v| v.as_str().map(str::to_string))
+}
+
+/// The folder-level value of `key` from dbt_project.yml's `models:` for the model at `path`.
+pub fn model_folder_config(manifest: &ProjectManifest, path: &Path, key: &str) -> Option<serde_yaml::Value> {
+    let folders = folders_under(path, &manifest.root_dir, &manifest.config.model_paths);
+    folder_config_value(&manifest.config.models, &manifest.config.name, &folders, key)
+}
+
+/// The folder-level configs from dbt_project.yml's `models:` for the model at `path`.
+pub fn model_folder_configs(manifest: &ProjectManifest, path: &Path) -> BTreeMap<String, serde_yaml::Value> {
+    let folders = folders_under(path, &manifest.root_dir, &manifest.config.model_paths);
+    folder_configs(&manifest.config.models, &manifest.config.name, &folders)
+}
+
+/// A model's materialization and where it is set: `config()`, manifest.json when it is
+/// current for the model, dbt_project.yml, or dbt's default of a view.
+pub fn model_materialization(manifest: &ProjectManifest, name: &str) -> Option<(String, &'static str)> {
+    let path = manifest.models.get(name)?.value().clone();
+    let text = std::fs::read_to_string(&path).unwrap_or_default();
+    if let Some(materialized) = crate::jinja::parse_config(&text).and_then(|c| c.get("materialized").map(str::to_string)) {
+        return Some((materialized, "config()"));
+    }
+    // dbt also applied configs from schema.yml, which the folder lookup doesn't know about
+    let current = |artifact: &crate::artifacts::ManifestArtifact, node: &crate::artifacts::ArtifactNode| {
+        artifact.covers(&path) && node.patch_path.as_deref().is_none_or(|p| artifact.covers(p))
+    };
+    if let Some(materialized) = manifest.artifact.as_deref()
+        .and_then(|artifact| artifact.node_for_file(&path).filter(|node| current(artifact, node)))
+        .and_then(|node| node.materialized.clone())
+    {
+        return Some((materialized, "manifest.json"));
+    }
+    match model_folder_config(manifest, &path, "materialized").and_then(|v| v.as_str().map(str::to_string)) {
+        Some(materialized) => Some((materialized, "dbt_project.yml")),
+        None => Some(("view".to_string(), "default")),
+    }
+}
+
+/// A model's `group` and its `access`, each from `config()`, its yml entry or
+/// dbt_project.yml, in that order. Access defaults to dbt's `protected`.
+pub fn model_access(manifest: &ProjectManifest, name: &str) -> (Option<String>, String) {
+    let path = manifest.models.get(name).map(|p| p.value().clone());
+    let text = path.as_ref().and_then(|p| std::fs::read_to_string(p).ok()).unwrap_or_default();
+    let model_config = crate::jinja::parse_config(&text);
+    // A versioned model's entry is under its unversioned name
+    let entry = manifest.model_entries.get(name).map(|e| e.value().clone())
+        .or_else(|| manifest.model_entries.iter().find(|e| e.versions.iter().any(|v| v.defined_in == name)).map(|e| e.value().clone()));
+    let in_file = |key: &str| model_config.as_ref().and_then(|c| c.get(key)).map(str::to_string);
+    let in_project = |key: &str| path.as_ref()
+        .and_then(|p| model_folder_config(manifest, p, key))
+        .and_then(|v| v.as_str().map(str::to_string));
+
+    let group = in_file("group").or_else(|| entry.as_ref().and_then(|e| e.group.clone())).or_else(|| in_project("group"));
+    let access = in_file("access").or_else(|| entry.as_ref().and_then(|e| e.access.clone())).or_else(|| in_project("access"));
+    (group, access.unwrap_or_else(|| "protected".to_string()))
+}
+
+/// The folder-level value of `key` from dbt_project.yml's `seeds:` for the seed at `path`.
+pub fn seed_folder_config(manifest: &ProjectManifest, path: &Path, key: &str) -> Option<serde_yaml::Value> {
+    let folders = folders_under(path, &manifest.root_dir, &manifest.config.seed_paths);
+    folder_config_value(&manifest.config.seeds, &manifest.config.name, &folders, key)
+}
+
+/// Folders between the configured path (e.g. `models/`) and the file.
+pub fn folders_under(path: &Path, root_dir: &Path, dirs: &[String]) -> Vec<String> {
+    let relative = dirs.iter().find_map(|dir| path.strip_prefix(root_dir.join(dir)).ok());
+    relative
+        .and_then(|r| r.parent())
+        .map(|p| p.iter().map(|c| c.to_string_lossy().to_string()).collect())
+        .unwrap_or_default()
+}
+
+/// Formats `database.schema.identifier`. Custom schemas are appended to the target
+/// schema like dbt's default `generate_schema_name`.
+fn format_relation(database: Option<&str>, target_schema: Option<&str>, custom_schema: Option<&str>, identifier: &str) -> String {
+    let target_schema = target_schema.unwrap_or("<target_schema>");
+    let schema = match custom_schema {
+        Some(custom) => format!("{}_{}", target_schema, custom),
+        None => target_schema.to_string(),
+    };
+    match database {
+        Some(database) => format!("{}.{}.{}", database, schema, identifier),
+        None => format!("{}.{}", schema, identifier),
+    }
+}
+
+/// The physical relation a `ref('name')` to a model resolves to, taking the model's
+/// `config()` block, its yml alias and folder-level configs into account. Ephemeral
+/// models are inlined as CTEs and have none.
+pub fn model_relation(manifest: &ProjectManifest, name: &str, target: &Target) -> Option<String> {
+    if model_materialization(manifest, name).is_some_and(|(m, _)| m == "ephemeral") {
+        return None;
+    }
+    let path = manifest.models.get(name)?.value().clone();
+    let text = std::fs::read_to_string(&path).unwrap_or_default();
+    let model_config = crate::jinja::parse_config(&text);
+    let in_file = |key: &str| model_config.as_ref().and_then(|c| c.get(key)).map(str::to_string);
+    let folders = folders_under(&path, &manifest.root_dir, &manifest.config.model_paths);
+    let section = &manifest.config.models;
+    let config = |key: &str| in_file(key).or_else(|| folder_config(section, &manifest.config.name, &folders, key));
+
+    let alias = in_file("alias")
+        .or_else(|| manifest.model_entries.get(name).and_then(|e| e.alias.clone()))
+        .or_else(|| folder_config(section, &manifest.config.name, &folders, "alias"))
+        .unwrap_or_else(|| name.to_string());
+    let database = config("database").or_else(|| target.database.clone());
+    Some(format_relation(database.as_deref(), target.schema.as_deref(), config("schema").as_deref(), &alias))
+}
+
+/// The physical relation of a source table from its yml entry, falling back to
+/// `default_database` when the source sets none.
+pub fn source_relation(def: &crate::project::SourceDef, default_database: Option<&str>) -> String {
+    match def.database.as_deref().or(default_database) {
+        Some(database) => format!("{}.{}.{}", database, def.schema, def.identifier),
+        None => format!("{}.{}", def.schema, def.identifier),
+    }
+}
+
+/// The physical relation of a seed, using folder-level configs under `seeds:`.
+pub fn seed_relation(manifest: &ProjectManifest, name: &str, target: &Target) -> Option<String> {
+    let path = manifest.seeds.get(name)?.value().clone();
+    let folders = folders_under(&path, &manifest.root_dir, &manifest.config.seed_paths);
+    let config = |key: &str| folder_config(&manifest.config.seeds, &manifest.config.name, &folders, key);
+
+    let alias = config("alias").unwrap_or_else(|| name.to_string());
+    let database = config("database").or_else(|| target.database.clone());
+    Some(format_relation(database.as_deref(), target.schema.as_deref(), config("schema").as_deref(), &alias))
+}
+
+/// The relation a ref or source resolves to, or None when the manifest doesn't know it.
+pub fn ref_relation(manifest: &ProjectManifest, dbt_ref: &DbtRef, target: &Target) -> Option<String> {
+    match dbt_ref {
+        DbtRef::Model(_) | DbtRef::VersionedModel(..) => {
+            let name = manifest.ref_target_name(dbt_ref)?;
+            seed_relation(manifest, &name, target).or_else(|| model_relation(manifest, &name, target))
+        }
+        DbtRef::PackageModel(pkg, name) if *pkg == manifest.config.name => model_relation(manifest, name, target),
+        DbtRef::Source(src, tbl) => {
+            let def = manifest.sources.get(&format!("{}.{}", src, tbl))?;
+            Some(source_relation(&def, target.database.as_deref()))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_relation_with_folder_and_model_config() {
+        let root = std::env::temp_dir().join(format!("dbt-lsp-relation-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("models").join("marts").join("finance")).unwrap();
+        std::fs::write(root.join("dbt_project.yml"), "\
+name: shop
+profile: shop
+models:
+  shop:
+    marts:
+      +schema: marts
+      finance:
+        +database: finance_db
+").unwrap();
+        std::fs::write(root.join("profiles.yml"), "\
+shop:
+  target: prod
+  outputs:
+    prod:
+      type: bigquery
+      project: analytics
+      dataset: prod
+").unwrap();
+        std::fs::write(root.join("models").join("marts").join("finance").join("fct_orders.sql"), "{{ config(alias='orders') }}\nselect 1").unwrap();
+        std::fs::write(root.join("models").join("marts").join("dim_users.sql"), "{{ config(schema='core') }}\nselect 1").unwrap();
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+
+        let target = manifest.target.clone().unwrap();
+        assert_eq!((target.database.as_deref(), target.schema.as_deref()), (Some("analytics"), Some("prod")));
+        assert_eq!(model_relation(&manifest, "fct_orders", &target).as_deref(), Some("finance_db.prod_marts.orders"));
+        assert_eq!(model_relation(&manifest, "dim_users", &target).as_deref(), Some("analytics.prod_core.dim_users"));
+        assert_eq!(model_relation(&manifest, "dim_users", &Target::default()).as_deref(), Some("<target_schema>_core.dim_users"));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_folder_configs() {
+        let section: serde_yaml::Value = serde_yaml::from_str("\
+shop:
+  +materialized: view
+  tags: nightly
+  staging:
+    materialized: table
+    +materialized: ephemeral
+    +tags: [staging, nightly]
+    +persist_docs:
+      relation: true
+    legacy:
+      enabled: false
+").unwrap();
+        let folders = |path: &[&str]| path.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+        let configs = folder_configs(&section, "shop", &folders(&["staging", "legacy"]));
+        let keys: Vec<&str> = configs.keys().map(String::as_str).collect();
+        assert_eq!(keys, vec!["enabled", "materialized", "persist_docs", "tags"]);
+        // `+` beats the legacy key on the same level; tags add up
+        assert_eq!(configs["materialized"].as_str(), Some("ephemeral"));
+        assert_eq!(configs["tags"], serde_yaml::from_str::<serde_yaml::Value>("[nightly, staging]").unwrap());
+        assert_eq!(folder_config(&section, "shop", &folders(&["marts"]), "materialized").as_deref(), Some("view"));
+        assert!(folder_configs(&section, "other", &[]).is_empty());
+    }
+
+    #[test]
+    fn test_ephemeral_model_has_no_relation() {
+        let root = std::env::temp_dir().join(format!("dbt-lsp-ephemeral-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("models").join("intermediate")).unwrap();
+        std::fs::write(root.join("dbt_project.yml"), "name: shop\nmodels:\n  shop:\n    intermediate:\n      +materialized: ephemeral\n").unwrap();
+        std::fs::write(root.join("models").join("intermediate").join("int_orders.sql"), "select 1").unwrap();
+        std::fs::write(root.join("models").join("intermediate").join("int_users.sql"), "{{ config(materialized='table') }}\nselect 1").unwrap();
+        std::fs::write(root.join("models").join("stg_users.sql"), "select 1").unwrap();
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+
+        assert_eq!(model_materialization(&manifest, "int_orders"), Some(("ephemeral".to_string(), "dbt_project.yml")));
+        assert_eq!(model_materialization(&manifest, "int_users"), Some(("table".to_string(), "config()")));
+        assert_eq!(model_materialization(&manifest, "stg_users"), Some(("view".to_string(), "default")));
+        assert_eq!(model_relation(&manifest, "int_orders", &Target::default()), None);
+        assert_eq!(model_relation(&manifest, "int_users", &Target::default()).as_deref(), Some("<target_schema>.int_users"));
+        assert!(crate::hover::model_markdown(&manifest, "int_orders", None).contains("materialized: `ephemeral` (from dbt_project.yml)"));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,227 @@
+use crate::jinja::DbtRef;
+use crate::position::PositionEncoding;
+use crate::references::ReferenceTarget;
+use crate::project::ProjectManifest;
+use crate::state::DocumentState;
+use dashmap::DashMap;
+use std::collections::HashMap;
+use tower_lsp::lsp_types::{
+    DocumentChangeOperation, DocumentChanges, OneOf, OptionalVersionedTextDocumentIdentifier, RenameFile,
+    ResourceOp, TextDocumentEdit, TextEdit, Url, WorkspaceEdit,
+};
+use ropey::Rope;
+
+/// The file's text, from the open document when there is one.
+fn current_text(documents: &DashMap<Url, DocumentState>, path: &std::path::Path) -> Option<(Url, Rope)> {
+    let uri = Url::from_file_path(path).ok()?;
+    let rope = match documents.get(&uri) {
+        Some(doc) => doc.text.clone(),
+        None => Rope::from_str(&std::fs::read_to_string(path).ok()?),
+    };
+    Some((uri, rope))
+}
+
+/// Builds edits rewriting every `ref('old_name')` in the project's SQL files (everything in
+/// the reference index) and yml files (tests, exposures, metrics) to `new_name`. Open
+/// documents are read from their in-memory rope so unsaved edits are respected.
+pub fn model_ref_edits(
+    manifest: &ProjectManifest,
+    documents: &DashMap<Url, DocumentState>,
+    old_name: &str,
+    new_name: &str,
+    encoding: PositionEncoding,
+) -> HashMap<Url, Vec<TextEdit>> {
+    let mut changes = HashMap::new();
+    manifest.ensure_reference_index();
+    let paths: Vec<_> = manifest.references.iter().map(|entry| entry.key().clone()).collect();
+    let yaml = manifest.yaml_files();
+
+    for (path, is_yaml) in paths.into_iter().map(|p| (p, false)).chain(yaml.into_iter().map(|p| (p, true))) {
+        let Some((uri, rope)) = current_text(documents, &path) else { continue };
+        let text = rope.to_string();
+        // yml calls `ref('name')` without the braces
+        let names = match is_yaml {
+            true => crate::jinja::find_yaml_model_ref_names(&text, old_name),
+            false => crate::jinja::find_model_ref_names(&text, old_name),
+        };
+        let edits: Vec<TextEdit> = names
+            .into_iter()
+            .map(|range| TextEdit {
+                range: crate::position::byte_range_to_range(&rope, &range, encoding),
+                new_text: new_name.to_string(),
+            })
+            .collect();
+        if !edits.is_empty() {
+            changes.insert(uri, edits);
+        }
+    }
+    changes
+}
+
+/// Whether `name` can be used unquoted as a CTE or model name.
+pub fn is_valid_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Edits renaming a CTE at its definition and every usage. Fails with a message for the
+/// client when the new name isn't usable.
+pub fn cte_rename_edits(
+    occurrences: &crate::columns::CteOccurrences,
+    rope: &Rope,
+    new_name: &str,
+    encoding: PositionEncoding,
+) -> Result<Vec<TextEdit>, String> {
+    if !is_valid_identifier(new_name) {
+        return Err(format!("'{}' is not a valid CTE name.", new_name));
+    }
+    if occurrences.siblings.iter().any(|s| s.eq_ignore_ascii_case(new_name)) {
+        return Err(format!("A CTE named '{}' already exists in this WITH clause.", new_name));
+    }
+    Ok(std::iter::once(&occurrences.definition)
+        .chain(&occurrences.usages)
+        .map(|range| TextEdit {
+            range: crate::position::byte_range_to_range(rope, range, encoding),
+            new_text: new_name.to_string(),
+        })
+        .collect())
+}
+
+/// Renames model `old_name`: its .sql file, every ref to it and the `name:` of its yml
+/// entry. The file rename comes last so the text edits still address the old layout.
+/// Without `rename_file` (the client can't rename files) only the text is edited.
+pub fn model_rename_edit(
+    manifest: &ProjectManifest,
+    documents: &DashMap<Url, DocumentState>,
+    old_name: &str,
+    new_name: &str,
+    rename_file: bool,
+    encoding: PositionEncoding,
+) -> Result<WorkspaceEdit, String> {
+    if !is_valid_identifier(new_name) {
+        return Err(format!("'{}' is not a valid model name.", new_name));
+    }
+    if manifest.has_ref_target(new_name) {
+        return Err(format!("A model, seed or snapshot named '{}' already exists.", new_name));
+    }
+    let old_path = manifest.models.get(old_name).map(|p| p.clone()).ok_or_else(|| format!("Model '{}' not found.", old_name))?;
+    let old_uri = Url::from_file_path(&old_path).map_err(|_| "Model path is not a valid URI.".to_string())?;
+    let new_uri = Url::from_file_path(old_path.with_file_name(format!("{}.sql", new_name)))
+        .map_err(|_| "Model path is not a valid URI.".to_string())?;
+
+    let mut changes = model_ref_edits(manifest, documents, old_name, new_name, encoding);
+    if let Some(entry) = manifest.model_entries.get(old_name) {
+        if let Some((uri, rope)) = current_text(documents, &entry.path) {
+            changes.entry(uri).or_default().push(TextEdit {
+                range: crate::position::line_span_to_range(&rope, entry.line, entry.column, old_name.len(), encoding),
+                new_text: new_name.to_string(),
+            });
+        }
+    }
+
+    if !rename_file {
+        return Ok(WorkspaceEdit { changes: Some(changes), ..WorkspaceEdit::default() });
+    }
+    let mut operations: Vec<DocumentChangeOperation> = changes.into_iter()
+        .map(|(uri, edits)| DocumentChangeOperation::Edit(TextDocumentEdit {
+            text_document: OptionalVersionedTextDocumentIdentifier { uri, version: None },
+            edits: edits.into_iter().map(OneOf::Left).collect(),
+        }))
+        .collect();
+    operations.push(DocumentChangeOperation::Op(ResourceOp::Rename(RenameFile {
+        old_uri,
+        new_uri,
+        options: None,
+        annotation_id: None,
+    })));
+    Ok(WorkspaceEdit {
+        document_changes: Some(DocumentChanges::Operations(operations)),
+        ..WorkspaceEdit::default()
+    })
+}
+
+/// A project-wide renameable name (CTEs are handled per document).
+#[derive(Debug, Clone, PartialEq)]
+pub enum RenameTarget {
+    Model(String),
+    Macro(String),
+}
+
+/// The model or macro name at `byte_idx` and the byte range of the name itself: the quoted
+/// name in `ref('...')`, the unqualified name of a macro call, or the name in a
+/// `{% macro %}` header on the cursor's line. Only names defined in this project count.
+pub fn rename_target_at(
+    manifest: &ProjectManifest,
+    text: &str,
+    refs: &[(DbtRef, std::ops::Range<usize>)],
+    byte_idx: usize,
+) -> Option<(RenameTarget, std::ops::Range<usize>)> {
+    if let Some((dbt_ref, range)) = refs.iter().find(|(_, r)| r.contains(&byte_idx)) {
+        return match dbt_ref {
+            DbtRef::Model(name) if manifest.models.contains_key(name) => {
+                let quoted = crate::jinja::find_model_ref_names(&text[range.clone()], name).into_iter().next()?;
+                Some((RenameTarget::Model(name.clone()), range.start + quoted.start..range.start + quoted.end))
+            }
+            DbtRef::Macro(_) => match ReferenceTarget::from_ref(dbt_ref, &manifest.config.name)? {
+                ReferenceTarget::Macro(name) if manifest.macros.contains_key(&name) => {
+                    let start = range.end - name.len();
+                    Some((RenameTarget::Macro(name), start..range.end))
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+    }
+
+    let line_start = text[..byte_idx].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[byte_idx..].find('\n').map_or(text.len(), |i| byte_idx + i);
+    let (name, range) = crate::references::macro_definitions(&text[line_start..line_end]).into_iter().next()?;
+    manifest.macros.contains_key(&name)
+        .then(|| (RenameTarget::Macro(name), line_start + range.start..line_start + range.end))
+}
+
+/// Edits renaming macro `old_name` in its `{% macro %}` header and at every call,
+/// including calls through the project namespace (only the name part is replaced).
+pub fn macro_rename_edits(
+    manifest: &ProjectManifest,
+    documents: &DashMap<Url, DocumentState>,
+    old_name: &str,
+    new_name: &str,
+    encoding: PositionEncoding,
+) -> Result<HashMap<Url, Vec<TextEdit>>, String> {
+    if !is_valid_identifier(new_name) {
+        return Err(format!("'{}' is not a valid macro name.", new_name));
+    }
+    if manifest.macros.contains_key(new_name) {
+        return Err(format!("A macro named '{}' already exists.", new_name));
+    }
+
+    manifest.ensure_reference_index();
+    let paths: Vec<_> = manifest.references.iter().map(|entry| entry.key().clone()).collect();
+    let target = ReferenceTarget::Macro(old_name.to_string());
+    let mut changes = HashMap::new();
+    for path in paths {
+        let Some((uri, rope)) = current_text(documents, &path) else { continue };
+        let text = rope.to_string();
+        let comments = crate::jinja::comment_spans(&text);
+
+        let calls = crate::jinja::extract_refs(&text).into_iter()
+            .filter(|(dbt_ref, _)| ReferenceTarget::from_ref(dbt_ref, &manifest.config.name).as_ref() == Some(&target))
+            .map(|(_, range)| range.end - old_name.len()..range.end);
+        let headers = crate::references::macro_definitions(&text).into_iter()
+            .filter(|(name, _)| name == old_name)
+            .map(|(_, range)| range);
+        let edits: Vec<TextEdit> = calls.chain(headers)
+            .filter(|range| !comments.iter().any(|c| c.contains(&range.start)))
+            .map(|range| TextEdit {
+                range: crate::position::byte_range_to_range(&rope, &range, encoding),
+                new_text: new_name.to_string(),
+            })
+            .collect();
+        if !edits.is_empty() {
+            changes.insert(uri, edits);
+        }
+    }
+    Ok(changes)
+}
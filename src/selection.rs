@@ -0,0 +1,194 @@
+use crate::position::{byte_range_to_range, PositionEncoding};
+use crate::state::DocumentState;
+use std::ops::Range;
+use tower_lsp::lsp_types::SelectionRange;
+
+fn is_word_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+/// The identifier around `idx`, if the cursor touches one.
+fn word_at(text: &str, idx: usize) -> Option<Range<usize>> {
+    let bytes = text.as_bytes();
+    let start = idx - bytes[..idx].iter().rev().take_while(|b| is_word_byte(**b)).count();
+    let end = idx + bytes[idx..].iter().take_while(|b| is_word_byte(**b)).count();
+    (end > start).then_some(start..end)
+}
+
+/// The `{{ ... }}` expression around `idx`.
+fn jinja_expression_at(text: &str, idx: usize) -> Option<Range<usize>> {
+    let start = text[..idx].rfind("{{")?;
+    if text[start..idx].contains("}}") {
+        return None;
+    }
+    let end = idx + text[idx..].find("}}")? + 2;
+    Some(start..end)
+}
+
+/// The quoted string around `idx` within `expr`, quotes included.
+fn quoted_string_at(text: &str, expr: &Range<usize>, idx: usize) -> Option<Range<usize>> {
+    let mut open: Option<(usize, u8)> = None;
+    for (offset, b) in text.as_bytes()[expr.clone()].iter().enumerate() {
+        let pos = expr.start + offset;
+        match open {
+            None if *b == b'\'' || *b == b'"' => open = Some((pos, *b)),
+            Some((start, quote)) if *b == quote => {
+                if (start..=pos).contains(&idx) {
+                    return Some(start..pos + 1);
+                }
+                open = None;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The innermost `name(...)` call around `idx` within `expr`.
+fn call_at(text: &str, expr: &Range<usize>, idx: usize) -> Option<Range<usize>> {
+    let bytes = text.as_bytes();
+    let mut depth = 0;
+    let mut open = None;
+    for pos in (expr.start..idx.min(expr.end)).rev() {
+        match bytes[pos] {
+            b')' => depth += 1,
+            b'(' if depth == 0 => {
+                open = Some(pos);
+                break;
+            }
+            b'(' => depth -= 1,
+            _ => {}
+        }
+    }
+    let open = open?;
+    let name_start = open - bytes[..open].iter().rev().take_while(|b| is_word_byte(**b)).count();
+    let mut depth = 0;
+    for (pos, b) in bytes.iter().enumerate().take(expr.end).skip(open) {
+        match b {
+            b'(' => depth += 1,
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(name_start..pos + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The trimmed line around `idx`, then the paragraph of non-blank lines around it.
+fn line_and_paragraph(text: &str, idx: usize) -> Vec<Range<usize>> {
+    let line_start = text[..idx].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[idx..].find('\n').map_or(text.len(), |i| idx + i);
+    let line = &text[line_start..line_end];
+    let trimmed_start = line_start + (line.len() - line.trim_start().len());
+    let trimmed_end = line_start + line.trim_end().len();
+
+    let mut para_start = line_start;
+    while para_start > 0 {
+        let prev_start = text[..para_start - 1].rfind('\n').map_or(0, |i| i + 1);
+        if text[prev_start..para_start - 1].trim().is_empty() {
+            break;
+        }
+        para_start = prev_start;
+    }
+    let mut para_end = line_end;
+    while para_end < text.len() {
+        let next_end = text[para_end + 1..].find('\n').map_or(text.len(), |i| para_end + 1 + i);
+        if text[para_end + 1..next_end].trim().is_empty() {
+            break;
+        }
+        para_end = next_end;
+    }
+    vec![trimmed_start..trimmed_end.max(trimmed_start), para_start..para_end]
+}
+
+/// Byte ranges around `idx` from smallest to largest, each containing the one before:
+/// identifier, quoted string, call, `{{ }}` expression, then the SQL nodes from the tree
+/// (or the line and paragraph when the tree has errors) and the whole document.
+pub fn selection_chain(doc: &DocumentState, idx: usize) -> Vec<Range<usize>> {
+    let text = doc.text.to_string();
+    let idx = idx.min(text.len());
+    let mut candidates: Vec<Range<usize>> = Vec::new();
+
+    candidates.extend(word_at(&text, idx));
+    let expr = doc.refs.iter()
+        .map(|(_, range)| range.clone())
+        .filter(|range| range.contains(&idx))
+        .min_by_key(|range| range.len())
+        .or_else(|| jinja_expression_at(&text, idx));
+    if let Some(expr) = &expr {
+        candidates.extend(quoted_string_at(&text, expr, idx));
+        candidates.extend(call_at(&text, expr, idx));
+        candidates.push(expr.clone());
+    }
+    // Nodes parsed from the blanked-out jinja only cover part of the expression
+    let straddles = |range: &Range<usize>| expr.as_ref()
+        .is_some_and(|e| range.start < e.end && e.start < range.end && !(range.start <= e.start && e.end <= range.end));
+
+    match doc.tree.as_ref().filter(|tree| !tree.root_node().has_error()) {
+        Some(tree) => {
+            let mut node = tree.root_node().descendant_for_byte_range(idx, idx);
+            while let Some(current) = node {
+                if !straddles(&current.byte_range()) {
+                    candidates.push(current.byte_range());
+                }
+                node = current.parent();
+            }
+            candidates.extend(doc.ctes.values().map(|cte| cte.body_range.clone()).filter(|r| r.contains(&idx)));
+        }
+        None => candidates.extend(line_and_paragraph(&text, idx)),
+    }
+    candidates.push(0..text.len());
+
+    candidates.sort_by_key(|range| range.len());
+    let mut chain: Vec<Range<usize>> = Vec::new();
+    for range in candidates {
+        let grows = chain.last().is_none_or(|last| {
+            range.start <= last.start && range.end >= last.end && range.len() > last.len()
+        });
+        if grows && range.start <= idx && idx <= range.end {
+            chain.push(range);
+        }
+    }
+    chain
+}
+
+/// The selection chain around `idx` as nested LSP selection ranges.
+pub fn selection_range(doc: &DocumentState, idx: usize, encoding: PositionEncoding) -> Option<SelectionRange> {
+    selection_chain(doc, idx).into_iter().rev().fold(None, |parent, range| {
+        Some(SelectionRange {
+            range: byte_range_to_range(&doc.text, &range, encoding),
+            parent: parent.map(Box::new),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ropey::Rope;
+
+    fn slices(text: &str, idx: usize) -> Vec<String> {
+        let mut doc = DocumentState::text_only(Rope::from_str(text));
+        doc.refs = crate::jinja::extract_refs(text);
+        selection_chain(&doc, idx).into_iter().map(|r| text[r].to_string()).collect()
+    }
+
+    #[test]
+    fn test_ref_levels() {
+        let text = "select *\nfrom {{ ref('orders') }}\nwhere 1 = 1\n\nselect 2";
+        let idx = text.find("orders").unwrap() + 2;
+        assert_eq!(slices(text, idx), vec![
+            "orders",
+            "'orders'",
+            "ref('orders')",
+            "{{ ref('orders') }}",
+            "from {{ ref('orders') }}",
+            "select *\nfrom {{ ref('orders') }}\nwhere 1 = 1",
+            text,
+        ]);
+    }
+}
@@ -0,0 +1,141 @@
+use crate::position::{byte_to_position, PositionEncoding};
+use regex::Regex;
+use ropey::Rope;
+use std::ops::Range;
+use std::sync::OnceLock;
+use tower_lsp::lsp_types::{SemanticToken, SemanticTokenType, SemanticTokensLegend};
+
+/// Token types in legend order; a token's `token_type` indexes into this.
+const TOKEN_TYPES: [SemanticTokenType; 5] = [
+    SemanticTokenType::MACRO,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::STRING,
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::COMMENT,
+];
+const DELIMITER: u32 = 0;
+const FUNCTION: u32 = 1;
+const STRING: u32 = 2;
+const KEYWORD: u32 = 3;
+const COMMENT: u32 = 4;
+
+const JINJA_KEYWORDS: &[&str] = &[
+    "if", "elif", "else", "endif", "for", "endfor", "in", "set", "endset", "macro", "endmacro",
+    "call", "endcall", "filter", "endfilter", "do", "return", "not", "and", "or", "is",
+    "true", "false", "none", "True", "False", "None", "raw", "endraw",
+];
+
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend { token_types: TOKEN_TYPES.to_vec(), token_modifiers: Vec::new() }
+}
+
+fn re_jinja_span() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r#"(?s)\{#.*?#\}|\{\{-?.*?-?\}\}|\{%-?.*?-?%\}"#).unwrap())
+}
+
+/// Strings, function names and keywords inside the body of a `{{ }}` or `{% %}` tag.
+/// In a statement the first word is always a keyword (`materialization`, `docs`, ...).
+fn body_tokens(text: &str, body: Range<usize>, statement: bool, tokens: &mut Vec<(Range<usize>, u32)>) {
+    let bytes = text.as_bytes();
+    let mut pos = body.start;
+    let mut first_word = statement;
+    while pos < body.end {
+        let b = bytes[pos];
+        if b == b'\'' || b == b'"' {
+            let end = text[pos + 1..body.end].find(b as char).map_or(body.end, |i| pos + 1 + i + 1);
+            tokens.push((pos..end, STRING));
+            pos = end;
+        } else if b.is_ascii_alphabetic() || b == b'_' {
+            let len = bytes[pos..body.end].iter().take_while(|c| c.is_ascii_alphanumeric() || **c == b'_').count();
+            let word = &text[pos..pos + len];
+            let is_call = text[pos + len..body.end].trim_start().starts_with('(');
+            if first_word || JINJA_KEYWORDS.contains(&word) {
+                tokens.push((pos..pos + len, KEYWORD));
+            } else if is_call {
+                tokens.push((pos..pos + len, FUNCTION));
+            }
+            first_word = false;
+            pos += len;
+        } else {
+            pos += 1;
+        }
+    }
+}
+
+/// Byte ranges and token types for every jinja construct in `text`, in order.
+fn raw_tokens(text: &str) -> Vec<(Range<usize>, u32)> {
+    let mut tokens = Vec::new();
+    for span in re_jinja_span().find_iter(text) {
+        let s = span.as_str();
+        if s.starts_with("{#") {
+            tokens.push((span.range(), COMMENT));
+            continue;
+        }
+        let open = if s[2..].starts_with('-') { 3 } else { 2 };
+        let close = if s[..s.len() - 2].ends_with('-') && s.len() - 3 >= open { 3 } else { 2 };
+        tokens.push((span.start()..span.start() + open, DELIMITER));
+        body_tokens(text, span.start() + open..span.end() - close, s.starts_with("{%"), &mut tokens);
+        tokens.push((span.end() - close..span.end(), DELIMITER));
+    }
+    tokens
+}
+
+/// Semantic tokens for the jinja in `rope`, delta-encoded as LSP expects. Multi-line
+/// tokens are split per line. With `range`, only tokens overlapping it are kept.
+pub fn semantic_tokens(rope: &Rope, range: Option<Range<usize>>, encoding: PositionEncoding) -> Vec<SemanticToken> {
+    let text = rope.to_string();
+    let mut encoded = Vec::new();
+    let (mut prev_line, mut prev_start) = (0, 0);
+    for (span, token_type) in raw_tokens(&text) {
+        if range.as_ref().is_some_and(|r| span.end <= r.start || span.start >= r.end) {
+            continue;
+        }
+        let mut start = span.start;
+        while start < span.end {
+            let line = rope.byte_to_line(start);
+            let line_end = if line + 1 < rope.len_lines() { rope.line_to_byte(line + 1) } else { rope.len_bytes() };
+            let end = span.end.min(line_end);
+            let segment_end = text[start..end].trim_end_matches(['\n', '\r']).len() + start;
+            if segment_end > start {
+                let from = byte_to_position(rope, start, encoding);
+                let to = byte_to_position(rope, segment_end, encoding);
+                let delta_start = if from.line == prev_line { from.character - prev_start } else { from.character };
+                encoded.push(SemanticToken {
+                    delta_line: from.line - prev_line,
+                    delta_start,
+                    length: to.character - from.character,
+                    token_type,
+                    token_modifiers_bitset: 0,
+                });
+                (prev_line, prev_start) = (from.line, from.character);
+            }
+            start = end;
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw_tokens() {
+        let text = "{{ ref('a') }} {%- if is_incremental() %}{# note #}";
+        let tokens: Vec<(&str, u32)> = raw_tokens(text).into_iter().map(|(r, t)| (&text[r], t)).collect();
+        assert_eq!(tokens, vec![
+            ("{{", DELIMITER), ("ref", FUNCTION), ("'a'", STRING), ("}}", DELIMITER),
+            ("{%-", DELIMITER), ("if", KEYWORD), ("is_incremental", FUNCTION), ("%}", DELIMITER),
+            ("{# note #}", COMMENT),
+        ]);
+    }
+
+    #[test]
+    fn test_multiline_tokens_split_per_line() {
+        let rope = Rope::from_str("select 1\n{#\n  a\n#}");
+        let tokens = semantic_tokens(&rope, None, PositionEncoding::Utf16);
+        let summary: Vec<(u32, u32, u32)> = tokens.iter().map(|t| (t.delta_line, t.delta_start, t.length)).collect();
+        assert_eq!(summary, vec![(1, 0, 2), (1, 0, 3), (1, 0, 2)]);
+    }
+}
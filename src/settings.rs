@@ -0,0 +1,243 @@
+use crate::project::DbtProjectConfig;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use sqlparser::dialect::Dialect;
+
+/// User-facing configuration, populated from `initializationOptions` and
+/// `workspace/didChangeConfiguration` (either flat or nested under `"dbt-lsp"`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Settings {
+    /// sqlparser dialect used for syntax diagnostics (bigquery, snowflake, postgres, ...).
+    /// Unset, it follows the adapter of the profile's target, else BigQuery.
+    pub dialect: Option<String>,
+    /// Master switch for all published diagnostics.
+    pub diagnostics: bool,
+    /// Report SQL syntax errors from sqlparser.
+    pub sql_diagnostics: bool,
+    /// Documents larger than this (in bytes) are tracked but not analyzed.
+    pub max_file_size: usize,
+    /// The dbt project's directory, relative to the workspace root or absolute. Unset,
+    /// the workspace root itself is the project.
+    pub project_dir: Option<String>,
+    /// Used instead of dbt_project.yml's `model-paths`, `seed-paths`, `macro-paths` and
+    /// `snapshot-paths` when set.
+    pub model_paths: Option<Vec<String>>,
+    pub seed_paths: Option<Vec<String>>,
+    pub macro_paths: Option<Vec<String>>,
+    pub snapshot_paths: Option<Vec<String>>,
+    /// Model directories to scan in addition to dbt_project.yml's `model-paths`.
+    pub extra_model_paths: Vec<String>,
+    /// Always scan the project files, ignoring target/manifest.json and catalog.json from
+    /// the last dbt run.
+    pub filesystem_only: bool,
+    /// Installed packages whose models aren't offered in ref completion. Refs to them
+    /// are still validated.
+    pub completion_exclude_packages: Vec<String>,
+    /// Maximum number of lines of a macro's body shown on hover.
+    pub macro_hover_lines: usize,
+    /// Hover markdown longer than this many characters is truncated.
+    pub hover_max_chars: usize,
+    /// Show a CTE's whole body on hover instead of its columns and a short preview.
+    pub cte_hover_full_body: bool,
+    /// Show the resolved relation after ref() and source() calls as inlay hints.
+    pub inlay_hints: bool,
+    /// The dbt executable the commands run, looked up on PATH unless absolute.
+    pub dbt_executable: String,
+    /// Let `dbt-lsp.previewModel` run queries against the warehouse.
+    pub enable_preview: bool,
+    /// Shell command that runs the query it reads from stdin and prints the rows as a JSON array.
+    pub preview_command: String,
+    /// Rows fetched by a preview unless the command asks for another number.
+    pub preview_limit: usize,
+    /// Used for relation names when profiles.yml can't be read.
+    pub target_database: Option<String>,
+    pub target_schema: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            dialect: None,
+            diagnostics: true,
+            sql_diagnostics: true,
+            max_file_size: 2 * 1024 * 1024,
+            project_dir: None,
+            model_paths: None,
+            seed_paths: None,
+            macro_paths: None,
+            snapshot_paths: None,
+            extra_model_paths: Vec::new(),
+            filesystem_only: false,
+            completion_exclude_packages: Vec::new(),
+            macro_hover_lines: 40,
+            hover_max_chars: 10_000,
+            cte_hover_full_body: false,
+            inlay_hints: true,
+            dbt_executable: "dbt".to_string(),
+            enable_preview: false,
+            preview_command: "bq query --format=json --nouse_legacy_sql".to_string(),
+            preview_limit: 100,
+            target_database: None,
+            target_schema: None,
+        }
+    }
+}
+
+impl Settings {
+    /// Merges a (possibly partial) settings object into `self`.
+    /// Returns the keys that were not recognised so the caller can log them. Keys whose
+    /// value has the wrong type are left unchanged and named in the error; the other
+    /// keys are still applied.
+    pub fn apply(&mut self, value: &serde_json::Value) -> Result<Vec<String>, String> {
+        let value = value.get("dbt-lsp").unwrap_or(value);
+        let Some(update) = value.as_object() else {
+            return Ok(Vec::new());
+        };
+
+        let mut unknown = Vec::new();
+        let mut invalid = Vec::new();
+        for (key, val) in update {
+            let mut merged = serde_json::to_value(&*self).map_err(|e| e.to_string())?;
+            let Some(current) = merged.as_object_mut().filter(|c| c.contains_key(key)) else {
+                unknown.push(key.clone());
+                continue;
+            };
+            current.insert(key.clone(), val.clone());
+            match serde_json::from_value(merged) {
+                Ok(settings) => *self = settings,
+                Err(e) => invalid.push(format!("'{}': {}", key, e)),
+            }
+        }
+        if invalid.is_empty() {
+            Ok(unknown)
+        } else {
+            Err(invalid.join("; "))
+        }
+    }
+
+    /// Whether going from `previous` to these settings means rebuilding the projects.
+    pub fn affects_indexing(&self, previous: &Settings) -> bool {
+        self.project_dir != previous.project_dir
+            || self.model_paths != previous.model_paths
+            || self.seed_paths != previous.seed_paths
+            || self.macro_paths != previous.macro_paths
+            || self.snapshot_paths != previous.snapshot_paths
+            || self.extra_model_paths != previous.extra_model_paths
+            || self.filesystem_only != previous.filesystem_only
+    }
+
+    /// The root of the dbt project in `workspace_root`. Fails when `projectDir` is set
+    /// but doesn't lead to a dbt_project.yml.
+    pub fn project_root(&self, workspace_root: &Path) -> Result<PathBuf, String> {
+        let Some(dir) = &self.project_dir else {
+            return Ok(workspace_root.to_path_buf());
+        };
+        // An absolute projectDir replaces the workspace root
+        let root = workspace_root.join(dir);
+        if !root.join("dbt_project.yml").is_file() {
+            return Err(format!("projectDir '{}' resolves to {}, which has no dbt_project.yml", dir, root.display()));
+        }
+        Ok(root)
+    }
+
+    /// Replaces the project's paths with the configured ones and appends the extra model
+    /// paths. Returns the configured paths that don't exist in the project.
+    pub fn override_paths(&self, config: &mut DbtProjectConfig, root_dir: &Path) -> Vec<String> {
+        let mut missing = Vec::new();
+        let overrides = [
+            ("modelPaths", &self.model_paths, &mut config.model_paths),
+            ("seedPaths", &self.seed_paths, &mut config.seed_paths),
+            ("macroPaths", &self.macro_paths, &mut config.macro_paths),
+            ("snapshotPaths", &self.snapshot_paths, &mut config.snapshot_paths),
+        ];
+        for (key, configured, paths) in overrides {
+            let Some(configured) = configured else { continue };
+            missing.extend(configured.iter().filter(|p| !root_dir.join(p).is_dir()).map(|p| format!("{} entry '{}'", key, p)));
+            *paths = configured.clone();
+        }
+        config.model_paths.extend(self.extra_model_paths.iter().cloned());
+        missing
+    }
+
+    /// The target used for relation names: profiles.yml when available, otherwise the
+    /// configured fallback.
+    pub fn relation_target(&self, profile_target: Option<&crate::profiles::Target>) -> crate::profiles::Target {
+        match profile_target {
+            Some(target) => target.clone(),
+            None => crate::profiles::Target {
+                database: self.target_database.clone(),
+                schema: self.target_schema.clone(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// The dialect name in effect: the configured one, else the one matching the
+    /// profile's adapter, else BigQuery.
+    pub fn dialect_name(&self, profile_target: Option<&crate::profiles::Target>) -> String {
+        self.dialect.clone()
+            .or_else(|| profile_target?.adapter_type.as_deref().map(|a| crate::profiles::adapter_dialect(a).to_string()))
+            .unwrap_or_else(|| "bigquery".to_string())
+    }
+
+    /// The dialect in effect, falling back to BigQuery for unknown names.
+    pub fn sql_dialect(&self, profile_target: Option<&crate::profiles::Target>) -> Box<dyn Dialect> {
+        sqlparser::dialect::dialect_from_str(self.dialect_name(profile_target))
+            .unwrap_or_else(|| Box::new(sqlparser::dialect::BigQueryDialect))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_partial_and_unknown_keys() {
+        let mut settings = Settings::default();
+        let update = serde_json::json!({ "dbt-lsp": { "sqlDiagnostics": false, "colour": "blue" } });
+        let unknown = settings.apply(&update).unwrap();
+
+        assert_eq!(unknown, vec!["colour".to_string()]);
+        assert!(!settings.sql_diagnostics);
+        assert_eq!(settings.dialect, None);
+        assert_eq!(settings.dialect_name(None), "bigquery");
+
+        let snowflake = crate::profiles::Target { adapter_type: Some("snowflake".to_string()), ..Default::default() };
+        assert_eq!(settings.dialect_name(Some(&snowflake)), "snowflake");
+        settings.apply(&serde_json::json!({ "dialect": "postgres" })).unwrap();
+        assert_eq!(settings.dialect_name(Some(&snowflake)), "postgres");
+
+        let error = settings.apply(&serde_json::json!({ "maxFileSize": "big", "modelPaths": ["transform/models"] })).unwrap_err();
+        assert!(error.starts_with("'maxFileSize': invalid type"));
+        assert_eq!(settings.max_file_size, Settings::default().max_file_size);
+        assert_eq!(settings.model_paths, Some(vec!["transform/models".to_string()]));
+    }
+
+    #[test]
+    fn test_project_dir_and_path_overrides() {
+        let workspace = std::env::temp_dir().join(format!("dbt-lsp-project-dir-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&workspace);
+        std::fs::create_dir_all(workspace.join("transform").join("dbt_models")).unwrap();
+        std::fs::write(workspace.join("transform").join("dbt_project.yml"), "name: shop\n").unwrap();
+
+        let mut settings = Settings::default();
+        assert_eq!(settings.project_root(&workspace), Ok(workspace.clone()));
+        settings.apply(&serde_json::json!({ "projectDir": "transform", "modelPaths": ["dbt_models", "missing"], "extraModelPaths": ["extra"] })).unwrap();
+        let root = settings.project_root(&workspace).unwrap();
+        assert_eq!(root, workspace.join("transform"));
+        // Absolute directories are taken as they are
+        settings.project_dir = Some(root.display().to_string());
+        assert_eq!(settings.project_root(std::path::Path::new("/elsewhere")), Ok(root.clone()));
+        settings.project_dir = Some("nope".to_string());
+        assert!(settings.project_root(&workspace).unwrap_err().contains("has no dbt_project.yml"));
+
+        let mut config: DbtProjectConfig = serde_yaml::from_str("name: shop\nmodel-paths: [models]\n").unwrap();
+        let missing = settings.override_paths(&mut config, &root);
+        assert_eq!(config.model_paths, ["dbt_models", "missing", "extra"]);
+        assert_eq!(config.seed_paths, ["seeds"]);
+        assert_eq!(missing, ["modelPaths entry 'missing'"]);
+
+        let _ = std::fs::remove_dir_all(workspace);
+    }
+}
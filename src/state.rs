@@ -1,11 +1,17 @@
 use crate::project::ProjectManifest;
-use crate::jinja::DbtRef;
+use crate::jinja::{DbtRef, ModelConfig};
+use crate::position::PositionEncoding;
+use crate::settings::Settings;
+use crate::hover::SeedPreview;
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use ropey::Rope;
 use tree_sitter::Tree;
-use tower_lsp::lsp_types::{Url, Diagnostic};
+use tower_lsp::lsp_types::{ClientCapabilities, Url, Diagnostic};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicUsize;
 
 #[derive(Debug, Clone)]
 pub struct CteDefinition {
@@ -27,10 +33,95 @@ pub struct DocumentState {
     pub ctes: std::collections::HashMap<String, CteDefinition>,
     pub aliases: std::collections::HashMap<String, AliasDefinition>,
     pub diagnostics: Vec<Diagnostic>,
+    /// The model's parsed `{{ config(...) }}` call, if it has one.
+    pub config: Option<ModelConfig>,
+    /// Set when an incremental edit couldn't be applied; cleared by the next full text.
+    pub out_of_sync: bool,
+}
+
+impl DocumentState {
+    /// State for a document that is tracked for edits and navigation but not analyzed
+    /// (yml files, oversized SQL).
+    pub fn text_only(text: Rope) -> Self {
+        Self {
+            text,
+            tree: None,
+            refs: Vec::new(),
+            ctes: Default::default(),
+            aliases: Default::default(),
+            diagnostics: Vec::new(),
+            config: None,
+            out_of_sync: false,
+        }
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct GlobalState {
-    pub manifest: RwLock<Option<Arc<ProjectManifest>>>,
+    /// One manifest per dbt project root (workspace folder).
+    pub manifests: RwLock<HashMap<PathBuf, Arc<ProjectManifest>>>,
     pub documents: DashMap<Url, DocumentState>,
+    pub position_encoding: RwLock<PositionEncoding>,
+    pub client_capabilities: RwLock<ClientCapabilities>,
+    pub settings: RwLock<Settings>,
+    pub workspace_roots: RwLock<Vec<PathBuf>>,
+    /// Number of project scans currently running.
+    pub indexing: AtomicUsize,
+    /// Seed CSV previews for hover, keyed by path and invalidated by mtime.
+    pub seed_previews: DashMap<PathBuf, SeedPreview>,
+    /// Models with a `dbt run` started from the editor still in flight.
+    pub running_models: DashSet<PathBuf>,
+    /// Failing tests from the last `dbt test` of each model, with the file each is reported on.
+    pub test_failures: DashMap<PathBuf, Vec<(Url, Diagnostic)>>,
+    /// Files last published with project-level errors (duplicate names, exposures'
+    /// dependencies), to clear once resolved.
+    pub project_diagnostic_files: DashSet<Url>,
+}
+
+impl GlobalState {
+    /// Returns the manifest of the project containing `path`. With nested roots the
+    /// innermost one wins.
+    pub async fn manifest_for_path(&self, path: &Path) -> Option<Arc<ProjectManifest>> {
+        self.manifests.read().await.iter()
+            .filter(|(root, _)| path.starts_with(root))
+            .max_by_key(|(root, _)| root.components().count())
+            .map(|(_, manifest)| manifest.clone())
+    }
+
+    /// The cached preview of a seed file, re-read when the file's mtime changed.
+    pub fn seed_preview(&self, path: &Path) -> Option<SeedPreview> {
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        if let Some(cached) = self.seed_previews.get(path) {
+            if cached.modified.is_some() && cached.modified == modified {
+                return Some(cached.clone());
+            }
+        }
+        let preview = crate::hover::read_seed_preview(path)?;
+        self.seed_previews.insert(path.to_path_buf(), preview.clone());
+        Some(preview)
+    }
+
+    pub async fn manifest_for(&self, uri: &Url) -> Option<Arc<ProjectManifest>> {
+        let path = uri.to_file_path().ok()?;
+        self.manifest_for_path(&path).await
+    }
+
+    /// Marks the model at `path` as running until the returned guard drops, including
+    /// when the request running it is cancelled. None when it's already running.
+    pub fn start_run(&self, path: &Path) -> Option<RunningModel<'_>> {
+        self.running_models.insert(path.to_path_buf())
+            .then(|| RunningModel { models: &self.running_models, path: path.to_path_buf() })
+    }
+}
+
+/// A model's entry in [`GlobalState::running_models`], removed on drop.
+pub struct RunningModel<'a> {
+    models: &'a DashSet<PathBuf>,
+    path: PathBuf,
+}
+
+impl Drop for RunningModel<'_> {
+    fn drop(&mut self) {
+        self.models.remove(&self.path);
+    }
 }
@@ -0,0 +1,185 @@
+use crate::jinja::DbtRef;
+use crate::project::ProjectManifest;
+use serde::Serialize;
+use std::collections::HashSet;
+
+/// Macros dbt calls by itself, which never show up as calls in the project.
+const IMPLICIT_MACROS: &[&str] = &["generate_schema_name", "generate_alias_name", "generate_database_name"];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ProjectCounts {
+    pub models: usize,
+    pub seeds: usize,
+    pub sources: usize,
+    pub macros: usize,
+    pub snapshots: usize,
+}
+
+/// The `dbt-lsp.projectStats` report. Every list is sorted.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ProjectStats {
+    pub counts: ProjectCounts,
+    /// Models nothing refs and no exposure depends on.
+    pub orphans: Vec<String>,
+    /// Models without a yml entry.
+    pub undocumented: Vec<String>,
+    /// `source.table` names nothing selects from.
+    pub unused_sources: Vec<String>,
+    /// Project macros no file calls. Adapter implementations (`x__name`) and the macros
+    /// dbt calls itself are left out.
+    pub unused_macros: Vec<String>,
+}
+
+fn sorted(names: impl Iterator<Item = String>) -> Vec<String> {
+    let mut names: Vec<String> = names.collect();
+    names.sort();
+    names
+}
+
+/// Builds the report from the manifest and the reference index; the hooks in
+/// dbt_project.yml count as macro calls. Needs the reference index.
+pub fn project_stats(manifest: &ProjectManifest) -> ProjectStats {
+    let project = &manifest.config.name;
+    let mut referenced_models: HashSet<String> = HashSet::new();
+    let mut referenced_sources: HashSet<String> = HashSet::new();
+    let mut called_macros: HashSet<String> = HashSet::new();
+    let mut note = |dbt_ref: &DbtRef, from_model: Option<&str>| match dbt_ref {
+        DbtRef::Model(_) | DbtRef::VersionedModel(..) => {
+            if let Some(name) = manifest.ref_target_name(dbt_ref).filter(|name| from_model != Some(name.as_str())) {
+                referenced_models.insert(name);
+            }
+        }
+        DbtRef::PackageModel(pkg, name) if pkg == project => { referenced_models.insert(name.clone()); }
+        DbtRef::Source(source, table) => { referenced_sources.insert(format!("{}.{}", source, table)); }
+        DbtRef::Macro(name) => {
+            let name = name.strip_prefix(&format!("{}.", project)).unwrap_or(name);
+            called_macros.insert(name.to_string());
+        }
+        _ => {}
+    };
+
+    for file in manifest.references.iter() {
+        let from_model = manifest.model_name_for_path(file.key());
+        for (dbt_ref, _) in &file.refs {
+            note(dbt_ref, from_model.as_deref());
+        }
+    }
+    for exposure in manifest.exposures.iter() {
+        for (dbt_ref, _) in &exposure.depends_on {
+            note(dbt_ref, None);
+        }
+    }
+    for semantic_model in manifest.semantic_models.iter() {
+        if let Some((dbt_ref, _)) = &semantic_model.model {
+            note(dbt_ref, None);
+        }
+    }
+    // Generic tests in yml call their `test_` macros
+    for tests in manifest.generic_tests.iter() {
+        for test in tests.value() {
+            let name = test.name.strip_prefix(&format!("{}.", project)).unwrap_or(&test.name);
+            if !name.contains('.') {
+                note(&DbtRef::Macro(format!("test_{}", name)), None);
+            }
+        }
+    }
+    let hooks = std::fs::read_to_string(manifest.root_dir.join("dbt_project.yml")).unwrap_or_default();
+    for (dbt_ref, _) in crate::jinja::extract_refs(&hooks) {
+        note(&dbt_ref, None);
+    }
+
+    ProjectStats {
+        counts: ProjectCounts {
+            models: manifest.models.len(),
+            seeds: manifest.seeds.len(),
+            sources: manifest.sources.len(),
+            macros: manifest.macros.len(),
+            snapshots: manifest.snapshots.len(),
+        },
+        orphans: sorted(manifest.models.iter().map(|m| m.key().clone()).filter(|m| !referenced_models.contains(m))),
+        undocumented: sorted(manifest.models.iter().map(|m| m.key().clone()).filter(|m| !manifest.model_entries.contains_key(m))),
+        unused_sources: sorted(manifest.sources.iter().map(|s| s.key().clone()).filter(|s| !referenced_sources.contains(s))),
+        unused_macros: sorted(manifest.macros.iter()
+            .map(|m| m.key().clone())
+            .filter(|m| !m.contains("__") && !IMPLICIT_MACROS.contains(&m.as_str()) && !called_macros.contains(m))),
+    }
+}
+
+impl ProjectStats {
+    /// The report as markdown: a counts table, then one section per non-empty list.
+    pub fn to_markdown(&self, project: &str) -> String {
+        let c = &self.counts;
+        let mut out = format!(
+            "# {}\n\n| | Count |\n|---|---|\n| Models | {} |\n| Seeds | {} |\n| Sources | {} |\n| Macros | {} |\n| Snapshots | {} |\n",
+            project, c.models, c.seeds, c.sources, c.macros, c.snapshots
+        );
+        let sections = [
+            ("Orphan models (no downstream refs or exposures)", &self.orphans),
+            ("Models without yml documentation", &self.undocumented),
+            ("Sources never referenced", &self.unused_sources),
+            ("Macros never called", &self.unused_macros),
+        ];
+        for (title, names) in sections {
+            if names.is_empty() {
+                continue;
+            }
+            out.push_str(&format!("\n## {} ({})\n\n", title, names.len()));
+            for name in names {
+                out.push_str(&format!("- `{}`\n", name));
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_project_stats() {
+        let root = std::env::temp_dir().join(format!("dbt-lsp-project-stats-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("models")).unwrap();
+        std::fs::create_dir_all(root.join("macros")).unwrap();
+        std::fs::write(root.join("dbt_project.yml"), "name: shop\non-run-end:\n  - \"{{ grant_select() }}\"\n").unwrap();
+        std::fs::write(root.join("models").join("schema.yml"), "\
+sources:
+  - name: raw
+    tables:
+      - name: orders
+      - name: events
+models:
+  - name: stg_orders
+exposures:
+  - name: dashboard
+    depends_on:
+      - ref('fct_orders')
+").unwrap();
+        std::fs::write(root.join("models").join("stg_orders.sql"), "select {{ cents_to_dollars('amount') }} from {{ source('raw', 'orders') }}").unwrap();
+        std::fs::write(root.join("models").join("fct_orders.sql"), "select * from {{ ref('stg_orders') }}").unwrap();
+        std::fs::write(root.join("models").join("scratch.sql"), "select * from {{ ref('scratch') }}").unwrap();
+        std::fs::write(root.join("macros").join("utils.sql"), "\
+{% macro cents_to_dollars(col) %}{{ col }} / 100{% endmacro %}
+{% macro grant_select() %}{% endmacro %}
+{% macro unused_helper() %}{% endmacro %}
+{% macro bigquery__unused_helper() %}{% endmacro %}
+{% macro generate_schema_name(custom, node) %}{% endmacro %}
+").unwrap();
+        let manifest = ProjectManifest::load(root.clone()).unwrap();
+        manifest.ensure_reference_index();
+
+        let stats = project_stats(&manifest);
+        assert_eq!(stats.counts, ProjectCounts { models: 3, seeds: 0, sources: 2, macros: 5, snapshots: 0 });
+        // A model refing itself doesn't count as used
+        assert_eq!(stats.orphans, vec!["scratch"]);
+        assert_eq!(stats.undocumented, vec!["fct_orders", "scratch"]);
+        assert_eq!(stats.unused_sources, vec!["raw.events"]);
+        assert_eq!(stats.unused_macros, vec!["unused_helper"]);
+
+        let markdown = stats.to_markdown("shop");
+        assert!(markdown.contains("| Models | 3 |"));
+        assert!(markdown.contains("## Sources never referenced (1)\n\n- `raw.events`\n"));
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
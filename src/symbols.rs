@@ -0,0 +1,332 @@
+use crate::position::PositionEncoding;
+use crate::project::ProjectManifest;
+use crate::state::DocumentState;
+use regex::Regex;
+use ropey::Rope;
+use std::ops::Range;
+use std::sync::OnceLock;
+use tower_lsp::lsp_types::{DocumentSymbol, FoldingRange, FoldingRangeKind, Location, Position, Range as LspRange, SymbolInformation, SymbolKind, Url};
+
+/// An outline entry in byte offsets, before nesting.
+struct Entry {
+    name: String,
+    detail: Option<String>,
+    kind: SymbolKind,
+    range: Range<usize>,
+    selection: Range<usize>,
+}
+
+fn re_jinja_block() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r#"(?s)\{%-?\s*(if|for|endif|endfor)\b(.*?)-?%\}"#).unwrap())
+}
+
+fn re_macro_block() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r#"(?s)\{%-?\s*macro\s+([a-zA-Z0-9_]+)\s*\(.*?\{%-?\s*endmacro\s*-?%\}"#).unwrap())
+}
+
+/// `{% if %}` and `{% for %}` blocks, matched with their closing tags. Unclosed blocks
+/// are left out.
+fn jinja_blocks(text: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut open: Vec<(&str, String, Range<usize>)> = Vec::new();
+    for cap in re_jinja_block().captures_iter(text) {
+        let tag = cap.get(0).map_or(0..0, |m| m.range());
+        let (keyword, closing) = match &cap[1] {
+            "if" => ("if", false),
+            "for" => ("for", false),
+            "endif" => ("if", true),
+            _ => ("for", true),
+        };
+        if !closing {
+            let header = format!("{} {}", keyword, cap[2].split_whitespace().collect::<Vec<_>>().join(" "));
+            open.push((keyword, header, tag));
+            continue;
+        }
+        if let Some(idx) = open.iter().rposition(|(k, _, _)| *k == keyword) {
+            let (_, header, start_tag) = open.remove(idx);
+            entries.push(Entry {
+                name: header,
+                detail: None,
+                kind: SymbolKind::NAMESPACE,
+                range: start_tag.start..tag.end,
+                selection: start_tag,
+            });
+        }
+    }
+    entries
+}
+
+/// Byte ranges of the matched `{% if %}`, `{% for %}` and `{% macro %}` blocks in `text`,
+/// and of the block tags whose partner is missing.
+pub fn block_ranges(text: &str) -> (Vec<Range<usize>>, Vec<Range<usize>>) {
+    let mut blocks: Vec<Range<usize>> = jinja_blocks(text).into_iter().map(|e| e.range).collect();
+    blocks.extend(re_macro_block().find_iter(text).map(|m| m.range()));
+    let unmatched = re_jinja_block().find_iter(text)
+        .map(|m| m.range())
+        .filter(|tag| !blocks.iter().any(|b| b.start == tag.start || b.end == tag.end))
+        .collect();
+    (blocks, unmatched)
+}
+
+/// The top-level select of the model, after any WITH clause.
+fn final_select(doc: &DocumentState) -> Option<Range<usize>> {
+    let tree = doc.tree.as_ref()?;
+    let root = tree.root_node();
+    let mut cursor = root.walk();
+    let statement = root.named_children(&mut cursor).find(|n| n.kind() == "query_statement")?;
+    let query = statement.named_child(0).filter(|n| n.kind() == "query_expr")?;
+    let mut query_cursor = query.walk();
+    let body = query.named_children(&mut query_cursor).find(|n| n.kind() != "cte_clause")?;
+    Some(body.start_byte()..query.end_byte())
+}
+
+/// Arranges entries into a tree by range containment.
+fn nest(mut entries: Vec<Entry>, rope: &Rope, encoding: PositionEncoding) -> Vec<DocumentSymbol> {
+    entries.sort_by(|a, b| a.range.start.cmp(&b.range.start).then(b.range.end.cmp(&a.range.end)));
+
+    fn build(entries: &[Entry], idx: &mut usize, end: usize, rope: &Rope, encoding: PositionEncoding) -> Vec<DocumentSymbol> {
+        let mut symbols = Vec::new();
+        while *idx < entries.len() && entries[*idx].range.start < end {
+            let entry = &entries[*idx];
+            *idx += 1;
+            let children = build(entries, idx, entry.range.end, rope, encoding);
+            #[allow(deprecated)]
+            symbols.push(DocumentSymbol {
+                name: entry.name.clone(),
+                detail: entry.detail.clone(),
+                kind: entry.kind,
+                tags: None,
+                deprecated: None,
+                range: crate::position::byte_range_to_range(rope, &entry.range, encoding),
+                selection_range: crate::position::byte_range_to_range(rope, &entry.selection, encoding),
+                children: (!children.is_empty()).then_some(children),
+            });
+        }
+        symbols
+    }
+
+    let mut idx = 0;
+    build(&entries, &mut idx, usize::MAX, rope, encoding)
+}
+
+/// The outline of a document: config block, CTEs, jinja blocks and the final select for
+/// models; one symbol per `{% macro %}` block for macro files.
+pub fn document_symbols(doc: &DocumentState, encoding: PositionEncoding) -> Vec<DocumentSymbol> {
+    let text = doc.text.to_string();
+    let mut entries = Vec::new();
+
+    if crate::jinja::is_macro_file(&text) {
+        for cap in re_macro_block().captures_iter(&text) {
+            let (Some(block), Some(name)) = (cap.get(0), cap.get(1)) else { continue };
+            entries.push(Entry {
+                name: name.as_str().to_string(),
+                detail: Some("macro".to_string()),
+                kind: SymbolKind::FUNCTION,
+                range: block.range(),
+                selection: name.range(),
+            });
+        }
+        return nest(entries, &doc.text, encoding);
+    }
+
+    if let Some(config) = &doc.config {
+        entries.push(Entry {
+            name: "config".to_string(),
+            detail: config.get("materialized").map(str::to_string),
+            kind: SymbolKind::PROPERTY,
+            range: config.range.clone(),
+            selection: config.range.clone(),
+        });
+    }
+    for (name, cte) in &doc.ctes {
+        // The body range stops before the closing paren
+        let end = (cte.body_range.end + 1).min(text.len());
+        entries.push(Entry {
+            name: name.clone(),
+            detail: Some("CTE".to_string()),
+            kind: SymbolKind::STRUCT,
+            range: cte.name_range.start..end,
+            selection: cte.name_range.clone(),
+        });
+    }
+    entries.extend(jinja_blocks(&text));
+    if let Some(range) = final_select(doc) {
+        let keyword_end = (range.start + "select".len()).min(range.end);
+        entries.push(Entry {
+            name: "final select".to_string(),
+            detail: None,
+            kind: SymbolKind::OBJECT,
+            range: range.clone(),
+            selection: range.start..keyword_end,
+        });
+    }
+    nest(entries, &doc.text, encoding)
+}
+
+/// A fold from the line of `range.start` to the line before `range.end`, so the closing
+/// paren or end tag stays visible. None when that spans no lines.
+fn fold(rope: &Rope, range: &Range<usize>, kind: Option<FoldingRangeKind>) -> Option<FoldingRange> {
+    let start_line = rope.byte_to_line(range.start.min(rope.len_bytes()));
+    let end_line = rope.byte_to_line(range.end.min(rope.len_bytes())).checked_sub(1)?;
+    (end_line > start_line).then_some(FoldingRange {
+        start_line: start_line as u32,
+        start_character: None,
+        end_line: end_line as u32,
+        end_character: None,
+        kind,
+        collapsed_text: None,
+    })
+}
+
+/// Runs of two or more `--` comment lines.
+fn comment_folds(text: &str) -> Vec<FoldingRange> {
+    let mut folds = Vec::new();
+    let mut run_start: Option<usize> = None;
+    let lines: Vec<&str> = text.lines().collect();
+    for (idx, line) in lines.iter().chain(std::iter::once(&"")).enumerate() {
+        let is_comment = line.trim_start().starts_with("--");
+        match (is_comment, run_start) {
+            (true, None) => run_start = Some(idx),
+            (false, Some(start)) => {
+                if idx - 1 > start {
+                    folds.push(FoldingRange {
+                        start_line: start as u32,
+                        start_character: None,
+                        end_line: (idx - 1) as u32,
+                        end_character: None,
+                        kind: Some(FoldingRangeKind::Comment),
+                        collapsed_text: None,
+                    });
+                }
+                run_start = None;
+            }
+            _ => {}
+        }
+    }
+    folds
+}
+
+/// Folding ranges for CTE bodies, a multi-line config() call, jinja if/for/macro blocks
+/// and runs of `--` comments.
+pub fn folding_ranges(doc: &DocumentState) -> Vec<FoldingRange> {
+    let text = doc.text.to_string();
+    let rope = &doc.text;
+    let mut folds = Vec::new();
+
+    for cte in doc.ctes.values() {
+        folds.extend(fold(rope, &cte.body_range, Some(FoldingRangeKind::Region)));
+    }
+    if let Some(config) = &doc.config {
+        // Keep the line with the closing `) }}` visible
+        let close = text[..config.range.end].rfind(')').unwrap_or(config.range.end);
+        folds.extend(fold(rope, &(config.range.start..close), Some(FoldingRangeKind::Region)));
+    }
+    for block in jinja_blocks(&text) {
+        let end_tag = text[..block.range.end].rfind("{%").unwrap_or(block.range.end);
+        folds.extend(fold(rope, &(block.range.start..end_tag), Some(FoldingRangeKind::Region)));
+    }
+    for block in re_macro_block().find_iter(&text) {
+        let end_tag = text[..block.end()].rfind("{%").unwrap_or(block.end());
+        folds.extend(fold(rope, &(block.start()..end_tag), Some(FoldingRangeKind::Region)));
+    }
+    folds.extend(comment_folds(&text));
+    folds.sort_by_key(|f| (f.start_line, std::cmp::Reverse(f.end_line)));
+    folds
+}
+
+/// Most workspace symbols returned for one query.
+pub const WORKSPACE_SYMBOL_LIMIT: usize = 200;
+
+/// How well `name` matches `query`, lower is better: 0 for a prefix, 1 for a substring,
+/// 2 for the query's characters appearing in order. Case-insensitive.
+pub fn match_rank(name: &str, query: &str) -> Option<u8> {
+    let name = name.to_lowercase();
+    let query = query.to_lowercase();
+    if name.starts_with(&query) {
+        return Some(0);
+    }
+    if name.contains(&query) {
+        return Some(1);
+    }
+    let mut chars = name.chars();
+    query.chars().all(|q| chars.any(|c| c == q)).then_some(2)
+}
+
+/// Manifest entries matching `query`, with their rank.
+pub fn workspace_symbols(manifest: &ProjectManifest, query: &str, encoding: PositionEncoding) -> Vec<(u8, SymbolInformation)> {
+    let mut symbols = Vec::new();
+    // yml files with a matching source, to convert its column to the client's encoding
+    let mut yml_texts: std::collections::HashMap<std::path::PathBuf, Rope> = std::collections::HashMap::new();
+    let mut push = |name: &str, kind: SymbolKind, container: &str, path: &std::path::Path, line: usize, column: usize| {
+        let Some(rank) = match_rank(name, query) else { return };
+        let Ok(uri) = Url::from_file_path(path) else { return };
+        let position = match column {
+            0 => Position::new(line as u32, 0),
+            _ => {
+                let rope = yml_texts.entry(path.to_path_buf())
+                    .or_insert_with(|| Rope::from_str(&std::fs::read_to_string(path).unwrap_or_default()));
+                crate::position::line_span_to_range(rope, line, column, 0, encoding).start
+            }
+        };
+        #[allow(deprecated)]
+        symbols.push((rank, SymbolInformation {
+            name: name.to_string(),
+            kind,
+            tags: None,
+            deprecated: None,
+            location: Location { uri, range: LspRange::new(position, position) },
+            container_name: Some(container.to_string()),
+        }));
+    };
+
+    for model in manifest.models.iter() {
+        push(model.key(), SymbolKind::FILE, "model", model.value(), 0, 0);
+    }
+    for seed in manifest.seeds.iter() {
+        push(seed.key(), SymbolKind::FILE, "seed", seed.value(), 0, 0);
+    }
+    for snapshot in manifest.snapshots.iter() {
+        push(snapshot.key(), SymbolKind::FILE, "snapshot", &snapshot.path, snapshot.line, 0);
+    }
+    for analysis in manifest.analyses.iter() {
+        push(analysis.key(), SymbolKind::FILE, "analysis", analysis.value(), 0, 0);
+    }
+    for source in manifest.sources.iter() {
+        push(source.key(), SymbolKind::STRUCT, "source", &source.path, source.line, source.column);
+    }
+    for macro_def in manifest.macros.iter() {
+        push(macro_def.key(), SymbolKind::FUNCTION, "macro", &macro_def.path, macro_def.line, 0);
+    }
+    symbols
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_rank() {
+        assert_eq!(match_rank("stg_orders", "STG"), Some(0));
+        assert_eq!(match_rank("stg_orders", "orders"), Some(1));
+        assert_eq!(match_rank("stg_orders", "sord"), Some(2));
+        assert_eq!(match_rank("stg_orders", "xyz"), None);
+    }
+
+    #[test]
+    fn test_comment_folds() {
+        let folds: Vec<(u32, u32)> = comment_folds("-- a\n-- b\nselect 1\n-- single\nx\n  -- c\n  -- d")
+            .into_iter()
+            .map(|f| (f.start_line, f.end_line))
+            .collect();
+        assert_eq!(folds, vec![(0, 1), (5, 6)]);
+    }
+
+    #[test]
+    fn test_jinja_blocks_nest() {
+        let text = "{% if is_incremental() %}{% for c in cols %}x{% endfor %}{% endif %}{% if x %}";
+        let names: Vec<(String, Range<usize>)> = jinja_blocks(text).into_iter().map(|e| (e.name, e.range)).collect();
+        assert_eq!(names, vec![("for c in cols".to_string(), 25..57), ("if is_incremental()".to_string(), 0..68)]);
+    }
+}
@@ -0,0 +1,233 @@
+use std::sync::OnceLock;
+use regex::Regex;
+
+/// A `key: value` line in a yml file, with enough structure to tell apart identically
+/// named entries (e.g. the same table name under two different sources).
+///
+/// serde_yaml doesn't expose locations, so this is a line-based scanner: it tracks the
+/// indentation stack and labels each enclosing list item by its `name:` when it has one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct YamlKey {
+    /// Labels of the enclosing keys and named list items, outermost first.
+    /// Unnamed list items are labelled `-`.
+    pub path: Vec<String>,
+    pub key: String,
+    /// Inline scalar value with quotes and trailing comments stripped.
+    pub value: Option<String>,
+    /// Zero-based line of the key.
+    pub line: usize,
+    /// Byte column of the key. yml keys are indented with ASCII, so this equals the
+    /// UTF-16 column in practice.
+    pub key_column: usize,
+    /// Byte column of the inline value (end of line when there is none).
+    pub value_column: usize,
+}
+
+fn re_key() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r#"^(?:"([^"]+)"|'([^']+)'|([^\s:#'"][^:#]*?))\s*:(?:\s+|$)"#).unwrap())
+}
+
+struct Frame {
+    /// Indentation level doubled, plus one for list items, so a sequence written at the
+    /// same indentation as its parent key still nests under it.
+    level: usize,
+    label: String,
+    is_item: bool,
+}
+
+pub fn scan_keys(text: &str) -> Vec<YamlKey> {
+    let mut keys = Vec::new();
+    let mut stack: Vec<Frame> = Vec::new();
+    // Indentation of a key whose value is a block scalar (`|`, `>`); deeper lines are content
+    let mut block_scalar_indent: Option<usize> = None;
+
+    for (line_idx, line) in text.lines().enumerate() {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with("---") {
+            continue;
+        }
+        if let Some(block_indent) = block_scalar_indent {
+            if indent > block_indent {
+                continue;
+            }
+            block_scalar_indent = None;
+        }
+
+        let mut content = trimmed;
+        let mut column = indent;
+        if let Some(rest) = content.strip_prefix("- ").or_else(|| (content == "-").then_some("")) {
+            let level = indent * 2 + 1;
+            while stack.last().is_some_and(|f| f.level >= level) {
+                stack.pop();
+            }
+            stack.push(Frame { level, label: "-".to_string(), is_item: true });
+            let rest = rest.trim_start();
+            column = indent + (trimmed.len() - rest.len());
+            content = rest;
+        }
+
+        let Some(cap) = re_key().captures(content) else { continue };
+        let key = cap.get(1).or(cap.get(2)).or(cap.get(3)).map(|m| m.as_str().trim().to_string()).unwrap_or_default();
+        let level = column * 2;
+        while stack.last().is_some_and(|f| f.level >= level) {
+            stack.pop();
+        }
+
+        let value_offset = cap.get(0).map_or(content.len(), |m| m.end());
+        let raw_value = content[value_offset..].trim_end();
+        let value = clean_scalar(raw_value);
+        if value.as_deref().is_some_and(|v| v.starts_with('|') || v.starts_with('>')) {
+            block_scalar_indent = Some(column);
+        }
+
+        // `name:` labels the list item it belongs to and isn't part of its own path
+        let names_item = key == "name" && stack.last().is_some_and(|f| f.is_item);
+        if names_item {
+            if let (Some(frame), Some(name)) = (stack.last_mut(), value.as_ref()) {
+                frame.label = name.clone();
+            }
+        }
+        let depth = if names_item { stack.len() - 1 } else { stack.len() };
+        keys.push(YamlKey {
+            path: stack[..depth].iter().map(|f| f.label.clone()).collect(),
+            key: key.clone(),
+            value,
+            line: line_idx,
+            key_column: column,
+            value_column: column + value_offset,
+        });
+
+        if raw_value.is_empty() || raw_value.starts_with('#') {
+            stack.push(Frame { level, label: key, is_item: false });
+        }
+    }
+    keys
+}
+
+fn clean_scalar(raw: &str) -> Option<String> {
+    let raw = raw.trim();
+    if raw.is_empty() || raw.starts_with('#') {
+        return None;
+    }
+    if let Some(inner) = raw.strip_prefix('"').and_then(|r| r.split_once('"')).map(|(v, _)| v) {
+        return Some(inner.to_string());
+    }
+    if let Some(inner) = raw.strip_prefix('\'').and_then(|r| r.split_once('\'')).map(|(v, _)| v) {
+        return Some(inner.to_string());
+    }
+    let unquoted = raw.split(" #").next().unwrap_or(raw).trim();
+    Some(unquoted.to_string())
+}
+
+/// A generic test listed under a `data_tests:` (or `tests:`) key: `- unique`,
+/// `- accepted_values:` with its arguments, or an entry of `[unique, not_null]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct YamlTest {
+    /// As written, e.g. `dbt_utils.unique_combination_of_columns`.
+    pub name: String,
+    pub line: usize,
+    /// Byte column of the name.
+    pub column: usize,
+}
+
+fn re_tests_key() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r#"^(?:data_tests|tests)\s*:(?:\s+|$)"#).unwrap())
+}
+
+fn re_test_name() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r#"^["']?([a-zA-Z_][a-zA-Z0-9_]*(?:\.[a-zA-Z_][a-zA-Z0-9_]*)?)["']?\s*(?::|#|$)"#).unwrap())
+}
+
+/// The generic tests of every `data_tests:` and `tests:` list in a properties file.
+pub fn test_entries(text: &str) -> Vec<YamlTest> {
+    let mut tests = Vec::new();
+    // Indentation of the open tests key, and of its list items once the first is seen
+    let mut block: Option<(usize, Option<usize>)> = None;
+
+    for (line_idx, line) in text.lines().enumerate() {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if let Some((key_indent, item_indent)) = block.as_mut() {
+            let is_item = trimmed == "-" || trimmed.starts_with("- ");
+            // A sequence may sit at its key's own indentation
+            if indent > *key_indent || (indent == *key_indent && is_item) {
+                if is_item && item_indent.is_none_or(|i| i == indent) {
+                    *item_indent = Some(indent);
+                    let rest = trimmed[1..].trim_start();
+                    let column = indent + (trimmed.len() - rest.len());
+                    if let Some(name) = re_test_name().captures(rest).and_then(|c| c.get(1)) {
+                        tests.push(YamlTest { name: name.as_str().to_string(), line: line_idx, column: column + name.start() });
+                    }
+                }
+                continue;
+            }
+            block = None;
+        }
+
+        let (column, content) = match trimmed.strip_prefix("- ") {
+            Some(rest) => (indent + trimmed.len() - rest.trim_start().len(), rest.trim_start()),
+            None => (indent, trimmed),
+        };
+        let Some(key) = re_tests_key().find(content) else { continue };
+        let value = content[key.end()..].split(" #").next().unwrap_or_default().trim_end();
+        if value.is_empty() {
+            block = Some((column, None));
+        } else if let Some(inner) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            let mut offset = column + key.end() + 1;
+            for item in inner.split(',') {
+                let name = item.trim().trim_matches(|c| c == '"' || c == '\'');
+                if re_test_name().is_match(name) && !name.contains(':') {
+                    tests.push(YamlTest { name: name.to_string(), line: line_idx, column: offset + item.find(name).unwrap_or(0) });
+                }
+                offset += item.len() + 1;
+            }
+        }
+    }
+    tests
+}
+
+/// Finds the `name: <name>` line of the list item at `path`, e.g.
+/// `["sources", "raw", "tables"]` + `"users"` for a source table.
+pub fn find_named_item<'a>(keys: &'a [YamlKey], path: &[&str], name: &str) -> Option<&'a YamlKey> {
+    keys.iter().find(|k| {
+        k.key == "name"
+            && k.value.as_deref() == Some(name)
+            && k.path.len() == path.len()
+            && k.path.iter().zip(path).all(|(a, b)| a == b)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_table_under_two_sources() {
+        let yml = "\
+version: 2
+sources:
+  - name: raw
+    description: |
+      name: not a key
+    tables:
+      - name: users
+      - name: orders
+  - name: archive
+    tables:
+    - name: users # legacy copy
+";
+        let keys = scan_keys(yml);
+        let raw_users = find_named_item(&keys, &["sources", "raw", "tables"], "users").unwrap();
+        assert_eq!((raw_users.line, raw_users.value_column), (6, 14));
+        let archive_users = find_named_item(&keys, &["sources", "archive", "tables"], "users").unwrap();
+        assert_eq!((archive_users.line, archive_users.value_column), (10, 12));
+        assert!(find_named_item(&keys, &["sources", "raw"], "not a key").is_none());
+    }
+}